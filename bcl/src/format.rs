@@ -0,0 +1,303 @@
+//! Canonical formatting of BCL text.
+//!
+//! BCL written by hand, or pasted together from several sources, drifts in
+//! layout: indentation, spacing and case vary, and controls end up out of
+//! order. `format_bcl` parses the text into sections and re-emits it the way
+//! BC Manager and the devices themselves write it:
+//!
+//! - Section lines, such as `$encoder 3`, start in the first column, and
+//!   their parameter lines, such as `.showvalue on`, are indented by two
+//!   spaces.
+//! - Keywords are in lower case, and hexadecimal bytes, such as `$B0` in a
+//!   `.tx` line, in upper case. Other arguments are left as they are, and
+//!   quoted strings, such as preset names, are kept exactly.
+//! - Arguments are separated by single spaces.
+//! - Each run of control sections, between other sections such as
+//!   `$preset` or `$store`, is sorted: encoders, then buttons, then faders,
+//!   each by number. A control defined more than once stays in the order
+//!   given, so the last definition still wins.
+//!
+//! The order of parameter lines within a section is kept, since it can
+//! matter: a `.tx` line, for instance, adds to those before it. Comments,
+//! lines starting with `;`, stay with the line that follows them, and are
+//! indented like it. Blank lines are dropped.
+//!
+//! The text can also be colored with ANSI escapes, for review in a terminal.
+
+use super::Result;
+
+/// The kinds of control section, in the order they're sorted in.
+const CONTROLS: [&str; 3] = ["encoder", "button", "fader"];
+
+const SECTION_COLOR: &str = "\x1b[1;34m";
+const PARAMETER_COLOR: &str = "\x1b[36m";
+const STRING_COLOR: &str = "\x1b[32m";
+const NUMBER_COLOR: &str = "\x1b[33m";
+const COMMENT_COLOR: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+/// A line of BCL.
+enum Line {
+    Comment(String),
+    /// A section line, starting with `$`, or a parameter line, starting with
+    /// `.`.
+    Statement {
+        sigil: char,
+        keyword: String,
+        args: Vec<String>,
+    },
+}
+
+/// A section line, its parameter lines, and the comments among them.
+#[derive(Default)]
+struct Section {
+    /// The comments before the section line.
+    comments: Vec<Line>,
+    /// The section line. Only comments and parameter lines before the first
+    /// section have none.
+    header: Option<Line>,
+    body: Vec<Line>,
+}
+
+impl Section {
+    /// The sort key of a control section: its kind and number.
+    fn control(&self) -> Option<(usize, u32)> {
+        let Some(Line::Statement { keyword, args, .. }) = &self.header else {
+            return None;
+        };
+        let kind = CONTROLS.iter().position(|c| c == keyword)?;
+        let number = args.first()?.parse().ok()?;
+        Some((kind, number))
+    }
+}
+
+/// Formats BCL text canonically, optionally with ANSI colors. Fails if a line
+/// isn't a section, parameter or comment.
+pub fn format_bcl<S: AsRef<str>>(lines: &[S], color: bool) -> Result<Vec<String>> {
+    let mut sections = parse(lines)?;
+    sort_controls(&mut sections);
+    let mut out = vec![];
+    for section in &sections {
+        for line in &section.comments {
+            out.push(render(line, "", color));
+        }
+        if let Some(header) = &section.header {
+            out.push(render(header, "", color));
+        }
+        for line in &section.body {
+            out.push(render(line, "  ", color));
+        }
+    }
+    Ok(out)
+}
+
+fn parse<S: AsRef<str>>(lines: &[S]) -> Result<Vec<Section>> {
+    let mut sections = vec![Section::default()];
+    let mut comments = vec![];
+    for (n, line) in lines.iter().enumerate() {
+        let line = line.as_ref().trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with(';') {
+            comments.push(Line::Comment(line.to_string()));
+            continue;
+        }
+        let mut words = split_words(line).into_iter();
+        let first = words.next().unwrap_or_default();
+        let sigil = first.chars().next().unwrap_or_default();
+        if first.len() < 2 || !(sigil == '$' || sigil == '.') {
+            return Err(format!("line {}: expected a section or parameter: {line}", n + 1).into());
+        }
+        let statement = Line::Statement {
+            sigil,
+            keyword: first[1..].to_lowercase(),
+            args: words.collect(),
+        };
+        if sigil == '$' {
+            sections.push(Section {
+                comments: std::mem::take(&mut comments),
+                header: Some(statement),
+                body: vec![],
+            });
+        } else {
+            let body = &mut sections.last_mut().unwrap().body;
+            body.append(&mut comments);
+            body.push(statement);
+        }
+    }
+    // Comments at the end stay at the end.
+    sections.push(Section {
+        comments,
+        ..Section::default()
+    });
+    Ok(sections)
+}
+
+/// Splits a line into words at whitespace. A quoted string is one word, with
+/// the whitespace in it.
+pub(crate) fn split_words(line: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut rest = line.trim_start();
+    while !rest.is_empty() {
+        let end = if let Some(quoted) = rest.strip_prefix('\'') {
+            quoted.find('\'').map_or(rest.len(), |i| i + 2)
+        } else {
+            rest.find(char::is_whitespace).unwrap_or(rest.len())
+        };
+        words.push(rest[..end].to_string());
+        rest = rest[end..].trim_start();
+    }
+    words
+}
+
+/// Sorts each run of control sections by kind and number.
+fn sort_controls(sections: &mut [Section]) {
+    let mut start = 0;
+    while start < sections.len() {
+        let len = sections[start..]
+            .iter()
+            .take_while(|s| s.control().is_some())
+            .count();
+        sections[start..start + len].sort_by_key(|s| s.control());
+        start += len.max(1);
+    }
+}
+
+fn render(line: &Line, indent: &str, color: bool) -> String {
+    match line {
+        Line::Comment(text) => format!("{indent}{}", paint(text, COMMENT_COLOR, color)),
+        Line::Statement {
+            sigil,
+            keyword,
+            args,
+        } => {
+            let keyword_color = if *sigil == '$' {
+                SECTION_COLOR
+            } else {
+                PARAMETER_COLOR
+            };
+            let mut s = format!(
+                "{indent}{}",
+                paint(&format!("{sigil}{keyword}"), keyword_color, color)
+            );
+            for arg in args {
+                s.push(' ');
+                s.push_str(&render_arg(arg, color));
+            }
+            s
+        }
+    }
+}
+
+fn render_arg(arg: &str, color: bool) -> String {
+    if arg.starts_with('\'') {
+        paint(arg, STRING_COLOR, color)
+    } else if let Some(hex) = arg.strip_prefix('$') {
+        paint(&format!("${}", hex.to_uppercase()), NUMBER_COLOR, color)
+    } else if arg.parse::<i32>().is_ok() {
+        paint(arg, NUMBER_COLOR, color)
+    } else {
+        arg.to_string()
+    }
+}
+
+fn paint(text: &str, ansi: &str, color: bool) -> String {
+    if color {
+        format!("{ansi}{text}{RESET}")
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSY: [&str; 16] = [
+        "$REV R1",
+        "; Volume, on the first row",
+        "\t$encoder   2",
+        "   .TX $b0 $02 Val",
+        "",
+        ".showvalue    on",
+        "$Button 1",
+        "  ; Mute",
+        "  .easypar CC 1 65 127 0 toggleoff",
+        "$encoder 1",
+        ".easypar  CC 1  1 0 127 absolute",
+        "$preset",
+        "  .name 'My  Preset  '",
+        "$store 3",
+        "$end",
+        "; The end",
+    ];
+
+    const GOLDEN: [&str; 15] = [
+        "$rev R1",
+        "$encoder 1",
+        "  .easypar CC 1 1 0 127 absolute",
+        "; Volume, on the first row",
+        "$encoder 2",
+        "  .tx $B0 $02 Val",
+        "  .showvalue on",
+        "$button 1",
+        "  ; Mute",
+        "  .easypar CC 1 65 127 0 toggleoff",
+        "$preset",
+        "  .name 'My  Preset  '",
+        "$store 3",
+        "$end",
+        "; The end",
+    ];
+
+    #[test]
+    fn formats_to_golden_text() {
+        assert_eq!(format_bcl(&MESSY, false).unwrap(), GOLDEN);
+    }
+
+    #[test]
+    fn formatting_is_idempotent() {
+        let once = format_bcl(&MESSY, false).unwrap();
+        assert_eq!(format_bcl(&once, false).unwrap(), once);
+    }
+
+    #[test]
+    fn repeated_control_keeps_its_order() {
+        let lines = [
+            "$encoder 2",
+            "  .showvalue on",
+            "$encoder 1",
+            "$encoder 2",
+            "  .showvalue off",
+        ];
+        let expected = [
+            "$encoder 1",
+            "$encoder 2",
+            "  .showvalue on",
+            "$encoder 2",
+            "  .showvalue off",
+        ];
+        assert_eq!(format_bcl(&lines, false).unwrap(), expected);
+    }
+
+    #[test]
+    fn colors_only_when_asked() {
+        let colored = format_bcl(&["$encoder 1"], true).unwrap();
+        assert_eq!(
+            colored,
+            [format!(
+                "{SECTION_COLOR}$encoder{RESET} {NUMBER_COLOR}1{RESET}"
+            )]
+        );
+    }
+
+    #[test]
+    fn rejects_other_lines() {
+        let err = format_bcl(&["$rev R1", "encoder 1"], false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 2: expected a section or parameter: encoder 1"
+        );
+    }
+}
@@ -0,0 +1,252 @@
+//! Behringer's B-Control Language (BCL)
+//!
+//! BCL is the text that B-Control devices send and receive to describe their
+//! presets and global setup. This crate reads and writes it: it formats BCL,
+//! checks it against a schema of the devices' sections and keywords, makes
+//! new presets from templates, and reads device timing from global setups.
+//!
+//! It was split out of `bcr2kosc`, which re-exports `BControlModel` from its
+//! `b_control` module, so that other programs can use it.
+
+#![deny(missing_docs)]
+
+use std::error::Error;
+use std::fmt::Display;
+use std::time::Duration;
+
+mod format;
+mod schema;
+mod template;
+pub use format::*;
+pub use schema::*;
+pub use template::*;
+
+/// Specifies the B-Control device models addressed by a B-Control request, or
+/// responding to one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BControlModel {
+    /// The BCR2000, with rotary encoders.
+    BCR,
+    /// The BCF2000, with motorized faders.
+    BCF,
+    /// Either model.
+    Any,
+}
+
+impl Display for BControlModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BControlModel::BCR => "BCR",
+            BControlModel::BCF => "BCF",
+            BControlModel::Any => "?",
+        }
+        .fmt(f)
+    }
+}
+
+type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+/// A block of BCL, from its `$rev` line to its `$end` line.
+pub struct BclBlock {
+    /// The model named by the `$rev` line.
+    pub model: BControlModel,
+    /// The revision number on the `$rev` line, if it has one.
+    pub rev: Option<u8>,
+    /// The sections between the `$rev` and `$end` lines.
+    pub sections: Vec<BclSection>,
+}
+
+/// A section of a BCL block, which starts with a line such as `$global` and
+/// holds the keyword lines that follow it.
+pub enum BclSection {
+    /// A `$global` section: the device's setup.
+    Global(GlobalData),
+    /// A `$preset` section: the name and settings of the current preset.
+    Preset,
+    /// A `$button` section: the settings of one button.
+    Button,
+    /// An `$encoder` section: the settings of one encoder.
+    Encoder,
+    /// A `$fader` section: the settings of one fader of a BCF.
+    Fader,
+}
+
+/// The keywords of a `$global` section. A keyword the section doesn't give is
+/// `None`. Those that are `Option<()>` are only noted as present, not read.
+pub struct GlobalData {
+    /// `.midimode`, how the device connects to MIDI.
+    pub midimode: Option<MidiMode>,
+    /// `.startup`, the preset the device loads when it is switched on.
+    pub startup: Option<u8>,
+    /// `.footsw`, the foot switch's polarity.
+    pub footsw: Option<()>,
+    /// `.rxch`, the channel the device receives program changes on.
+    pub rxch: Option<()>,
+    /// `.deviceid`, the device's number.
+    pub device_id: Option<()>,
+    /// `.txinterval`, the least time between messages the device sends.
+    pub txinterval: Option<()>,
+    /// `.deadtime`, how long the device ignores a control's incoming values
+    /// after it has sent one.
+    pub deadtime: Option<()>,
+}
+
+impl Display for BclBlock {
+    /// Writes the block's `$rev` and `$end` lines.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let model = match self.model {
+            BControlModel::BCR => "R",
+            BControlModel::BCF => "F",
+            BControlModel::Any => "?",
+        };
+        write!(f, "$rev{model}")?;
+        if let Some(r) = self.rev {
+            write!(f, "{r}")?;
+        }
+        writeln!(f)?;
+        writeln!(f, "$end")
+    }
+}
+
+/// The modes of a device's MIDI connections, set by `.midimode` in its
+/// `$global` section. `U` modes use USB and `S` modes use the device's MIDI
+/// ports alone; see the device's manual for how each routes messages.
+pub enum MidiMode {
+    /// USB mode 1.
+    U1,
+    /// USB mode 2.
+    U2,
+    /// USB mode 3.
+    U3,
+    /// USB mode 4.
+    U4,
+    /// Standalone mode 1.
+    S1,
+    /// Standalone mode 2.
+    S2,
+    /// Standalone mode 3.
+    S3,
+    /// Standalone mode 4.
+    S4,
+}
+
+/// Determines which B-Control model a block of BCL text targets.
+///
+/// The `$rev` line names the model the BCL was written for. Some sections
+/// only exist on one model: faders on the BCF, and encoders 33 through 56 on
+/// the BCR. Returns `BControlModel::Any` if nothing in the text ties it to a
+/// specific model, and an error if the text contradicts itself.
+pub fn required_model<S: AsRef<str>>(lines: &[S]) -> Result<BControlModel> {
+    let mut rev_model = BControlModel::Any;
+    let mut content_model = BControlModel::Any;
+    for line in lines {
+        let mut words = line.as_ref().split_whitespace();
+        match words.next() {
+            Some("$rev") => {
+                rev_model = match words.next().and_then(|r| r.chars().next()) {
+                    Some('R') => BControlModel::BCR,
+                    Some('F') => BControlModel::BCF,
+                    _ => BControlModel::Any,
+                }
+            }
+            Some("$fader") => content_model = merge_model(content_model, BControlModel::BCF)?,
+            Some("$encoder") => {
+                if let Some(n) = words.next().and_then(|n| n.parse::<u8>().ok()) {
+                    if n > 32 {
+                        content_model = merge_model(content_model, BControlModel::BCR)?;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    merge_model(rev_model, content_model)
+}
+
+/// Verifies that BCL text can be sent to a device of the given model.
+pub fn check_model<S: AsRef<str>>(lines: &[S], target: BControlModel) -> Result<()> {
+    let required = required_model(lines)?;
+    match (required, target) {
+        (BControlModel::Any, _) | (_, BControlModel::Any) => Ok(()),
+        (r, t) if r == t => Ok(()),
+        (r, t) => Err(format!("BCL is written for a {r}, but the device is a {t}").into()),
+    }
+}
+
+/// Writes the BCL that changes a device's number, from 1 through 16, in its
+/// global setup, leaving the rest of the setup as it is.
+pub fn device_id_bcl(model: BControlModel, device: u8) -> Vec<String> {
+    let bcf = model == BControlModel::BCF;
+    vec![
+        format!("$rev {}1", if bcf { "F" } else { "R" }),
+        "$global".to_string(),
+        format!("  .deviceid {device}"),
+        "$end".to_string(),
+    ]
+}
+
+/// A device's MIDI timing, from the `.txinterval` and `.deadtime` lines of
+/// its `$global` block, in milliseconds. A value the block doesn't give is
+/// `None`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DeviceTiming {
+    /// The least time between messages the device sends.
+    pub txinterval: Option<u16>,
+    /// How long the device ignores incoming values for a control after it
+    /// has sent one.
+    pub deadtime: Option<u16>,
+}
+
+impl DeviceTiming {
+    /// Reads the timing from the `$global` block in BCL text.
+    pub fn from_bcl<S: AsRef<str>>(lines: &[S]) -> Self {
+        let mut timing = DeviceTiming::default();
+        let mut in_global = false;
+        for line in lines {
+            let mut words = line.as_ref().split_whitespace();
+            match words.next() {
+                Some(w) if w.starts_with('$') => in_global = w == "$global",
+                Some(".txinterval") if in_global => {
+                    timing.txinterval = words.next().and_then(|n| n.parse().ok())
+                }
+                Some(".deadtime") if in_global => {
+                    timing.deadtime = words.next().and_then(|n| n.parse().ok())
+                }
+                _ => {}
+            }
+        }
+        timing
+    }
+
+    /// Whether the BCL gave any timing.
+    pub fn is_known(&self) -> bool {
+        self.txinterval.is_some() || self.deadtime.is_some()
+    }
+
+    /// The time to leave between lines of BCL sent to the device, so that
+    /// uploads keep to its own pace: the longer of the two times.
+    pub fn pacing(&self) -> Duration {
+        let ms = self.txinterval.unwrap_or(0).max(self.deadtime.unwrap_or(0));
+        Duration::from_millis(ms as u64)
+    }
+}
+
+impl Display for DeviceTiming {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ms = |v: Option<u16>| v.map_or("unknown".to_string(), |v| format!("{v} ms"));
+        write!(
+            f,
+            "transmit interval {}, dead time {}",
+            ms(self.txinterval),
+            ms(self.deadtime)
+        )
+    }
+}
+
+fn merge_model(a: BControlModel, b: BControlModel) -> Result<BControlModel> {
+    match (a, b) {
+        (BControlModel::Any, m) | (m, BControlModel::Any) => Ok(m),
+        (a, b) if a == b => Ok(a),
+        (a, b) => Err(format!("BCL contains both {a} and {b} specific content").into()),
+    }
+}
@@ -0,0 +1,836 @@
+//! What this program knows of BCL, as data.
+//!
+//! `schema` describes the sections of BCL, the parameters each takes, the
+//! arguments of each parameter and their allowed values, and which models
+//! they apply to, so that editors can build forms for BCL without working it
+//! out again from dumps. The `schema` command prints it, one line per
+//! section or form of a parameter, with tab-separated fields:
+//!
+//! ```text
+//! section    $encoder   33-56      BCR        the lower encoders
+//! parameter  $encoder   .mode      any        mode:off|1dot|...
+//! ```
+//!
+//! A section line gives the section, the numbers it takes, if any, the model
+//! they apply to, and a description. A parameter line gives the section, the
+//! parameter, its model, and its arguments, separated by spaces: a keyword
+//! that must appear as it is, or `NAME:VALUES`, where VALUES is a range such
+//! as `0-127`, keywords separated by `|`, `string(N)` for a quoted string of
+//! up to N characters, or `bytes...` for any number of bytes. A parameter
+//! with several forms, such as `.easypar`, has a line for each.
+//!
+//! The schema covers what's been seen in dumps from the devices. The devices
+//! accept forms it doesn't list, such as `.easypar` for program changes and
+//! MMC.
+//!
+//! `check_bcl` checks BCL text against the schema, for the model its `$rev`
+//! line names, so that mistakes are found before the text is sent to a
+//! device, which only reports them by number. Since the schema isn't
+//! complete, it can reject text that a device would accept.
+
+use std::fmt::Display;
+
+use crate::format::split_words;
+use crate::{BControlModel, Result};
+
+/// A section of BCL, such as `$encoder`.
+#[derive(Debug)]
+pub struct SectionSchema {
+    /// The section keyword, with its `$`.
+    pub name: &'static str,
+    /// The numbers the section takes, and the models they apply to. Empty if
+    /// the section isn't numbered.
+    pub numbers: &'static [NumberRange],
+    /// The model the section applies to, if it isn't numbered.
+    pub model: BControlModel,
+    /// What the section sets up.
+    pub description: &'static str,
+    /// The parameters the section takes.
+    pub parameters: &'static [ParameterSchema],
+}
+
+/// Numbers of a section that apply to a model.
+#[derive(Debug)]
+pub struct NumberRange {
+    /// The lowest number.
+    pub low: u8,
+    /// The highest number.
+    pub high: u8,
+    /// The model that has sections with these numbers.
+    pub model: BControlModel,
+    /// Which controls the numbers stand for.
+    pub description: &'static str,
+}
+
+/// A parameter line of a section, such as `.easypar`.
+#[derive(Debug)]
+pub struct ParameterSchema {
+    /// The parameter keyword, with its `.`.
+    pub name: &'static str,
+    /// The model the parameter applies to.
+    pub model: BControlModel,
+    /// The lists of arguments the parameter takes. Most have one.
+    pub forms: &'static [&'static [ArgSchema]],
+}
+
+/// An argument of a parameter.
+#[derive(Debug)]
+pub enum ArgSchema {
+    /// A keyword that appears as it is.
+    Keyword(&'static str),
+    /// A number in a range, or one of some keywords.
+    Number {
+        /// What the argument stands for.
+        name: &'static str,
+        /// The lowest number.
+        low: i32,
+        /// The highest number.
+        high: i32,
+        /// The keywords allowed in place of a number.
+        keywords: &'static [&'static str],
+    },
+    /// One of some keywords.
+    Choice {
+        /// What the argument stands for.
+        name: &'static str,
+        /// The keywords allowed.
+        keywords: &'static [&'static str],
+    },
+    /// A string in single quotes, of up to this many characters.
+    Text {
+        /// What the argument stands for.
+        name: &'static str,
+        /// The most characters allowed.
+        length: usize,
+    },
+    /// Any number of bytes, to the end of the line.
+    Bytes {
+        /// What the argument stands for.
+        name: &'static str,
+    },
+}
+
+impl Display for ArgSchema {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArgSchema::Keyword(k) => write!(f, "{k}"),
+            ArgSchema::Number {
+                name,
+                low,
+                high,
+                keywords,
+            } => {
+                write!(f, "{name}:{low}-{high}")?;
+                for k in *keywords {
+                    write!(f, "|{k}")?;
+                }
+                Ok(())
+            }
+            ArgSchema::Choice { name, keywords } => write!(f, "{name}:{}", keywords.join("|")),
+            ArgSchema::Text { name, length } => write!(f, "{name}:string({length})"),
+            ArgSchema::Bytes { name } => write!(f, "{name}:bytes..."),
+        }
+    }
+}
+
+/// Returns what's known of BCL's sections, in the order they appear in a
+/// dump.
+pub fn schema() -> &'static [SectionSchema] {
+    SECTIONS
+}
+
+/// Describes the schema as lines of tab-separated fields. See the module
+/// documentation.
+pub fn schema_lines() -> Vec<String> {
+    let mut lines = vec![];
+    for section in schema() {
+        if section.numbers.is_empty() {
+            lines.push(format!(
+                "section\t{}\t\t{}\t{}",
+                section.name,
+                model_name(section.model),
+                section.description
+            ));
+        }
+        for n in section.numbers {
+            lines.push(format!(
+                "section\t{}\t{}-{}\t{}\t{}",
+                section.name,
+                n.low,
+                n.high,
+                model_name(n.model),
+                n.description
+            ));
+        }
+        for p in section.parameters {
+            for form in p.forms {
+                let args: Vec<String> = form.iter().map(|a| a.to_string()).collect();
+                lines.push(format!(
+                    "parameter\t{}\t{}\t{}\t{}",
+                    section.name,
+                    p.name,
+                    model_name(p.model),
+                    args.join(" ")
+                ));
+            }
+        }
+    }
+    lines
+}
+
+/// Checks BCL text against the schema. Fails with the number of the first
+/// line that the schema doesn't allow, and why.
+pub fn check_bcl<S: AsRef<str>>(lines: &[S]) -> Result<()> {
+    let mut model = ANY;
+    let mut section: Option<&SectionSchema> = None;
+    for (n, line) in lines.iter().enumerate() {
+        let line = line.as_ref().trim();
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+        let words = split_words(line);
+        let keyword = words[0].to_lowercase();
+        let args = &words[1..];
+        let fail = |why: String| Err(format!("line {}: {why}", n + 1).into());
+        if keyword.starts_with('$') {
+            let Some(s) = SECTIONS.iter().find(|s| s.name == keyword) else {
+                return fail(format!("unknown section {keyword}"));
+            };
+            if s.name == "$rev" {
+                model = match args.first().and_then(|r| r.chars().next()) {
+                    Some('R') => BControlModel::BCR,
+                    Some('F') => BControlModel::BCF,
+                    _ => return fail(format!("$rev takes R1 or F1, not \"{}\"", args.join(" "))),
+                };
+            } else if let Err(why) = check_section(s, args, model) {
+                return fail(why);
+            }
+            section = Some(s);
+        } else if keyword.starts_with('.') {
+            let Some(s) = section else {
+                return fail(format!("{keyword} is outside of any section"));
+            };
+            let Some(p) = s.parameters.iter().find(|p| p.name == keyword) else {
+                let owners: Vec<&str> = SECTIONS
+                    .iter()
+                    .filter(|o| o.parameters.iter().any(|p| p.name == keyword))
+                    .map(|o| o.name)
+                    .collect();
+                return fail(if owners.is_empty() {
+                    format!("unknown parameter {keyword}")
+                } else {
+                    format!(
+                        "{} takes no {keyword}, which belongs in {}",
+                        s.name,
+                        owners.join(" or ")
+                    )
+                });
+            };
+            if !fits(p.model, model) {
+                return fail(format!("{keyword} is for a {}", p.model));
+            }
+            if !p.forms.iter().any(|form| matches_form(form, args)) {
+                let forms: Vec<String> = p
+                    .forms
+                    .iter()
+                    .map(|form| {
+                        let args: Vec<String> = form.iter().map(|a| a.to_string()).collect();
+                        format!("{keyword} {}", args.join(" "))
+                            .trim_end()
+                            .to_string()
+                    })
+                    .collect();
+                return fail(format!("expected {}, not \"{line}\"", forms.join(" or ")));
+            }
+        } else {
+            return fail(format!("expected a section or parameter, not \"{line}\""));
+        }
+    }
+    Ok(())
+}
+
+/// Checks the arguments of a section line, for a model.
+fn check_section(
+    section: &SectionSchema,
+    args: &[String],
+    model: BControlModel,
+) -> std::result::Result<(), String> {
+    let name = section.name;
+    if section.numbers.is_empty() {
+        if !args.is_empty() {
+            return Err(format!("{name} takes no number"));
+        }
+        if !fits(section.model, model) {
+            return Err(format!("{name} is for a {}", section.model));
+        }
+        return Ok(());
+    }
+    let ranges: Vec<String> = section
+        .numbers
+        .iter()
+        .map(|r| format!("{}-{}", r.low, r.high))
+        .collect();
+    let number = match args {
+        [n] => n.parse::<u8>().ok(),
+        _ => None,
+    };
+    let Some(range) = number.and_then(|n| {
+        section
+            .numbers
+            .iter()
+            .find(|r| (r.low..=r.high).contains(&n))
+    }) else {
+        return Err(format!("{name} takes a number in {}", ranges.join(", ")));
+    };
+    if !fits(range.model, model) {
+        return Err(format!(
+            "{name} {}-{} is for a {}",
+            range.low, range.high, range.model
+        ));
+    }
+    Ok(())
+}
+
+/// Whether something for one model can be used on a device of another.
+fn fits(wanted: BControlModel, model: BControlModel) -> bool {
+    wanted == ANY || model == ANY || wanted == model
+}
+
+/// Whether the arguments of a parameter line match one of its forms.
+fn matches_form(form: &[ArgSchema], args: &[String]) -> bool {
+    if let Some(ArgSchema::Bytes { .. }) = form.last() {
+        if args.len() < form.len() - 1 {
+            return false;
+        }
+    } else if args.len() != form.len() {
+        return false;
+    }
+    form.iter()
+        .zip(args)
+        .all(|(schema, arg)| schema.matches(arg))
+}
+
+impl ArgSchema {
+    /// Whether an argument is allowed.
+    fn matches(&self, arg: &str) -> bool {
+        match self {
+            ArgSchema::Keyword(k) => arg.eq_ignore_ascii_case(k),
+            ArgSchema::Number {
+                low,
+                high,
+                keywords,
+                ..
+            } => {
+                arg.parse().is_ok_and(|n: i32| (*low..=*high).contains(&n))
+                    || keywords.contains(&arg)
+            }
+            ArgSchema::Choice { keywords, .. } => keywords.contains(&arg),
+            ArgSchema::Text { length, .. } => arg
+                .strip_prefix('\'')
+                .and_then(|a| a.strip_suffix('\''))
+                .is_some_and(|text| text.chars().count() <= *length),
+            ArgSchema::Bytes { .. } => true,
+        }
+    }
+}
+
+fn model_name(model: BControlModel) -> &'static str {
+    match model {
+        BControlModel::BCR => "BCR",
+        BControlModel::BCF => "BCF",
+        BControlModel::Any => "any",
+    }
+}
+
+const ANY: BControlModel = BControlModel::Any;
+
+const ON_OFF: &[&str] = &["on", "off"];
+
+const CHANNEL: ArgSchema = ArgSchema::Number {
+    name: "channel",
+    low: 1,
+    high: 16,
+    keywords: &[],
+};
+
+const fn number(name: &'static str, low: i32, high: i32) -> ArgSchema {
+    ArgSchema::Number {
+        name,
+        low,
+        high,
+        keywords: &[],
+    }
+}
+
+const fn choice(name: &'static str, keywords: &'static [&'static str]) -> ArgSchema {
+    ArgSchema::Choice { name, keywords }
+}
+
+/// A parameter that is switched on or off. This is a macro rather than a
+/// function so that its table of forms is a constant, like the tables of the
+/// parameters written out in full.
+macro_rules! switch {
+    ($name:expr) => {
+        ParameterSchema {
+            name: $name,
+            model: ANY,
+            forms: &[&[choice("value", ON_OFF)]],
+        }
+    };
+}
+
+/// A parameter with one number in a range. See `switch!`.
+macro_rules! value {
+    ($name:expr, $low:expr, $high:expr) => {
+        ParameterSchema {
+            name: $name,
+            model: ANY,
+            forms: &[&[number("value", $low, $high)]],
+        }
+    };
+}
+
+/// The modes of continuous controls sending control changes or NRPNs.
+const CONTINUOUS_MODES: &[&str] = &[
+    "absolute",
+    "absolute/14",
+    "relative-1",
+    "relative-2",
+    "relative-3",
+];
+
+/// The GS/XG parameters that controls can send.
+const GS_XG_PARAMETERS: &[&str] = &[
+    "volume",
+    "panorama",
+    "rev-send",
+    "crs-send",
+    "dly-send",
+    "cutoff",
+    "resonance",
+    "eg-attack",
+    "eg-decay",
+    "eg-release",
+    "modulation",
+    "p-time",
+    "v-rate",
+    "v-depth",
+    "v-delay",
+];
+
+const CONTINUOUS_EASYPAR: ParameterSchema = ParameterSchema {
+    name: ".easypar",
+    model: ANY,
+    forms: &[
+        &[
+            ArgSchema::Keyword("CC"),
+            CHANNEL,
+            number("control", 0, 127),
+            number("min", 0, 127),
+            number("max", 0, 127),
+            choice("mode", CONTINUOUS_MODES),
+        ],
+        &[
+            ArgSchema::Keyword("NRPN"),
+            CHANNEL,
+            number("parameter", 0, 16383),
+            number("min", 0, 16383),
+            number("max", 0, 16383),
+            choice("mode", CONTINUOUS_MODES),
+        ],
+        &[ArgSchema::Keyword("PB"), CHANNEL, number("max", 0, 127)],
+        &[
+            ArgSchema::Keyword("GS/XG"),
+            CHANNEL,
+            choice("parameter", GS_XG_PARAMETERS),
+            number("min", 0, 127),
+            number("max", 0, 127),
+        ],
+    ],
+};
+
+const TX: ParameterSchema = ParameterSchema {
+    name: ".tx",
+    model: ANY,
+    forms: &[&[ArgSchema::Bytes { name: "message" }]],
+};
+
+const SECTIONS: &[SectionSchema] = &[
+    SectionSchema {
+        name: "$rev",
+        numbers: &[],
+        model: ANY,
+        description: "the model and revision the BCL is written for, as R1 or F1",
+        parameters: &[],
+    },
+    SectionSchema {
+        name: "$global",
+        numbers: &[],
+        model: ANY,
+        description: "the device's global setup",
+        parameters: &[
+            ParameterSchema {
+                name: ".midimode",
+                model: ANY,
+                forms: &[&[choice(
+                    "mode",
+                    &["U-1", "U-2", "U-3", "U-4", "S-1", "S-2", "S-3", "S-4"],
+                )]],
+            },
+            ParameterSchema {
+                name: ".startup",
+                model: ANY,
+                forms: &[&[ArgSchema::Number {
+                    name: "preset",
+                    low: 1,
+                    high: 32,
+                    keywords: &["last"],
+                }]],
+            },
+            ParameterSchema {
+                name: ".footsw",
+                model: ANY,
+                forms: &[&[choice("polarity", &["auto", "norm", "inv"])]],
+            },
+            ParameterSchema {
+                name: ".rxch",
+                model: ANY,
+                forms: &[&[ArgSchema::Number {
+                    name: "channel",
+                    low: 1,
+                    high: 16,
+                    keywords: &["off"],
+                }]],
+            },
+            value!(".deviceid", 1, 16),
+            value!(".txinterval", 0, 255),
+            value!(".deadtime", 0, 255),
+        ],
+    },
+    SectionSchema {
+        name: "$preset",
+        numbers: &[],
+        model: ANY,
+        description: "the settings of the preset in the edit buffer",
+        parameters: &[
+            ParameterSchema {
+                name: ".name",
+                model: ANY,
+                forms: &[&[ArgSchema::Text {
+                    name: "name",
+                    length: 24,
+                }]],
+            },
+            switch!(".snapshot"),
+            switch!(".request"),
+            value!(".egroups", 1, 4),
+            switch!(".fkeys"),
+            switch!(".lock"),
+            ParameterSchema {
+                name: ".init",
+                model: ANY,
+                forms: &[&[]],
+            },
+        ],
+    },
+    SectionSchema {
+        name: "$encoder",
+        numbers: &[
+            NumberRange {
+                low: 1,
+                high: 32,
+                model: ANY,
+                description: "the push encoders, eight in each of four groups",
+            },
+            NumberRange {
+                low: 33,
+                high: 56,
+                model: BControlModel::BCR,
+                description: "the lower encoders",
+            },
+        ],
+        model: ANY,
+        description: "",
+        parameters: &[
+            CONTINUOUS_EASYPAR,
+            switch!(".showvalue"),
+            ParameterSchema {
+                name: ".mode",
+                model: ANY,
+                forms: &[&[choice(
+                    "mode",
+                    &[
+                        "off",
+                        "1dot",
+                        "1dot/off",
+                        "12dot",
+                        "12dot/off",
+                        "bar",
+                        "bar/off",
+                        "spread",
+                        "pan",
+                        "qual",
+                        "cut",
+                        "damp",
+                    ],
+                )]],
+            },
+            ParameterSchema {
+                name: ".resolution",
+                model: ANY,
+                forms: &[&[
+                    number("speed1", 0, 16383),
+                    number("speed2", 0, 16383),
+                    number("speed3", 0, 16383),
+                    number("speed4", 0, 16383),
+                ]],
+            },
+            value!(".default", 0, 16383),
+            TX,
+        ],
+    },
+    SectionSchema {
+        name: "$button",
+        numbers: &[NumberRange {
+            low: 1,
+            high: 64,
+            model: ANY,
+            description: "the buttons, including those of the push encoders",
+        }],
+        model: ANY,
+        description: "",
+        parameters: &[
+            ParameterSchema {
+                name: ".easypar",
+                model: ANY,
+                forms: &[
+                    &[
+                        ArgSchema::Keyword("CC"),
+                        CHANNEL,
+                        number("control", 0, 127),
+                        number("on", 0, 127),
+                        number("off", 0, 127),
+                        choice("mode", &["toggleon", "toggleoff"]),
+                    ],
+                    &[
+                        ArgSchema::Keyword("CC"),
+                        CHANNEL,
+                        number("control", 0, 127),
+                        number("max", 0, 127),
+                        number("min", 0, 127),
+                        ArgSchema::Keyword("increment"),
+                        number("step", -64, 64),
+                    ],
+                    &[
+                        ArgSchema::Keyword("NOTE"),
+                        CHANNEL,
+                        number("key", 0, 127),
+                        number("velocity", 0, 127),
+                        choice("mode", &["toggleon", "toggleoff"]),
+                    ],
+                ],
+            },
+            switch!(".showvalue"),
+            value!(".default", 0, 127),
+            TX,
+        ],
+    },
+    SectionSchema {
+        name: "$fader",
+        numbers: &[NumberRange {
+            low: 1,
+            high: 8,
+            model: BControlModel::BCF,
+            description: "the motorized faders",
+        }],
+        model: BControlModel::BCF,
+        description: "",
+        parameters: &[
+            CONTINUOUS_EASYPAR,
+            switch!(".showvalue"),
+            switch!(".motor"),
+            ParameterSchema {
+                name: ".override",
+                model: ANY,
+                forms: &[&[choice("behavior", &["move", "pickup"])]],
+            },
+            switch!(".keyoverride"),
+            value!(".default", 0, 16383),
+            TX,
+        ],
+    },
+    SectionSchema {
+        name: "$store",
+        numbers: &[NumberRange {
+            low: 1,
+            high: 32,
+            model: ANY,
+            description: "stores the edit buffer in a preset memory",
+        }],
+        model: ANY,
+        description: "",
+        parameters: &[],
+    },
+    SectionSchema {
+        name: "$recall",
+        numbers: &[NumberRange {
+            low: 1,
+            high: 32,
+            model: ANY,
+            description: "loads a preset memory into the edit buffer",
+        }],
+        model: ANY,
+        description: "",
+        parameters: &[],
+    },
+    SectionSchema {
+        name: "$end",
+        numbers: &[],
+        model: ANY,
+        description: "the end of the BCL",
+        parameters: &[],
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a line for every section number and every parameter form that
+    /// applies to a model, with every value each argument allows at its ends.
+    fn every_line(model: BControlModel) -> Vec<String> {
+        let mut lines = vec![];
+        for section in schema() {
+            if section.name == "$rev" {
+                lines.push(if model == BControlModel::BCF {
+                    "$rev F1".to_string()
+                } else {
+                    "$rev R1".to_string()
+                });
+                continue;
+            }
+            if !fits(section.model, model) {
+                continue;
+            }
+            let mut headers = vec![];
+            if section.numbers.is_empty() {
+                headers.push(section.name.to_string());
+            }
+            for r in section.numbers.iter().filter(|r| fits(r.model, model)) {
+                headers.push(format!("{} {}", section.name, r.low));
+                headers.push(format!("{} {}", section.name, r.high));
+            }
+            for header in headers {
+                lines.push(header);
+                for p in section.parameters.iter().filter(|p| fits(p.model, model)) {
+                    for form in p.forms {
+                        for i in 0..2 {
+                            let args: Vec<String> = form.iter().map(|a| sample(a, i)).collect();
+                            lines.push(format!("  {} {}", p.name, args.join(" ")));
+                        }
+                    }
+                }
+            }
+        }
+        lines
+    }
+
+    /// An allowed value of an argument: its first, or its last.
+    fn sample(arg: &ArgSchema, last: usize) -> String {
+        match arg {
+            ArgSchema::Keyword(k) => k.to_string(),
+            ArgSchema::Number {
+                low,
+                high,
+                keywords,
+                ..
+            } => match (last, keywords.last()) {
+                (0, _) => low.to_string(),
+                (_, Some(k)) => k.to_string(),
+                (_, None) => high.to_string(),
+            },
+            ArgSchema::Choice { keywords, .. } => keywords[last * (keywords.len() - 1)].to_string(),
+            ArgSchema::Text { length, .. } => format!("'{}'", "x".repeat(last * length)),
+            ArgSchema::Bytes { .. } => "$F0 $7F $F7".to_string(),
+        }
+    }
+
+    fn error(lines: &[&str]) -> String {
+        check_bcl(lines).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn every_schema_line_checks() {
+        for model in [BControlModel::BCR, BControlModel::BCF] {
+            let lines = every_line(model);
+            assert!(lines.len() > 100);
+            if let Err(e) = check_bcl(&lines) {
+                panic!("{model}: {e}");
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_sections_and_parameters() {
+        assert_eq!(
+            error(&["$rev R1", "$knob 1"]),
+            "line 2: unknown section $knob"
+        );
+        assert_eq!(
+            error(&["$rev R1", "$encoder 1", "  .speed 3"]),
+            "line 3: unknown parameter .speed"
+        );
+        assert_eq!(
+            error(&["$rev R1", "\"encoder 1\""]),
+            "line 2: expected a section or parameter, not \"\"encoder 1\"\""
+        );
+    }
+
+    #[test]
+    fn rejects_misplaced_parameters() {
+        assert_eq!(
+            error(&["$rev F1", "$encoder 1", "  .motor on"]),
+            "line 3: $encoder takes no .motor, which belongs in $fader"
+        );
+        assert_eq!(
+            error(&["$rev R1", ".showvalue on"]),
+            "line 2: $rev takes no .showvalue, which belongs in $encoder or $button or $fader"
+        );
+        assert_eq!(
+            error(&[".showvalue on"]),
+            "line 1: .showvalue is outside of any section"
+        );
+    }
+
+    #[test]
+    fn rejects_sections_for_another_model() {
+        assert_eq!(
+            error(&["$rev F1", "$encoder 33"]),
+            "line 2: $encoder 33-56 is for a BCR"
+        );
+        assert_eq!(
+            error(&["$rev R1", "$fader 1"]),
+            "line 2: $fader 1-8 is for a BCF"
+        );
+        assert_eq!(
+            error(&["$rev R1", "$encoder 57"]),
+            "line 2: $encoder takes a number in 1-32, 33-56"
+        );
+    }
+
+    #[test]
+    fn rejects_arguments_out_of_range() {
+        assert_eq!(
+            error(&["$rev R1", "$encoder 1", "  .mode dots"]),
+            "line 3: expected .mode mode:off|1dot|1dot/off|12dot|12dot/off|bar|bar/off|\
+             spread|pan|qual|cut|damp, not \".mode dots\""
+        );
+        assert_eq!(
+            error(&["$rev R1", "$global", "  .deviceid 17"]),
+            "line 3: expected .deviceid value:1-16, not \".deviceid 17\""
+        );
+        let long_name = format!("  .name '{}'", "x".repeat(25));
+        assert!(error(&["$rev R1", "$preset", &long_name])
+            .starts_with("line 3: expected .name name:string(24)"));
+    }
+}
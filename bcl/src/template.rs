@@ -0,0 +1,224 @@
+//! BCL for presets in common layouts.
+//!
+//! Building a preset from scratch in BCL means writing a section for every
+//! control. `new_preset` writes one for a common layout instead, as a start
+//! to edit from:
+//!
+//! - `strips`: eight channel strips, with volume on the first row of the
+//!   lower encoders, or on the faders of a BCF, pan on the push encoders,
+//!   and mute on the first row of buttons. Volumes take the first eight
+//!   control numbers, pans the next eight, and mutes the eight after that.
+//! - `cc`: the 32 push encoders, in all four groups, on consecutive control
+//!   numbers.
+//! - `nrpn`: the 32 push encoders, and on a BCR the 24 lower encoders too,
+//!   on consecutive 14-bit NRPN parameters, as many synths use.
+//!
+//! Every control sends on the same MIDI channel. The sections are written in
+//! the order `format_bcl` sorts them in.
+
+use std::str::FromStr;
+
+use super::Result;
+
+/// A layout for a new preset.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PresetTemplate {
+    /// `strips`: eight channel strips.
+    Strips,
+    /// `cc`: the push encoders on consecutive control numbers.
+    Cc,
+    /// `nrpn`: the encoders on consecutive NRPN parameters.
+    Nrpn,
+}
+
+/// The names of the templates.
+pub const PRESET_TEMPLATES: [&str; 3] = ["strips", "cc", "nrpn"];
+
+impl FromStr for PresetTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "strips" => Ok(PresetTemplate::Strips),
+            "cc" => Ok(PresetTemplate::Cc),
+            "nrpn" => Ok(PresetTemplate::Nrpn),
+            _ => Err(format!("unknown preset template \"{s}\"")),
+        }
+    }
+}
+
+/// How a preset is laid out and named.
+pub struct PresetOptions {
+    /// Whether the preset is for a BCF, rather than a BCR.
+    pub bcf: bool,
+    /// The MIDI channel, from 1 through 16.
+    pub channel: u8,
+    /// The first control or NRPN parameter number.
+    pub first: u16,
+    /// The preset's name. Only the first 24 characters are used.
+    pub name: String,
+    /// The preset memory, from 1 through 32, to store the preset in. If
+    /// `None`, it's left in the device's edit buffer.
+    pub store: Option<u8>,
+}
+
+/// Writes the BCL for a preset laid out by a template. Fails if the control
+/// or parameter numbers run out of range.
+pub fn new_preset(template: PresetTemplate, options: &PresetOptions) -> Result<Vec<String>> {
+    let mut bcl = vec![
+        format!("$rev {}1", if options.bcf { "F" } else { "R" }),
+        "$preset".to_string(),
+        format!("  .name '{:<24.24}'", options.name),
+        "  .snapshot off".to_string(),
+        "  .request off".to_string(),
+        "  .egroups 4".to_string(),
+        "  .fkeys on".to_string(),
+        "  .lock off".to_string(),
+        "  .init".to_string(),
+    ];
+    let ch = options.channel;
+    match template {
+        PresetTemplate::Strips => {
+            let cc = numbers(options.first, 24, 127)?;
+            let volume = |i: usize| format!("CC {ch} {} 0 127 absolute", cc[i]);
+            for i in 0..8 {
+                let pan = format!("CC {ch} {} 0 127 absolute", cc[8 + i]);
+                encoder(&mut bcl, 1 + i, &pan, "pan");
+            }
+            if !options.bcf {
+                for i in 0..8 {
+                    encoder(&mut bcl, 33 + i, &volume(i), "bar");
+                }
+            }
+            for i in 0..8 {
+                bcl.push(format!("$button {}", 33 + i));
+                bcl.push(format!("  .easypar CC {ch} {} 127 0 toggleon", cc[16 + i]));
+                bcl.push("  .showvalue on".to_string());
+                bcl.push("  .default 0".to_string());
+            }
+            if options.bcf {
+                for i in 0..8 {
+                    fader(&mut bcl, 1 + i, &volume(i));
+                }
+            }
+        }
+        PresetTemplate::Cc => {
+            for (i, cc) in numbers(options.first, 32, 127)?.into_iter().enumerate() {
+                let easypar = format!("CC {ch} {cc} 0 127 absolute");
+                encoder(&mut bcl, 1 + i, &easypar, "1dot");
+            }
+        }
+        PresetTemplate::Nrpn => {
+            let count = if options.bcf { 32 } else { 56 };
+            for (i, nrpn) in numbers(options.first, count, 16383)?
+                .into_iter()
+                .enumerate()
+            {
+                let easypar = format!("NRPN {ch} {nrpn} 0 16383 absolute/14");
+                encoder(&mut bcl, 1 + i, &easypar, "1dot");
+            }
+        }
+    }
+    if let Some(preset) = options.store {
+        bcl.push(format!("$store {preset}"));
+    }
+    bcl.push("$end".to_string());
+    Ok(bcl)
+}
+
+/// Returns `count` consecutive numbers from `first`, failing if they go past
+/// `max`.
+fn numbers(first: u16, count: u16, max: u16) -> Result<Vec<u16>> {
+    let last = first.saturating_add(count - 1);
+    if last > max {
+        return Err(
+            format!("the template needs numbers {first} through {last}, past {max}").into(),
+        );
+    }
+    Ok((first..=last).collect())
+}
+
+fn encoder(bcl: &mut Vec<String>, n: usize, easypar: &str, mode: &str) {
+    bcl.push(format!("$encoder {n}"));
+    bcl.push(format!("  .easypar {easypar}"));
+    bcl.push("  .showvalue on".to_string());
+    bcl.push(format!("  .mode {mode}"));
+    bcl.push("  .resolution 96 96 96 96".to_string());
+    bcl.push("  .default 0".to_string());
+}
+
+fn fader(bcl: &mut Vec<String>, n: usize, easypar: &str) {
+    bcl.push(format!("$fader {n}"));
+    bcl.push(format!("  .easypar {easypar}"));
+    bcl.push("  .showvalue on".to_string());
+    bcl.push("  .default 0".to_string());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{check_bcl, format_bcl, required_model, BControlModel};
+
+    fn options(bcf: bool) -> PresetOptions {
+        PresetOptions {
+            bcf,
+            channel: 2,
+            first: 10,
+            name: "Test".to_string(),
+            store: Some(5),
+        }
+    }
+
+    fn count(bcl: &[String], section: &str) -> usize {
+        bcl.iter()
+            .filter(|l| l.split_whitespace().next() == Some(section))
+            .count()
+    }
+
+    #[test]
+    fn templates_parse_back() {
+        for name in PRESET_TEMPLATES {
+            let template: PresetTemplate = name.parse().unwrap();
+            for bcf in [false, true] {
+                let bcl = new_preset(template, &options(bcf)).unwrap();
+                check_bcl(&bcl).unwrap();
+                let model = required_model(&bcl).unwrap();
+                assert_eq!(model == BControlModel::BCF, bcf, "{name}");
+                assert_eq!(format_bcl(&bcl, false).unwrap(), bcl, "{name}");
+            }
+        }
+    }
+
+    #[test]
+    fn strips_have_encoders_buttons_and_faders() {
+        let bcr = new_preset(PresetTemplate::Strips, &options(false)).unwrap();
+        assert_eq!(count(&bcr, "$encoder"), 16);
+        assert_eq!(count(&bcr, "$button"), 8);
+        assert_eq!(count(&bcr, "$fader"), 0);
+        assert!(bcr.contains(&"$encoder 33".to_string()));
+        assert!(bcr.contains(&"  .easypar CC 2 10 0 127 absolute".to_string()));
+        assert!(bcr.contains(&"  .easypar CC 2 26 127 0 toggleon".to_string()));
+        assert_eq!(bcr[bcr.len() - 2..], ["$store 5", "$end"]);
+
+        let bcf = new_preset(PresetTemplate::Strips, &options(true)).unwrap();
+        assert_eq!(count(&bcf, "$encoder"), 8);
+        assert_eq!(count(&bcf, "$button"), 8);
+        assert_eq!(count(&bcf, "$fader"), 8);
+    }
+
+    #[test]
+    fn nrpn_covers_the_encoders_of_each_model() {
+        let bcr = new_preset(PresetTemplate::Nrpn, &options(false)).unwrap();
+        assert_eq!(count(&bcr, "$encoder"), 56);
+        let bcf = new_preset(PresetTemplate::Nrpn, &options(true)).unwrap();
+        assert_eq!(count(&bcf, "$encoder"), 32);
+    }
+
+    #[test]
+    fn numbers_out_of_range_fail() {
+        let mut o = options(false);
+        o.first = 100;
+        assert!(new_preset(PresetTemplate::Cc, &o).is_err());
+        assert!("faders".parse::<PresetTemplate>().is_err());
+    }
+}
@@ -0,0 +1,62 @@
+//! Selection of the MIDI system that ports belong to.
+//!
+//! `midir` talks to one MIDI system, chosen when it's built: ALSA on Linux,
+//! CoreMIDI on macOS, and WinMM or WinRT on Windows. Built with the `jack`
+//! feature, it talks to JACK instead, on Linux and macOS, so that ports are
+//! those of the JACK graph. `Backend::select` checks that the system asked
+//! for at run time is the one built in, so that asking for JACK from a build
+//! without it fails clearly, rather than with ports that can't be found.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use super::{ErrorKind, Result};
+
+/// A MIDI system.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// The platform's own MIDI system.
+    Native,
+    /// The JACK Audio Connection Kit.
+    Jack,
+}
+
+impl Backend {
+    /// The MIDI system that ports are opened on.
+    pub const BUILT: Backend = if cfg!(all(feature = "jack", not(windows))) {
+        Backend::Jack
+    } else {
+        Backend::Native
+    };
+
+    /// Checks that ports can be opened on this MIDI system.
+    pub fn select(self) -> Result<()> {
+        if self == Backend::BUILT {
+            Ok(())
+        } else {
+            Err(ErrorKind::BackendUnavailable.into())
+        }
+    }
+}
+
+impl Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Backend::Native => "native",
+            Backend::Jack => "jack",
+        }
+        .fmt(f)
+    }
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "native" => Ok(Backend::Native),
+            "jack" => Ok(Backend::Jack),
+            _ => Err(format!("unknown MIDI backend \"{s}\"")),
+        }
+    }
+}
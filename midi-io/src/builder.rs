@@ -0,0 +1,371 @@
+#![allow(dead_code)]
+//! Builders for `MidiStream` and `MidiSink`, for options beyond the port.
+//!
+//! System real-time messages, such as timing clock and active sensing, can't
+//! be represented by `MidiMessage`. By default a stream drops them before
+//! they reach its channel, so that a clock running at 24 messages per beat
+//! costs next to nothing. They can instead be passed through to a separate
+//! channel, as status bytes.
+//!
+//! A sink writes SysEx in a lane of its own, behind channel messages, so
+//! that a bulky SysEx transfer can't hold up time-sensitive control changes.
+//! Each lane can be paced with a minimum interval between messages. A sink
+//! bound to a port can rebind to it after it fails, such as when the device
+//! is unplugged and plugged in again.
+//!
+//! Instead of binding to an existing port, a builder can create a virtual
+//! port for other MIDI software to connect to, except on Windows. Binding to
+//! a port named `rtpmidi:HOST:PORT` joins an RTP-MIDI session with the host;
+//! see the `rtpmidi` module. A sink can also be made without a port, passing
+//! messages to a channel or discarding them, for tests and dry runs.
+//!
+//! ```text
+//! let midi_in = MidiStream::builder()
+//!     .client_name("bcr2kosc")
+//!     .capacity(1024)
+//!     .ignore(Ignore::TimeAndActiveSense)
+//!     .bind("BCR2000")?;
+//! ```
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::channel::mpsc::{self, UnboundedSender};
+use midir::{Ignore, MidiInput, MidiOutput};
+use tracing::{debug, error, info};
+
+use super::port::find_midir_port;
+use super::rtpmidi::{rtpmidi_host, Session};
+use super::{
+    message_from_bytes, run_midi_writer, Ack, InputConnection, MidiMessage, MidiSink, MidiStream,
+    OutputConnection, Pacing, Rebind, Receiver, Result, WriteRequest, WriterStatus, MAX_PENDING,
+};
+
+/// What a `MidiStream` does with system real-time messages: timing clock,
+/// start, continue, stop, active sensing and reset.
+#[derive(Default)]
+pub enum RealTime {
+    /// Drop them. Where the platform allows, timing clock and active sensing
+    /// are dropped by the MIDI driver.
+    #[default]
+    Ignore,
+    /// Send their status bytes, such as `0xF8` for timing clock, to a
+    /// separate channel.
+    PassThrough(UnboundedSender<u8>),
+}
+
+/// The callback of a midir input connection.
+type InputCallback = Box<dyn FnMut(u64, &[u8], &mut ()) + Send + 'static>;
+
+/// Sets the options of a `MidiStream`.
+pub struct MidiStreamBuilder {
+    client_name: String,
+    capacity: Option<usize>,
+    ignore: Ignore,
+    realtime: RealTime,
+}
+
+impl Default for MidiStreamBuilder {
+    fn default() -> Self {
+        MidiStreamBuilder {
+            client_name: "midi-io MIDI input".to_string(),
+            capacity: None,
+            ignore: Ignore::None,
+            realtime: RealTime::Ignore,
+        }
+    }
+}
+
+impl MidiStreamBuilder {
+    /// Sets the client name under which the port is opened, which some
+    /// platforms show to other MIDI software.
+    pub fn client_name(mut self, name: &str) -> Self {
+        self.client_name = name.to_string();
+        self
+    }
+
+    /// Limits the number of received messages that can wait to be read.
+    /// Messages that arrive when the stream is full are dropped, and logged.
+    /// By default the stream is unbounded.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// Sets the kinds of message that the MIDI driver drops: system
+    /// exclusive, timing, or active sensing. By default, only real-time
+    /// messages are dropped; see `realtime`.
+    pub fn ignore(mut self, ignore: Ignore) -> Self {
+        self.ignore = ignore;
+        self
+    }
+
+    /// Sets what's done with system real-time messages. By default they're
+    /// dropped.
+    pub fn realtime(mut self, realtime: RealTime) -> Self {
+        self.realtime = realtime;
+        self
+    }
+
+    /// Creates the stream for the named MIDI I/O port, or for the RTP-MIDI
+    /// host it names.
+    pub fn bind(self, port_name: &str) -> Result<MidiStream> {
+        if let Some(host) = rtpmidi_host(port_name) {
+            let session = Session::join(host, &self.client_name)?;
+            let (_, mut cb, rx) = self.callback();
+            let subscription = session.subscribe(Box::new(move |buf| cb(0, buf, &mut ())));
+            info!("midi-io listener started on \"{port_name}\"");
+            return Ok(MidiStream {
+                rx,
+                _midi_cxn: InputConnection::RtpMidi {
+                    _subscription: subscription,
+                },
+            });
+        }
+        let (midi_input, cb, rx) = self.prepare()?;
+        let midi_input_port = find_midir_port(&midi_input, port_name)?;
+        let midi_cxn = midi_input.connect(&midi_input_port, "midi-io listener", cb, ())?;
+        info!("midi-io listener started on \"{port_name}\"");
+
+        Ok(MidiStream {
+            rx,
+            _midi_cxn: InputConnection::Midir { _cxn: midi_cxn },
+        })
+    }
+
+    /// Creates a virtual port with the given name, to which other MIDI
+    /// software can send, and the stream of messages sent to it. Other
+    /// software sees it as an output port. Virtual ports aren't available on
+    /// Windows.
+    #[cfg(unix)]
+    pub fn create_virtual(self, port_name: &str) -> Result<MidiStream> {
+        use midir::os::unix::VirtualInput;
+
+        let (midi_input, cb, rx) = self.prepare()?;
+        let midi_cxn = midi_input.create_virtual(port_name, cb, ())?;
+        info!("midi-io listener started on virtual port \"{port_name}\"");
+
+        Ok(MidiStream {
+            rx,
+            _midi_cxn: InputConnection::Midir { _cxn: midi_cxn },
+        })
+    }
+
+    /// Sets up a `MidiInput` with the options, and a callback for its
+    /// connection that sends to the stream's channel.
+    fn prepare(self) -> Result<(MidiInput, InputCallback, Receiver)> {
+        let mut midi_input = MidiInput::new(&self.client_name)?;
+        let (ignore, cb, rx) = self.callback();
+        midi_input.ignore(ignore);
+        Ok((midi_input, Box::new(cb), rx))
+    }
+
+    /// Returns what the MIDI driver should drop, and a callback that sends
+    /// the rest to the stream's channel.
+    fn callback(
+        self,
+    ) -> (
+        Ignore,
+        impl FnMut(u64, &[u8], &mut ()) + Send + 'static,
+        Receiver,
+    ) {
+        let (ignore, realtime) = match self.realtime {
+            RealTime::Ignore => (self.ignore | Ignore::TimeAndActiveSense, None),
+            RealTime::PassThrough(tx) => (self.ignore, Some(tx)),
+        };
+        let (mut tx, rx) = match self.capacity {
+            Some(n) => {
+                let (tx, rx) = mpsc::channel(n);
+                (Sender::Bounded(tx), Receiver::Bounded(rx))
+            }
+            None => {
+                let (tx, rx) = mpsc::unbounded();
+                (Sender::Unbounded(tx), Receiver::Unbounded(rx))
+            }
+        };
+
+        let cb = move |_time: u64, buf: &[u8], _context: &mut ()| {
+            debug!("midi-io received {} bytes.", buf.len());
+            if let [status @ 0xF8..=0xFF] = buf {
+                if let Some(realtime) = &realtime {
+                    // The receiver may have been dropped; nobody's listening.
+                    realtime.unbounded_send(*status).ok();
+                }
+                return;
+            }
+            tx.send(message_from_bytes(buf));
+        };
+        (ignore, cb, rx)
+    }
+}
+
+/// The sending end of a `MidiStream`'s channel, used by the port callback.
+enum Sender {
+    Unbounded(UnboundedSender<MidiMessage>),
+    Bounded(mpsc::Sender<MidiMessage>),
+}
+
+impl Sender {
+    fn send(&mut self, msg: MidiMessage) {
+        let result = match self {
+            Sender::Unbounded(tx) => tx.unbounded_send(msg).map_err(|e| e.to_string()),
+            Sender::Bounded(tx) => tx.try_send(msg).map_err(|e| e.to_string()),
+        };
+        if let Err(e) = result {
+            error!("midi-io listener error on send: {e}");
+        }
+    }
+}
+
+/// Sets the options of a `MidiSink`.
+pub struct MidiSinkBuilder {
+    client_name: String,
+    capacity: usize,
+    pacing: Pacing,
+    rebind: Option<Duration>,
+}
+
+impl Default for MidiSinkBuilder {
+    fn default() -> Self {
+        MidiSinkBuilder {
+            client_name: "midi-io MIDI output".to_string(),
+            capacity: MAX_PENDING,
+            pacing: Pacing::default(),
+            rebind: None,
+        }
+    }
+}
+
+impl MidiSinkBuilder {
+    /// Sets the client name under which the port is opened, which some
+    /// platforms show to other MIDI software.
+    pub fn client_name(mut self, name: &str) -> Self {
+        self.client_name = name.to_string();
+        self
+    }
+
+    /// Sets the number of messages that can be queued to be written before a
+    /// sender has to wait for them. Clones of the sink share the setting.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Sets the minimum interval between channel messages, and other messages
+    /// that aren't SysEx. By default they're written as fast as the port
+    /// takes them.
+    pub fn channel_interval(mut self, interval: Duration) -> Self {
+        self.pacing.channel = interval;
+        self
+    }
+
+    /// Sets the minimum interval between SysEx messages, for devices that
+    /// can't keep up with a dense transfer. Channel messages are still
+    /// written in between. By default SysEx is written as fast as the port
+    /// takes it.
+    pub fn sysex_interval(mut self, interval: Duration) -> Self {
+        self.pacing.sysex = interval;
+        self
+    }
+
+    /// Reconnects a sink bound to a port when the port fails, trying again
+    /// at this interval until it succeeds. Messages sent while the port is
+    /// gone are dropped, and reported as failed by the sink's next flush. By
+    /// default, the sink fails for good when its port does.
+    pub fn rebind(mut self, interval: Duration) -> Self {
+        self.rebind = Some(interval);
+        self
+    }
+
+    /// Creates the sink for the named MIDI port, or for the RTP-MIDI host it
+    /// names.
+    ///
+    /// This starts an OS thread to handle writes, which may be synchronous,
+    /// depending on operating system and MIDI port driver.
+    pub fn bind(self, port_name: &str) -> Result<MidiSink> {
+        let midi_cxn = connect_output(&self.client_name, port_name)?;
+        info!("midi-io writer started on \"{port_name}\"");
+        let rebind = self.rebind.map(|interval| {
+            let (client_name, port) = (self.client_name.clone(), port_name.to_string());
+            Rebind {
+                port_name: port_name.to_string(),
+                connect: Box::new(move || connect_output(&client_name, &port)),
+                interval,
+            }
+        });
+        Ok(self.start_with(midi_cxn, rebind))
+    }
+
+    /// Creates a virtual port with the given name, from which other MIDI
+    /// software can receive, and the sink of messages sent from it. Other
+    /// software sees it as an input port. Virtual ports aren't available on
+    /// Windows.
+    #[cfg(unix)]
+    pub fn create_virtual(self, port_name: &str) -> Result<MidiSink> {
+        use midir::os::unix::VirtualOutput;
+
+        let midi_output = MidiOutput::new(&self.client_name)?;
+        let midi_cxn = midi_output.create_virtual(port_name)?;
+        info!("midi-io writer started on virtual port \"{port_name}\"");
+        Ok(self.start(OutputConnection::Midir(midi_cxn)))
+    }
+
+    /// Creates a sink that passes messages to a channel, instead of writing
+    /// them to a port, such as for tests. Messages are paced as they would
+    /// be for a port.
+    pub fn channel(self, tx: UnboundedSender<MidiMessage>) -> MidiSink {
+        self.start(OutputConnection::Channel(tx))
+    }
+
+    /// Creates a sink that writes nothing, for when there's no device to
+    /// write to. Messages are still paced, and acknowledged once due.
+    pub fn discard(self) -> MidiSink {
+        self.start(OutputConnection::Discard(false))
+    }
+
+    /// Creates a sink that logs messages instead of writing them, for dry
+    /// runs.
+    pub fn dry_run(self) -> MidiSink {
+        info!("midi-io writer started for a dry run");
+        self.start(OutputConnection::Discard(true))
+    }
+
+    /// Starts the writer thread for a connection.
+    fn start(self, midi_cxn: OutputConnection) -> MidiSink {
+        self.start_with(midi_cxn, None)
+    }
+
+    /// Starts the writer thread for a connection, which rebinds as `rebind`
+    /// says, if given.
+    pub(super) fn start_with(self, midi_cxn: OutputConnection, rebind: Option<Rebind>) -> MidiSink {
+        let (data_tx, data_rx) = std::sync::mpsc::channel::<WriteRequest>();
+        let (response_tx, response_rx) = mpsc::unbounded::<Ack>();
+        let pacing = self.pacing;
+        let status = Arc::new(WriterStatus::default());
+        let writer_status = status.clone();
+        std::thread::spawn(move || {
+            run_midi_writer(data_rx, midi_cxn, pacing, writer_status, rebind);
+        });
+        MidiSink {
+            data_q: Some(data_tx),
+            response_q: response_rx,
+            response_tx,
+            pending_count: 0,
+            max_pending: self.capacity,
+            failed_count: 0,
+            status,
+        }
+    }
+}
+
+/// Connects to the named MIDI port for output, or joins a session with the
+/// RTP-MIDI host it names.
+fn connect_output(client_name: &str, port_name: &str) -> Result<OutputConnection> {
+    if let Some(host) = rtpmidi_host(port_name) {
+        return Ok(OutputConnection::RtpMidi(Session::join(host, client_name)?));
+    }
+    let midi_output = MidiOutput::new(client_name)?;
+    let midi_output_port = find_midir_port(&midi_output, port_name)?;
+    let midi_cxn = midi_output.connect(&midi_output_port, "midi-io sender")?;
+    Ok(OutputConnection::Midir(midi_cxn))
+}
@@ -0,0 +1,103 @@
+//! Conversions between raw MIDI bytes and `MidiMessage`.
+//!
+//! `midi_control::MidiMessage` is the MIDI representation used throughout
+//! this crate. Its own byte conversions treat every message as three bytes
+//! long, which is wrong for Program Change and Channel Pressure. These
+//! adapters correct that, and should be used wherever bytes meet the MIDI
+//! ports.
+
+use midi_control::{consts, Channel, MidiMessage};
+
+/// Creates a `MidiMessage` from bytes received from a MIDI port.
+///
+/// Messages that can't be represented, including system real-time messages,
+/// become `MidiMessage::Invalid`.
+pub fn message_from_bytes(buf: &[u8]) -> MidiMessage {
+    match buf {
+        [status, data] if *status < consts::SYSEX => {
+            let channel = Channel::from_midi_cmd(*status);
+            match status & consts::EVENT_TYPE_MASK {
+                consts::PROGRAM_CHANGE => MidiMessage::ProgramChange(channel, *data),
+                consts::CHANNEL_KEY_PRESSURE => MidiMessage::ChannelPressure(channel, *data),
+                _ => MidiMessage::Invalid,
+            }
+        }
+        _ => MidiMessage::from(buf),
+    }
+}
+
+/// Converts a `MidiMessage` into the bytes to be sent to a MIDI port.
+///
+/// Returns an empty vector for `MidiMessage::Invalid`.
+pub fn message_to_bytes(msg: MidiMessage) -> Vec<u8> {
+    match msg {
+        MidiMessage::ProgramChange(ch, program) => vec![consts::PROGRAM_CHANGE | ch as u8, program],
+        MidiMessage::ChannelPressure(ch, pressure) => {
+            vec![consts::CHANNEL_KEY_PRESSURE | ch as u8, pressure]
+        }
+        msg => msg.into(),
+    }
+}
+
+/// Makes a copy of a `MidiMessage`, which doesn't implement `Clone`.
+pub fn copy_message(msg: &MidiMessage) -> MidiMessage {
+    use midi_control::message::SysExType;
+    use midi_control::{ControlEvent, SysExEvent};
+    use MidiMessage::*;
+    match msg {
+        Invalid => Invalid,
+        NoteOn(ch, e) => NoteOn(*ch, e.clone()),
+        NoteOff(ch, e) => NoteOff(*ch, e.clone()),
+        PolyKeyPressure(ch, e) => PolyKeyPressure(*ch, e.clone()),
+        ControlChange(ch, ControlEvent { control, value }) => ControlChange(
+            *ch,
+            ControlEvent {
+                control: *control,
+                value: *value,
+            },
+        ),
+        ProgramChange(ch, p) => ProgramChange(*ch, *p),
+        ChannelPressure(ch, p) => ChannelPressure(*ch, *p),
+        PitchBend(ch, lsb, msb) => PitchBend(*ch, *lsb, *msb),
+        SysEx(SysExEvent { r#type, data }) => SysEx(SysExEvent {
+            r#type: match r#type {
+                SysExType::Manufacturer(m) => SysExType::Manufacturer(*m),
+                SysExType::NonRealTime(d, ids) => SysExType::NonRealTime(*d, *ids),
+                SysExType::RealTime(d, ids) => SysExType::RealTime(*d, *ids),
+            },
+            data: data.clone(),
+        }),
+    }
+}
+
+/// Formats bytes as space-separated hex, e.g. `B0 07 7F`.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{b:02X}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Splits a stream of MIDI bytes into messages, each starting with a status
+/// byte. Returns `None` if the bytes don't start with a status byte, since
+/// running status isn't supported.
+pub fn split_messages(bytes: &[u8]) -> Option<Vec<&[u8]>> {
+    if bytes.first().is_some_and(|b| *b < 0x80) {
+        return None;
+    }
+    let mut msgs = vec![];
+    let mut start = 0;
+    for (i, b) in bytes.iter().enumerate().skip(1) {
+        // A SysEx message runs through its EOX, which is a status byte.
+        let in_sysex = bytes[start] == consts::SYSEX;
+        if *b >= 0x80 && !(in_sysex && *b == consts::EOX) {
+            msgs.push(&bytes[start..i]);
+            start = i;
+        }
+    }
+    if start < bytes.len() {
+        msgs.push(&bytes[start..]);
+    }
+    Some(msgs)
+}
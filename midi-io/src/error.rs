@@ -5,31 +5,66 @@ use std::fmt::Display;
 
 use futures::channel::mpsc;
 use midi_control::MidiMessage;
-use midir::MidiInput;
+use midir::{MidiInput, MidiOutput};
 
 
 /// Error enum for errors originating in or evoked by `midi-io`.
 #[derive(Debug)]
 pub enum MidiIoError {
+    /// A message couldn't be passed on through a channel.
     ChannelSender(mpsc::SendError),
+    /// A message couldn't be passed on to a writer thread.
     StdChannelSender(std::sync::mpsc::SendError<MidiMessage>),
+    /// The MIDI system couldn't be opened.
     MidiInit(midir::InitError),
+    /// A message couldn't be sent to a MIDI port.
     MidiSend(midir::SendError),
+    /// A MIDI input port couldn't be connected.
     MidiInputConnect(midir::ConnectError<MidiInput>),
+    /// A MIDI output port couldn't be connected.
+    MidiOutputConnect(midir::ConnectError<MidiOutput>),
+    /// A task couldn't be spawned.
     SpawnError(futures::task::SpawnError),
+    /// Network I/O failed, for RTP-MIDI sessions.
+    Io(std::io::Error),
+    /// An error detected by `midi-io` itself.
     Regular(ErrorKind),
 }
 
-#[derive(Clone, Copy, Debug)]
+/// The errors that `midi-io` detects itself.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ErrorKind {
+    /// No MIDI port has the given name.
     MidiPortNameNotFound,
+    /// A stream or sink isn't connected to a port.
     NotConnected,
+    /// An input port was given where an output was needed, or vice versa.
+    WrongDirection,
+    /// The requested MIDI backend wasn't built in.
+    BackendUnavailable,
+    /// An RTP-MIDI host refused a session.
+    SessionRejected,
+    /// An RTP-MIDI host didn't answer an invitation.
+    NoAnswer,
+    /// A message is too long to be sent.
+    MessageTooLong,
+    /// Messages couldn't be written to the port.
+    WriteFailed,
+    /// The writer has stopped, after its port failed.
+    WriterStopped,
 }
 impl Display for ErrorKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match *self {
             ErrorKind::MidiPortNameNotFound => "named MIDI port not found",
             ErrorKind::NotConnected => "not connected to a MIDI port",
+            ErrorKind::WrongDirection => "MIDI port opened in the wrong direction",
+            ErrorKind::BackendUnavailable => "MIDI backend not available in this build",
+            ErrorKind::SessionRejected => "RTP-MIDI host rejected the session",
+            ErrorKind::NoAnswer => "RTP-MIDI host did not answer",
+            ErrorKind::MessageTooLong => "MIDI message too long to send",
+            ErrorKind::WriteFailed => "MIDI messages could not be written to the port",
+            ErrorKind::WriterStopped => "MIDI writer stopped after its port failed",
         }.fmt(f)
     }
 }
@@ -42,12 +77,26 @@ impl Display for MidiIoError {
             MidiIoError::MidiInit(e) => e.fmt(f),
             MidiIoError::MidiSend(e) => e.fmt(f),
             MidiIoError::MidiInputConnect(e) => e.fmt(f),
+            MidiIoError::MidiOutputConnect(e) => e.fmt(f),
             MidiIoError::SpawnError(e) => e.fmt(f),
+            MidiIoError::Io(e) => e.fmt(f),
             MidiIoError::Regular(k) => k.fmt(f),
         }
     }
 }
 
+impl MidiIoError {
+    /// Returns true if the error means that a port can't be written any
+    /// more, rather than that one message couldn't be written to it.
+    pub fn is_port_failure(&self) -> bool {
+        !matches!(
+            self,
+            MidiIoError::MidiSend(midir::SendError::InvalidData(_))
+                | MidiIoError::Regular(ErrorKind::MessageTooLong)
+        )
+    }
+}
+
 impl Error for MidiIoError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         None
@@ -90,9 +139,20 @@ impl From<midir::ConnectError<MidiInput>> for MidiIoError {
         MidiIoError::MidiInputConnect(e)
     }
 }
+impl From<midir::ConnectError<MidiOutput>> for MidiIoError {
+    fn from(e: midir::ConnectError<MidiOutput>) -> Self {
+        MidiIoError::MidiOutputConnect(e)
+    }
+}
 impl From<futures::task::SpawnError> for MidiIoError {
     fn from(e: futures::task::SpawnError) -> Self {
         MidiIoError::SpawnError(e)
     }
 }
+impl From<std::io::Error> for MidiIoError {
+    fn from(e: std::io::Error) -> Self {
+        MidiIoError::Io(e)
+    }
+}
+/// The result of `midi-io` operations.
 pub type Result<T> = std::result::Result<T, MidiIoError>;
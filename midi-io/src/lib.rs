@@ -0,0 +1,646 @@
+#![deny(missing_docs)]
+//! Asynchronous MIDI I/O via `Stream` and `Sink`.
+//! 
+//! A crate that wraps MIDI port I/O mechanisms with MIDI `Stream` and `Sink`
+//! structs that work with types from the `midi-control` crate. For internal
+//! implementation, it relies on the platform-agnostic `midir` crate.
+//! 
+//! This crate is runtime-agnostic. It was split out of `bcr2kosc`, which
+//! remains its main user, so that other programs can use it.
+//!
+//! `MidiMessage`, re-exported here, is the one MIDI message type used by
+//! users of the crate. The `convert` sub-module translates it to and from the
+//! bytes exchanged with MIDI ports.
+//!
+//! The `shared` sub-module lets several users share one connection to a port.
+//!
+//! The `port` sub-module lists ports, and finds them by name. `MidiStream` and
+//! `MidiSink` can be bound to a port by name, or opened from a `Port`. The
+//! `builder` sub-module sets options for them, such as the client name shown
+//! to other MIDI software, and the size of their buffers.
+//!
+//! The `backend` sub-module checks which MIDI system ports are opened on.
+//!
+//! The `rtpmidi` sub-module reaches devices attached to other computers,
+//! through RTP-MIDI sessions over the network. Streams and sinks bound to a
+//! port named `rtpmidi:HOST:PORT` use a session instead of a local port.
+
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex};
+use std::task::{Poll, Waker};
+use std::time::{Duration, Instant};
+
+use futures::channel::mpsc::UnboundedReceiver;
+use futures::channel::mpsc::{self, UnboundedSender};
+use futures::{Sink, Stream};
+use midir::{MidiInputConnection, MidiOutputConnection};
+use pin_project::pin_project;
+use tracing::{debug, error, info};
+
+mod backend;
+mod builder;
+mod convert;
+mod error;
+mod port;
+mod rtpmidi;
+mod shared;
+pub use backend::*;
+pub use builder::*;
+pub use convert::*;
+pub use error::*;
+pub use midi_control::MidiMessage;
+pub use midir::Ignore;
+pub use port::*;
+pub use rtpmidi::{rtpmidi_host, RTPMIDI_PREFIX};
+pub use shared::*;
+
+/// A stream that provides MIDI messages recieved from a named MIDI I/O port.
+/// The stream is backed by a channel, which is unbounded unless the stream was
+/// built with a capacity. The connection to the port is closed when the stream
+/// is dropped.
+pub struct MidiStream {
+    /// Keep this alive until we stop. Since `midir` is callback-driven, we
+    /// don't actually need to reference this once it's set up.
+    _midi_cxn: InputConnection,
+
+    /// Our underlying stream implementation. The callback can run at an time,
+    /// so we need this buffered storage for it. The callback is also
+    /// synchronous,so we need the channel's ability to receive data
+    /// synchronously.
+    rx: Receiver,
+}
+
+/// What a `MidiStream` receives from: a connection to a port, or a listener
+/// to an RTP-MIDI session. Either is only held, to keep it open, so its field
+/// is named like an unused variable.
+enum InputConnection {
+    Midir {
+        _cxn: MidiInputConnection<()>,
+    },
+    RtpMidi {
+        _subscription: rtpmidi::Subscription,
+    },
+}
+
+/// What a `MidiSink`'s writer thread writes to.
+enum OutputConnection {
+    Midir(MidiOutputConnection),
+    RtpMidi(Arc<rtpmidi::Session>),
+    /// Messages are passed on whole, instead of being written to a port.
+    Channel(UnboundedSender<MidiMessage>),
+    /// Messages are dropped, and logged if `true`.
+    Discard(bool),
+}
+
+impl OutputConnection {
+    fn send(&mut self, bytes: &[u8]) -> Result<()> {
+        match self {
+            OutputConnection::Midir(cxn) => cxn.send(bytes).map_err(MidiIoError::from),
+            OutputConnection::RtpMidi(session) => session.send(bytes),
+            OutputConnection::Channel(tx) => tx
+                .unbounded_send(message_from_bytes(bytes))
+                .map_err(|_| MidiIoError::from(ErrorKind::NotConnected)),
+            OutputConnection::Discard(log) => {
+                if *log {
+                    info!("midi-io dry run: {:?}", message_from_bytes(bytes));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The receiving end of a `MidiStream`'s channel.
+enum Receiver {
+    Unbounded(UnboundedReceiver<MidiMessage>),
+    Bounded(mpsc::Receiver<MidiMessage>),
+}
+
+impl Stream for MidiStream {
+    type Item = MidiMessage;
+
+    fn poll_next(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        match &mut self.get_mut().rx {
+            Receiver::Unbounded(rx) => Pin::new(rx).poll_next(cx),
+            Receiver::Bounded(rx) => Pin::new(rx).poll_next(cx),
+        }
+    }
+}
+
+impl MidiStream {
+    /// Creates a new MidiListener stream for the named MIDI I/O port, with
+    /// default options. See `MidiStreamBuilder` for others.
+    pub fn bind(port_name: &str) -> Result<MidiStream> {
+        MidiStream::builder().bind(port_name)
+    }
+
+    /// Returns a builder, to set options before binding to a port.
+    pub fn builder() -> MidiStreamBuilder {
+        MidiStreamBuilder::default()
+    }
+}
+
+/// A Sink which transmits MIDI messages in the form of
+/// `midi_connect::MidiMessage` structs to a single MIDI port.
+///
+/// Clones share the port connection, each flushing only its own messages.
+#[pin_project]
+pub struct MidiSink {
+    #[pin]
+    data_q: Option<std::sync::mpsc::Sender<WriteRequest>>,
+    #[pin]
+    response_q: mpsc::UnboundedReceiver<Ack>,
+    response_tx: UnboundedSender<Ack>,
+    pending_count: usize,
+    max_pending: usize,
+    /// The number of this sink's messages that couldn't be written since
+    /// the last flush that reported them.
+    failed_count: usize,
+    status: Arc<WriterStatus>,
+}
+
+/// A message for the writer thread, with the channel on which to confirm that
+/// it was sent.
+type WriteRequest = (MidiMessage, UnboundedSender<Ack>);
+
+/// The writer's confirmation of a sink's messages: how many were written,
+/// and how many couldn't be.
+#[derive(Clone, Copy, Debug, Default)]
+struct Ack {
+    written: usize,
+    failed: usize,
+}
+
+/// Whether a writer thread is still running, shared with its sinks, so that
+/// they fail rather than wait for it once it has stopped.
+#[derive(Default)]
+struct WriterStatus {
+    stopped: AtomicBool,
+    /// Wakers of sinks waiting for confirmations.
+    waiting: Mutex<Vec<Waker>>,
+}
+
+impl WriterStatus {
+    fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Acquire)
+    }
+
+    /// Wakes `waker` when the writer stops, unless it's woken otherwise
+    /// first.
+    fn wait(&self, waker: &Waker) {
+        let mut waiting = self.waiting.lock().unwrap();
+        if !waiting.iter().any(|w| w.will_wake(waker)) {
+            waiting.push(waker.clone());
+        }
+    }
+
+    /// Forgets the waiting sinks, once confirmations have woken them.
+    fn forget_waiting(&self) {
+        self.waiting.lock().unwrap().clear();
+    }
+
+    fn stop(&self) {
+        self.stopped.store(true, Ordering::Release);
+        for w in self.waiting.lock().unwrap().drain(..) {
+            w.wake();
+        }
+    }
+}
+
+/// Marks a writer stopped when its thread ends, even by panicking.
+struct StopOnDrop(Arc<WriterStatus>);
+
+impl Drop for StopOnDrop {
+    fn drop(&mut self) {
+        self.0.stop();
+    }
+}
+
+/// How a writer reconnects to its port after the port fails.
+struct Rebind {
+    /// The port's name, for logging.
+    port_name: String,
+    /// Connects to the port again.
+    connect: Box<dyn Fn() -> Result<OutputConnection> + Send>,
+    /// Time between attempts.
+    interval: Duration,
+}
+
+/// The number of messages that can be queued to the writer thread before a
+/// sender has to wait for them to be written, unless the sink was built with
+/// another capacity.
+const MAX_PENDING: usize = 256;
+
+// Windows MIDI port drivers may or may not pend when sending. This
+// implementation assumes that they do, and output is performed on a separate task. In order to verify that a send is
+// complete (at least to the point of handoff to the API), we use a response
+// channel. The writer acknowledges messages in batches, sending each sink the
+// number of its messages written, so that a dense stream of messages doesn't
+// wake the sending task for every one.
+//
+// A write that fails because the port is gone, such as when the device is
+// unplugged, stops the writer: what's queued is confirmed as failed, and the
+// sinks' flushes and sends fail from then on. A sink built to rebind instead
+// keeps its writer running, failing messages while the port is gone, and
+// reconnecting to the port by name at an interval until it's back. Messages
+// that fail to be written are reported by the sink's next flush.
+//
+// The writer queues messages in two lanes, one for SysEx and one for channel
+// and other short messages. Short messages go first: a bulky SysEx transfer,
+// such as a preset dump, is written one message at a time, with any short
+// messages that arrive in the meantime written in between. Each lane can be
+// paced, with a minimum interval between its messages. Messages keep their
+// order within a lane, but a short message can overtake SysEx sent before
+// it.
+
+/// The pacing of the writer's lanes.
+#[derive(Clone, Copy, Debug, Default)]
+struct Pacing {
+    /// The minimum interval between channel and other short messages.
+    channel: Duration,
+    /// The minimum interval between SysEx messages.
+    sysex: Duration,
+}
+
+/// Messages waiting to be written, of one kind.
+struct Lane {
+    queue: VecDeque<WriteRequest>,
+    interval: Duration,
+    /// When the next message can be written.
+    next: Instant,
+}
+
+impl Lane {
+    fn new(interval: Duration) -> Self {
+        Lane {
+            queue: VecDeque::new(),
+            interval,
+            next: Instant::now(),
+        }
+    }
+
+    /// When the next message can be written, if there is one.
+    fn due(&self) -> Option<Instant> {
+        (!self.queue.is_empty()).then_some(self.next)
+    }
+
+    /// Takes the next message, if there is one and it's due.
+    fn pop(&mut self, now: Instant) -> Option<WriteRequest> {
+        if now < self.next {
+            return None;
+        }
+        let request = self.queue.pop_front()?;
+        self.next = now + self.interval;
+        Some(request)
+    }
+}
+
+impl MidiSink {
+    /// Returns a new `MidiSink` bound to the named MIDI port, with default
+    /// options. See `MidiSinkBuilder` for others.
+    /// 
+    /// This starts an OS thread to handle writes, which may be synchronous,
+    /// depending on operating system and MIDI port driver.
+    pub fn bind(port_name: &str) -> Result<Self> {
+        MidiSink::builder().bind(port_name)
+    }
+
+    /// Returns a builder, to set options before binding to a port.
+    pub fn builder() -> MidiSinkBuilder {
+        MidiSinkBuilder::default()
+    }
+}
+
+impl Clone for MidiSink {
+    fn clone(&self) -> Self {
+        let (response_tx, response_rx) = mpsc::unbounded::<Ack>();
+        MidiSink {
+            data_q: self.data_q.clone(),
+            response_q: response_rx,
+            response_tx,
+            pending_count: 0,
+            max_pending: self.max_pending,
+            failed_count: 0,
+            status: self.status.clone(),
+        }
+    }
+}
+
+fn run_midi_writer(
+    data_rx: std::sync::mpsc::Receiver<WriteRequest>,
+    midi_cxn: OutputConnection,
+    pacing: Pacing,
+    status: Arc<WriterStatus>,
+    rebind: Option<Rebind>,
+) {
+    let _stop = StopOnDrop(status.clone());
+    // `None` while the port is gone and being rebound.
+    let mut midi_cxn = Some(midi_cxn);
+    let mut retry_at = Instant::now();
+    let mut channel = Lane::new(pacing.channel);
+    let mut sysex = Lane::new(pacing.sysex);
+    let mut connected = true;
+    loop {
+        // Queue everything that has arrived.
+        while let Ok(request) = data_rx.try_recv() {
+            queue_request(request, &mut channel, &mut sysex);
+        }
+
+        if let (None, Some(rebind)) = (&midi_cxn, &rebind) {
+            if Instant::now() >= retry_at {
+                match (rebind.connect)() {
+                    Ok(cxn) => {
+                        info!("midi-io writer rebound to \"{}\"", rebind.port_name);
+                        midi_cxn = Some(cxn);
+                    }
+                    Err(e) => {
+                        debug!("midi-io rebind to \"{}\" failed: {e}", rebind.port_name);
+                        retry_at = Instant::now() + rebind.interval;
+                    }
+                }
+            }
+        }
+
+        // Write what's due, short messages first, and at most one SysEx
+        // message before checking for more short messages. Then acknowledge
+        // it all at once.
+        let mut acks: Vec<(UnboundedSender<Ack>, Ack)> = vec![];
+        let mut failure = None;
+        let now = Instant::now();
+        while let Some(request) = channel.pop(now) {
+            write_request(&mut midi_cxn, request, &mut acks, &mut failure);
+        }
+        if let Some(request) = sysex.pop(now) {
+            write_request(&mut midi_cxn, request, &mut acks, &mut failure);
+        }
+        send_acks(acks, &status);
+
+        if let Some(e) = failure {
+            error!("midi-io writer lost its port: {e}");
+            midi_cxn = None;
+            match &rebind {
+                Some(rebind) => retry_at = Instant::now() + rebind.interval,
+                None => {
+                    fail_queued(&data_rx, &mut channel, &mut sysex, &status);
+                    break;
+                }
+            }
+        }
+
+        // Wait for the next message to come due, for another to arrive, or
+        // for the next attempt to rebind. Once the sinks are gone, what's
+        // queued is still written.
+        let queued = [channel.due(), sysex.due()].into_iter().flatten().min();
+        let due = match queued {
+            None if !connected => break,
+            queued => queued
+                .into_iter()
+                .chain(midi_cxn.is_none().then_some(retry_at))
+                .min(),
+        };
+        let received = match due {
+            None => data_rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            Some(t) if connected => {
+                data_rx.recv_timeout(t.saturating_duration_since(Instant::now()))
+            }
+            Some(t) => {
+                std::thread::sleep(t.saturating_duration_since(Instant::now()));
+                continue;
+            }
+        };
+        match received {
+            Ok(request) => queue_request(request, &mut channel, &mut sysex),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => connected = false,
+        }
+    }
+    info!("midi-io listener thread exiting")
+}
+
+/// Queues a message in its lane.
+fn queue_request(request: WriteRequest, channel: &mut Lane, sysex: &mut Lane) {
+    match request.0 {
+        MidiMessage::SysEx(_) => sysex.queue.push_back(request),
+        _ => channel.queue.push_back(request),
+    }
+}
+
+/// Writes a message, counting it in the acknowledgements for its sink. A
+/// message is failed without writing it while the port is gone, and the
+/// first error that means the port is gone is kept in `failure`.
+fn write_request(
+    midi_cxn: &mut Option<OutputConnection>,
+    (item, response_tx): WriteRequest,
+    acks: &mut Vec<(UnboundedSender<Ack>, Ack)>,
+    failure: &mut Option<MidiIoError>,
+) {
+    let written = match midi_cxn {
+        Some(cxn) if failure.is_none() => {
+            debug!("midi-io sending MIDI msg: {item:?}");
+            let bytes = message_to_bytes(item);
+            match cxn.send(&bytes) {
+                Ok(()) => {
+                    debug!("midi-io sent {} bytes.", bytes.len());
+                    true
+                }
+                Err(e) => {
+                    error!("midi-io send error: {e:?}");
+                    if e.is_port_failure() {
+                        *failure = Some(e);
+                    }
+                    false
+                }
+            }
+        }
+        _ => false,
+    };
+    let i = match acks
+        .iter()
+        .position(|(tx, _)| tx.same_receiver(&response_tx))
+    {
+        Some(i) => i,
+        None => {
+            acks.push((response_tx, Ack::default()));
+            acks.len() - 1
+        }
+    };
+    let ack = &mut acks[i].1;
+    if written {
+        ack.written += 1;
+    } else {
+        ack.failed += 1;
+    }
+}
+
+/// Sends sinks their acknowledgements.
+fn send_acks(acks: Vec<(UnboundedSender<Ack>, Ack)>, status: &WriterStatus) {
+    if acks.is_empty() {
+        return;
+    }
+    for (response_tx, ack) in acks {
+        // The sink may have been dropped without waiting.
+        if let Err(e) = response_tx.unbounded_send(ack) {
+            debug!("midi-io response send error: {e}");
+        }
+    }
+    status.forget_waiting();
+}
+
+/// Fails every queued message, and those that have arrived since, when the
+/// writer stops.
+fn fail_queued(
+    data_rx: &std::sync::mpsc::Receiver<WriteRequest>,
+    channel: &mut Lane,
+    sysex: &mut Lane,
+    status: &WriterStatus,
+) {
+    let mut acks = vec![];
+    let queued: Vec<WriteRequest> = channel
+        .queue
+        .drain(..)
+        .chain(sysex.queue.drain(..))
+        .chain(data_rx.try_iter())
+        .collect();
+    for request in queued {
+        write_request(&mut None, request, &mut acks, &mut None);
+    }
+    send_acks(acks, status);
+}
+
+impl Sink<MidiMessage> for MidiSink {
+    type Error = MidiIoError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Result<()>> {
+        if self.pending_count < self.max_pending {
+            Poll::Ready(Ok(()))
+        } else {
+            self.poll_flush(cx)
+        }
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: MidiMessage) -> Result<()> {
+        if self.status.is_stopped() {
+            return Err(MidiIoError::from(ErrorKind::WriterStopped));
+        }
+        match self.data_q {
+            Some(ref data_q) => data_q
+                .send((item, self.response_tx.clone()))
+                .map_err(|e| MidiIoError::from(std::sync::mpsc::SendError(e.0 .0)))
+                .inspect(|_| *self.project().pending_count += 1),
+            None => Err(MidiIoError::from(ErrorKind::NotConnected)),
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Result<()>> {
+        while *self.as_mut().project().pending_count > 0 {
+            let this = self.as_mut().project();
+            if let Poll::Ready(Some(ack)) = this.response_q.poll_next(cx) {
+                *this.pending_count = this.pending_count.saturating_sub(ack.written + ack.failed);
+                *this.failed_count += ack.failed;
+                continue;
+            }
+            // Messages the writer held when it stopped are never confirmed.
+            this.status.wait(cx.waker());
+            if !this.status.is_stopped() {
+                return Poll::Pending;
+            }
+            *this.pending_count = 0;
+            *this.failed_count = 0;
+            return Poll::Ready(Err(MidiIoError::from(ErrorKind::WriterStopped)));
+        }
+        let this = self.project();
+        if *this.failed_count > 0 {
+            *this.failed_count = 0;
+            return Poll::Ready(Err(MidiIoError::from(ErrorKind::WriteFailed)));
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Result<()>> {
+        if let Poll::Ready(Ok(())) = self.as_mut().poll_flush(cx) {
+            self.data_q = None;
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::SinkExt;
+    use midi_control::{Channel, ControlEvent};
+
+    fn cc(value: u8) -> MidiMessage {
+        MidiMessage::ControlChange(Channel::Ch1, ControlEvent { control: 7, value })
+    }
+
+    fn is_kind(result: Result<()>, kind: ErrorKind) -> bool {
+        matches!(result, Err(MidiIoError::Regular(k)) if k == kind)
+    }
+
+    /// Waits up to a few seconds for the writer thread to do something.
+    fn wait_until(done: impl Fn() -> bool) {
+        for _ in 0..500 {
+            if done() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("timed out");
+    }
+
+    #[test]
+    fn writer_stops_when_port_fails() {
+        let (tx, rx) = mpsc::unbounded();
+        let mut sink = MidiSink::builder().channel(tx);
+        block_on(sink.send(cc(1))).unwrap();
+        drop(rx);
+        assert!(is_kind(block_on(sink.send(cc(2))), ErrorKind::WriteFailed));
+        wait_until(|| sink.status.is_stopped());
+        assert!(is_kind(
+            Pin::new(&mut sink).start_send(cc(3)),
+            ErrorKind::WriterStopped
+        ));
+        assert!(is_kind(
+            block_on(sink.send(cc(4))),
+            ErrorKind::WriterStopped
+        ));
+    }
+
+    #[test]
+    fn writer_rebinds() {
+        let (tx, rx) = mpsc::unbounded();
+        let (new_tx, mut new_rx) = mpsc::unbounded();
+        let new_tx = Mutex::new(Some(new_tx));
+        let rebind = Rebind {
+            port_name: "test".to_string(),
+            connect: Box::new(move || match new_tx.lock().unwrap().take() {
+                Some(tx) => Ok(OutputConnection::Channel(tx)),
+                None => Err(MidiIoError::from(ErrorKind::MidiPortNameNotFound)),
+            }),
+            interval: Duration::from_millis(10),
+        };
+        let mut sink = MidiSink::builder().start_with(OutputConnection::Channel(tx), Some(rebind));
+        drop(rx);
+        assert!(is_kind(block_on(sink.send(cc(1))), ErrorKind::WriteFailed));
+        assert!(!sink.status.is_stopped());
+        // Messages fail until the writer has reconnected.
+        let mut value = 2;
+        while block_on(sink.send(cc(value))).is_err() {
+            assert!(value < 100, "writer didn't rebind");
+            std::thread::sleep(Duration::from_millis(10));
+            value += 1;
+        }
+        assert_eq!(new_rx.try_next().unwrap(), Some(cc(value)));
+        assert!(!sink.status.is_stopped());
+    }
+}
@@ -0,0 +1,144 @@
+//! MIDI ports, listed and found by name.
+//!
+//! A `Port` describes a port as it was when the ports were listed. Ports come
+//! and go as devices are connected and disconnected, so a `Port` is opened by
+//! name, and opening fails if the port has since gone away.
+//!
+//! On macOS, CoreMIDI network sessions appear as ports, through which MIDI is
+//! exchanged over RTP-MIDI with other computers in the session, such as a Mac
+//! with a B-Control attached. CoreMIDI names them after the "Network" device
+//! that carries them, as in "Network Session 1", and they're marked as
+//! network session ports when listed.
+//!
+//! Elsewhere, a device on another computer can be reached through an RTP-MIDI
+//! session of this program's own, named as a port `rtpmidi:HOST:PORT`. Such a
+//! port isn't listed, but is always found.
+
+use std::fmt::Display;
+
+use midir::{MidiIO, MidiInput, MidiOutput};
+
+use super::{rtpmidi_host, ErrorKind, MidiIoError, MidiSink, MidiStream, Result};
+
+/// Whether a port receives or sends MIDI.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// A port from which MIDI is received.
+    Input,
+    /// A port to which MIDI is sent.
+    Output,
+}
+
+impl Display for Direction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Direction::Input => "input",
+            Direction::Output => "output",
+        }
+        .fmt(f)
+    }
+}
+
+/// A MIDI port.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Port {
+    /// The name of the port.
+    pub name: String,
+    /// The position of the port among those in its direction, counting from
+    /// zero. Positions change as other ports come and go.
+    pub index: usize,
+    /// Whether the port is an input or an output.
+    pub direction: Direction,
+    /// Whether the port belongs to a CoreMIDI network session. Always false
+    /// on other platforms.
+    pub network_session: bool,
+}
+
+impl Port {
+    /// Lists the ports in one direction. The list can differ on subsequent
+    /// calls, as MIDI devices are connected or disconnected.
+    pub fn list(direction: Direction) -> Result<Vec<Port>> {
+        let names = match direction {
+            Direction::Input => port_names(&MidiInput::new("midi-io port list")?),
+            Direction::Output => port_names(&MidiOutput::new("midi-io port list")?),
+        };
+        Ok(names
+            .into_iter()
+            .enumerate()
+            .map(|(index, name)| Port {
+                network_session: is_network_session(&name),
+                name,
+                index,
+                direction,
+            })
+            .collect())
+    }
+
+    /// Finds the first network session port in a direction, if there is one.
+    pub fn find_network_session(direction: Direction) -> Result<Option<Port>> {
+        Ok(Port::list(direction)?
+            .into_iter()
+            .find(|p| p.network_session))
+    }
+
+    /// Finds the port with the given name and direction. A port naming an
+    /// RTP-MIDI host is found without looking, since the host can't be
+    /// listed; whether it answers is known when the port is opened.
+    pub fn find(name: &str, direction: Direction) -> Result<Port> {
+        if rtpmidi_host(name).is_some() {
+            return Ok(Port {
+                name: name.to_string(),
+                index: 0,
+                direction,
+                network_session: false,
+            });
+        }
+        Port::list(direction)?
+            .into_iter()
+            .find(|p| p.name == name)
+            .ok_or(MidiIoError::Regular(ErrorKind::MidiPortNameNotFound))
+    }
+
+    /// Opens an input port as a stream of the messages it receives.
+    pub fn open_input(&self) -> Result<MidiStream> {
+        match self.direction {
+            Direction::Input => MidiStream::bind(&self.name),
+            Direction::Output => Err(MidiIoError::Regular(ErrorKind::WrongDirection)),
+        }
+    }
+
+    /// Opens an output port as a sink of messages to send.
+    pub fn open_output(&self) -> Result<MidiSink> {
+        match self.direction {
+            Direction::Output => MidiSink::bind(&self.name),
+            Direction::Input => Err(MidiIoError::Regular(ErrorKind::WrongDirection)),
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn is_network_session(name: &str) -> bool {
+    name.starts_with("Network ")
+}
+
+#[cfg(not(target_os = "macos"))]
+fn is_network_session(_name: &str) -> bool {
+    false
+}
+
+fn port_names<T: MidiIO>(midi_io: &T) -> Vec<String> {
+    midi_io
+        .ports()
+        .iter()
+        .map(|p| midi_io.port_name(p).unwrap_or_default())
+        .collect()
+}
+
+/// Finds the `midir` port with the given name, in order to connect to it.
+pub(super) fn find_midir_port<T: MidiIO>(midi_io: &T, name: &str) -> Result<T::Port> {
+    midi_io
+        .ports()
+        .into_iter()
+        .find(|p| midi_io.port_name(p).ok().as_deref() == Some(name))
+        .ok_or(MidiIoError::Regular(ErrorKind::MidiPortNameNotFound))
+}
@@ -0,0 +1,730 @@
+//! RTP-MIDI (AppleMIDI) sessions.
+//!
+//! A B-Control attached to another computer can be reached over the network
+//! through an RTP-MIDI session, such as macOS offers as Network MIDI, and
+//! rtpMIDI on Windows. Binding a `MidiStream` or `MidiSink` to a port named
+//! `rtpmidi:HOST:PORT`, where PORT is the host's session (control) port,
+//! invites the host into a session instead of opening a local port. A stream
+//! and a sink bound to the same host share one session, which ends when the
+//! last of them is dropped.
+//!
+//! This end is the session initiator: it sends invitations to the host's
+//! control port and to the data port after it, and keeps the clocks of the
+//! session in sync. MIDI is sent one message per packet, without a recovery
+//! journal, and journals in the packets received are ignored, so a lost
+//! packet loses its messages. That's rare on a local network. Of the
+//! options of a `MidiStream`, only the handling of real-time messages
+//! applies; the others are for MIDI drivers.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::ErrorKind as IoErrorKind;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use tracing::{debug, error, info, warn};
+
+use super::{ErrorKind, MidiIoError, Result};
+
+/// The prefix of port names that name RTP-MIDI hosts.
+pub const RTPMIDI_PREFIX: &str = "rtpmidi:";
+
+/// The first bytes of session commands, which distinguish them from MIDI.
+const SIGNATURE: [u8; 2] = [0xFF, 0xFF];
+const INVITATION: [u8; 2] = *b"IN";
+const ACCEPTED: [u8; 2] = *b"OK";
+const REJECTED: [u8; 2] = *b"NO";
+const BYE: [u8; 2] = *b"BY";
+const SYNC: [u8; 2] = *b"CK";
+const PROTOCOL_VERSION: u32 = 2;
+
+/// The RTP payload type of MIDI.
+const PAYLOAD_TYPE: u8 = 0x61;
+
+/// The longest MIDI command list a packet header can describe.
+const MAX_COMMANDS_LEN: usize = 0x0FFF;
+
+/// How long to wait for the answer to an invitation.
+const INVITATION_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How many times to send an invitation before giving up.
+const INVITATION_TRIES: u32 = 5;
+
+/// Time between clock synchronizations.
+const SYNC_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often the session's threads check whether it has ended.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Sessions in use, by host, shared by the streams and sinks bound to them.
+static SESSIONS: Mutex<Vec<(SocketAddr, Weak<Session>)>> = Mutex::new(Vec::new());
+
+/// Returns the host and port named by a port name, if it names one.
+pub fn rtpmidi_host(port_name: &str) -> Option<&str> {
+    port_name.strip_prefix(RTPMIDI_PREFIX)
+}
+
+/// A receiver of the messages of a session, as bytes.
+type Listener = Box<dyn FnMut(&[u8]) + Send>;
+
+/// The parts of a session shared with its threads.
+struct Shared {
+    control: UdpSocket,
+    data: UdpSocket,
+    /// The host's data port.
+    data_remote: SocketAddr,
+    ssrc: u32,
+    token: u32,
+    started: Instant,
+    seq: Mutex<u16>,
+    listeners: Mutex<Vec<(usize, Listener)>>,
+    next_listener: AtomicUsize,
+    /// Set when the session is dropped.
+    stopping: AtomicBool,
+    /// Set when the host ends the session.
+    ended: AtomicBool,
+}
+
+/// An RTP-MIDI session with a host.
+pub struct Session {
+    remote: SocketAddr,
+    shared: Arc<Shared>,
+}
+
+impl Session {
+    /// Returns the session with a host, given as `HOST:PORT`, starting one
+    /// under the given name if there isn't one already.
+    pub fn join(host: &str, name: &str) -> Result<Arc<Session>> {
+        let remote = host
+            .to_socket_addrs()?
+            .next()
+            .ok_or(MidiIoError::Regular(ErrorKind::MidiPortNameNotFound))?;
+        let mut sessions = SESSIONS.lock().unwrap();
+        sessions.retain(|(_, s)| s.strong_count() > 0);
+        if let Some(session) = sessions
+            .iter()
+            .find(|(a, _)| *a == remote)
+            .and_then(|(_, s)| s.upgrade())
+        {
+            return Ok(session);
+        }
+        let session = Arc::new(Session::start(remote, name)?);
+        sessions.push((remote, Arc::downgrade(&session)));
+        Ok(session)
+    }
+
+    fn start(remote: SocketAddr, name: &str) -> Result<Session> {
+        let data_port = remote
+            .port()
+            .checked_add(1)
+            .ok_or(MidiIoError::Regular(ErrorKind::MidiPortNameNotFound))?;
+        let data_remote = SocketAddr::new(remote.ip(), data_port);
+        let local: SocketAddr = if remote.is_ipv4() {
+            ([0u8; 4], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let shared = Arc::new(Shared {
+            control: UdpSocket::bind(local)?,
+            data: UdpSocket::bind(local)?,
+            data_remote,
+            ssrc: random(1),
+            token: random(2),
+            started: Instant::now(),
+            seq: Mutex::new(0),
+            listeners: Mutex::default(),
+            next_listener: AtomicUsize::new(0),
+            stopping: AtomicBool::new(false),
+            ended: AtomicBool::new(false),
+        });
+        shared.invite(&shared.control, remote, name)?;
+        shared.invite(&shared.data, data_remote, name)?;
+        info!("midi-io joined RTP-MIDI session with {remote}");
+
+        let data_shared = shared.clone();
+        std::thread::spawn(move || run_data(data_shared));
+        let control_shared = shared.clone();
+        std::thread::spawn(move || run_control(control_shared, remote));
+        Ok(Session { remote, shared })
+    }
+
+    /// Passes each message received in the session to `listener`, until the
+    /// subscription is dropped.
+    pub fn subscribe(self: &Arc<Self>, listener: Listener) -> Subscription {
+        let id = self.shared.next_listener.fetch_add(1, Ordering::Relaxed);
+        self.shared.listeners.lock().unwrap().push((id, listener));
+        Subscription {
+            session: self.clone(),
+            id,
+        }
+    }
+
+    /// Sends a MIDI message, as bytes.
+    pub fn send(&self, bytes: &[u8]) -> Result<()> {
+        if self.shared.ended.load(Ordering::Relaxed) {
+            return Err(MidiIoError::Regular(ErrorKind::NotConnected));
+        }
+        if bytes.len() > MAX_COMMANDS_LEN {
+            return Err(MidiIoError::Regular(ErrorKind::MessageTooLong));
+        }
+        let seq = {
+            let mut seq = self.shared.seq.lock().unwrap();
+            *seq = seq.wrapping_add(1);
+            *seq
+        };
+        let mut packet = Vec::with_capacity(bytes.len() + 14);
+        packet.extend_from_slice(&[0x80, PAYLOAD_TYPE]);
+        packet.extend_from_slice(&seq.to_be_bytes());
+        packet.extend_from_slice(&(self.shared.timestamp() as u32).to_be_bytes());
+        packet.extend_from_slice(&self.shared.ssrc.to_be_bytes());
+        // The command list has a short header if its length fits in four
+        // bits, and a long one otherwise.
+        let len = bytes.len();
+        if len <= 0x0F {
+            packet.push(len as u8);
+        } else {
+            packet.push(0x80 | (len >> 8) as u8);
+            packet.push(len as u8);
+        }
+        packet.extend_from_slice(bytes);
+        self.shared.data.send_to(&packet, self.shared.data_remote)?;
+        Ok(())
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.shared.stopping.store(true, Ordering::Relaxed);
+        let bye = session_packet(BYE, self.shared.token, self.shared.ssrc);
+        if let Err(e) = self.shared.control.send_to(&bye, self.remote) {
+            debug!("midi-io RTP-MIDI end of session not sent: {e}");
+        }
+        info!("midi-io left RTP-MIDI session with {}", self.remote);
+    }
+}
+
+/// A listener to a session, which stops listening when dropped.
+pub struct Subscription {
+    session: Arc<Session>,
+    id: usize,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.session
+            .shared
+            .listeners
+            .lock()
+            .unwrap()
+            .retain(|(id, _)| *id != self.id);
+    }
+}
+
+impl Shared {
+    /// Invites the host into the session on one of its ports.
+    fn invite(&self, socket: &UdpSocket, to: SocketAddr, name: &str) -> Result<()> {
+        let mut packet = session_packet(INVITATION, self.token, self.ssrc);
+        packet.extend_from_slice(name.as_bytes());
+        packet.push(0);
+        socket.set_read_timeout(Some(INVITATION_TIMEOUT))?;
+        let mut buf = [0u8; 256];
+        for _ in 0..INVITATION_TRIES {
+            socket.send_to(&packet, to)?;
+            let len = match socket.recv_from(&mut buf) {
+                Ok((len, _)) => len,
+                Err(e) if is_timeout(&e) => continue,
+                Err(e) => return Err(e.into()),
+            };
+            match parse_session_packet(&buf[..len]) {
+                Some((ACCEPTED, token)) if token == self.token => return Ok(()),
+                Some((REJECTED, token)) if token == self.token => {
+                    return Err(MidiIoError::Regular(ErrorKind::SessionRejected))
+                }
+                _ => {}
+            }
+        }
+        Err(MidiIoError::Regular(ErrorKind::NoAnswer))
+    }
+
+    /// The session's clock, in units of 100 microseconds.
+    fn timestamp(&self) -> u64 {
+        (self.started.elapsed().as_micros() / 100) as u64
+    }
+
+    fn send_sync(&self, count: u8, timestamps: [u64; 3]) {
+        let mut packet = Vec::with_capacity(36);
+        packet.extend_from_slice(&SIGNATURE);
+        packet.extend_from_slice(&SYNC);
+        packet.extend_from_slice(&self.ssrc.to_be_bytes());
+        packet.extend_from_slice(&[count, 0, 0, 0]);
+        for t in timestamps {
+            packet.extend_from_slice(&t.to_be_bytes());
+        }
+        if let Err(e) = self.data.send_to(&packet, self.data_remote) {
+            error!("midi-io RTP-MIDI clock sync send error: {e}");
+        }
+    }
+
+    /// Answers the host's part of a clock synchronization.
+    fn answer_sync(&self, packet: &[u8]) {
+        if packet.len() < 36 {
+            return;
+        }
+        let timestamp = |i: usize| {
+            let start = 12 + 8 * i;
+            u64::from_be_bytes(packet[start..start + 8].try_into().unwrap())
+        };
+        match packet[8] {
+            0 => self.send_sync(1, [timestamp(0), self.timestamp(), 0]),
+            1 => self.send_sync(2, [timestamp(0), timestamp(1), self.timestamp()]),
+            _ => {}
+        }
+    }
+
+    fn dispatch(&self, message: &[u8]) {
+        for (_, listener) in self.listeners.lock().unwrap().iter_mut() {
+            listener(message);
+        }
+    }
+}
+
+/// Receives MIDI and clock synchronizations on the data port, and starts
+/// synchronizations at intervals.
+fn run_data(shared: Arc<Shared>) {
+    if let Err(e) = shared.data.set_read_timeout(Some(POLL_INTERVAL)) {
+        error!("midi-io RTP-MIDI setup error: {e}");
+        return;
+    }
+    let mut parser = Parser::default();
+    let mut next_sync = Instant::now();
+    let mut buf = vec![0u8; 4096];
+    while !shared.stopping.load(Ordering::Relaxed) {
+        if Instant::now() >= next_sync {
+            shared.send_sync(0, [shared.timestamp(), 0, 0]);
+            next_sync = Instant::now() + SYNC_INTERVAL;
+        }
+        let len = match shared.data.recv_from(&mut buf) {
+            Ok((len, _)) => len,
+            Err(e) if is_timeout(&e) => continue,
+            Err(e) => {
+                error!("midi-io RTP-MIDI receive error: {e}");
+                break;
+            }
+        };
+        let packet = &buf[..len];
+        if packet.starts_with(&SIGNATURE) {
+            if packet.get(2..4) == Some(&SYNC[..]) {
+                shared.answer_sync(packet);
+            }
+            continue;
+        }
+        for message in parser.parse(packet) {
+            debug!("midi-io received {} bytes over RTP-MIDI.", message.len());
+            shared.dispatch(&message);
+        }
+    }
+    debug!("midi-io RTP-MIDI data thread exiting");
+}
+
+/// Watches the control port for the host ending the session.
+fn run_control(shared: Arc<Shared>, remote: SocketAddr) {
+    if let Err(e) = shared.control.set_read_timeout(Some(POLL_INTERVAL)) {
+        error!("midi-io RTP-MIDI setup error: {e}");
+        return;
+    }
+    let mut buf = [0u8; 256];
+    while !shared.stopping.load(Ordering::Relaxed) {
+        let len = match shared.control.recv_from(&mut buf) {
+            Ok((len, _)) => len,
+            Err(e) if is_timeout(&e) => continue,
+            Err(e) => {
+                error!("midi-io RTP-MIDI receive error: {e}");
+                break;
+            }
+        };
+        if let Some((BYE, _)) = parse_session_packet(&buf[..len]) {
+            warn!("midi-io RTP-MIDI host {remote} ended the session");
+            shared.ended.store(true, Ordering::Relaxed);
+            break;
+        }
+    }
+    debug!("midi-io RTP-MIDI control thread exiting");
+}
+
+/// Builds a session command, without the name that invitations add.
+fn session_packet(command: [u8; 2], token: u32, ssrc: u32) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(32);
+    packet.extend_from_slice(&SIGNATURE);
+    packet.extend_from_slice(&command);
+    packet.extend_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+    packet.extend_from_slice(&token.to_be_bytes());
+    packet.extend_from_slice(&ssrc.to_be_bytes());
+    packet
+}
+
+/// Returns the command and initiator token of a session command.
+fn parse_session_packet(packet: &[u8]) -> Option<([u8; 2], u32)> {
+    if packet.len() < 16 || !packet.starts_with(&SIGNATURE) {
+        return None;
+    }
+    let command = [packet[2], packet[3]];
+    let token = u32::from_be_bytes(packet[8..12].try_into().unwrap());
+    Some((command, token))
+}
+
+fn is_timeout(e: &std::io::Error) -> bool {
+    matches!(e.kind(), IoErrorKind::WouldBlock | IoErrorKind::TimedOut)
+}
+
+/// Makes a random number, for identifiers that must differ between sessions.
+fn random(salt: u32) -> u32 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(salt);
+    hasher.finish() as u32
+}
+
+/// Extracts MIDI messages from RTP-MIDI packets. SysEx can be split across
+/// packets, so the parser keeps the part received so far.
+#[derive(Default)]
+struct Parser {
+    sysex: Option<Vec<u8>>,
+}
+
+impl Parser {
+    /// Returns the messages in a packet, as bytes.
+    fn parse(&mut self, packet: &[u8]) -> Vec<Vec<u8>> {
+        if packet.len() < 13 || packet[0] >> 6 != 2 || packet[1] & 0x7F != PAYLOAD_TYPE {
+            return vec![];
+        }
+        let mut pos = 12 + 4 * (packet[0] & 0x0F) as usize;
+        if packet[0] & 0x10 != 0 {
+            // A header extension, of a length given in 32-bit words.
+            let Some(words) = packet.get(pos + 2..pos + 4) else {
+                return vec![];
+            };
+            pos += 4 + 4 * u16::from_be_bytes([words[0], words[1]]) as usize;
+        }
+        let Some(&flags) = packet.get(pos) else {
+            return vec![];
+        };
+        let (len, start) = if flags & 0x80 != 0 {
+            let Some(&low) = packet.get(pos + 1) else {
+                return vec![];
+            };
+            (((flags & 0x0F) as usize) << 8 | low as usize, pos + 2)
+        } else {
+            ((flags & 0x0F) as usize, pos + 1)
+        };
+        match packet.get(start..start + len) {
+            Some(commands) => self.parse_commands(commands, flags & 0x20 != 0),
+            None => vec![],
+        }
+    }
+
+    /// Returns the messages of a command list. Each command but the first is
+    /// preceded by a delta time, as is the first if `first_delta` is set.
+    fn parse_commands(&mut self, list: &[u8], first_delta: bool) -> Vec<Vec<u8>> {
+        let mut messages = vec![];
+        let mut running = None;
+        let mut pos = 0;
+        let mut first = true;
+        while pos < list.len() {
+            if !first || first_delta {
+                // Up to four bytes, each but the last with the top bit set.
+                for _ in 0..4 {
+                    let more = list[pos] & 0x80 != 0;
+                    pos += 1;
+                    if !more || pos >= list.len() {
+                        break;
+                    }
+                }
+                if pos >= list.len() {
+                    break;
+                }
+            }
+            first = false;
+            let status = if list[pos] & 0x80 != 0 {
+                pos += 1;
+                list[pos - 1]
+            } else {
+                match running {
+                    Some(status) => status,
+                    None => break,
+                }
+            };
+            let len = match status {
+                0x80..=0xBF | 0xE0..=0xEF => {
+                    running = Some(status);
+                    2
+                }
+                0xC0..=0xDF => {
+                    running = Some(status);
+                    1
+                }
+                0xF0 | 0xF7 => {
+                    running = None;
+                    let Some(end) = list[pos..].iter().position(|b| b & 0x80 != 0) else {
+                        break;
+                    };
+                    let (data, terminator) = (&list[pos..pos + end], list[pos + end]);
+                    pos += end + 1;
+                    self.sysex_segment(status, data, terminator, &mut messages);
+                    continue;
+                }
+                0xF1 | 0xF3 => {
+                    running = None;
+                    1
+                }
+                0xF2 => {
+                    running = None;
+                    2
+                }
+                0xF4..=0xF6 => {
+                    running = None;
+                    0
+                }
+                _ => 0,
+            };
+            let Some(data) = list.get(pos..pos + len) else {
+                break;
+            };
+            let mut message = vec![status];
+            message.extend_from_slice(data);
+            messages.push(message);
+            pos += len;
+        }
+        messages
+    }
+
+    /// Adds a segment of SysEx. A segment starts with `0xF0`, or `0xF7` if
+    /// it continues an earlier one, and ends with `0xF7` if it's the last,
+    /// `0xF0` if more follow, or `0xF4` if the message is cancelled.
+    fn sysex_segment(
+        &mut self,
+        start: u8,
+        data: &[u8],
+        terminator: u8,
+        messages: &mut Vec<Vec<u8>>,
+    ) {
+        let mut sysex = if start == 0xF0 {
+            vec![0xF0]
+        } else {
+            match self.sysex.take() {
+                Some(sysex) => sysex,
+                None => return,
+            }
+        };
+        sysex.extend_from_slice(data);
+        match terminator {
+            0xF7 => {
+                sysex.push(0xF7);
+                messages.push(sysex);
+            }
+            0xF0 => self.sysex = Some(sysex),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An RTP-MIDI packet with a command list, given the list's flags apart
+    /// from its length.
+    fn packet(flags: u8, commands: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x80, PAYLOAD_TYPE, 0, 1, 0, 0, 0, 2, 0x12, 0x34, 0x56, 0x78];
+        let len = commands.len();
+        if len <= 0x0F {
+            packet.push(flags | len as u8);
+        } else {
+            packet.push(flags | 0x80 | (len >> 8) as u8);
+            packet.push(len as u8);
+        }
+        packet.extend_from_slice(commands);
+        packet
+    }
+
+    fn parse(commands: &[u8]) -> Vec<Vec<u8>> {
+        Parser::default().parse(&packet(0, commands))
+    }
+
+    #[test]
+    fn one_message() {
+        assert_eq!(parse(&[0x90, 60, 100]), vec![vec![0x90, 60, 100]]);
+        assert_eq!(parse(&[0xC3, 5]), vec![vec![0xC3, 5]]);
+        assert_eq!(parse(&[0xF8]), vec![vec![0xF8]]);
+        assert!(parse(&[]).is_empty());
+    }
+
+    #[test]
+    fn delta_times() {
+        // A one-byte delta time, then one of three bytes.
+        assert_eq!(
+            parse(&[0xB0, 7, 100, 0x05, 0xE1, 0, 64, 0x81, 0x80, 0x00, 0xC0, 5]),
+            vec![vec![0xB0, 7, 100], vec![0xE1, 0, 64], vec![0xC0, 5]]
+        );
+        // The Z flag says the first command has a delta time too.
+        assert_eq!(
+            Parser::default().parse(&packet(0x20, &[0x83, 0x00, 0x90, 60, 100])),
+            vec![vec![0x90, 60, 100]]
+        );
+    }
+
+    #[test]
+    fn running_status() {
+        assert_eq!(
+            parse(&[0x90, 60, 100, 0x00, 62, 90, 0x00, 0xD0, 3, 0x00, 4]),
+            vec![
+                vec![0x90, 60, 100],
+                vec![0x90, 62, 90],
+                vec![0xD0, 3],
+                vec![0xD0, 4]
+            ]
+        );
+        // System messages cancel running status.
+        assert_eq!(
+            parse(&[0x90, 60, 100, 0x00, 0xF6, 0x00, 62, 90]),
+            vec![vec![0x90, 60, 100], vec![0xF6]]
+        );
+        // Data without a status is dropped.
+        assert!(parse(&[60, 100]).is_empty());
+    }
+
+    #[test]
+    fn long_header() {
+        let mut commands = vec![0x90, 0, 100];
+        for key in 1..8 {
+            commands.extend_from_slice(&[0x00, key, 100]);
+        }
+        let packet = packet(0, &commands);
+        assert_eq!(packet[12], 0x80);
+        assert_eq!(packet[13], 24);
+        let messages = Parser::default().parse(&packet);
+        assert_eq!(messages.len(), 8);
+        assert_eq!(messages[7], vec![0x90, 7, 100]);
+    }
+
+    #[test]
+    fn header_extension_and_csrcs() {
+        let mut extended = packet(0, &[0x90, 60, 100]);
+        extended[0] |= 0x10 | 0x01;
+        // A CSRC, then an extension of one word.
+        let more = [0, 0, 0, 9, 0xBE, 0xDE, 0x00, 0x01, 1, 2, 3, 4];
+        extended.splice(12..12, more);
+        assert_eq!(
+            Parser::default().parse(&extended),
+            vec![vec![0x90, 60, 100]]
+        );
+    }
+
+    #[test]
+    fn whole_sysex() {
+        assert_eq!(
+            parse(&[0xF0, 0x00, 0x20, 0x32, 0xF7, 0x00, 0xB0, 7, 1]),
+            vec![vec![0xF0, 0x00, 0x20, 0x32, 0xF7], vec![0xB0, 7, 1]]
+        );
+    }
+
+    #[test]
+    fn sysex_across_packets() {
+        let mut parser = Parser::default();
+        assert!(parser
+            .parse(&packet(0, &[0xF0, 0x00, 0x20, 0x32, 0xF0]))
+            .is_empty());
+        assert!(parser.parse(&packet(0, &[0xF7, 0x28, 0xF0])).is_empty());
+        assert_eq!(
+            parser.parse(&packet(0, &[0xF7, 0x7F, 0xF7, 0x00, 0xC0, 1])),
+            vec![
+                vec![0xF0, 0x00, 0x20, 0x32, 0x28, 0x7F, 0xF7],
+                vec![0xC0, 1]
+            ]
+        );
+        // A continuation without a start is dropped.
+        assert!(parser.parse(&packet(0, &[0xF7, 0x01, 0xF7])).is_empty());
+    }
+
+    #[test]
+    fn cancelled_sysex() {
+        let mut parser = Parser::default();
+        assert!(parser.parse(&packet(0, &[0xF0, 0x01, 0xF0])).is_empty());
+        assert!(parser.parse(&packet(0, &[0xF7, 0x02, 0xF4])).is_empty());
+        assert!(parser.parse(&packet(0, &[0xF7, 0x03, 0xF7])).is_empty());
+    }
+
+    #[test]
+    fn not_midi() {
+        let mut bad_version = packet(0, &[0x90, 60, 100]);
+        bad_version[0] = 0x40;
+        let mut bad_type = packet(0, &[0x90, 60, 100]);
+        bad_type[1] = 0x60;
+        for p in [bad_version, bad_type] {
+            assert!(Parser::default().parse(&p).is_empty());
+        }
+    }
+
+    #[test]
+    fn truncated_packets() {
+        let packets = [
+            packet(0, &[0xB0, 7, 100, 0x81, 0x80, 0x00, 0xC0, 5]),
+            packet(0x20, &[0x83, 0x00, 0x90, 60, 100, 0x00, 61, 100]),
+            packet(0, &[0xF0, 0x00, 0x20, 0x32, 0xF7]),
+            packet(0, &[0xF2, 1, 2, 0x00, 0xF3, 1, 0x00, 0xF1, 1]),
+        ];
+        for p in &packets {
+            for len in 0..p.len() {
+                // The header says there's more than is left.
+                assert!(Parser::default().parse(&p[..len]).is_empty());
+                // The header is corrected, but a command is cut off.
+                let mut short = p[..len].to_vec();
+                if len > 12 {
+                    short[12] = (short[12] & 0xF0) | (len - 13) as u8;
+                }
+                Parser::default().parse(&short);
+            }
+        }
+        // Only the whole commands of a cut-off list are returned.
+        let cut = packet(0, &[0xB0, 7, 100, 0x00, 0xC0]);
+        assert_eq!(Parser::default().parse(&cut), vec![vec![0xB0, 7, 100]]);
+        let mut extended = packet(0, &[0xF8]);
+        extended[0] |= 0x1F;
+        assert!(Parser::default().parse(&extended).is_empty());
+    }
+
+    #[test]
+    fn garbage() {
+        // A fixed sequence of pseudo-random bytes, so failures repeat.
+        let mut seed = 0x2545_F491_u32;
+        let mut next = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as u8
+        };
+        let mut parser = Parser::default();
+        for i in 0..2000 {
+            let len = i % 64;
+            let mut commands: Vec<u8> = (0..len).map(|_| next()).collect();
+            parser.parse(&packet(0, &commands));
+            parser.parse(&packet(0x20, &commands));
+            commands.splice(0..0, [0x80, PAYLOAD_TYPE]);
+            parser.parse(&commands);
+            parser.parse_commands(&commands, i % 2 == 0);
+        }
+    }
+
+    #[test]
+    fn session_packets() {
+        let bye = session_packet(BYE, 0x0102_0304, 0x0506_0708);
+        assert_eq!(bye.len(), 16);
+        assert_eq!(parse_session_packet(&bye), Some((BYE, 0x0102_0304)));
+        let mut invitation = session_packet(INVITATION, 7, 8);
+        invitation.extend_from_slice(b"bcr2kosc\0");
+        assert_eq!(parse_session_packet(&invitation), Some((INVITATION, 7)));
+        assert_eq!(parse_session_packet(&bye[..15]), None);
+        assert_eq!(parse_session_packet(&[0u8; 16]), None);
+        assert_eq!(parse_session_packet(&[]), None);
+    }
+}
@@ -0,0 +1,65 @@
+//! Sharing of a single MIDI port connection among several users.
+//!
+//! Some platforms allow only one connection to a MIDI port. `SharedMidiInput`
+//! distributes messages received from one input stream to any number of
+//! subscribers, and `MidiSink` can be cloned to give each user its own handle
+//! on one output connection. Messages are sent whole, so a long SysEx message
+//! is never interleaved with others.
+
+use std::sync::{Arc, Mutex};
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::{Stream, StreamExt};
+use tracing::debug;
+
+use super::{copy_message, MidiMessage};
+
+/// Chooses the messages a subscriber receives.
+pub type MidiFilter = fn(&MidiMessage) -> bool;
+
+/// The filter and channel of each subscriber to a `SharedMidiInput`.
+type Subscribers = Arc<Mutex<Vec<(MidiFilter, UnboundedSender<MidiMessage>)>>>;
+
+/// Distributes messages from one MIDI input stream to subscribers. Clones
+/// share the same set of subscribers.
+#[derive(Clone, Default)]
+pub struct SharedMidiInput {
+    subscribers: Subscribers,
+}
+
+impl SharedMidiInput {
+    /// Returns a stream of the messages accepted by `filter`, from among those
+    /// received after this call. The subscription ends when the stream is
+    /// dropped.
+    pub fn subscribe(&self, filter: MidiFilter) -> UnboundedReceiver<MidiMessage> {
+        let (tx, rx) = mpsc::unbounded();
+        self.subscribers.lock().unwrap().push((filter, tx));
+        rx
+    }
+
+    /// Reads messages from `src` and passes them to subscribers until `src`
+    /// is exhausted. Subscribers whose streams have been dropped are removed.
+    pub async fn distribute<S>(&self, src: S)
+    where
+        S: Stream<Item = MidiMessage>,
+    {
+        futures::pin_mut!(src);
+        while let Some(msg) = src.next().await {
+            self.subscribers.lock().unwrap().retain(|(filter, tx)| {
+                !filter(&msg) || tx.unbounded_send(copy_message(&msg)).is_ok()
+            });
+        }
+        debug!("midi-io shared input source exhausted.");
+        self.subscribers.lock().unwrap().clear();
+    }
+}
+
+/// A `MidiFilter` that accepts every message.
+pub fn all_messages(_: &MidiMessage) -> bool {
+    true
+}
+
+/// A `MidiFilter` that accepts only SysEx messages.
+pub fn sysex_messages(msg: &MidiMessage) -> bool {
+    matches!(msg, MidiMessage::SysEx(_))
+}
@@ -6,7 +6,14 @@
 //! 
 //! The `io` sub-module, which is re-exported here, contains functions for
 //! requesting and receiving specific types of data from a B-Control when given
-//! a `Stream` and `Sink` of `MidiMessage` objects. See `midi-io`.
+//! a `Stream` and `Sink` of `MidiMessage` objects. See `midi-io`. The
+//! `client` sub-module, also re-exported, wraps them in `BControlClient`,
+//! which adds backup and restore of a whole device, and progress reporting.
+//! The `blink` sub-module, also re-exported, makes a device show which one it
+//! is.
+//!
+//! `BControlModel` belongs to the `bcl` crate, which describes devices by
+//! model too, and is re-exported here.
 //!
 //! This is based on the amazing reverse engineering work by Mark van den
 //! Berg, published on https://mountainutilities.eu/. It follows patterns
@@ -15,18 +22,25 @@
 
 // TODO: Review overhead introduced by using MidiMessage. Consider bypassing.
 
-use std::{error::Error, fmt::Display};
+use std::{borrow::Cow, error::Error, fmt::Display, str::FromStr};
 
 use midi_control::{message::SysExType, sysex::ManufacturerId, MidiMessage, SysExEvent};
 
+mod blink;
+mod client;
 mod io;
+pub use blink::*;
+pub use client::*;
 pub use io::*;
 
+pub use bcl::BControlModel;
+
 /// Behringer's MIDI manufacturer ID.
 pub const BEHRINGER: ManufacturerId = ManufacturerId::ExtId(0x20u8, 0x32u8);
 
 /// B-Control mode system exclusive data. All system exclusive message data
 /// to or from the BC devices have this structure.
+#[derive(Debug, PartialEq, Eq)]
 pub struct BControlSysEx {
     pub device: DeviceID,
     pub model: BControlModel,
@@ -36,7 +50,7 @@ pub struct BControlSysEx {
 /// BControl device number. Each controller can be set to answer queries
 /// addressed to a specific device number, 0 through 15. In the controller's LCD
 /// display or in UIs, the numbers are usually shown as 1 through 16.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DeviceID {
     /// BC device number, zero through 15.
     Device(u8),
@@ -53,6 +67,74 @@ impl DeviceID {
     }
 }
 
+/// Makes the messages commonly sent to B-Controls, addressed to the device
+/// number it's configured with. By default, messages are addressed to any
+/// device. They're addressed to any model, since the device number is enough
+/// to pick a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BControlMessages {
+    pub device: DeviceID,
+}
+
+impl Default for BControlMessages {
+    fn default() -> Self {
+        BControlMessages {
+            device: DeviceID::Any,
+        }
+    }
+}
+
+impl BControlMessages {
+    /// Messages to device number `device`, zero through 15.
+    pub fn device(device: u8) -> Self {
+        BControlMessages {
+            device: DeviceID::Device(device),
+        }
+    }
+
+    /// Makes the system exclusive data of a command.
+    pub fn sysex(&self, command: BControlCommand) -> BControlSysEx {
+        BControlSysEx {
+            device: self.device,
+            model: BControlModel::Any,
+            command,
+        }
+    }
+
+    /// Makes the message for a command.
+    pub fn message(&self, command: BControlCommand) -> MidiMessage {
+        MidiMessage::from(&self.sysex(command))
+    }
+
+    /// Asks the device to identify itself.
+    pub fn request_identity(&self) -> MidiMessage {
+        self.message(BControlCommand::RequestIdentity)
+    }
+
+    /// Requests the BCL of a preset, or of all presets and the global setup.
+    pub fn request_preset(&self, preset: PresetIndex) -> MidiMessage {
+        self.message(BControlCommand::RequestData(preset))
+    }
+
+    /// Requests the BCL of the global setup.
+    pub fn request_global_setup(&self) -> MidiMessage {
+        self.message(BControlCommand::RequestGlobalSetup)
+    }
+
+    /// Selects stored preset `index`, zero through 31.
+    pub fn select_preset(&self, index: u8) -> MidiMessage {
+        self.message(BControlCommand::SelectPreset { index })
+    }
+
+    /// Sends a line of BCL, numbered `msg_index` within its block.
+    pub fn bcl_line(&self, msg_index: u16, text: &str) -> MidiMessage {
+        self.message(BControlCommand::SendBclMessage {
+            msg_index,
+            text: text.into(),
+        })
+    }
+}
+
 type ParseError = Box<dyn Error>;
 fn error<T>(s: &str) -> Result<T, ParseError> {
     Err(ParseError::from(s))
@@ -78,7 +160,7 @@ impl BControlSysEx {
         v.push(midi_control::consts::EOX);
     }
     pub fn from_midi(m: &[u8]) -> Result<(Self, usize), ParseError> {
-        if m.len() == 0 {
+        if m.is_empty() {
             return error("no sysex data");
         }
         // Elide EOX byte if present. Some MIDI parser packages do this already,
@@ -103,24 +185,26 @@ impl BControlSysEx {
                 0x01 => (BControlCommand::RequestIdentity, 0),
                 0x02 => (
                     BControlCommand::SendIdentity {
-                        id_string: string_from_midi(&m[3..])?,
+                        id_string: string_from_midi(&m[3..]),
                     },
                     m.len() - 3,
                 ),
                 0x20 => (
                     BControlCommand::SendBclMessage {
                         msg_index: u14_from_midi_msb_lsb(&m[3..])?,
-                        text: string_from_midi(&m[5..])?,
+                        text: string_from_midi(&m[5..]),
                     },
                     m.len() - 3,
                 ),
                 0x21 => {
                     if m.len() > 6 {
                         // Supposedly the preset name will be exactly 26 chars.
+                        // The preset is where a BCL reply's message index
+                        // is, as its low byte, so a zero comes first.
                         (
                             BControlCommand::SendPresetName {
-                                preset: PresetIndex::from_midi(&m[3..])?,
-                                name: string_from_midi(&m[4..])?,
+                                preset: PresetIndex::from_midi(&m[4..])?,
+                                name: string_from_midi(&m[5..]),
                             },
                             m.len() - 3,
                         )
@@ -134,7 +218,12 @@ impl BControlSysEx {
                         )
                     }
                 }
-                0x22 => (BControlCommand::SelectPreset { index: m[3] }, 1),
+                0x22 => (
+                    BControlCommand::SelectPreset {
+                        index: u8_from_midi(&m[3..])?,
+                    },
+                    1,
+                ),
                 0x34 => (
                     BControlCommand::SendFirmware {
                         data: m[3..].to_vec(),
@@ -160,8 +249,20 @@ impl BControlSysEx {
                     1,
                 ),
                 0x43 => (BControlCommand::RequestSnapshot, 0),
-                0x78 => (BControlCommand::SendText, 0),
-                cmd => return error(&format!("invalid B-Control command {cmd:x}")),
+                0x78 => (
+                    BControlCommand::SendText {
+                        text: string_from_midi(&m[3..]),
+                    },
+                    m.len() - 3,
+                ),
+                0x80..=0xff => return error(&format!("invalid B-Control command {:x}", m[2])),
+                opcode => (
+                    BControlCommand::Other {
+                        opcode,
+                        data: m[3..].to_vec(),
+                    },
+                    m.len() - 3,
+                ),
             };
             let result = BControlSysEx {
                 device,
@@ -183,11 +284,10 @@ impl From<&BControlSysEx> for Vec<u8> {
 impl From<&BControlSysEx> for MidiMessage {
     fn from(bc: &BControlSysEx) -> Self {
         let bdata = bc.to_midi();
-        let req = MidiMessage::SysEx(SysExEvent {
+        MidiMessage::SysEx(SysExEvent {
             r#type: SysExType::Manufacturer(BEHRINGER),
             data: bdata,
-        });
-        req
+        })
     }
 }
 
@@ -201,7 +301,7 @@ impl TryFrom<&MidiMessage> for BControlSysEx {
         }) = value
         {
             // Recognized as a Behringer sysex. Parse the sysex payload.
-            match BControlSysEx::from_midi(&data) {
+            match BControlSysEx::from_midi(data) {
                 Ok(bcse) => Ok(bcse.0),
                 Err(e) => Err(e),
             }
@@ -222,66 +322,75 @@ impl TryFrom<&[u8]> for BControlSysEx {
     }
 }
 
-/// Specifies the B-Control device models addressed by a B-Control request, or
-/// responding to one.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum BControlModel {
-    BCR,
-    BCF,
-    Any,
-}
-
-impl Display for BControlModel {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            BControlModel::BCR => "BCR",
-            BControlModel::BCF => "BCF",
-            BControlModel::Any => "?",
-        }
-        .fmt(f)
-    }
-}
-
 /// B-Control command data appears in system exclusive messages sent to or
 /// recieved from  B-Control devices.
+///
+/// Not every command is modeled. Presets are stored and recalled with the BCL
+/// lines `$store` and `$recall`, which the device acknowledges like any other
+/// line, with a `BclReply`; there's no separate acknowledgement to model.
+/// What the device sends in learn mode isn't modeled, and like any other
+/// command without a variant, it's kept as `Other`.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum BControlCommand {
-    ///
+    /// A line of BCL, numbered so that the device's reply can be matched to
+    /// it.
     SendBclMessage {
         msg_index: u16,
-        text: String,
+        text: BclString,
     },
 
+    /// Asks for the device's identity, answered with `SendIdentity`.
     RequestIdentity,
+    /// Selects a preset, by its index from zero.
     SelectPreset {
         index: u8,
     },
+    /// A block of a firmware update, answered with `FirmwareReply`.
     SendFirmware {
         data: Vec<u8>,
     },
+    /// Asks for the BCL of a preset, or of all of them.
     RequestData(PresetIndex),
+    /// Asks for the BCL of the global setup.
     RequestGlobalSetup,
+    /// Asks for a preset's name, answered with `SendPresetName`.
     RequestPresetName {
         preset: PresetIndex,
     },
+    /// Asks for the values of the current preset's controls.
     RequestSnapshot,
 
+    /// The device's model and firmware, answering `RequestIdentity`.
     SendIdentity {
-        id_string: String,
+        id_string: BclString,
     },
+    /// The device's answer to a line of BCL, with an error code that's zero
+    /// if the line was accepted. See `BclError`.
     BclReply {
         msg_index: u16,
         error_code: u8,
     },
+    /// A preset's name, answering `RequestPresetName`.
     SendPresetName {
         preset: PresetIndex,
-        name: String,
+        name: BclString,
     },
+    /// The device's answer to a block of firmware.
     FirmwareReply {
         mem_addr: u16,
         err: u8,
     },
-    SendText,
+    /// Text the device sends outside of BCL, kept as received.
+    SendText {
+        text: BclString,
+    },
+
+    /// A command that isn't otherwise modeled, kept as its opcode and data so
+    /// that it can be passed on, logged or saved unchanged.
+    Other {
+        opcode: u8,
+        data: Vec<u8>,
+    },
 }
 impl BControlCommand {
     pub fn extend_midi(&self, v: &mut Vec<u8>) {
@@ -290,7 +399,7 @@ impl BControlCommand {
                 v.push(0x01);
             }
             BControlCommand::SendBclMessage { msg_index, text } => {
-                v.push(0x02);
+                v.push(0x20);
                 u14_to_midi_msb_lsb(*msg_index, v);
                 extend_midi_from_string(text, v);
             }
@@ -339,13 +448,190 @@ impl BControlCommand {
                 u14_to_midi_msb_lsb(*mem_addr, v);
                 v.push(*err);
             }
-            BControlCommand::SendText => {
+            BControlCommand::SendText { text } => {
                 v.push(0x78);
+                extend_midi_from_string(text, v);
+            }
+            BControlCommand::Other { opcode, data } => {
+                v.push(*opcode);
+                v.extend_from_slice(data);
             }
         }
     }
 }
 
+/// Text sent to or by a B-Control. The devices allow any characters in
+/// preset names, and don't insist on UTF-8 elsewhere either, so the bytes are
+/// kept as they were, to be passed on or saved unchanged. Text that isn't
+/// UTF-8 is shown with replacement characters.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BclString(Vec<u8>);
+
+impl BclString {
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        BclString(bytes.to_vec())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Returns the text, with any bytes that aren't UTF-8 replaced.
+    pub fn to_string_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.0)
+    }
+}
+
+impl Display for BclString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_string_lossy().fmt(f)
+    }
+}
+
+impl From<&str> for BclString {
+    fn from(s: &str) -> Self {
+        BclString(s.as_bytes().to_vec())
+    }
+}
+
+impl From<String> for BclString {
+    fn from(s: String) -> Self {
+        BclString(s.into_bytes())
+    }
+}
+
+/// The errors a B-Control reports in a BCL reply, as documented by Mountain
+/// Utilities. A device acknowledges every BCL line this way, including the
+/// `$store` and `$recall` lines that store and recall presets, so a failed
+/// store shows up here.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BclError {
+    UnknownToken,
+    DataWithoutToken,
+    ArgumentMissing,
+    WrongDevice,
+    WrongRevision,
+    MissingRevision,
+    InternalError,
+    ModeMissing,
+    BadItemIndex,
+    NotANumber,
+    ValueOutOfRange,
+    InvalidArgument,
+    InvalidCommand,
+    WrongNumberOfArguments,
+    TooMuchData,
+    AlreadyDefined,
+    PresetMissing,
+    PresetTooComplex,
+    WrongPreset,
+    PresetTooNew,
+    PresetCheck,
+    SequenceError,
+    WrongContext,
+    /// A code that isn't documented.
+    Other(u8),
+}
+
+impl BclError {
+    /// Returns the error for the code in a BCL reply, or `None` if the code
+    /// is zero, meaning the line was accepted.
+    pub fn from_code(code: u8) -> Option<BclError> {
+        use BclError::*;
+        Some(match code {
+            0 => return None,
+            1 => UnknownToken,
+            2 => DataWithoutToken,
+            3 => ArgumentMissing,
+            4 => WrongDevice,
+            5 => WrongRevision,
+            6 => MissingRevision,
+            7 => InternalError,
+            8 => ModeMissing,
+            9 => BadItemIndex,
+            10 => NotANumber,
+            11 => ValueOutOfRange,
+            12 => InvalidArgument,
+            13 => InvalidCommand,
+            14 => WrongNumberOfArguments,
+            15 => TooMuchData,
+            16 => AlreadyDefined,
+            17 => PresetMissing,
+            18 => PresetTooComplex,
+            19 => WrongPreset,
+            20 => PresetTooNew,
+            21 => PresetCheck,
+            22 => SequenceError,
+            23 => WrongContext,
+            n => Other(n),
+        })
+    }
+
+    /// Returns the error's code in a BCL reply.
+    pub fn code(self) -> u8 {
+        use BclError::*;
+        match self {
+            UnknownToken => 1,
+            DataWithoutToken => 2,
+            ArgumentMissing => 3,
+            WrongDevice => 4,
+            WrongRevision => 5,
+            MissingRevision => 6,
+            InternalError => 7,
+            ModeMissing => 8,
+            BadItemIndex => 9,
+            NotANumber => 10,
+            ValueOutOfRange => 11,
+            InvalidArgument => 12,
+            InvalidCommand => 13,
+            WrongNumberOfArguments => 14,
+            TooMuchData => 15,
+            AlreadyDefined => 16,
+            PresetMissing => 17,
+            PresetTooComplex => 18,
+            WrongPreset => 19,
+            PresetTooNew => 20,
+            PresetCheck => 21,
+            SequenceError => 22,
+            WrongContext => 23,
+            Other(n) => n,
+        }
+    }
+}
+
+impl Display for BclError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use BclError::*;
+        let s = match self {
+            UnknownToken => "unknown token",
+            DataWithoutToken => "data without token",
+            ArgumentMissing => "argument missing",
+            WrongDevice => "wrong device",
+            WrongRevision => "wrong revision",
+            MissingRevision => "missing revision",
+            InternalError => "internal error",
+            ModeMissing => "mode missing",
+            BadItemIndex => "bad item index",
+            NotANumber => "not a number",
+            ValueOutOfRange => "value out of range",
+            InvalidArgument => "invalid argument",
+            InvalidCommand => "invalid command",
+            WrongNumberOfArguments => "wrong number of arguments",
+            TooMuchData => "too much data",
+            AlreadyDefined => "already defined",
+            PresetMissing => "preset missing",
+            PresetTooComplex => "preset too complex",
+            WrongPreset => "wrong preset",
+            PresetTooNew => "preset too new",
+            PresetCheck => "preset check failed",
+            SequenceError => "sequence error",
+            WrongContext => "wrong context",
+            Other(n) => return write!(f, "error code {n}"),
+        };
+        write!(f, "{s} (error code {})", self.code())
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum PresetIndex {
     Preset(u8),
@@ -370,6 +656,22 @@ impl PresetIndex {
         }
     }
 }
+impl FromStr for PresetIndex {
+    type Err = ParseError;
+
+    /// Parses a preset number from 1 to 32, "temp", or "all".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(PresetIndex::All),
+            "temp" => Ok(PresetIndex::Temporary),
+            _ => match s.parse::<u8>() {
+                Ok(n) if (1u8..=32u8).contains(&n) => Ok(PresetIndex::Preset(n - 1)),
+                _ => error("invalid preset index"),
+            },
+        }
+    }
+}
+
 impl Display for PresetIndex {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -406,15 +708,12 @@ fn u14_from_midi_msb_lsb(m: &[u8]) -> Result<u16, ParseError> {
     }
 }
 
-fn string_from_midi(m: &[u8]) -> Result<String, ParseError> {
-    match String::from_utf8(m.to_vec()) {
-        Ok(s) => Ok(s),
-        Err(e) => error(&format!("invalid string in MIDI, {e:?}")),
-    }
+fn string_from_midi(m: &[u8]) -> BclString {
+    BclString::from_bytes(m)
 }
 
-fn extend_midi_from_string(text: &str, v: &mut Vec<u8>) {
-    text.as_bytes().iter().for_each(|c| v.push(*c));
+fn extend_midi_from_string(text: &BclString, v: &mut Vec<u8>) {
+    v.extend_from_slice(text.as_bytes());
 }
 
 fn u14_to_midi_msb_lsb(n: u16, m: &mut Vec<u8>) {
@@ -425,3 +724,286 @@ fn u14_to_midi_msb_lsb(n: u16, m: &mut Vec<u8>) {
         m.push((n & 0x007f) as u8);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Opcodes with their own `BControlCommand` variants.
+    const MODELED: [u8; 12] = [
+        0x01, 0x02, 0x20, 0x21, 0x22, 0x34, 0x35, 0x40, 0x41, 0x42, 0x43, 0x78,
+    ];
+
+    /// A small deterministic generator, so that failures are reproducible.
+    struct Gen(u64);
+
+    impl Gen {
+        fn next(&mut self) -> u64 {
+            // xorshift64
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+
+        fn data_byte(&mut self) -> u8 {
+            self.below(0x80) as u8
+        }
+
+        fn text(&mut self, min: u64, max: u64) -> BclString {
+            let len = min + self.below(max - min + 1);
+            let text: String = (0..len)
+                .map(|_| (0x20 + self.below(0x5f)) as u8 as char)
+                .collect();
+            text.into()
+        }
+
+        fn preset(&mut self) -> PresetIndex {
+            match self.below(3) {
+                0 => PresetIndex::All,
+                1 => PresetIndex::Temporary,
+                _ => PresetIndex::Preset(self.below(32) as u8),
+            }
+        }
+
+        fn command(&mut self) -> BControlCommand {
+            use BControlCommand::*;
+            match self.below(15) {
+                0 => SendBclMessage {
+                    msg_index: self.below(16384) as u16,
+                    text: self.text(0, 40),
+                },
+                1 => RequestIdentity,
+                2 => SelectPreset {
+                    index: self.below(32) as u8,
+                },
+                3 => SendFirmware {
+                    data: (0..self.below(64)).map(|_| self.data_byte()).collect(),
+                },
+                4 => RequestData(self.preset()),
+                5 => RequestGlobalSetup,
+                6 => RequestPresetName {
+                    preset: self.preset(),
+                },
+                7 => RequestSnapshot,
+                8 => SendIdentity {
+                    id_string: self.text(0, 20),
+                },
+                9 => BclReply {
+                    msg_index: self.below(16384) as u16,
+                    error_code: self.data_byte(),
+                },
+                // Preset names are told apart from BCL replies by length.
+                10 => SendPresetName {
+                    preset: self.preset(),
+                    name: self.text(3, 26),
+                },
+                11 => FirmwareReply {
+                    mem_addr: self.below(16384) as u16,
+                    err: self.data_byte(),
+                },
+                12 => SendText {
+                    text: self.text(0, 40),
+                },
+                _ => Other {
+                    opcode: loop {
+                        let opcode = self.data_byte();
+                        if !MODELED.contains(&opcode) {
+                            break opcode;
+                        }
+                    },
+                    data: (0..self.below(16)).map(|_| self.data_byte()).collect(),
+                },
+            }
+        }
+
+        fn sysex(&mut self) -> BControlSysEx {
+            BControlSysEx {
+                device: match self.below(17) {
+                    16 => DeviceID::Any,
+                    d => DeviceID::Device(d as u8),
+                },
+                model: match self.below(3) {
+                    0 => BControlModel::BCR,
+                    1 => BControlModel::BCF,
+                    _ => BControlModel::Any,
+                },
+                command: self.command(),
+            }
+        }
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let mut gen = Gen(0x2545_f491_4f6c_dd1d);
+        for _ in 0..10_000 {
+            let x = gen.sysex();
+            let bytes = x.to_midi();
+            let (y, _) = BControlSysEx::from_midi(&bytes)
+                .unwrap_or_else(|e| panic!("{x:?} encoded as {bytes:02x?}: {e}"));
+            assert_eq!(x, y, "encoded as {bytes:02x?}");
+        }
+    }
+
+    #[test]
+    fn midi_message_round_trip() {
+        let mut gen = Gen(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..1_000 {
+            let x = gen.sysex();
+            let msg = MidiMessage::from(&x);
+            let y = BControlSysEx::try_from(&msg).unwrap();
+            assert_eq!(x, y);
+        }
+    }
+
+    /// Messages as they appear after the manufacturer ID, in the formats
+    /// documented by Mountain Utilities. These are written from the
+    /// documentation, not captured from a device; captures of each kind of
+    /// message a device sends would be better.
+    #[test]
+    fn reference_vectors() {
+        let vectors: [(&[u8], BControlSysEx); 6] = [
+            (
+                &[0x7f, 0x7f, 0x01, 0xf7],
+                BControlSysEx {
+                    device: DeviceID::Any,
+                    model: BControlModel::Any,
+                    command: BControlCommand::RequestIdentity,
+                },
+            ),
+            (
+                b"\x00\x15\x02BCR2000 1.10\xf7",
+                BControlSysEx {
+                    device: DeviceID::Device(0),
+                    model: BControlModel::BCR,
+                    command: BControlCommand::SendIdentity {
+                        id_string: "BCR2000 1.10".into(),
+                    },
+                },
+            ),
+            (
+                b"\x00\x15\x20\x00\x00$rev R1\xf7",
+                BControlSysEx {
+                    device: DeviceID::Device(0),
+                    model: BControlModel::BCR,
+                    command: BControlCommand::SendBclMessage {
+                        msg_index: 0,
+                        text: "$rev R1".into(),
+                    },
+                },
+            ),
+            (
+                &[0x00, 0x15, 0x21, 0x01, 0x02, 0x00, 0xf7],
+                BControlSysEx {
+                    device: DeviceID::Device(0),
+                    model: BControlModel::BCR,
+                    command: BControlCommand::BclReply {
+                        msg_index: 130,
+                        error_code: 0,
+                    },
+                },
+            ),
+            (
+                &[0x03, 0x14, 0x40, 0x7e, 0xf7],
+                BControlSysEx {
+                    device: DeviceID::Device(3),
+                    model: BControlModel::BCF,
+                    command: BControlCommand::RequestData(PresetIndex::All),
+                },
+            ),
+            (
+                &[0x00, 0x7f, 0x22, 0x1f, 0xf7],
+                BControlSysEx {
+                    device: DeviceID::Device(0),
+                    model: BControlModel::Any,
+                    command: BControlCommand::SelectPreset { index: 31 },
+                },
+            ),
+        ];
+        for (bytes, expected) in vectors {
+            assert_eq!(BControlSysEx::try_from(bytes).unwrap(), expected);
+            assert_eq!(expected.to_midi(), bytes);
+        }
+    }
+
+    #[test]
+    fn preset_names_need_not_be_utf8() {
+        let bytes: &[u8] = b"\x00\x15\x21\x00\x03Caf\xe9 \xff\xfe Bass\xf7";
+        let sysex = BControlSysEx::try_from(bytes).unwrap();
+        match &sysex.command {
+            BControlCommand::SendPresetName { preset, name } => {
+                assert_eq!(*preset, PresetIndex::Preset(3));
+                assert_eq!(name.as_bytes(), b"Caf\xe9 \xff\xfe Bass");
+                assert_eq!(name.to_string(), "Caf\u{fffd} \u{fffd}\u{fffd} Bass");
+            }
+            c => panic!("parsed as {c:?}"),
+        }
+        assert_eq!(sysex.to_midi(), bytes);
+    }
+
+    #[test]
+    fn malformed_messages_are_rejected() {
+        let bad: [&[u8]; 6] = [
+            &[],
+            &[0x00, 0x15],
+            &[0x10, 0x15, 0x01],
+            &[0x00, 0x16, 0x01],
+            &[0x00, 0x15, 0x81],
+            &[0x00, 0x15, 0x22],
+        ];
+        for bytes in bad {
+            assert!(BControlSysEx::try_from(bytes).is_err(), "{bytes:02x?}");
+        }
+    }
+
+    #[test]
+    fn messages_are_addressed_as_configured() {
+        let any = BControlMessages::default().sysex(BControlCommand::RequestIdentity);
+        assert_eq!(any.to_midi(), [0x7f, 0x7f, 0x01, 0xf7]);
+        let all = BControlCommand::RequestData(PresetIndex::All);
+        let device = BControlMessages::device(2).sysex(all);
+        assert_eq!(device.to_midi(), [0x02, 0x7f, 0x40, 0x7e, 0xf7]);
+    }
+
+    #[test]
+    fn bcl_errors() {
+        assert_eq!(BclError::from_code(0), None);
+        for code in 1..=127 {
+            let e = BclError::from_code(code).unwrap();
+            assert_eq!(e.code(), code);
+            assert_eq!(matches!(e, BclError::Other(_)), code > 23, "{code}");
+        }
+        assert_eq!(
+            BclError::ValueOutOfRange.to_string(),
+            "value out of range (error code 11)"
+        );
+        let bytes: &[u8] = &[0x00, 0x15, 0x21, 0x00, 0x05, 0x0b, 0xf7];
+        match BControlSysEx::try_from(bytes).unwrap().command {
+            BControlCommand::BclReply {
+                msg_index: 5,
+                error_code,
+            } => assert_eq!(
+                BclError::from_code(error_code),
+                Some(BclError::ValueOutOfRange)
+            ),
+            c => panic!("parsed as {c:?}"),
+        }
+    }
+
+    #[test]
+    fn text_is_kept() {
+        let bytes: &[u8] = b"\x00\x15\x78BCR2000\xf7";
+        let sysex = BControlSysEx::try_from(bytes).unwrap();
+        assert_eq!(
+            sysex.command,
+            BControlCommand::SendText {
+                text: "BCR2000".into()
+            }
+        );
+        assert_eq!(sysex.to_midi(), bytes);
+    }
+}
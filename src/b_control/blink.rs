@@ -0,0 +1,152 @@
+//! Making a B-Control show which one it is.
+//!
+//! Several BCR2000s look the same, and their device numbers don't show. To
+//! tell them apart, `blink` has one of them flash the LED rings of its first
+//! eight push encoders, and the LEDs of its two rows of buttons, and on a
+//! BCF2000 move its faders, a few times. The controls are taken over for
+//! this by a preset sent to the device's edit buffer, and the edit buffer is
+//! restored afterwards, with any changes not yet stored.
+//!
+//! The preset puts the controls on MIDI channel 16, which the flashing is
+//! sent on. Turning a control during the flashing sends MIDI on that channel
+//! too.
+
+use std::error::Error;
+use std::time::Duration;
+
+use futures::{Sink, SinkExt, Stream};
+use midi_control::{Channel, ControlEvent, MidiMessage};
+use tokio_util::sync::CancellationToken;
+
+use super::io::{get_preset_bcl, send_bcl, Cancelled};
+use super::{BControlModel, PresetIndex};
+
+type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+/// The number of times the LEDs flash.
+const FLASHES: usize = 6;
+
+/// How long the LEDs stay on, and then off, in each flash.
+const FLASH_TIME: Duration = Duration::from_millis(300);
+
+/// The MIDI channel of the controls that flash.
+const CHANNEL: Channel = Channel::Ch16;
+
+/// The control numbers of the encoders, buttons and faders that flash.
+const ENCODERS: u8 = 0;
+const BUTTONS: u8 = 8;
+const FADERS: u8 = 24;
+
+/// Makes a device flash its LEDs, and on a BCF2000 move its faders, so that
+/// it can be told apart from others. The device's edit buffer is replaced
+/// while it flashes, and restored afterwards. `pacing` paces the BCL sent,
+/// as for `send_bcl`.
+///
+/// Waits indefinitely for the device's answers; callers should apply a
+/// timeout.
+pub async fn blink<I, O>(
+    device: u8,
+    model: BControlModel,
+    pacing: Duration,
+    midi_in: &mut I,
+    midi_out: &mut O,
+    cancel: &CancellationToken,
+) -> Result<()>
+where
+    I: Stream<Item = MidiMessage> + Unpin,
+    O: Sink<MidiMessage> + Unpin,
+    O::Error: std::error::Error + Send + Sync + 'static,
+{
+    let saved = get_preset_bcl(device, PresetIndex::Temporary, midi_in, midi_out, cancel).await?;
+    send_bcl(
+        device,
+        &blink_preset(device, model),
+        pacing,
+        midi_in,
+        midi_out,
+        cancel,
+    )
+    .await?;
+    let flashed = flash(model, midi_out, cancel).await;
+    // The edit buffer is restored even if the flashing failed, unless it
+    // was cancelled.
+    send_bcl(device, &saved, pacing, midi_in, midi_out, cancel).await?;
+    flashed
+}
+
+async fn flash<O>(model: BControlModel, midi_out: &mut O, cancel: &CancellationToken) -> Result<()>
+where
+    O: Sink<MidiMessage> + Unpin,
+    O::Error: std::error::Error + Send + Sync + 'static,
+{
+    for _ in 0..FLASHES {
+        for value in [127, 0] {
+            if cancel.is_cancelled() {
+                return Err(Cancelled.into());
+            }
+            for control in controls(model) {
+                let event = ControlEvent { control, value };
+                midi_out
+                    .feed(MidiMessage::ControlChange(CHANNEL, event))
+                    .await?;
+            }
+            midi_out.flush().await?;
+            tokio::time::sleep(FLASH_TIME).await;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the control numbers of a model's controls that flash.
+fn controls(model: BControlModel) -> Vec<u8> {
+    let mut controls: Vec<u8> = (ENCODERS..ENCODERS + 8)
+        .chain(BUTTONS..BUTTONS + 16)
+        .collect();
+    if model == BControlModel::BCF {
+        controls.extend(FADERS..FADERS + 8);
+    }
+    controls
+}
+
+/// Writes the BCL of the preset that puts the controls that flash on their
+/// control numbers.
+fn blink_preset(device: u8, model: BControlModel) -> Vec<String> {
+    let bcf = model == BControlModel::BCF;
+    let ch = CHANNEL as u8 + 1;
+    let name = format!("IDENTIFY DEVICE {}", device + 1);
+    let mut bcl = vec![
+        format!("$rev {}1", if bcf { "F" } else { "R" }),
+        "$preset".to_string(),
+        format!("  .name '{name:<24}'"),
+        "  .snapshot off".to_string(),
+        "  .request off".to_string(),
+        "  .egroups 1".to_string(),
+        "  .fkeys off".to_string(),
+        "  .lock on".to_string(),
+        "  .init".to_string(),
+    ];
+    for i in 0..8 {
+        bcl.push(format!("$encoder {}", 1 + i));
+        let cc = ENCODERS + i;
+        bcl.push(format!("  .easypar CC {ch} {cc} 0 127 absolute"));
+        bcl.push("  .showvalue off".to_string());
+        bcl.push("  .mode bar".to_string());
+    }
+    for i in 0..16 {
+        bcl.push(format!("$button {}", 33 + i));
+        let cc = BUTTONS + i;
+        bcl.push(format!("  .easypar CC {ch} {cc} 127 0 toggleoff"));
+        bcl.push("  .showvalue off".to_string());
+    }
+    if bcf {
+        for i in 0..8 {
+            bcl.push(format!("$fader {}", 1 + i));
+            let cc = FADERS + i;
+            bcl.push(format!("  .easypar CC {ch} {cc} 0 127 absolute"));
+            bcl.push("  .showvalue off".to_string());
+            bcl.push("  .motor on".to_string());
+        }
+    }
+    bcl.push("$end".to_string());
+    bcl
+}
@@ -0,0 +1,307 @@
+#![allow(dead_code)]
+//! A client for managing a B-Control, with progress reporting.
+//!
+//! `BControlClient` owns the `Stream` and `Sink` of MIDI connected to a
+//! device, and offers the operations of the `io` module as methods, along
+//! with backup and restore of the whole device. Transfers of BCL can take a
+//! minute or more, so a progress callback can be set to hear about each block
+//! and line as it goes, for instance to drive a progress bar. Operations can
+//! be cancelled through the client's `CancellationToken`.
+
+use std::error::Error;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use bcl::DeviceTiming;
+use futures::{Sink, Stream, StreamExt};
+use midi_io::MidiMessage;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use super::io::{
+    get_identity, get_preset_bcl, get_presets_pipelined, request_bcl, send_bcl, PresetDump,
+};
+use super::{BControlCommand, BControlModel, BControlSysEx, PresetIndex};
+
+type LocalError = Box<dyn Error + Send + Sync + 'static>;
+type Result<T> = std::result::Result<T, LocalError>;
+
+/// The number of preset memories in a B-Control.
+const PRESETS: u8 = 32;
+
+/// An event in the progress of an operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Progress {
+    /// A backup or restore has started on a block of BCL.
+    Block {
+        /// The zero-based number of the preset, or `None` for the global
+        /// setup.
+        preset: Option<u8>,
+        /// The number of blocks already done.
+        done: usize,
+        /// The number of blocks in the operation.
+        total: usize,
+    },
+    /// A line of BCL was received from the device.
+    Received {
+        /// The number of lines received so far in the block.
+        lines: usize,
+    },
+    /// The device acknowledged a line of BCL sent to it.
+    Sent {
+        /// The number of lines acknowledged so far in the block.
+        lines: usize,
+        /// The number of lines in the block.
+        total: usize,
+    },
+}
+
+/// A callback that receives progress events.
+pub type ProgressFn = Box<dyn FnMut(Progress) + Send>;
+
+/// Passes progress events to the callback, if any, counting the lines of
+/// each block.
+#[derive(Default)]
+struct Reporter {
+    callback: Option<ProgressFn>,
+    lines: usize,
+}
+
+impl Reporter {
+    fn block(&mut self, preset: Option<u8>, done: usize, total: usize) {
+        self.lines = 0;
+        self.emit(Progress::Block {
+            preset,
+            done,
+            total,
+        });
+    }
+
+    fn emit(&mut self, event: Progress) {
+        if let Some(f) = self.callback.as_mut() {
+            f(event);
+        }
+    }
+}
+
+/// Manages a B-Control through a `Stream` and `Sink` of MIDI messages.
+pub struct BControlClient<I, O> {
+    device: u8,
+    midi_in: I,
+    midi_out: O,
+    /// Shared by the operation in progress and the input it's watching.
+    progress: Mutex<Reporter>,
+    /// The time left between lines of BCL sent to the device. Zero until the
+    /// device's timing is known.
+    pacing: Duration,
+    cancel: CancellationToken,
+}
+
+impl<I, O> BControlClient<I, O>
+where
+    I: Stream<Item = MidiMessage> + Unpin,
+    O: Sink<MidiMessage> + Unpin,
+    O::Error: std::error::Error + Send + Sync + 'static,
+{
+    /// Creates a client for the device with the given zero-based number.
+    pub fn new(device: u8, midi_in: I, midi_out: O) -> Self {
+        BControlClient {
+            device,
+            midi_in,
+            midi_out,
+            progress: Mutex::default(),
+            pacing: Duration::ZERO,
+            cancel: CancellationToken::new(),
+        }
+    }
+
+    /// Sets a callback to receive progress events.
+    pub fn on_progress(self, f: impl FnMut(Progress) + Send + 'static) -> Self {
+        self.progress.lock().unwrap().callback = Some(Box::new(f));
+        self
+    }
+
+    /// Sets the token that cancels the client's operations. Once it's
+    /// cancelled, every operation fails with `Cancelled`.
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Sets the time left between lines of BCL sent to the device. It's
+    /// replaced by the device's own timing once that's known, from a global
+    /// setup fetched or restored.
+    pub fn with_pacing(mut self, pacing: Duration) -> Self {
+        self.pacing = pacing;
+        self
+    }
+
+    /// Returns the MIDI stream and sink.
+    pub fn into_inner(self) -> (I, O) {
+        (self.midi_in, self.midi_out)
+    }
+
+    /// Requests the identity of the device, returning its model and identity
+    /// string.
+    ///
+    /// Waits indefinitely for an answer; callers should apply a timeout.
+    pub async fn identity(&mut self) -> Result<(BControlModel, String)> {
+        get_identity(
+            self.device,
+            &mut self.midi_in,
+            &mut self.midi_out,
+            &self.cancel,
+        )
+        .await
+    }
+
+    /// Requests the BCL of the device's global setup. The device's timing
+    /// in it is adopted for later uploads.
+    pub async fn get_global(&mut self) -> Result<Vec<String>> {
+        let mut midi_in = watch(&mut self.midi_in, &self.progress, self.device, 0);
+        let lines = request_bcl(
+            self.device,
+            BControlCommand::RequestGlobalSetup,
+            &mut midi_in,
+            &mut self.midi_out,
+            &self.cancel,
+        )
+        .await?
+        .ok_or_else(|| LocalError::from("The device sent no global setup."))?;
+        drop(midi_in);
+        self.adopt_timing(&lines);
+        Ok(lines)
+    }
+
+    /// Paces later uploads by the timing in a global setup, if it gives any.
+    fn adopt_timing<S: AsRef<str>>(&mut self, lines: &[S]) {
+        let timing = DeviceTiming::from_bcl(lines);
+        if timing.is_known() {
+            self.pacing = timing.pacing();
+            info!(
+                "Device {} has {timing}; BCL is sent {} ms apart.",
+                self.device + 1,
+                self.pacing.as_millis()
+            );
+        }
+    }
+
+    /// Requests the BCL of a preset. See `get_preset_bcl`.
+    pub async fn get_preset(&mut self, preset: PresetIndex) -> Result<Vec<String>> {
+        let mut midi_in = watch(&mut self.midi_in, &self.progress, self.device, 0);
+        get_preset_bcl(
+            self.device,
+            preset,
+            &mut midi_in,
+            &mut self.midi_out,
+            &self.cancel,
+        )
+        .await
+    }
+
+    /// Sends BCL to the device. See `send_bcl`.
+    pub async fn send_bcl<S: AsRef<str>>(&mut self, lines: &[S]) -> Result<()> {
+        let total = lines.len();
+        let mut midi_in = watch(&mut self.midi_in, &self.progress, self.device, total);
+        send_bcl(
+            self.device,
+            lines,
+            self.pacing,
+            &mut midi_in,
+            &mut self.midi_out,
+            &self.cancel,
+        )
+        .await
+    }
+
+    /// Backs up the device: its global setup, followed by each stored
+    /// preset. Presets that the device doesn't send, because they're empty,
+    /// are left out. See `get_presets_pipelined`.
+    pub async fn backup(&mut self) -> Result<Vec<PresetDump>> {
+        let total = 1 + PRESETS as usize;
+        self.progress.lock().unwrap().block(None, 0, total);
+        let lines = self.get_global().await?;
+        let mut dumps = vec![PresetDump {
+            preset: None,
+            lines,
+        }];
+        let presets: Vec<u8> = (0..PRESETS).collect();
+        let progress = &self.progress;
+        let mut midi_in = watch(&mut self.midi_in, progress, self.device, 0);
+        let on_preset = |n: u8| {
+            progress
+                .lock()
+                .unwrap()
+                .block(Some(n), 1 + n as usize, total)
+        };
+        dumps.extend(
+            get_presets_pipelined(
+                self.device,
+                &presets,
+                &mut midi_in,
+                &mut self.midi_out,
+                &self.cancel,
+                on_preset,
+            )
+            .await?,
+        );
+        Ok(dumps)
+    }
+
+    /// Restores blocks from a backup, in the order given. Each preset is
+    /// stored in the memory it came from. The timing in a restored global
+    /// setup paces the blocks after it.
+    pub async fn restore(&mut self, dumps: &[PresetDump]) -> Result<()> {
+        for (done, dump) in dumps.iter().enumerate() {
+            let total = dumps.len();
+            self.progress
+                .lock()
+                .unwrap()
+                .block(dump.preset, done, total);
+            self.send_bcl(&dump.restore_lines()).await?;
+            if dump.preset.is_none() {
+                self.adopt_timing(&dump.lines);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Wraps MIDI input from a device, reporting the BCL lines it receives, and
+/// the acknowledgements of lines sent, out of `total`. Lines are counted from
+/// zero, and again from zero at the start of each block.
+fn watch<'a, I>(
+    midi_in: &'a mut I,
+    progress: &'a Mutex<Reporter>,
+    device: u8,
+    total: usize,
+) -> impl Stream<Item = MidiMessage> + Unpin + 'a
+where
+    I: Stream<Item = MidiMessage> + Unpin,
+{
+    progress.lock().unwrap().lines = 0;
+    midi_in.inspect(move |msg| {
+        let received = match BControlSysEx::try_from(msg) {
+            Ok(BControlSysEx {
+                device: d,
+                command: BControlCommand::SendBclMessage { .. },
+                ..
+            }) if d.match_device(device) => true,
+            Ok(BControlSysEx {
+                device: d,
+                command: BControlCommand::BclReply { .. },
+                ..
+            }) if d.match_device(device) => false,
+            _ => return,
+        };
+        let mut reporter = progress.lock().unwrap();
+        reporter.lines += 1;
+        let lines = reporter.lines;
+        let event = if received {
+            Progress::Received { lines }
+        } else {
+            Progress::Sent { lines, total }
+        };
+        reporter.emit(event);
+    })
+}
@@ -1,71 +1,433 @@
 //! Easy I/O of B-Control messages via `MidiMessage` `Stream` and `Sink`.
+//!
+//! Each operation takes a `CancellationToken`. When the token is cancelled,
+//! the operation stops at the next message boundary and returns `Cancelled`:
+//! no message is taken from the input stream without being handled, and
+//! messages being sent are sent in full. A cancelled transfer of BCL leaves
+//! the device part way through the block, which it discards when the next
+//! block starts with `$rev`.
 
 use std::error::Error;
+use std::fmt::Display;
+use std::time::Duration;
 
-use futures::{Sink, SinkExt, Stream, StreamExt};
-use log::info;
-use midi_control::MidiMessage;
+use tokio::time::Instant;
 
-use super::{BControlCommand, BControlModel, BControlSysEx, DeviceID, PresetIndex};
+use futures::{pin_mut, select_biased, stream, FutureExt, Sink, SinkExt, Stream, StreamExt};
+use midi_io::MidiMessage;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use super::{
+    BControlCommand, BControlMessages, BControlModel, BControlSysEx, BclError, DeviceID,
+    PresetIndex,
+};
 
 type LocalError = Box<dyn Error + Send + Sync + 'static>;
 type Result<T> = std::result::Result<T, LocalError>;
 
-pub async fn recv_bcl<I>(device: u8, midi_in: &mut I) -> Result<Vec<String>>
+/// How long the device can be silent before a dump of all presets is taken
+/// to be complete. The device doesn't mark the end of the dump.
+const DUMP_IDLE: Duration = Duration::from_secs(2);
+
+/// The least time between pipelined requests, so that the device's input
+/// isn't flooded while it's sending.
+const REQUEST_GAP: Duration = Duration::from_millis(20);
+
+/// The error returned by an operation that was cancelled.
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Cancelled.")
+    }
+}
+
+impl Error for Cancelled {}
+
+/// Waits for the next message, unless cancelled first. A message that
+/// arrives along with the cancellation stays in the stream.
+async fn next_message<I>(midi_in: &mut I, cancel: &CancellationToken) -> Result<Option<MidiMessage>>
+where
+    I: Stream<Item = MidiMessage> + Unpin,
+{
+    select_biased! {
+        _ = cancel.cancelled().fuse() => Err(LocalError::from(Cancelled)),
+        msg = midi_in.next().fuse() => Ok(msg),
+    }
+}
+
+/// Returns `Cancelled` if the token has been cancelled.
+fn check_cancelled(cancel: &CancellationToken) -> Result<()> {
+    if cancel.is_cancelled() {
+        return Err(LocalError::from(Cancelled));
+    }
+    Ok(())
+}
+
+/// Picks out the BCL lines sent by a device, checking that none are missing.
+struct BclReceiver {
+    device: u8,
+    next_line_index: u16,
+    /// True until the first line of a block, which can restart the count.
+    at_block_start: bool,
+}
+
+impl BclReceiver {
+    fn new(device: u8) -> Self {
+        BclReceiver {
+            device,
+            next_line_index: 0,
+            at_block_start: true,
+        }
+    }
+
+    /// Returns the text of the message if it's a line of BCL from the device.
+    /// Text that isn't UTF-8, as preset names can be, is decoded lossily
+    /// rather than failing the dump.
+    fn accept(&mut self, msg: &MidiMessage) -> Result<Option<String>> {
+        let (msg_index, text) = match BControlSysEx::try_from(msg) {
+            Ok(BControlSysEx {
+                device,
+                command: BControlCommand::SendBclMessage { msg_index, text },
+                ..
+            }) if device.match_device(self.device) => (msg_index, text.to_string()),
+            _ => return Ok(None),
+        };
+        if msg_index != self.next_line_index && !(self.at_block_start && msg_index == 0) {
+            return Err(LocalError::from(
+                "Missing or out-of-order BCL lines received.",
+            ));
+        }
+        self.next_line_index = msg_index + 1;
+        if self.next_line_index >= 16384 {
+            info!("BCL line index wrapped.");
+            self.next_line_index = 0;
+        }
+        self.at_block_start = text == "$end";
+        Ok(Some(text))
+    }
+}
+
+pub async fn recv_bcl<I>(
+    device: u8,
+    midi_in: &mut I,
+    cancel: &CancellationToken,
+) -> Result<Vec<String>>
 where
     I: Stream<Item = MidiMessage> + Unpin,
 {
+    let mut receiver = BclReceiver::new(device);
     let mut v = Vec::<String>::new();
-    let mut next_line_index = 0;
-    while let Some(msg) = midi_in.next().await {
-        if let Some(sysex) = BControlSysEx::try_from(&msg).ok() {
-            if sysex.device.match_device(device) {
-                if let BControlCommand::SendBclMessage { msg_index, text } = sysex.command {
-                    if msg_index == next_line_index {
-                        next_line_index += 1;
-                        if next_line_index >= 16384 {
-                            info!("BCL line index wrapped.");
-                            next_line_index = 0;
-                        }
-                        let done = text == "$end";
-                        v.push(text);
-                        if done {
-                            break;
-                        }
-                    } else {
-                        return Err(LocalError::from(
-                            "Missing or out-of-order BCL lines received.",
-                        ));
-                    }
-                }
+    while let Some(msg) = next_message(midi_in, cancel).await? {
+        if let Some(text) = receiver.accept(&msg)? {
+            let done = text == "$end";
+            v.push(text);
+            if done {
+                break;
             }
         }
     }
     Ok(v)
 }
 
+/// Receives a block of BCL, through `$end`. Returns `None` if the device
+/// sends nothing for `idle` before the block starts.
+async fn recv_bcl_block<I>(
+    receiver: &mut BclReceiver,
+    midi_in: &mut I,
+    idle: Duration,
+    cancel: &CancellationToken,
+) -> Result<Option<Vec<String>>>
+where
+    I: Stream<Item = MidiMessage> + Unpin,
+{
+    let mut v = Vec::<String>::new();
+    loop {
+        let msg = if v.is_empty() {
+            match tokio::time::timeout(idle, next_message(midi_in, cancel)).await {
+                Ok(msg) => msg?,
+                Err(_) => return Ok(None),
+            }
+        } else {
+            next_message(midi_in, cancel).await?
+        };
+        let msg = match msg {
+            Some(msg) => msg,
+            None if v.is_empty() => return Ok(None),
+            None => return Err(LocalError::from("MIDI input ended in the middle of BCL.")),
+        };
+        if let Some(text) = receiver.accept(&msg)? {
+            let done = text == "$end";
+            v.push(text);
+            if done {
+                return Ok(Some(v));
+            }
+        }
+    }
+}
+
+/// One block of BCL from a dump of all presets.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PresetDump {
+    /// The zero-based number of the preset, or `None` for the global setup.
+    pub preset: Option<u8>,
+    /// The BCL, through `$end`.
+    pub lines: Vec<String>,
+}
+
+impl PresetDump {
+    /// Returns a BCL comment naming the block, to separate it from others.
+    pub fn header(&self) -> String {
+        match self.preset {
+            Some(n) => format!("; $preset {}", n + 1),
+            None => "; $global".to_string(),
+        }
+    }
+
+    /// Returns the BCL that restores the block. A preset is given a `$store`
+    /// line, if it has none, so that it's stored where it came from.
+    pub fn restore_lines(&self) -> Vec<String> {
+        let mut lines = self.lines.clone();
+        if let Some(n) = self.preset {
+            if Self::stored_preset(&lines).is_none() {
+                let end = lines
+                    .iter()
+                    .rposition(|l| l.trim() == "$end")
+                    .unwrap_or(lines.len());
+                lines.insert(end, format!("$store {}", n + 1));
+            }
+        }
+        lines
+    }
+
+    /// Finds the preset number in a `$store` line, which the device includes
+    /// so that the dump can be sent back to restore its presets.
+    fn stored_preset(lines: &[String]) -> Option<u8> {
+        lines.iter().find_map(|l| {
+            let n = l.trim().strip_prefix("$store")?.trim().parse::<u8>().ok()?;
+            (1..=32).contains(&n).then(|| n - 1)
+        })
+    }
+}
+
+/// Requests all presets from a device, yielding the global setup followed by
+/// each stored preset as the device sends it. Empty presets aren't sent.
+///
+/// The stream ends when the device has been silent for a while after the
+/// last preset, or after an error.
+pub fn get_all_presets<'a, I, O>(
+    device: u8,
+    midi_in: &'a mut I,
+    midi_out: &'a mut O,
+    cancel: &'a CancellationToken,
+) -> impl Stream<Item = Result<PresetDump>> + 'a
+where
+    I: Stream<Item = MidiMessage> + Unpin,
+    O: Sink<MidiMessage> + Unpin,
+    O::Error: std::error::Error + Send + Sync + 'static,
+{
+    // The state includes the number of blocks received, or None after an
+    // error. The request is sent before the first block is received.
+    let state = (midi_in, midi_out, BclReceiver::new(device), Some(0u8));
+    stream::unfold(
+        state,
+        move |(midi_in, midi_out, mut receiver, count)| async move {
+            let count = count?;
+            if let Err(e) = check_cancelled(cancel) {
+                return Some((Err(e), (midi_in, midi_out, receiver, None)));
+            }
+            if count == 0 {
+                let request = BControlMessages::device(device).request_preset(PresetIndex::All);
+                if let Err(e) = midi_out.send(request).await {
+                    let e = LocalError::from(e);
+                    return Some((Err(e), (midi_in, midi_out, receiver, None)));
+                }
+            }
+            match recv_bcl_block(&mut receiver, midi_in, DUMP_IDLE, cancel).await {
+                Ok(Some(lines)) => {
+                    let preset = match count {
+                        0 => None,
+                        n => Some(PresetDump::stored_preset(&lines).unwrap_or(n - 1)),
+                    };
+                    let dump = PresetDump { preset, lines };
+                    Some((Ok(dump), (midi_in, midi_out, receiver, Some(count + 1))))
+                }
+                Ok(None) if count == 0 => {
+                    let e = LocalError::from("The device sent no BCL.");
+                    Some((Err(e), (midi_in, midi_out, receiver, None)))
+                }
+                Ok(None) => None,
+                Err(e) => Some((Err(e), (midi_in, midi_out, receiver, None))),
+            }
+        },
+    )
+}
+
+/// Sends a request, `RequestData` or `RequestGlobalSetup`, and receives the
+/// block of BCL sent in reply. Returns `None` if the device sends nothing for
+/// a couple of seconds, which is taken to mean that there's nothing to send,
+/// as for an empty preset.
+pub async fn request_bcl<I, O>(
+    device: u8,
+    command: BControlCommand,
+    midi_in: &mut I,
+    midi_out: &mut O,
+    cancel: &CancellationToken,
+) -> Result<Option<Vec<String>>>
+where
+    I: Stream<Item = MidiMessage> + Unpin,
+    O: Sink<MidiMessage> + Unpin,
+    O::Error: std::error::Error + Send + Sync + 'static,
+{
+    check_cancelled(cancel)?;
+    midi_out
+        .send(BControlMessages::device(device).message(command))
+        .await
+        .map_err(LocalError::from)?;
+    recv_bcl_block(&mut BclReceiver::new(device), midi_in, DUMP_IDLE, cancel).await
+}
+
+/// Requests presets one after another, returning those the device sends.
+/// Presets it doesn't send, because they're empty, are left out.
+/// `on_preset` is called with each preset's number as its dump is awaited.
+///
+/// Requests are pipelined: once the dump of a preset starts to arrive, the
+/// next preset is requested, so that the device can go straight on to it.
+/// Only one request is ever ahead of the dump arriving, so a preset that
+/// sends nothing is recognized by the device going quiet, as in
+/// `request_bcl`. Line indexes must run on from one dump to the next, except
+/// that each dump can start again from zero; anything else, such as
+/// interleaved dumps, is an error.
+pub async fn get_presets_pipelined<I, O, F>(
+    device: u8,
+    presets: &[u8],
+    midi_in: &mut I,
+    midi_out: &mut O,
+    cancel: &CancellationToken,
+    mut on_preset: F,
+) -> Result<Vec<PresetDump>>
+where
+    I: Stream<Item = MidiMessage> + Unpin,
+    O: Sink<MidiMessage> + Unpin,
+    O::Error: std::error::Error + Send + Sync + 'static,
+    F: FnMut(u8),
+{
+    let mut receiver = BclReceiver::new(device);
+    let mut dumps = vec![];
+    let mut upcoming = presets.iter().copied();
+    // The preset whose dump is awaited, and the one requested after it.
+    let mut current = match upcoming.next() {
+        Some(n) => n,
+        None => return Ok(dumps),
+    };
+    let mut requested: Option<u8> = None;
+    on_preset(current);
+    send_command(device, preset_request(current), midi_out).await?;
+    let mut last_request = Instant::now();
+    let mut lines = vec![];
+    loop {
+        let msg = if lines.is_empty() {
+            match tokio::time::timeout(DUMP_IDLE, next_message(midi_in, cancel)).await {
+                Ok(msg) => msg?,
+                Err(_) => {
+                    // The current preset is empty. Nothing was requested
+                    // after it, since its dump never started.
+                    current = match upcoming.next() {
+                        Some(n) => n,
+                        None => return Ok(dumps),
+                    };
+                    on_preset(current);
+                    send_command(device, preset_request(current), midi_out).await?;
+                    last_request = Instant::now();
+                    continue;
+                }
+            }
+        } else {
+            next_message(midi_in, cancel).await?
+        };
+        let msg = match msg {
+            Some(msg) => msg,
+            None if lines.is_empty() => return Ok(dumps),
+            None => return Err(LocalError::from("MIDI input ended in the middle of BCL.")),
+        };
+        let text = match receiver.accept(&msg)? {
+            Some(text) => text,
+            None => continue,
+        };
+        if lines.is_empty() && requested.is_none() {
+            if let Some(n) = upcoming.next() {
+                tokio::time::sleep_until(last_request + REQUEST_GAP).await;
+                send_command(device, preset_request(n), midi_out).await?;
+                last_request = Instant::now();
+                requested = Some(n);
+            }
+        }
+        let done = text == "$end";
+        lines.push(text);
+        if done {
+            dumps.push(PresetDump {
+                preset: Some(current),
+                lines: std::mem::take(&mut lines),
+            });
+            current = match requested.take() {
+                Some(n) => n,
+                None => return Ok(dumps),
+            };
+            on_preset(current);
+        }
+    }
+}
+
+fn preset_request(preset: u8) -> BControlCommand {
+    BControlCommand::RequestData(PresetIndex::Preset(preset))
+}
+
+/// Sends a command to a device.
+async fn send_command<O>(device: u8, command: BControlCommand, midi_out: &mut O) -> Result<()>
+where
+    O: Sink<MidiMessage> + Unpin,
+    O::Error: std::error::Error + Send + Sync + 'static,
+{
+    midi_out
+        .send(BControlMessages::device(device).message(command))
+        .await
+        .map_err(LocalError::from)
+}
+
+/// Requests a preset from a device. For `PresetIndex::All`, the BCL of each
+/// block from `get_all_presets` follows its header.
 pub async fn get_preset_bcl<I, O>(
     device: u8,
     preset: PresetIndex,
     midi_in: &mut I,
     midi_out: &mut O,
+    cancel: &CancellationToken,
 ) -> Result<Vec<String>>
 where
     I: Stream<Item = MidiMessage> + Unpin,
     O: Sink<MidiMessage> + Unpin,
     O::Error: std::error::Error + Send + Sync + 'static,
 {
-    let lines = recv_bcl(device, midi_in);
+    if preset == PresetIndex::All {
+        let dumps = get_all_presets(device, midi_in, midi_out, cancel);
+        pin_mut!(dumps);
+        let mut lines = vec![];
+        while let Some(dump) = dumps.next().await {
+            let dump = dump?;
+            lines.push(dump.header());
+            lines.extend(dump.lines);
+        }
+        return Ok(lines);
+    }
+    check_cancelled(cancel)?;
+    let lines = recv_bcl(device, midi_in, cancel);
 
-    let bdata = BControlSysEx {
-        device: DeviceID::Device(device),
-        model: BControlModel::Any,
-        command: BControlCommand::RequestData(preset),
-    };
     midi_out
-        .send(MidiMessage::from(&bdata))
+        .send(BControlMessages::device(device).request_preset(preset))
         .await
-        .map_err(|e| LocalError::from(e))?;
+        .map_err(LocalError::from)?;
     lines.await
 }
 
@@ -73,22 +435,217 @@ pub async fn get_global_bcl<I, O>(
     device: u8,
     midi_in: &mut I,
     midi_out: &mut O,
+    cancel: &CancellationToken,
 ) -> Result<Vec<String>>
 where
     I: Stream<Item = MidiMessage> + Unpin,
     O: Sink<MidiMessage> + Unpin,
     O::Error: std::error::Error + Send + Sync + 'static,
 {
-    let lines = recv_bcl(device, midi_in);
+    check_cancelled(cancel)?;
+    let lines = recv_bcl(device, midi_in, cancel);
 
-    let bdata = BControlSysEx {
-        device: DeviceID::Device(device),
-        model: BControlModel::Any,
-        command: BControlCommand::RequestGlobalSetup,
-    };
     midi_out
-        .send(MidiMessage::from(&bdata))
+        .send(BControlMessages::device(device).request_global_setup())
         .await
-        .map_err(|e| LocalError::from(e))?;
+        .map_err(LocalError::from)?;
     lines.await
 }
+
+/// Requests the identity of a B-Control, returning its model and identity
+/// string.
+///
+/// Waits indefinitely for an answer; callers should apply a timeout.
+pub async fn get_identity<I, O>(
+    device: u8,
+    midi_in: &mut I,
+    midi_out: &mut O,
+    cancel: &CancellationToken,
+) -> Result<(BControlModel, String)>
+where
+    I: Stream<Item = MidiMessage> + Unpin,
+    O: Sink<MidiMessage> + Unpin,
+    O::Error: std::error::Error + Send + Sync + 'static,
+{
+    check_cancelled(cancel)?;
+    midi_out
+        .send(BControlMessages::device(device).request_identity())
+        .await
+        .map_err(LocalError::from)?;
+    while let Some(msg) = next_message(midi_in, cancel).await? {
+        if let Ok(BControlSysEx {
+            device: DeviceID::Device(d),
+            model,
+            command: BControlCommand::SendIdentity { id_string },
+        }) = BControlSysEx::try_from(&msg)
+        {
+            if d == device {
+                return Ok((model, id_string.to_string()));
+            }
+        }
+    }
+    Err(LocalError::from(
+        "MIDI input ended before identity was received.",
+    ))
+}
+
+/// Sends BCL text to a B-Control.
+///
+/// Lines are sent one at a time. The device acknowledges each line, and the
+/// next line is not sent until the acknowledgement arrives, and `pacing` has
+/// passed since the last line was sent; see `bcl::DeviceTiming`. An error is
+/// returned if the device rejects a line. If cancelled while waiting for an
+/// acknowledgement, the acknowledgement may still arrive, and is left in the
+/// stream.
+pub async fn send_bcl<I, O, S>(
+    device: u8,
+    lines: &[S],
+    pacing: Duration,
+    midi_in: &mut I,
+    midi_out: &mut O,
+    cancel: &CancellationToken,
+) -> Result<()>
+where
+    I: Stream<Item = MidiMessage> + Unpin,
+    O: Sink<MidiMessage> + Unpin,
+    O::Error: std::error::Error + Send + Sync + 'static,
+    S: AsRef<str>,
+{
+    let messages = BControlMessages::device(device);
+    let mut next = Instant::now();
+    for (i, line) in lines.iter().enumerate() {
+        tokio::time::sleep_until(next).await;
+        check_cancelled(cancel)?;
+        let msg_index = (i % 16384) as u16;
+        next = Instant::now() + pacing;
+        midi_out
+            .send(messages.bcl_line(msg_index, line.as_ref()))
+            .await
+            .map_err(LocalError::from)?;
+        recv_bcl_reply(device, msg_index, midi_in, cancel).await?;
+    }
+    Ok(())
+}
+
+async fn recv_bcl_reply<I>(
+    device: u8,
+    msg_index: u16,
+    midi_in: &mut I,
+    cancel: &CancellationToken,
+) -> Result<()>
+where
+    I: Stream<Item = MidiMessage> + Unpin,
+{
+    while let Some(msg) = next_message(midi_in, cancel).await? {
+        if let Ok(sysex) = BControlSysEx::try_from(&msg) {
+            if sysex.device.match_device(device) {
+                if let BControlCommand::BclReply {
+                    msg_index: index,
+                    error_code,
+                } = sysex.command
+                {
+                    if index != msg_index {
+                        return Err(LocalError::from(format!(
+                            "BCL reply for line {index} received, expected line {msg_index}."
+                        )));
+                    }
+                    if let Some(e) = BclError::from_code(error_code) {
+                        return Err(LocalError::from(format!(
+                            "B-Control rejected BCL line {msg_index}: {e}."
+                        )));
+                    }
+                    return Ok(());
+                }
+            }
+        }
+    }
+    Err(LocalError::from(
+        "MIDI input ended before BCL reply was received.",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The messages of a device's dump of BCL blocks, each numbered from
+    /// zero.
+    fn dump(blocks: &[&[&str]]) -> Vec<MidiMessage> {
+        let device = BControlMessages::device(0);
+        blocks
+            .iter()
+            .flat_map(|block| {
+                block
+                    .iter()
+                    .enumerate()
+                    .map(move |(i, line)| device.bcl_line(i as u16, line))
+            })
+            .collect()
+    }
+
+    const GLOBAL: &[&str] = &["$rev R1", "$global", "  .deviceid 1", "$end"];
+    const STORED: &[&str] = &["$rev R1", "$preset", "  .name 'Mixer'", "$store 3", "$end"];
+    const UNSTORED: &[&str] = &["$rev R1", "$preset", "  .name 'Synth'", "$end"];
+
+    #[tokio::test]
+    async fn all_presets_stream_one_block_at_a_time() {
+        let mut midi_in = stream::iter(dump(&[GLOBAL, STORED, UNSTORED]));
+        let mut midi_out: Vec<MidiMessage> = vec![];
+        let cancel = CancellationToken::new();
+        let dumps: Vec<PresetDump> = get_all_presets(0, &mut midi_in, &mut midi_out, &cancel)
+            .map(|d| d.unwrap())
+            .collect()
+            .await;
+        assert_eq!(
+            midi_out,
+            [BControlMessages::device(0).request_preset(PresetIndex::All)]
+        );
+        let headers: Vec<String> = dumps.iter().map(PresetDump::header).collect();
+        assert_eq!(headers, ["; $global", "; $preset 3", "; $preset 2"]);
+        assert_eq!(dumps[0].lines, GLOBAL);
+        assert_eq!(dumps[1].restore_lines(), STORED);
+        assert_eq!(
+            dumps[2].restore_lines(),
+            ["$rev R1", "$preset", "  .name 'Synth'", "$store 2", "$end"]
+        );
+    }
+
+    #[tokio::test]
+    async fn all_presets_stop_at_missing_lines() {
+        let mut msgs = dump(&[GLOBAL, STORED]);
+        msgs.remove(5);
+        let mut midi_in = stream::iter(msgs);
+        let mut midi_out: Vec<MidiMessage> = vec![];
+        let cancel = CancellationToken::new();
+        let dumps: Vec<Result<PresetDump>> =
+            get_all_presets(0, &mut midi_in, &mut midi_out, &cancel)
+                .collect()
+                .await;
+        assert_eq!(dumps.len(), 2);
+        assert!(dumps[0].is_ok());
+        let e = dumps[1].as_ref().unwrap_err().to_string();
+        assert_eq!(e, "Missing or out-of-order BCL lines received.");
+    }
+
+    #[tokio::test]
+    async fn all_presets_as_one_listing() {
+        let mut midi_in = stream::iter(dump(&[GLOBAL, STORED]));
+        let mut midi_out: Vec<MidiMessage> = vec![];
+        let cancel = CancellationToken::new();
+        let lines = get_preset_bcl(0, PresetIndex::All, &mut midi_in, &mut midi_out, &cancel)
+            .await
+            .unwrap();
+        assert_eq!(lines.len(), 2 + GLOBAL.len() + STORED.len());
+        assert_eq!(lines[0], "; $global");
+        assert_eq!(lines[1 + GLOBAL.len()], "; $preset 3");
+    }
+
+    #[tokio::test]
+    async fn all_presets_fail_if_the_device_sends_nothing() {
+        let mut midi_in = stream::iter(vec![]);
+        let mut midi_out: Vec<MidiMessage> = vec![];
+        let cancel = CancellationToken::new();
+        let r = get_preset_bcl(0, PresetIndex::All, &mut midi_in, &mut midi_out, &cancel).await;
+        assert_eq!(r.unwrap_err().to_string(), "The device sent no BCL.");
+    }
+}
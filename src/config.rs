@@ -0,0 +1,272 @@
+//! The configuration file.
+//!
+//! MIDI port names differ from one platform to the next, and sometimes from
+//! one boot to the next, and they can be long. The configuration file gives
+//! them short aliases, which can be used wherever a command takes a port
+//! name, including in `serve`:
+//!
+//! ```text
+//! # The B-Control on my desk.
+//! bcr_in = "BCR2000 port 1"
+//! bcr_out = "BCR2000 port 1"
+//! ```
+//!
+//! Each line sets an alias, as a name, `=`, and the port name in double
+//! quotes. Blank lines, and lines starting with `#`, are ignored. A name
+//! that isn't an alias is used as a port name as it is.
+//!
+//! A line that ends with `device` and a device number instead names a
+//! B-Control, which is told apart from others by its port and its number:
+//!
+//! ```text
+//! FOH-BCR = "BCR2000 port 1" device 1
+//! ```
+//!
+//! The port may be given by an alias, and may be either of the device's
+//! ports. The name is shown along with the device's number in logs, in the
+//! output of `find`, and in status notifications.
+//!
+//! A line whose name is `preset` and a preset's number, from 1 through 32,
+//! or its quoted name, names the mappings that `serve` switches to when the
+//! device changes to that preset: a built-in profile, or a mapping file.
+//!
+//! ```text
+//! preset 1 = "reaper"
+//! preset "Lights" = "/home/me/lights.txt"
+//! ```
+//!
+//! Lines whose name is `surface` add devices to the one that `serve` serves,
+//! so that they're served as one surface, each with the MIDI channels it
+//! uses, as numbers and ranges separated by commas. The port, which may be
+//! given by an alias, is used for both the device's input and output:
+//!
+//! ```text
+//! surface = "BCF2000 port 1" channels 1
+//! surface = "BCR2000 port 1" channels 2-3,16
+//! ```
+//!
+//! The file is read from `config.toml` in the program's directory under the
+//! user's configuration directory, unless another is given.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+use crate::PGM;
+
+type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+/// The settings of the configuration file.
+#[derive(Debug, Default)]
+pub struct Config {
+    /// Port names, by alias.
+    aliases: HashMap<String, String>,
+    /// Device names, by port name and device number, from 1 through 16.
+    names: HashMap<(String, u8), String>,
+    /// The mappings to switch to with each preset, in the order given.
+    presets: Vec<(PresetKey, String)>,
+    /// The ports of further devices of the surface, with their channels,
+    /// numbered from 1.
+    surface: Vec<(String, Vec<u8>)>,
+}
+
+/// A preset of a B-Control, by number or by name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PresetKey {
+    /// A stored preset, from 1 through 32.
+    Number(u8),
+    /// The name of a preset, without trailing spaces.
+    Name(String),
+}
+
+impl PresetKey {
+    fn parse(s: &str) -> Option<PresetKey> {
+        match s.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+            Some(name) => Some(PresetKey::Name(name.trim_end().to_string())),
+            None => s
+                .parse::<u8>()
+                .ok()
+                .filter(|n| (1..=32).contains(n))
+                .map(PresetKey::Number),
+        }
+    }
+}
+
+impl Display for PresetKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PresetKey::Number(n) => write!(f, "{n}"),
+            PresetKey::Name(name) => write!(f, "\"{name}\""),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the configuration file at `path`, or, if that's `None`, at the
+    /// default path. A missing default file is the same as an empty one.
+    pub fn load(path: Option<&Path>) -> Result<Config> {
+        let (path, required) = match path {
+            Some(p) => (p.to_path_buf(), true),
+            None => match default_config_path() {
+                Some(p) => (p, false),
+                None => return Ok(Config::default()),
+            },
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(text) => Config::parse(&text).map_err(|e| format!("{}: {e}", path.display()).into()),
+            Err(e) if !required && e.kind() == std::io::ErrorKind::NotFound => {
+                Ok(Config::default())
+            }
+            Err(e) => Err(format!("{}: {e}", path.display()).into()),
+        }
+    }
+
+    /// Parses the text of a configuration file.
+    pub fn parse(text: &str) -> Result<Config> {
+        let mut config = Config::default();
+        let mut names = vec![];
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (alias, port) = line
+                .split_once('=')
+                .map(|(a, p)| (a.trim(), p.trim()))
+                .filter(|(a, _)| !a.is_empty())
+                .ok_or_else(|| format!("line {}: expected ALIAS = \"PORT\"", n + 1))?;
+            let (port, device) = port
+                .strip_prefix('"')
+                .and_then(|p| p.split_once('"'))
+                .ok_or_else(|| format!("line {}: the port name must be quoted", n + 1))?;
+            if let Some(preset) = alias.strip_prefix("preset ") {
+                let key = PresetKey::parse(preset.trim())
+                    .filter(|_| device.trim().is_empty())
+                    .ok_or_else(|| {
+                        format!(
+                            "line {}: expected preset 1 through 32, or a quoted name",
+                            n + 1
+                        )
+                    })?;
+                config.presets.push((key, port.to_string()));
+                continue;
+            }
+            if alias == "surface" {
+                let channels = device
+                    .trim()
+                    .strip_prefix("channels")
+                    .and_then(|c| parse_channels(c.trim()))
+                    .ok_or_else(|| {
+                        format!(
+                            "line {}: expected channels 1 through 16, such as 1,3-4",
+                            n + 1
+                        )
+                    })?;
+                config.surface.push((port.to_string(), channels));
+                continue;
+            }
+            match device.trim() {
+                "" => {
+                    config.aliases.insert(alias.to_string(), port.to_string());
+                }
+                device => {
+                    let device = device
+                        .strip_prefix("device")
+                        .and_then(|d| d.trim().parse::<u8>().ok())
+                        .filter(|d| (1..=16).contains(d))
+                        .ok_or_else(|| format!("line {}: expected device 1 through 16", n + 1))?;
+                    names.push(((port.to_string(), device), alias.to_string()));
+                }
+            }
+        }
+        // Names may refer to ports by aliases defined after them.
+        for ((port, device), name) in names {
+            let port = config.port_name(&port).to_string();
+            config.names.insert((port, device), name);
+        }
+        let surface = std::mem::take(&mut config.surface);
+        config.surface = surface
+            .into_iter()
+            .map(|(port, channels)| (config.port_name(&port).to_string(), channels))
+            .collect();
+        Ok(config)
+    }
+
+    /// Returns the port name that a name given by the user stands for.
+    pub fn port_name<'a>(&'a self, name: &'a str) -> &'a str {
+        self.aliases.get(name).map_or(name, String::as_str)
+    }
+
+    /// Returns the aliases of a port name.
+    pub fn aliases_of(&self, port_name: &str) -> Vec<&str> {
+        let mut aliases: Vec<&str> = self
+            .aliases
+            .iter()
+            .filter(|(_, p)| *p == port_name)
+            .map(|(a, _)| a.as_str())
+            .collect();
+        aliases.sort_unstable();
+        aliases
+    }
+
+    /// Returns the mappings to switch to with each preset.
+    pub fn preset_mappings(&self) -> &[(PresetKey, String)] {
+        &self.presets
+    }
+
+    /// Returns the ports of the surface's further devices, with the channels
+    /// each uses.
+    pub fn surface_devices(&self) -> &[(String, Vec<u8>)] {
+        &self.surface
+    }
+
+    /// Returns the name of the device with a number, from 1 through 16, on
+    /// either of two ports, if it has one.
+    pub fn device_name(&self, in_port_name: &str, out_port_name: &str, device: u8) -> Option<&str> {
+        [in_port_name, out_port_name]
+            .into_iter()
+            .find_map(|p| self.names.get(&(p.to_string(), device)))
+            .map(String::as_str)
+    }
+}
+
+/// Parses a list of MIDI channels and ranges of them, such as `1,3-4`.
+fn parse_channels(s: &str) -> Option<Vec<u8>> {
+    let mut channels = vec![];
+    for part in s.split(',') {
+        let (low, high) = part.split_once('-').unwrap_or((part, part));
+        let low = low.trim().parse::<u8>().ok()?;
+        let high = high.trim().parse::<u8>().ok()?;
+        if low < 1 || high > 16 || low > high {
+            return None;
+        }
+        for c in low..=high {
+            if !channels.contains(&c) {
+                channels.push(c);
+            }
+        }
+    }
+    Some(channels)
+}
+
+/// Describes a device by its number, from 1 through 16, and its name, if it
+/// has one.
+pub fn device_label(device: u8, name: Option<&str>) -> String {
+    match name {
+        Some(name) => format!("{name} (device {device})"),
+        None => format!("device {device}"),
+    }
+}
+
+/// Returns the path of the configuration file used when none is specified,
+/// if the user's configuration directory is known.
+pub fn default_config_path() -> Option<PathBuf> {
+    #[cfg(windows)]
+    let dir = std::env::var_os("APPDATA").map(PathBuf::from);
+    #[cfg(not(windows))]
+    let dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| Path::new(&h).join(".config")));
+    dir.map(|d| d.join(PGM).join("config.toml"))
+}
@@ -0,0 +1,156 @@
+//! Process exit codes, so that scripts can tell kinds of failure apart.
+//!
+//! ```text
+//! code  meaning
+//! 0     success
+//! 1     any other failure, including failed verify cases
+//! 2     invalid command line arguments
+//! 3     a MIDI port wasn't found
+//! 4     a device didn't respond
+//! 5     BCL was invalid, or rejected by the device
+//! 6     invalid configuration, such as a mapping file
+//! 130   interrupted by Ctrl-C
+//! ```
+//!
+//! Errors are marked with their kind of failure where they're detected, with
+//! `fail` or `OrFail::or_fail`. MIDI ports that aren't found, and cancelled
+//! device operations, are recognized without being marked.
+
+use std::error::Error;
+use std::fmt::Display;
+
+use midi_io::{ErrorKind, MidiIoError};
+
+use crate::b_control::Cancelled;
+
+type LocalError = Box<dyn Error + Send + Sync + 'static>;
+
+/// A kind of failure, with its exit code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Failure {
+    /// Any failure not listed below.
+    Other = 1,
+    /// Invalid command line arguments. Clap also uses this code.
+    Usage = 2,
+    /// A MIDI port wasn't found.
+    PortNotFound = 3,
+    /// A device didn't respond.
+    NoResponse = 4,
+    /// BCL was invalid, or rejected by the device.
+    Bcl = 5,
+    /// Invalid configuration, such as a mapping file.
+    Config = 6,
+    /// A device operation was cancelled by Ctrl-C. The code is the one
+    /// shells use for a process killed by SIGINT.
+    Cancelled = 130,
+}
+
+/// An error marked with its kind of failure.
+#[derive(Debug)]
+struct Failed {
+    failure: Failure,
+    error: LocalError,
+}
+
+impl Display for Failed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl Error for Failed {}
+
+/// Marks an error with its kind of failure.
+pub fn fail(failure: Failure, error: impl Into<LocalError>) -> LocalError {
+    Box::new(Failed {
+        failure,
+        error: error.into(),
+    })
+}
+
+/// Marks the error in a result with its kind of failure.
+pub trait OrFail<T> {
+    /// Marks the error, if any, with `failure`.
+    fn or_fail(self, failure: Failure) -> Result<T, LocalError>;
+}
+
+impl<T, E: Into<LocalError>> OrFail<T> for Result<T, E> {
+    fn or_fail(self, failure: Failure) -> Result<T, LocalError> {
+        self.map_err(|e| fail(failure, e))
+    }
+}
+
+/// Returns the kind of failure that an error represents.
+pub fn classify(error: &(dyn Error + 'static)) -> Failure {
+    if let Some(failed) = error.downcast_ref::<Failed>() {
+        if failed.error.is::<Cancelled>() {
+            return Failure::Cancelled;
+        }
+        return failed.failure;
+    }
+    if error.is::<Cancelled>() {
+        return Failure::Cancelled;
+    }
+    match error.downcast_ref::<MidiIoError>() {
+        Some(MidiIoError::Regular(ErrorKind::MidiPortNameNotFound)) => Failure::PortNotFound,
+        Some(MidiIoError::Regular(ErrorKind::NoAnswer)) => Failure::NoResponse,
+        _ => Failure::Other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn marked_errors_keep_their_failure() {
+        for failure in [Failure::Usage, Failure::Bcl, Failure::Config] {
+            let r: Result<(), &str> = Err("bad");
+            let e = r.or_fail(failure).unwrap_err();
+            assert_eq!(classify(&*e), failure);
+            assert_eq!(e.to_string(), "bad");
+        }
+    }
+
+    #[test]
+    fn unmarked_errors_are_recognized() {
+        let port = MidiIoError::Regular(ErrorKind::MidiPortNameNotFound);
+        assert_eq!(classify(&port), Failure::PortNotFound);
+        let silent = MidiIoError::Regular(ErrorKind::NoAnswer);
+        assert_eq!(classify(&silent), Failure::NoResponse);
+        assert_eq!(classify(&Cancelled), Failure::Cancelled);
+        assert_eq!(classify(&*LocalError::from("other")), Failure::Other);
+    }
+
+    #[test]
+    fn cancelling_outranks_the_mark() {
+        let e = fail(Failure::NoResponse, Cancelled);
+        assert_eq!(classify(&*e), Failure::Cancelled);
+    }
+
+    #[test]
+    fn codes_are_distinct() {
+        let codes = [
+            Failure::Other,
+            Failure::Usage,
+            Failure::PortNotFound,
+            Failure::NoResponse,
+            Failure::Bcl,
+            Failure::Config,
+            Failure::Cancelled,
+        ]
+        .map(|f| f as u8);
+        assert_eq!(codes, [1, 2, 3, 4, 5, 6, 130]);
+    }
+
+    #[test]
+    fn bad_mappings_are_config_failures() {
+        let missing = Path::new("no-such-mappings.map");
+        let Err(e) = crate::load_mappings(None, Some(missing)) else {
+            panic!("expected an error from a missing mapping file");
+        };
+        assert_eq!(classify(&*e), Failure::Config);
+    }
+}
@@ -4,25 +4,43 @@
 //! A service to translate between MIDI and OSC, specifically targeting
 //! Behringer B-Controllers (the B-Control Rotary and B-Control Faderport).
 //!
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use std::time::Duration;
 use std::{error::Error, net::SocketAddr};
 
-use clap::{Parser, Subcommand};
+use clap::builder::PossibleValuesParser;
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+use futures::channel::mpsc;
+use futures::future::{join, join_all};
 use futures::{pin_mut, select, FutureExt, SinkExt, Stream, StreamExt};
-use log::info;
-use midi_control::MidiMessage;
+use midi_control::Channel;
+use midi_io::{
+    message_from_bytes, message_to_bytes, split_messages, sysex_messages, to_hex, Backend,
+    Direction, MidiMessage, MidiSink, MidiStream, Port, RealTime, SharedMidiInput,
+};
 use simple_error::bail;
 use tokio::signal;
+use tokio_util::sync::CancellationToken;
+use tracing::level_filters::LevelFilter;
+use tracing::{info, warn};
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::EnvFilter;
 
 mod b_control;
-mod bcl;
-mod midi_io;
+mod config;
+mod exit;
 mod osc_service;
 mod translator;
 
 use crate::b_control::*;
-use crate::midi_io::{MidiSink, MidiStream};
+use crate::config::Config;
+use crate::exit::{fail, Failure, OrFail};
 use crate::osc_service::*;
+use crate::translator::testing::read_golden;
+use crate::translator::{Coercion, ServerTranslationSet, TranslationSetBuilder, PROFILES};
 
 #[cfg(winrt)]
 mod winrt;
@@ -32,13 +50,48 @@ use crate::winrt::*;
 /// Program name, used in a variety of log messages.
 pub const PGM: &str = "bcr2kosc";
 
+/// Translate between MIDI and OSC for Behringer B-Control devices.
+///
+/// The exit status tells what kind of failure, if any, occurred: 1 for
+/// failures not listed here, 2 for invalid arguments, 3 if a MIDI port wasn't
+/// found, 4 if a device didn't respond, 5 if BCL was invalid or rejected, and
+/// 6 if a mapping file or other configuration was invalid. Device operations
+/// interrupted by Ctrl-C exit with 130.
+///
+/// The serve command runs a service that translates MIDI from a B-Control
+/// to OSC sent to one or more destinations, such as a DAW or lighting
+/// console, and OSC received from them back to MIDI. Which messages are
+/// translated, and how, is set by a mapping file, a built-in profile, or
+/// both. A running service can be queried and changed with the ctl command.
+///
+/// The other commands work with B-Control devices directly: finding them,
+/// selecting presets, and sending or receiving presets as BCL, or raw MIDI.
 #[derive(Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version)]
 struct Cli {
     /// Logging verbosity. Specify multiple times for more verbosity, e.g. -vvv.
+    /// RUST_LOG, if set, overrides it, e.g. RUST_LOG=bcr2kosc=debug.
     #[arg(short, long, action = clap::ArgAction::Count)]
     verbose: u8,
 
+    /// The configuration file, which defines aliases for MIDI port names,
+    /// and names for devices.
+    /// Aliases can be given wherever a port name is expected.
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// The MIDI system to use: "native", the platform's own, or "jack". JACK
+    /// is only available in builds with the jack feature, which then use it
+    /// for all ports.
+    #[arg(long, global = true, default_value_t = Backend::BUILT)]
+    backend: Backend,
+
+    /// On macOS, use the ports of a CoreMIDI network session, if there is
+    /// one, instead of the ports named, to reach a B-Control attached to
+    /// another Mac over RTP-MIDI.
+    #[arg(long, global = true)]
+    prefer_network: bool,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -53,12 +106,46 @@ enum Commands {
     Listen {
         /// The name of the port to listen to. Use the list command to see ports.
         midi_in: String,
+        /// Also write each SysEx message received to a numbered .syx file in
+        /// this directory, e.g. to capture dumps started from the device.
+        #[arg(long)]
+        sysex_out: Option<PathBuf>,
+        /// Also show system real-time messages, such as timing clock and
+        /// active sensing, which are otherwise ignored.
+        #[arg(long)]
+        realtime: bool,
+    },
+    /// Send MIDI given as hex bytes, like amidi --send-hex.
+    SendHex {
+        /// The name of the MIDI port to send data to.
+        midi_out: String,
+        /// The bytes to send, e.g. "B0 07 7F" or "F0 00 20 32 7F 7F 01 F7".
+        /// Several messages can be given; each starts with a status byte.
+        #[arg(required = true)]
+        hex: Vec<String>,
+    },
+    /// Display received MIDI as hex bytes, one message per line, like amidi
+    /// --dump.
+    RecvHex {
+        /// The name of the port to listen to. Use the list command to see ports.
+        midi_in: String,
+        /// Stop when nothing has been received for this many seconds.
+        #[arg(long)]
+        timeout: Option<u64>,
     },
-    /// Find and list Behringer B-Control devices.
+    /// Find and list Behringer B-Control devices, by number, model, identity
+    /// string, and name from the configuration file, if any.
     Find {
         /// Time delay to listen for a response before giving up, in seconds.
         #[arg(long, default_value_t = 1)]
         delay: u64,
+        /// Keep probing until interrupted, printing devices as they appear,
+        /// with a "+", and disappear, with a "-".
+        #[arg(long)]
+        watch: bool,
+        /// Time between probes with --watch, in seconds.
+        #[arg(long, requires = "watch", default_value_t = 5)]
+        interval: u64,
         /// The name of the MIDI port recieve data from.
         midi_in: String,
         /// The name of the MIDI port to send data to.
@@ -79,6 +166,38 @@ enum Commands {
         #[arg(value_parser=parse_preset_arg)]
         preset: PresetIndex,
     },
+    /// Make a B-Control flash its LEDs, to tell it apart from others.
+    ///
+    /// The LED rings of the first eight push encoders and the LEDs of the
+    /// two rows of buttons flash a few times, and a BCF2000's faders move.
+    /// The device's edit buffer is replaced while it flashes, and restored
+    /// afterwards.
+    Identify {
+        /// The device number of the B-Control, from 1 through 16.
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=16))]
+        device: u8,
+        /// The name of the MIDI port recieve data from.
+        midi_in: String,
+        /// The name of the MIDI port to send data to.
+        midi_out: String,
+    },
+    /// Change the device number of a B-Control.
+    ///
+    /// Sets the device number in the device's global setup, and then checks
+    /// that the device identifies itself under its new number. Each device
+    /// on a chain of B-Controls needs a number of its own.
+    SetDeviceId {
+        /// The current device number of the B-Control, from 1 through 16.
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=16))]
+        device: u8,
+        /// The name of the MIDI port recieve data from.
+        midi_in: String,
+        /// The name of the MIDI port to send data to.
+        midi_out: String,
+        /// The new device number, from 1 through 16.
+        #[arg(value_parser = clap::value_parser!(u8).range(1..=16))]
+        new_device: u8,
+    },
     /// Get global settings BCL from a B-Control.
     GetGlobal {
         /// The device number of the B-Control, from 1 through 16.
@@ -103,22 +222,268 @@ enum Commands {
         ///
         /// If you specify "all", you get a dump of the device's global
         /// settings, followed by all filled memory presets. This can take
-        /// a few minutes.
+        /// a few minutes. Each is printed as it arrives, after a comment such
+        /// as "; $preset 3".
         #[arg(default_value_t = PresetIndex::Temporary, value_parser=parse_preset_arg)]
         preset: PresetIndex,
     },
-    /// Start an OSC service/client pair that translates to and from MIDI.
-    Serve {
-        /// The name of the input MIDI port.
+    /// Send BCL to a B-Control.
+    ///
+    /// The device is asked to identify itself first, and the BCL is checked
+    /// against the device's model. BCL written specifically for a BCR will not
+    /// be sent to a BCF, and vice versa.
+    SendBcl {
+        /// The device number of the B-Control, from 1 through 16.
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=16))]
+        device: u8,
+        /// Time delay to wait for the device to identify itself, and to send
+        /// its global setup, whose timing paces the upload, in seconds.
+        #[arg(long, default_value_t = 1)]
+        delay: u64,
+        /// Send the BCL even if it doesn't match the device's model, or if the
+        /// device doesn't identify itself.
+        #[arg(long)]
+        force: bool,
+        /// The name of the MIDI port recieve data from.
+        midi_in: String,
+        /// The name of the MIDI port to send data to.
+        midi_out: String,
+        /// The file containing the BCL to send.
+        file: PathBuf,
+    },
+    /// Back up a B-Control's global setup and stored presets to a directory.
+    ///
+    /// The global setup is written to global.bcl, and each preset to a file
+    /// such as preset-03.bcl. Empty presets are skipped. Progress is shown on
+    /// standard error.
+    ///
+    /// Several B-Controls can be backed up at once, by giving more than one
+    /// device number, or more pairs of ports with --ports. Each device number
+    /// is backed up on each pair of ports. Each device then gets its own
+    /// subdirectory: device-2 for device 2, or, with --ports, port-1-device-2
+    /// for device 2 on the first pair of ports.
+    Backup {
+        /// The device number of the B-Control, from 1 through 16. Can be
+        /// given more than once.
+        #[arg(long = "device", default_value = "1",
+              value_parser = clap::value_parser!(u8).range(1..=16))]
+        devices: Vec<u8>,
+        /// A further pair of MIDI ports, as "INPUT,OUTPUT", connected to
+        /// B-Controls to back up. Can be given more than once.
+        #[arg(long, value_parser = parse_port_pair)]
+        ports: Vec<(String, String)>,
+        /// The name of the MIDI port recieve data from.
+        midi_in: String,
+        /// The name of the MIDI port to send data to.
+        midi_out: String,
+        /// The directory to write the BCL files to. It's created if needed.
+        dir: PathBuf,
+    },
+    /// Restore a B-Control from a directory written by backup.
+    ///
+    /// The global setup is sent first, if there is one, and each preset is
+    /// stored in the memory it was backed up from. Progress is shown on
+    /// standard error.
+    Restore {
+        /// The device number of the B-Control, from 1 through 16.
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=16))]
+        device: u8,
+        /// The name of the MIDI port recieve data from.
         midi_in: String,
-        /// The name of the output MIDI port.
+        /// The name of the MIDI port to send data to.
         midi_out: String,
-        /// The address and port on which to listen for OSC via UDP.
-        osc_in_addr: SocketAddr,
-        /// The addresses from which to accept OSC and to which OSC will be
-        /// sent.
-        osc_out_addrs: Vec<SocketAddr>,
+        /// The directory to read the BCL files from.
+        dir: PathBuf,
+    },
+    /// Write BCL for a new preset laid out by a template.
+    ///
+    /// The templates are "strips", eight channel strips with volume, pan and
+    /// mute; "cc", 32 encoders on consecutive control numbers; and "nrpn",
+    /// encoders on consecutive 14-bit NRPN parameters. The BCL is written to
+    /// standard output, to edit and then send with send-bcl.
+    NewPreset {
+        /// The MIDI channel, from 1 through 16.
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=16))]
+        channel: u8,
+        /// The first control number, or NRPN parameter number for "nrpn".
+        #[arg(long, default_value_t = 1)]
+        first: u16,
+        /// The preset's name, of up to 24 characters.
+        #[arg(long)]
+        name: Option<String>,
+        /// Write the preset for a BCF2000, rather than a BCR2000.
+        #[arg(long)]
+        bcf: bool,
+        /// Store the preset in this memory, from 1 through 32, instead of
+        /// leaving it in the device's edit buffer.
+        #[arg(long, value_parser = clap::value_parser!(u8).range(1..=32))]
+        store: Option<u8>,
+        /// The template.
+        #[arg(value_parser = PossibleValuesParser::new(bcl::PRESET_TEMPLATES))]
+        template: String,
+    },
+    /// Format a BCL file canonically.
+    ///
+    /// Sections and parameters are laid out, spaced and cased consistently,
+    /// and controls are sorted by kind and number within each preset, so that
+    /// hand-edited files are easier to review and compare. The order of
+    /// parameters within a section is kept. The result is written to standard
+    /// output.
+    FmtBcl {
+        /// Color the output for a terminal.
+        #[arg(long)]
+        color: bool,
+        /// Rewrite the file instead of writing to standard output.
+        #[arg(long, conflicts_with = "color")]
+        in_place: bool,
+        /// The file containing the BCL to format.
+        file: PathBuf,
+    },
+    /// Describe the sections and parameters of BCL.
+    ///
+    /// Each line describes a section, or a form of a parameter, with its
+    /// allowed values and the models it applies to, in tab-separated fields,
+    /// for editors to build forms from.
+    Schema,
+    /// Start an OSC service/client pair that translates to and from MIDI.
+    Serve(Box<ServeArgs>),
+    /// Show which MIDI messages can be translated.
+    ///
+    /// Lists the kinds of MIDI message that translators handle, and the
+    /// channels and control or key numbers that the mappings cover.
+    Capabilities {
+        /// A file of mappings between MIDI and OSC. Without one, the mappings
+        /// used by serve without a mapping file are shown.
+        #[arg(long)]
+        mappings: Option<PathBuf>,
+        /// A built-in set of mappings, to which those from --mappings are
+        /// added.
+        #[arg(long, value_parser = PossibleValuesParser::new(PROFILES))]
+        profile: Option<String>,
+    },
+    /// List the mappings, with their descriptions.
+    ///
+    /// Each mapping is numbered, as in traces, and shown with its
+    /// description, if the mapping file gives one, the MIDI messages it
+    /// covers, and the unit, scale and color the mapping file gives it.
+    ShowMappings {
+        /// A file of mappings between MIDI and OSC. Without one, the mappings
+        /// used by serve without a mapping file are shown.
+        #[arg(long)]
+        mappings: Option<PathBuf>,
+        /// A built-in set of mappings, to which those from --mappings are
+        /// added.
+        #[arg(long, value_parser = PossibleValuesParser::new(PROFILES))]
+        profile: Option<String>,
+    },
+    /// Check mappings against a golden file of translations.
+    ///
+    /// Each line of the golden file pairs a MIDI message with the OSC message
+    /// it should translate to, or from, or both. Failed cases are listed.
+    Verify {
+        /// A file of mappings between MIDI and OSC. Without one, the mappings
+        /// used by serve without a mapping file are checked.
+        #[arg(long)]
+        mappings: Option<PathBuf>,
+        /// A built-in set of mappings, to which those from --mappings are
+        /// added.
+        #[arg(long, value_parser = PossibleValuesParser::new(PROFILES))]
+        profile: Option<String>,
+        /// The golden file.
+        cases: PathBuf,
+    },
+    /// Check an installation by running the service on loopback connections.
+    ///
+    /// Runs the service between local UDP sockets and in-process MIDI, and
+    /// checks that MIDI and OSC are translated in both directions for each
+    /// mapping, printing a report. No MIDI ports are opened.
+    SelfTest {
+        /// A file of mappings between MIDI and OSC. Without one, the mappings
+        /// used by serve without a mapping file are checked.
+        #[arg(long)]
+        mappings: Option<PathBuf>,
+        /// A built-in set of mappings, to which those from --mappings are
+        /// added.
+        #[arg(long, value_parser = PossibleValuesParser::new(PROFILES))]
+        profile: Option<String>,
+        /// The most mappings to check.
+        #[arg(long, default_value_t = 16)]
+        limit: usize,
+    },
+    /// Flood a running OSC service with synthetic OSC, and measure what it
+    /// translates.
+    ///
+    /// Sends float values to a mix of OSC addresses at a steady rate, then
+    /// reports how many messages the service received, translated and sent
+    /// on as MIDI, from its counters before and after. Other traffic to the
+    /// service during the test is counted too.
+    Loadtest {
+        /// The address and port on which the service listens for OSC.
+        target: SocketAddr,
+        /// OSC messages per second.
+        #[arg(long, default_value_t = 1000,
+              value_parser = clap::value_parser!(u32).range(1..))]
+        rate: u32,
+        /// How long to send, in seconds.
+        #[arg(long, default_value_t = 10,
+              value_parser = clap::value_parser!(u64).range(1..))]
+        duration: u64,
+        /// An OSC address to send to, optionally with a weight, as in
+        /// "/track/1/volume=3" for three messages to every one of weight 1.
+        /// Can be given more than once. Defaults to the addresses of the
+        /// mappings serve uses without a mapping file.
+        #[arg(long = "address", value_parser = parse_weighted_addr)]
+        addresses: Vec<(String, u32)>,
+        /// The path of the service's control socket.
+        #[arg(long)]
+        socket: Option<PathBuf>,
+    },
+    /// Send a command to a running OSC service.
+    ///
+    /// Commands are status, reload, set-mapping MAPPING, identity [DEVICE],
+    /// select-preset PRESET [DEVICE], get-preset PRESET [DEVICE], latency,
+    /// capabilities, stats, counters, and trace [COUNT].
+    Ctl {
+        /// The path of the service's control socket.
+        #[arg(long)]
+        socket: Option<PathBuf>,
+        /// The command and its arguments.
+        #[arg(required = true, trailing_var_arg = true)]
+        command: Vec<String>,
+    },
+    /// Show the contents of a file written by serve --capture.
+    CaptureDump {
+        /// The capture file.
+        file: PathBuf,
     },
+    /// Replay a file written by serve --capture through mappings, offline.
+    ///
+    /// The MIDI and OSC received in the capture are translated, and each is
+    /// listed with what it would be translated to now. Nothing is sent.
+    CaptureReplay {
+        /// A file of mappings between MIDI and OSC. Without one, the mappings
+        /// used by serve without a mapping file are used.
+        #[arg(long)]
+        mappings: Option<PathBuf>,
+        /// A built-in set of mappings, to which those from --mappings are
+        /// added.
+        #[arg(long, value_parser = PossibleValuesParser::new(PROFILES))]
+        profile: Option<String>,
+        /// The capture file.
+        file: PathBuf,
+    },
+    /// Write a shell completion script to standard output.
+    ///
+    /// For example, for bash: bcr2kosc completions bash >
+    /// /usr/share/bash-completion/completions/bcr2kosc
+    Completions {
+        /// The shell to complete commands for.
+        shell: Shell,
+    },
+    /// Write a manual page, in roff format, to standard output.
+    ///
+    /// For example: bcr2kosc manpage > /usr/share/man/man1/bcr2kosc.1
+    Manpage,
     #[cfg(winrt)]
     /// Rename a WinRT MIDI port.
     /// 
@@ -136,37 +501,373 @@ enum Commands {
         new_name: String,
     }
 }
-fn parse_preset_arg(s: &str) -> Result<PresetIndex> {
-    match s {
-        "all" => Ok(PresetIndex::All),
-        "temp" => Ok(PresetIndex::Temporary),
-        _ => {
-            let num = s.parse::<u8>().ok();
-            match num {
-                Some(n) if (1u8..=32u8).contains(&n) => Ok(PresetIndex::Preset(n - 1)),
-                _ => Err(LocalError::from("invalid preset index")),
+
+/// The arguments of the serve command.
+#[derive(Args)]
+struct ServeArgs {
+    /// The name of the input MIDI port.
+    midi_in: String,
+    /// The name of the output MIDI port.
+    midi_out: String,
+    /// The address and port on which to listen for OSC via UDP.
+    osc_in_addr: SocketAddr,
+    /// The addresses from which to accept OSC and to which OSC will be
+    /// sent.
+    osc_out_addrs: Vec<SocketAddr>,
+    /// A further address and port on which to listen for OSC. Can be
+    /// given more than once.
+    #[arg(long = "osc-in")]
+    osc_in_extra: Vec<SocketAddr>,
+    /// The local address and port from which to send OSC, if not the one
+    /// OSC is received on. Use port 0 for any available port.
+    #[arg(long)]
+    osc_out_bind: Option<SocketAddr>,
+    /// The format of OSC sent to a destination, as ADDRESS=FORMAT, where
+    /// FORMAT is generic, reaper, or options such as
+    /// bools=float,strings=ascii. With max-size=BYTES, larger bundles
+    /// are split. Can be given more than once.
+    #[arg(long = "osc-format", value_parser = parse_addr_format)]
+    osc_formats: Vec<(SocketAddr, OscFormat)>,
+    /// A file of mappings between MIDI and OSC, one per line.
+    #[arg(long)]
+    mappings: Option<PathBuf>,
+    /// A built-in set of mappings for a common OSC host. Mappings from
+    /// --mappings are added to it.
+    #[arg(long, value_parser = PossibleValuesParser::new(PROFILES))]
+    profile: Option<String>,
+    /// The path of the control socket used by the ctl command.
+    #[arg(long)]
+    ctl: Option<PathBuf>,
+    /// Measure round-trip latency to the device and OSC destinations at
+    /// this interval, in seconds.
+    #[arg(long)]
+    latency_interval: Option<u64>,
+    /// The MIDI channel and control number, e.g. "16:127", of a control
+    /// that the device echoes, used to measure MIDI latency.
+    #[arg(long, requires = "latency_interval", value_parser = parse_marker_arg)]
+    latency_marker: Option<(Channel, u8)>,
+    /// Log MIDI and OSC messages that no mapping handles, to help find
+    /// unmapped controls. Logging is rate limited.
+    #[arg(long)]
+    log_unmatched: bool,
+    /// What to do with OSC that no mapping handles, besides counting it:
+    /// "drop" it, "log" it, "passthrough" messages addressed
+    /// /cc/CHANNEL/CONTROL as control changes, or "reply" to the sender
+    /// with /bcr2kosc/error.
+    #[arg(long, default_value = "drop")]
+    unmatched_osc: UnmatchedAction,
+    /// Also translate all MIDI to and from fixed OSC addresses, without
+    /// mappings: /midi/CHANNEL/cc/CONTROL, /midi/CHANNEL/note/KEY and
+    /// /midi/CHANNEL/pb.
+    #[arg(long)]
+    raw_midi: bool,
+    /// Run as a hot standby: wait for heartbeats from a primary instance
+    /// on this local address and port, and start only when they stop.
+    #[arg(long)]
+    standby: Option<SocketAddr>,
+    /// How long, in seconds, heartbeats can be missing before a standby
+    /// takes over.
+    #[arg(long, requires = "standby", default_value_t = 2)]
+    standby_timeout: u64,
+    /// Send heartbeats to a standby instance at this address and port.
+    #[arg(long)]
+    heartbeat_to: Option<SocketAddr>,
+    /// Check the MIDI ports at this interval, in seconds, and tell OSC
+    /// destinations when they, or the device, go offline or come back.
+    /// Device status requires --keepalive.
+    #[arg(long)]
+    status_interval: Option<u64>,
+    /// Ask the device to identify itself at this interval, in seconds, to
+    /// detect when it stops answering.
+    #[arg(long)]
+    keepalive: Option<u64>,
+    /// The device number, from 1 through 16, pinged by --keepalive.
+    #[arg(long, requires = "keepalive", default_value_t = 1,
+          value_parser = clap::value_parser!(u8).range(1..=16))]
+    keepalive_device: u8,
+    /// A group of backup OSC destinations, as comma-separated addresses
+    /// and ports. Translated OSC goes to the first group, starting with
+    /// the usual destinations, that answers pings. Can be given more than
+    /// once, in priority order.
+    #[arg(long, value_parser = parse_addr_group)]
+    failover: Vec<Vec<SocketAddr>>,
+    /// How long, in seconds, a destination can go without answering
+    /// pings before it's taken to be down.
+    #[arg(long, requires = "failover", default_value_t = 3,
+          value_parser = clap::value_parser!(u64).range(1..))]
+    failover_timeout: u64,
+    /// How OSC arguments of unexpected types, such as integers or
+    /// numeric strings sent for faders, are treated: "permissive"
+    /// converts them where it can, "strict" ignores them. Mappings can
+    /// override this with the coercion option.
+    #[arg(long, default_value = "permissive")]
+    coercion: Coercion,
+    /// The minimum interval, in milliseconds, between SysEx messages sent
+    /// to the device by device operations. Translated control changes go
+    /// ahead of queued SysEx regardless.
+    #[arg(long, default_value_t = 0)]
+    sysex_interval: u64,
+    /// Reopen the MIDI output port at this interval, in seconds, after it
+    /// fails, such as when the device is unplugged, until it's back.
+    /// Without this, MIDI can't be sent once the port fails.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    midi_rebind: Option<u64>,
+    /// Send /bcr2kosc/heartbeat at this interval, in seconds, with a
+    /// counter and the uptime in seconds, so that a watchdog can restart
+    /// the service when it stalls.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    watchdog_interval: Option<u64>,
+    /// Send watchdog heartbeats to this address and port, instead of the
+    /// OSC destinations. Can be given more than once.
+    #[arg(long, requires = "watchdog_interval")]
+    watchdog_to: Vec<SocketAddr>,
+    /// The number of recent translations kept for inspection with "ctl
+    /// trace" or /bcr2kosc/trace. Zero turns tracing off.
+    #[arg(long, default_value_t = DEFAULT_TRACE_SIZE)]
+    trace_size: usize,
+    /// A file of learned ranges of control values, as lines of CHANNEL
+    /// CONTROL LOW HIGH. Controls whose learned range is narrower than
+    /// 0 through 127 are stretched to the full range before mappings see
+    /// them.
+    #[arg(long)]
+    ranges: Option<PathBuf>,
+    /// Learn the range of values that each control sends, saving them to
+    /// the --ranges file. They're used once the mappings are reloaded.
+    #[arg(long)]
+    learn_ranges: bool,
+    /// Check that the service could start: build the mappings, bind the
+    /// sockets, open the MIDI ports and ask the device to identify
+    /// itself. Print a readiness report and exit, instead of serving.
+    #[arg(long)]
+    check: bool,
+    /// A further OSC namespace, as PREFIX=INPUT,OUTPUT[,MAPPINGS]: OSC
+    /// under PREFIX is translated by the mapping file MAPPINGS, with the
+    /// prefix removed, to and from its own MIDI ports. Can be given more
+    /// than once.
+    #[arg(long = "namespace")]
+    namespaces: Vec<NamespaceConfig>,
+    /// Write every MIDI message and OSC packet received and sent, with
+    /// the time, to this file, for bug reports. See capture-dump.
+    #[arg(long)]
+    capture: Option<PathBuf>,
+    /// Read the name of the device's current preset at this interval, in
+    /// seconds, to switch to the mappings that the configuration file
+    /// names for it. Presets given by number are followed by the program
+    /// changes that the device sends, without polling.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    preset_poll: Option<u64>,
+    /// The device number, from 1 through 16, whose preset is read by
+    /// --preset-poll.
+    #[arg(long, requires = "preset_poll", default_value_t = 1,
+          value_parser = clap::value_parser!(u8).range(1..=16))]
+    preset_device: u8,
+    /// Start even if an OSC destination can't be sent to, such as one
+    /// with address 0.0.0.0 or port 0.
+    #[arg(long)]
+    force: bool,
+    /// Only translate MIDI to OSC, so that the device is never changed
+    /// remotely: OSC isn't translated to MIDI, device operations are
+    /// refused, and the MIDI output port isn't opened, so any name, such
+    /// as "-", can be given for it.
+    #[arg(long, conflicts_with_all = ["latency_interval", "keepalive", "preset_poll"])]
+    read_only: bool,
+    /// Only translate OSC to MIDI: MIDI isn't translated to OSC, device
+    /// operations other than selecting a preset are refused, and the MIDI
+    /// input port isn't opened, so any name, such as "-", can be given
+    /// for it.
+    #[arg(long, conflicts_with_all = ["read_only", "latency_interval", "keepalive",
+                                      "preset_poll", "learn_ranges"])]
+    write_only: bool,
+}
+
+impl Commands {
+    /// Returns the MIDI port names given to the command, which may be
+    /// aliases, with the directions of the ports.
+    fn port_names_mut(&mut self) -> Vec<(Direction, &mut String)> {
+        match self {
+            Commands::Listen { midi_in, .. } | Commands::RecvHex { midi_in, .. } => {
+                vec![(Direction::Input, midi_in)]
+            }
+            Commands::SendHex { midi_out, .. } | Commands::SelectPreset { midi_out, .. } => {
+                vec![(Direction::Output, midi_out)]
+            }
+            Commands::Find {
+                midi_in, midi_out, ..
             }
+            | Commands::GetGlobal {
+                midi_in, midi_out, ..
+            }
+            | Commands::Identify {
+                midi_in, midi_out, ..
+            }
+            | Commands::SetDeviceId {
+                midi_in, midi_out, ..
+            }
+            | Commands::GetPreset {
+                midi_in, midi_out, ..
+            }
+            | Commands::SendBcl {
+                midi_in, midi_out, ..
+            }
+            | Commands::Restore {
+                midi_in, midi_out, ..
+            } => vec![(Direction::Input, midi_in), (Direction::Output, midi_out)],
+            Commands::Serve(args) => vec![
+                (Direction::Input, &mut args.midi_in),
+                (Direction::Output, &mut args.midi_out),
+            ],
+            Commands::Backup {
+                ports,
+                midi_in,
+                midi_out,
+                ..
+            } => {
+                let mut names = vec![(Direction::Input, midi_in), (Direction::Output, midi_out)];
+                for (i, o) in ports {
+                    names.push((Direction::Input, i));
+                    names.push((Direction::Output, o));
+                }
+                names
+            }
+            _ => vec![],
         }
     }
 }
+fn parse_preset_arg(s: &str) -> Result<PresetIndex> {
+    s.parse::<PresetIndex>()
+        .map_err(|e| LocalError::from(e.to_string()))
+}
+fn parse_addr_group(s: &str) -> Result<Vec<SocketAddr>> {
+    s.split(',')
+        .map(|a| {
+            a.parse()
+                .map_err(|_| LocalError::from(format!("invalid address \"{a}\"")))
+        })
+        .collect()
+}
+
+fn parse_weighted_addr(s: &str) -> Result<(String, u32)> {
+    let (addr, weight) = match s.rsplit_once('=') {
+        Some((addr, weight)) => (addr, weight.parse::<u32>().map_err(|e| e.to_string())?),
+        None => (s, 1),
+    };
+    if !addr.starts_with('/') {
+        return Err(format!("\"{addr}\" is not an OSC address").into());
+    }
+    if weight == 0 {
+        return Err("the weight must be at least 1".into());
+    }
+    Ok((addr.to_string(), weight))
+}
+
+fn parse_addr_format(s: &str) -> Result<(SocketAddr, OscFormat)> {
+    let (addr, format) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected ADDRESS=FORMAT, got \"{s}\""))?;
+    let addr = addr
+        .parse()
+        .map_err(|_| format!("invalid address \"{addr}\""))?;
+    Ok((addr, format.parse::<OscFormat>()?))
+}
+
+fn parse_port_pair(s: &str) -> Result<(String, String)> {
+    match s.split_once(',') {
+        Some((midi_in, midi_out)) => Ok((midi_in.to_string(), midi_out.to_string())),
+        None => Err(format!("expected INPUT,OUTPUT port names, got \"{s}\"").into()),
+    }
+}
+
+fn parse_marker_arg(s: &str) -> Result<(Channel, u8)> {
+    let (channel, control) = s
+        .split_once(':')
+        .ok_or_else(|| LocalError::from("expected CHANNEL:CONTROL"))?;
+    let channel = match channel.parse::<u8>() {
+        Ok(n) if (1..=16).contains(&n) => Channel::from(n - 1),
+        _ => bail!("MIDI channel must be from 1 through 16."),
+    };
+    match control.parse::<u8>() {
+        Ok(n) if n < 128 => Ok((channel, n)),
+        _ => bail!("MIDI control number must be from 0 through 127."),
+    }
+}
 type LocalError = Box<dyn Error + Send + Sync + 'static>;
 type Result<T> = std::result::Result<T, LocalError>;
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    let cli = Cli::parse();
-    stderrlog::new()
-        .verbosity(cli.verbose as usize)
-        .init()
-        .unwrap();
+async fn main() -> ExitCode {
+    let mut cli = Cli::parse();
+    init_logging(cli.verbose);
+    match run(&mut cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            ExitCode::from(exit::classify(&*e) as u8)
+        }
+    }
+}
+
+/// Logs to stderr at the verbosity given, unless `RUST_LOG` says otherwise.
+/// Messages logged by dependencies through the `log` crate are included.
+fn init_logging(verbose: u8) {
+    let level = match verbose {
+        0 => LevelFilter::ERROR,
+        1 => LevelFilter::WARN,
+        2 => LevelFilter::INFO,
+        3 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    };
+    let filter = EnvFilter::builder()
+        .with_default_directive(level.into())
+        .from_env_lossy();
+    let registry = tracing_subscriber::registry().with(
+        tracing_subscriber::fmt::layer()
+            .with_writer(std::io::stderr)
+            .with_filter(filter),
+    );
+    #[cfg(feature = "console")]
+    let registry = registry.with(console_subscriber::spawn());
+    registry.init();
+}
+
+async fn run(cli: &mut Cli) -> Result<()> {
+    cli.backend.select().or_fail(Failure::Usage)?;
+    let config = Config::load(cli.config.as_deref()).or_fail(Failure::Config)?;
+    if let Some(command) = &mut cli.command {
+        for (direction, name) in command.port_names_mut() {
+            *name = config.port_name(name).to_string();
+            if cli.prefer_network {
+                if let Some(port) = Port::find_network_session(direction)? {
+                    info!("Using network session {direction} port \"{}\".", port.name);
+                    *name = port.name;
+                }
+            }
+        }
+    }
     match &cli.command {
-        Some(Commands::ListPorts {}) => Ok(list_ports()),
-        Some(Commands::Listen { midi_in }) => listen(midi_in).await,
+        Some(Commands::ListPorts {}) => list_ports(&config),
+        Some(Commands::Listen {
+            midi_in,
+            sysex_out,
+            realtime,
+        }) => listen(midi_in, sysex_out.as_deref(), *realtime).await,
+        Some(Commands::SendHex { midi_out, hex }) => send_hex(midi_out, hex).await,
+        Some(Commands::RecvHex { midi_in, timeout }) => recv_hex(midi_in, *timeout).await,
         Some(Commands::SelectPreset {
             device,
             midi_out,
             preset,
         }) => select_preset(midi_out, *device, *preset).await,
+        Some(Commands::Identify {
+            midi_in,
+            midi_out,
+            device,
+        }) => identify(midi_in, midi_out, *device).await,
+        Some(Commands::SetDeviceId {
+            midi_in,
+            midi_out,
+            device,
+            new_device,
+        }) => set_device_id(midi_in, midi_out, *device, *new_device).await,
         Some(Commands::GetGlobal {
             midi_in,
             midi_out,
@@ -178,17 +879,117 @@ async fn main() -> Result<()> {
             device,
             preset,
         }) => get_preset(midi_in, midi_out, *device, *preset).await,
-        Some(Commands::Find {
+        Some(Commands::SendBcl {
+            device,
             delay,
+            force,
+            midi_in,
+            midi_out,
+            file,
+        }) => send_bcl_file(midi_in, midi_out, *device, file, *force, *delay).await,
+        Some(Commands::Backup {
+            devices,
+            ports,
+            midi_in,
+            midi_out,
+            dir,
+        }) => {
+            let mut all_ports = vec![(midi_in.clone(), midi_out.clone())];
+            all_ports.extend(ports.iter().cloned());
+            backup(&all_ports, devices, dir).await
+        }
+        Some(Commands::Restore {
+            device,
             midi_in,
             midi_out,
-        }) => list_bcontrols(midi_in, midi_out, *delay).await,
-        Some(Commands::Serve {
+            dir,
+        }) => restore(midi_in, midi_out, *device, dir).await,
+        Some(Commands::NewPreset {
+            channel,
+            first,
+            name,
+            bcf,
+            store,
+            template,
+        }) => {
+            let options = bcl::PresetOptions {
+                bcf: *bcf,
+                channel: *channel,
+                first: *first,
+                name: name.clone().unwrap_or_else(|| template.clone()),
+                store: *store,
+            };
+            new_preset(template, &options)
+        }
+        Some(Commands::FmtBcl {
+            color,
+            in_place,
+            file,
+        }) => fmt_bcl(file, *color, *in_place),
+        Some(Commands::Schema) => {
+            for line in bcl::schema_lines() {
+                println!("{line}");
+            }
+            Ok(())
+        }
+        Some(Commands::Find {
+            delay,
+            watch,
+            interval,
             midi_in,
             midi_out,
-            osc_in_addr,
-            osc_out_addrs,
-        }) => serve(&midi_in, &midi_out, &osc_in_addr, &osc_out_addrs).await,
+        }) => {
+            if *watch {
+                watch_bcontrols(&config, midi_in, midi_out, *delay, *interval).await
+            } else {
+                list_bcontrols(&config, midi_in, midi_out, *delay).await
+            }
+        }
+        Some(Commands::Serve(args)) => serve(args, &config).await,
+        Some(Commands::Capabilities { mappings, profile }) => {
+            capabilities(profile.as_deref(), mappings.as_deref())
+        }
+        Some(Commands::ShowMappings { mappings, profile }) => {
+            show_mappings(profile.as_deref(), mappings.as_deref())
+        }
+        Some(Commands::Verify {
+            mappings,
+            profile,
+            cases,
+        }) => verify(profile.as_deref(), mappings.as_deref(), cases),
+        Some(Commands::SelfTest {
+            mappings,
+            profile,
+            limit,
+        }) => self_test(profile.as_deref(), mappings.as_deref(), *limit).await,
+        Some(Commands::Loadtest {
+            target,
+            rate,
+            duration,
+            addresses,
+            socket,
+        }) => {
+            let config = LoadTestConfig {
+                target: *target,
+                ctl_path: socket.clone().unwrap_or_else(default_ctl_path),
+                rate: *rate,
+                duration: Duration::from_secs(*duration),
+                addresses: addresses.clone(),
+            };
+            loadtest(config).await
+        }
+        Some(Commands::Ctl { socket, command }) => ctl(socket.as_deref(), command).await,
+        Some(Commands::CaptureDump { file }) => capture_dump(file),
+        Some(Commands::CaptureReplay {
+            mappings,
+            profile,
+            file,
+        }) => capture_replay(profile.as_deref(), mappings.as_deref(), file),
+        Some(Commands::Completions { shell }) => {
+            completions(*shell);
+            Ok(())
+        }
+        Some(Commands::Manpage) => manpage(),
         None => Ok(()),
         #[cfg(winrt)]
         Some(Commands::RenamePort { ptype, name, new_name }) =>
@@ -196,34 +997,154 @@ async fn main() -> Result<()> {
     }
 }
 
-fn list_ports() {
-    fn print_ports(dir: &str, lst: &[String]) {
-        match lst.len() {
-            0 => println!("No {dir} ports found"),
+fn completions(shell: Shell) {
+    clap_complete::generate(shell, &mut Cli::command(), PGM, &mut std::io::stdout());
+}
+
+fn manpage() -> Result<()> {
+    clap_mangen::Man::new(Cli::command()).render(&mut std::io::stdout())?;
+    Ok(())
+}
+
+fn list_ports(config: &Config) -> Result<()> {
+    for direction in [Direction::Input, Direction::Output] {
+        let ports = Port::list(direction)?;
+        match ports.len() {
+            0 => println!("No {direction} ports found"),
             _ => {
-                println!("\nAvailable {dir} ports:");
-                for (i, p) in lst.iter().enumerate() {
-                    println!("{i}: {p}");
+                println!("\nAvailable {direction} ports:");
+                for p in ports {
+                    let mut notes = config.aliases_of(&p.name);
+                    if p.network_session {
+                        notes.push("network session");
+                    }
+                    if notes.is_empty() {
+                        println!("{}: {}", p.index, p.name);
+                    } else {
+                        println!("{}: {} ({})", p.index, p.name, notes.join(", "));
+                    }
                 }
             }
         };
     }
-
-    print_ports("input", &midi_io::input_ports());
-    print_ports("output", &midi_io::output_ports());
+    Ok(())
 }
 
-async fn listen(port_name: &str) -> Result<()> {
-    async fn print_midi_input(midi_in: impl Stream<Item = MidiMessage>) {
+async fn listen(port_name: &str, sysex_out: Option<&Path>, realtime: bool) -> Result<()> {
+    async fn print_midi_input(
+        midi_in: impl Stream<Item = MidiMessage>,
+        sysex_out: Option<&Path>,
+    ) -> Result<()> {
         pin_mut!(midi_in);
+        let mut count = 0;
         while let Some(msg) = midi_in.next().await {
             println!("{msg:?}");
+            match sysex_out {
+                Some(dir) if matches!(msg, MidiMessage::SysEx(_)) => {
+                    // Don't overwrite files from an earlier capture.
+                    let path = loop {
+                        count += 1;
+                        let path = dir.join(format!("{count:04}.syx"));
+                        if !path.exists() {
+                            break path;
+                        }
+                    };
+                    std::fs::write(&path, message_to_bytes(msg))?;
+                    info!("Wrote {}", path.display());
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    if let Some(dir) = sysex_out {
+        std::fs::create_dir_all(dir)?;
+    }
+    let (builder, realtime_rx) = if realtime {
+        let (tx, rx) = mpsc::unbounded();
+        (
+            MidiStream::builder().realtime(RealTime::PassThrough(tx)),
+            Some(rx),
+        )
+    } else {
+        (MidiStream::builder(), None)
+    };
+    let midi_in = builder.bind(port_name)?;
+    let print_realtime = async {
+        if let Some(rx) = realtime_rx {
+            rx.for_each(|b| async move { println!("RealTime({})", to_hex(&[b])) })
+                .await;
+        }
+    };
+    select! {
+        (r, _) = join(print_midi_input(midi_in, sysex_out), print_realtime).fuse() => r?,
+        _ = signal::ctrl_c().fuse() => {}
+    };
+    Ok(())
+}
+
+async fn send_hex(port_name: &str, hex: &[String]) -> Result<()> {
+    let bytes = parse_hex(hex).or_fail(Failure::Usage)?;
+    let msgs = split_messages(&bytes)
+        .ok_or("MIDI data must start with a status byte.")
+        .or_fail(Failure::Usage)?;
+    for m in &msgs {
+        if !is_complete_message(m) {
+            let e = format!("Not a complete MIDI message: {}", to_hex(m));
+            return Err(fail(Failure::Usage, e));
         }
     }
+    let mut midi_out = MidiSink::bind(port_name)?;
+    for m in msgs {
+        midi_out.feed(message_from_bytes(m)).await?;
+    }
+    midi_out.flush().await?;
+    Ok(())
+}
+
+/// Checks that a message is as long as its status byte requires. SysEx
+/// messages must end with EOX.
+fn is_complete_message(m: &[u8]) -> bool {
+    match m[0] {
+        0x80..=0xbf | 0xe0..=0xef => m.len() == 3,
+        0xc0..=0xdf => m.len() == 2,
+        0xf0 => m.len() >= 5 && m[m.len() - 1] == 0xf7,
+        _ => false,
+    }
+}
+
+fn parse_hex(words: &[String]) -> Result<Vec<u8>> {
+    let digits: String = words.concat().split_whitespace().collect();
+    if !digits.chars().all(|c| c.is_ascii_hexdigit()) || !digits.len().is_multiple_of(2) {
+        bail!("Expected hex bytes of two digits each.");
+    }
+    Ok((0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap())
+        .collect())
+}
 
-    let midi_in = MidiStream::bind(port_name)?;
+async fn recv_hex(port_name: &str, timeout: Option<u64>) -> Result<()> {
+    let mut midi_in = MidiStream::bind(port_name)?;
+    let idle = timeout.map(Duration::from_secs);
+    let print = async {
+        loop {
+            let msg = match idle {
+                Some(idle) => match tokio::time::timeout(idle, midi_in.next()).await {
+                    Ok(msg) => msg,
+                    Err(_) => break,
+                },
+                None => midi_in.next().await,
+            };
+            match msg {
+                Some(msg) => println!("{}", to_hex(&message_to_bytes(msg))),
+                None => break,
+            }
+        }
+    };
     select! {
-        _ = print_midi_input(midi_in).fuse() => {},
+        _ = print.fuse() => {},
         _ = signal::ctrl_c().fuse() => {}
     };
     Ok(())
@@ -233,24 +1154,149 @@ async fn select_preset(midi_out: &str, device: u8, preset: PresetIndex) -> Resul
     match preset {
         PresetIndex::Preset(index) => {
             let mut midi_out = MidiSink::bind(midi_out)?;
-            let bdata = BControlSysEx {
-                device: DeviceID::Device(device),
-                model: BControlModel::Any,
-                command: BControlCommand::SelectPreset{index},
-            };
-            midi_out.send(MidiMessage::from(&bdata)).await?;
+            midi_out
+                .send(BControlMessages::device(device).select_preset(index))
+                .await?;
             Ok(())
-        },
-        _ => bail!("A specific stored preset must be selected."),
+        }
+        _ => Err(fail(
+            Failure::Usage,
+            "A specific stored preset must be selected.",
+        )),
+    }
+}
+
+/// Opens the named input and output ports, which connect to the same devices.
+fn open_ports(in_port_name: &str, out_port_name: &str) -> Result<(MidiStream, MidiSink)> {
+    let midi_in = Port::find(in_port_name, Direction::Input)?.open_input()?;
+    let midi_out = Port::find(out_port_name, Direction::Output)?.open_output()?;
+    Ok((midi_in, midi_out))
+}
+
+/// Returns a token that's cancelled by Ctrl-C, so that device operations stop
+/// cleanly rather than with the process.
+fn cancel_on_ctrl_c() -> CancellationToken {
+    let cancel = CancellationToken::new();
+    let token = cancel.clone();
+    tokio::spawn(async move {
+        if signal::ctrl_c().await.is_ok() {
+            info!("Cancelling.");
+            token.cancel();
+        }
+    });
+    cancel
+}
+
+async fn identify(in_port_name: &str, out_port_name: &str, device: u8) -> Result<()> {
+    let (mut midi_in, mut midi_out) = open_ports(in_port_name, out_port_name)?;
+    let cancel = cancel_on_ctrl_c();
+    let delay = 2;
+    let identity = tokio::time::timeout(
+        Duration::from_secs(delay),
+        get_identity(device - 1, &mut midi_in, &mut midi_out, &cancel),
+    )
+    .await;
+    let model = match identity {
+        Ok(Ok((model, _))) => model,
+        Ok(Err(e)) => return Err(fail(Failure::NoResponse, e)),
+        Err(_) => {
+            let e = format!("Device {device} did not identify itself.");
+            return Err(fail(Failure::NoResponse, e));
+        }
+    };
+    let pacing = device_pacing(device, &mut midi_in, &mut midi_out, &cancel, delay).await;
+    info!("Device {device}, a {model}, is flashing.");
+    blink(
+        device - 1,
+        model,
+        pacing,
+        &mut midi_in,
+        &mut midi_out,
+        &cancel,
+    )
+    .await
+    .or_fail(Failure::Bcl)
+}
+
+async fn set_device_id(
+    in_port_name: &str,
+    out_port_name: &str,
+    device: u8,
+    new_device: u8,
+) -> Result<()> {
+    let (mut midi_in, mut midi_out) = open_ports(in_port_name, out_port_name)?;
+    let cancel = cancel_on_ctrl_c();
+    let delay = 2;
+    let model = match ask_identity(device, &mut midi_in, &mut midi_out, &cancel, delay).await {
+        Some(Ok((model, _))) => model,
+        Some(Err(e)) => return Err(fail(Failure::NoResponse, e)),
+        None => {
+            let e = format!("Device {device} did not identify itself.");
+            return Err(fail(Failure::NoResponse, e));
+        }
+    };
+    if new_device == device {
+        info!("Device {device} already has that number.");
+        return Ok(());
+    }
+    // Two devices with one number would both take what's sent to it.
+    if let Some(Ok((other, _))) =
+        ask_identity(new_device, &mut midi_in, &mut midi_out, &cancel, delay).await
+    {
+        let e = format!("A {other} already answers as device {new_device}.");
+        return Err(fail(Failure::Bcl, e));
+    }
+    let pacing = device_pacing(device, &mut midi_in, &mut midi_out, &cancel, delay).await;
+    send_bcl(
+        device - 1,
+        &bcl::device_id_bcl(model, new_device),
+        pacing,
+        &mut midi_in,
+        &mut midi_out,
+        &cancel,
+    )
+    .await
+    .or_fail(Failure::Bcl)?;
+    match ask_identity(new_device, &mut midi_in, &mut midi_out, &cancel, delay).await {
+        Some(Ok(_)) => {
+            info!("Device {device}, a {model}, is now device {new_device}.");
+            Ok(())
+        }
+        _ => {
+            let e = format!("The device did not identify itself as device {new_device}.");
+            Err(fail(Failure::NoResponse, e))
+        }
     }
 }
 
+/// Asks a device, numbered from 1, to identify itself, giving it `delay`
+/// seconds to answer. Returns `None` if it doesn't answer in time.
+async fn ask_identity(
+    device: u8,
+    midi_in: &mut MidiStream,
+    midi_out: &mut MidiSink,
+    cancel: &CancellationToken,
+    delay: u64,
+) -> Option<Result<(BControlModel, String)>> {
+    tokio::time::timeout(
+        Duration::from_secs(delay),
+        get_identity(device - 1, midi_in, midi_out, cancel),
+    )
+    .await
+    .ok()
+}
+
 async fn get_global(in_port_name: &str, out_port_name: &str, device: u8) -> Result<()> {
-    let mut midi_in = MidiStream::bind(in_port_name)?;
-    let mut midi_out = MidiSink::bind(out_port_name)?;
-    for line in get_global_bcl(device - 1, &mut midi_in, &mut midi_out).await? {
+    let (mut midi_in, mut midi_out) = open_ports(in_port_name, out_port_name)?;
+    let cancel = cancel_on_ctrl_c();
+    let lines = get_global_bcl(device - 1, &mut midi_in, &mut midi_out, &cancel).await?;
+    for line in &lines {
         println!("{line}");
     }
+    let timing = bcl::DeviceTiming::from_bcl(&lines);
+    if timing.is_known() {
+        info!("Device {device} has {timing}.");
+    }
     Ok(())
 }
 
@@ -260,55 +1306,620 @@ async fn get_preset(
     device: u8,
     preset: PresetIndex,
 ) -> Result<()> {
-    let mut midi_in = MidiStream::bind(in_port_name)?;
-    let mut midi_out = MidiSink::bind(out_port_name)?;
-    for line in get_preset_bcl(device - 1, preset, &mut midi_in, &mut midi_out).await? {
+    let (mut midi_in, mut midi_out) = open_ports(in_port_name, out_port_name)?;
+    let cancel = cancel_on_ctrl_c();
+    if preset == PresetIndex::All {
+        // Each preset is printed as it arrives, since the whole dump takes a
+        // while.
+        let dumps = get_all_presets(device - 1, &mut midi_in, &mut midi_out, &cancel);
+        pin_mut!(dumps);
+        while let Some(dump) = dumps.next().await {
+            let dump = dump?;
+            let mut out = std::io::stdout().lock();
+            writeln!(out, "{}", dump.header())?;
+            for line in &dump.lines {
+                writeln!(out, "{line}")?;
+            }
+            out.flush()?;
+        }
+        return Ok(());
+    }
+    for line in get_preset_bcl(device - 1, preset, &mut midi_in, &mut midi_out, &cancel).await? {
         println!("{line}")
     }
     Ok(())
 }
 
-async fn list_bcontrols(in_port_name: &str, out_port_name: &str, delay: u64) -> Result<()> {
+async fn send_bcl_file(
+    in_port_name: &str,
+    out_port_name: &str,
+    device: u8,
+    file: &Path,
+    force: bool,
+    delay: u64,
+) -> Result<()> {
+    let text = std::fs::read_to_string(file).or_fail(Failure::Bcl)?;
+    let lines: Vec<&str> = text.lines().collect();
+    let (mut midi_in, mut midi_out) = open_ports(in_port_name, out_port_name)?;
+    let cancel = cancel_on_ctrl_c();
+    let identity = tokio::time::timeout(
+        Duration::from_secs(delay),
+        get_identity(device - 1, &mut midi_in, &mut midi_out, &cancel),
+    )
+    .await;
+    let pacing = match identity {
+        Ok(Ok((model, id_string))) => {
+            info!("Device {device} is a {model}, \"{id_string}\".");
+            if let Err(e) = bcl::check_model(&lines, model) {
+                if force {
+                    warn!("{e}. Sending anyway.");
+                } else {
+                    let e = format!("{e}. Use --force to send anyway.");
+                    return Err(fail(Failure::Bcl, e));
+                }
+            }
+            device_pacing(device, &mut midi_in, &mut midi_out, &cancel, delay).await
+        }
+        Ok(Err(e)) => return Err(fail(Failure::NoResponse, e)),
+        Err(_) => {
+            if force {
+                warn!("Device {device} did not identify itself. Sending anyway.");
+                Duration::ZERO
+            } else {
+                let e =
+                    format!("Device {device} did not identify itself. Use --force to send anyway.");
+                return Err(fail(Failure::NoResponse, e));
+            }
+        }
+    };
+    send_bcl(
+        device - 1,
+        &lines,
+        pacing,
+        &mut midi_in,
+        &mut midi_out,
+        &cancel,
+    )
+    .await
+    .or_fail(Failure::Bcl)
+}
+
+/// Finds the time to leave between lines of BCL sent to a device, from the
+/// timing in its global setup. See `bcl::DeviceTiming`.
+async fn device_pacing(
+    device: u8,
+    midi_in: &mut MidiStream,
+    midi_out: &mut MidiSink,
+    cancel: &CancellationToken,
+    delay: u64,
+) -> Duration {
+    let global = tokio::time::timeout(
+        Duration::from_secs(delay),
+        get_global_bcl(device - 1, midi_in, midi_out, cancel),
+    )
+    .await;
+    match global {
+        Ok(Ok(lines)) => {
+            let timing = bcl::DeviceTiming::from_bcl(&lines);
+            if timing.is_known() {
+                info!(
+                    "Device {device} has {timing}; BCL is sent {} ms apart.",
+                    timing.pacing().as_millis()
+                );
+            }
+            timing.pacing()
+        }
+        _ => {
+            warn!("Device {device} did not send its global setup. Sending without pacing.");
+            Duration::ZERO
+        }
+    }
+}
+
+fn new_preset(template: &str, options: &bcl::PresetOptions) -> Result<()> {
+    let template = template
+        .parse::<bcl::PresetTemplate>()
+        .or_fail(Failure::Usage)?;
+    for line in bcl::new_preset(template, options).or_fail(Failure::Usage)? {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+fn fmt_bcl(file: &Path, color: bool, in_place: bool) -> Result<()> {
+    let text = std::fs::read_to_string(file).or_fail(Failure::Bcl)?;
+    let lines: Vec<&str> = text.lines().collect();
+    let formatted = bcl::format_bcl(&lines, color).or_fail(Failure::Bcl)?;
+    if in_place {
+        let mut text = formatted.join("\n");
+        text.push('\n');
+        std::fs::write(file, text).or_fail(Failure::Bcl)?;
+    } else {
+        for line in formatted {
+            println!("{line}");
+        }
+    }
+    Ok(())
+}
+
+/// A client connected to shared ports, so that several can use one pair.
+type SharedClient = BControlClient<mpsc::UnboundedReceiver<MidiMessage>, MidiSink>;
+
+/// Backs up each device number on each pair of ports, concurrently.
+async fn backup(ports: &[(String, String)], devices: &[u8], dir: &Path) -> Result<()> {
+    let several = ports.len() * devices.len() > 1;
+    let cancel = cancel_on_ctrl_c();
+    let mut sources = vec![];
+    let mut tasks = vec![];
+    for (k, (in_port_name, out_port_name)) in ports.iter().enumerate() {
+        let (midi_in, midi_out) = open_ports(in_port_name, out_port_name)?;
+        let shared = SharedMidiInput::default();
+        for &device in devices {
+            let name = match ports.len() {
+                1 => format!("device-{device}"),
+                _ => format!("port-{}-device-{device}", k + 1),
+            };
+            let (label, target) = if several {
+                (Some(name.clone()), dir.join(&name))
+            } else {
+                (None, dir.to_path_buf())
+            };
+            let client = BControlClient::new(
+                device - 1,
+                shared.subscribe(sysex_messages),
+                midi_out.clone(),
+            )
+            .on_progress(show_progress(label))
+            .with_cancellation(cancel.clone());
+            let task = tokio::spawn(backup_device(client, target));
+            tasks.push((name, task));
+        }
+        sources.push(async move { shared.distribute(midi_in).await });
+    }
+    let names: Vec<String> = tasks.iter().map(|(name, _)| name.clone()).collect();
+    let results = select! {
+        r = join_all(tasks.into_iter().map(|(_, task)| task)).fuse() => r,
+        _ = join_all(sources).fuse() => return Err("MIDI input ended.".into()),
+    };
+    if !several {
+        eprintln!();
+    }
+    let mut failure = None;
+    for (name, result) in names.iter().zip(results) {
+        match result.map_err(LocalError::from).and_then(|r| r) {
+            Ok(count) => info!("Backed up {count} presets of {name}."),
+            Err(e) => {
+                if several {
+                    warn!("{name}: {e}");
+                }
+                failure.get_or_insert(e);
+            }
+        }
+    }
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// Backs up one device to `dir`, returning the number of presets written.
+async fn backup_device(mut client: SharedClient, dir: PathBuf) -> Result<usize> {
+    let dumps = client.backup().await.or_fail(Failure::NoResponse)?;
+    std::fs::create_dir_all(&dir)?;
+    for dump in &dumps {
+        let path = dir.join(backup_file_name(dump.preset));
+        std::fs::write(&path, dump.lines.join("\n") + "\n")
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+    }
+    Ok(dumps.len() - 1)
+}
+
+async fn restore(in_port_name: &str, out_port_name: &str, device: u8, dir: &Path) -> Result<()> {
+    let mut dumps = vec![];
+    for preset in std::iter::once(None).chain((0..32).map(Some)) {
+        let path = dir.join(backup_file_name(preset));
+        if path.exists() {
+            let text = std::fs::read_to_string(&path)
+                .map_err(|e| fail(Failure::Bcl, format!("{}: {e}", path.display())))?;
+            let lines = text.lines().map(str::to_string).collect();
+            dumps.push(PresetDump { preset, lines });
+        }
+    }
+    if dumps.is_empty() {
+        let e = format!("{} contains no backup files.", dir.display());
+        return Err(fail(Failure::Usage, e));
+    }
+    let (midi_in, midi_out) = open_ports(in_port_name, out_port_name)?;
+    let mut client = BControlClient::new(device - 1, midi_in, midi_out)
+        .on_progress(show_progress(None))
+        .with_cancellation(cancel_on_ctrl_c());
+    let result = client.restore(&dumps).await;
+    eprintln!();
+    result.or_fail(Failure::Bcl)
+}
+
+/// The name of the file that `backup` writes a block of BCL to.
+fn backup_file_name(preset: Option<u8>) -> String {
+    match preset {
+        Some(n) => format!("preset-{:02}.bcl", n + 1),
+        None => "global.bcl".to_string(),
+    }
+}
+
+/// Describes a block of BCL from a backup.
+fn block_name(preset: Option<u8>) -> String {
+    match preset {
+        Some(n) => format!("preset {}", n + 1),
+        None => "global setup".to_string(),
+    }
+}
+
+/// Returns a progress callback that shows the block and line counts on
+/// standard error, overwriting a single line per block. With a name, for one
+/// of several operations at once, only the start of each block is shown, on
+/// a line of its own, after the name.
+fn show_progress(name: Option<String>) -> impl FnMut(Progress) + Send {
+    let mut label = String::new();
+    move |p| {
+        if let Some(name) = &name {
+            if let Progress::Block {
+                preset,
+                done,
+                total,
+            } = p
+            {
+                eprintln!("{name}: [{}/{total}] {}", done + 1, block_name(preset));
+            }
+            return;
+        }
+        let count = match p {
+            Progress::Block {
+                preset,
+                done,
+                total,
+            } => {
+                if !label.is_empty() {
+                    eprintln!();
+                }
+                label = format!("[{}/{total}] {}", done + 1, block_name(preset));
+                "0 lines".to_string()
+            }
+            Progress::Received { lines } => format!("{lines} lines"),
+            Progress::Sent { lines, total } => format!("{lines}/{total} lines"),
+        };
+        eprint!("\r{label}: {count}");
+    }
+}
+
+/// A B-Control's number, from 1 through 16, model, and identity string.
+type FoundDevice = (u8, BControlModel, String);
+
+/// Describes a found B-Control, with its name from the configuration file
+/// last, if it has one.
+fn describe_found(
+    config: &Config,
+    in_port_name: &str,
+    out_port_name: &str,
+    (dev, model, id_string): &FoundDevice,
+) -> String {
+    match config.device_name(in_port_name, out_port_name, *dev) {
+        Some(name) => format!("{dev}, {model:}, {id_string}, {name}"),
+        None => format!("{dev}, {model:}, {id_string}"),
+    }
+}
+
+async fn list_bcontrols(
+    config: &Config,
+    in_port_name: &str,
+    out_port_name: &str,
+    delay: u64,
+) -> Result<()> {
+    let found = probe_bcontrols(in_port_name, out_port_name, delay).await?;
+    for d in &found {
+        println!("{}", describe_found(config, in_port_name, out_port_name, d));
+    }
+    if found.is_empty() {
+        return Err(fail(Failure::NoResponse, "No B-Control answered."));
+    }
+    Ok(())
+}
+
+/// Probes for B-Controls repeatedly until interrupted, printing the devices
+/// that appear and disappear. The ports are opened afresh for each probe, so
+/// that a device that's unplugged and plugged back in is found again.
+async fn watch_bcontrols(
+    config: &Config,
+    in_port_name: &str,
+    out_port_name: &str,
+    delay: u64,
+    interval: u64,
+) -> Result<()> {
+    let watch = async {
+        let mut known: Vec<FoundDevice> = vec![];
+        let mut ports_ok = true;
+        loop {
+            let found = match probe_bcontrols(in_port_name, out_port_name, delay).await {
+                Ok(found) => {
+                    if !ports_ok {
+                        info!("The MIDI ports are available again.");
+                        ports_ok = true;
+                    }
+                    found
+                }
+                Err(e) => {
+                    if ports_ok {
+                        warn!("{e}");
+                        ports_ok = false;
+                    }
+                    vec![]
+                }
+            };
+            let describe = |d| describe_found(config, in_port_name, out_port_name, d);
+            for d in found.iter().filter(|d| !known.contains(d)) {
+                println!("+ {}", describe(d));
+            }
+            for d in known.iter().filter(|d| !found.contains(d)) {
+                println!("- {}", describe(d));
+            }
+            known = found;
+            tokio::time::sleep(Duration::from_secs(interval)).await;
+        }
+    };
+    select! {
+        _ = watch.fuse() => {},
+        _ = signal::ctrl_c().fuse() => {},
+    };
+    Ok(())
+}
+
+/// Asks every B-Control on the ports to identify itself, and returns those
+/// that answer within `delay` seconds.
+async fn probe_bcontrols(
+    in_port_name: &str,
+    out_port_name: &str,
+    delay: u64,
+) -> Result<Vec<FoundDevice>> {
     let timeout = tokio::time::sleep(Duration::from_secs(delay));
     let midi_in = MidiStream::bind(in_port_name)?
         .filter_map(|m| async move { BControlSysEx::try_from(&m).ok() })
         .take_until(timeout);
 
-    let bdata = BControlSysEx {
-        device: DeviceID::Any,
-        model: BControlModel::Any,
-        command: BControlCommand::RequestIdentity,
-    };
-    let action = |sysex| async {
+    MidiSink::bind(out_port_name)?
+        .send(BControlMessages::default().request_identity())
+        .await?;
+    pin_mut!(midi_in);
+    let mut found = vec![];
+    while let Some(sysex) = midi_in.next().await {
         if let BControlSysEx {
             device: DeviceID::Device(dev),
             model,
             command: BControlCommand::SendIdentity { id_string },
         } = sysex
         {
-            let dev = dev + 1;
-            println!("{dev}, {model:}, {id_string}");
+            found.push((dev + 1, model, id_string.to_string()));
         }
+    }
+    Ok(found)
+}
+
+async fn serve(args: &ServeArgs, config: &Config) -> Result<()> {
+    let ctl_path = args
+        .ctl
+        .as_deref()
+        .map_or_else(default_ctl_path, Path::to_path_buf);
+    let latency = args.latency_interval.map(|secs| LatencyConfig {
+        interval: Duration::from_secs(secs),
+        marker: args.latency_marker,
+    });
+    let standby = args.standby.map(|listen| StandbyConfig {
+        listen,
+        timeout: Duration::from_secs(args.standby_timeout),
+    });
+    let keepalive = args.keepalive.map(|secs| KeepaliveConfig {
+        interval: Duration::from_secs(secs),
+        device: args.keepalive_device - 1,
+    });
+    let device_name = config.device_name(&args.midi_in, &args.midi_out, args.keepalive_device);
+    let failover = (!args.failover.is_empty()).then(|| FailoverConfig {
+        backups: args.failover.clone(),
+        timeout: Duration::from_secs(args.failover_timeout),
+    });
+    let watchdog = args.watchdog_interval.map(|secs| WatchdogConfig {
+        interval: Duration::from_secs(secs),
+        destinations: args.watchdog_to.clone(),
+    });
+    let presets = (!config.preset_mappings().is_empty()).then(|| PresetConfig {
+        mappings: config.preset_mappings().to_vec(),
+        poll: args.preset_poll.map(Duration::from_secs),
+        device: args.preset_device - 1,
+    });
+    let directions = match (args.read_only, args.write_only) {
+        (true, _) => Directions::ToOsc,
+        (_, true) => Directions::ToMidi,
+        _ => Directions::Both,
+    };
+    let svc = BCtlOscSvc::builder()
+        .midi_in(&args.midi_in)
+        .midi_out(&args.midi_out)
+        .osc_in(args.osc_in_addr)
+        .osc_in_extra(&args.osc_in_extra)
+        .osc_out(&args.osc_out_addrs)
+        .osc_out_bind(args.osc_out_bind)
+        .osc_formats(&args.osc_formats)
+        .mapping_file(args.mappings.as_deref())
+        .profile(args.profile.as_deref())
+        .ctl_path(&ctl_path)
+        .latency(latency)
+        .log_unmatched(args.log_unmatched)
+        .unmatched_osc(args.unmatched_osc)
+        .raw_midi(args.raw_midi)
+        .standby(standby)
+        .heartbeat_to(args.heartbeat_to)
+        .status_interval(args.status_interval.map(Duration::from_secs))
+        .keepalive(keepalive)
+        .device_name(device_name)
+        .failover(failover)
+        .coercion(args.coercion)
+        .sysex_interval(Duration::from_millis(args.sysex_interval))
+        .midi_rebind(args.midi_rebind.map(Duration::from_secs))
+        .watchdog(watchdog)
+        .trace_size(args.trace_size)
+        .ranges_file(args.ranges.as_deref())
+        .learn_ranges(args.learn_ranges)
+        .namespaces(&args.namespaces)
+        .capture_file(args.capture.as_deref())
+        .presets(presets)
+        .surface(&surface_members(config))
+        .directions(directions)
+        .force(args.force)
+        .build();
+    if args.check {
+        return check_serve(&svc).await;
+    }
+    // Bad mappings are reported before anything starts.
+    load_mappings(args.profile.as_deref(), args.mappings.as_deref())?;
+    select! {
+        r = svc.run().fuse() => {
+            if let Err(e) = r {
+                return Err(fail(exit::classify(&*e), e.to_string()));
+            }
+            info!("Stopped.");
+        },
+        _ = signal::ctrl_c().fuse() => {svc.stop().await; },
     };
-    MidiSink::bind(out_port_name)?
-        .send(MidiMessage::from(&bdata))
-        .await?;
-    midi_in.for_each(action).await;
     Ok(())
 }
 
-async fn serve(
-    midi_in: &str,
-    midi_out: &str,
-    osc_in_addr: &SocketAddr,
-    osc_out_addrs: &[SocketAddr],
-) -> Result<()> {
-    {
-        let mut svc = BCtlOscSvc::new(midi_in, midi_out, osc_in_addr, osc_out_addrs);
-        select! {
-            _ = svc.run().fuse() => {info!("Stopped.");},
-            _ = signal::ctrl_c().fuse() => {svc.stop().await; },
-        };
+/// Returns the further devices of the surface that the configuration file
+/// gives.
+fn surface_members(config: &Config) -> Vec<SurfaceMember> {
+    config
+        .surface_devices()
+        .iter()
+        .map(|(port, channels)| SurfaceMember {
+            port: port.clone(),
+            channels: channels.clone(),
+        })
+        .collect()
+}
+
+/// Prints a readiness report for `serve --check`.
+async fn check_serve(svc: &BCtlOscSvc) -> Result<()> {
+    let checks = svc.check().await;
+    for c in &checks {
+        match &c.failure {
+            None => println!("ok    {}", c.name),
+            Some(e) => println!("FAIL  {}: {e}", c.name),
+        }
+    }
+    let failed = checks.iter().filter(|c| c.failure.is_some()).count();
+    if failed == 0 {
+        println!("Ready.");
+        Ok(())
+    } else {
+        Err(format!("not ready: {failed} of {} checks failed", checks.len()).into())
+    }
+}
+
+fn load_mappings(profile: Option<&str>, mappings: Option<&Path>) -> Result<ServerTranslationSet> {
+    ServerTranslationSet::configured(profile, mappings)
+        .and_then(TranslationSetBuilder::build)
+        .map_err(|e| fail(Failure::Config, e.to_string()))
+}
+
+fn capabilities(profile: Option<&str>, mappings: Option<&Path>) -> Result<()> {
+    let set = load_mappings(profile, mappings)?;
+    for line in set.coverage_report() {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+fn show_mappings(profile: Option<&str>, mappings: Option<&Path>) -> Result<()> {
+    let set = load_mappings(profile, mappings)?;
+    for line in set.mapping_report() {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+fn verify(profile: Option<&str>, mappings: Option<&Path>, cases: &Path) -> Result<()> {
+    let set = load_mappings(profile, mappings)?;
+    let cases = read_golden(cases).map_err(|e| fail(Failure::Config, e.to_string()))?;
+    let failures = set.check_golden(&cases);
+    for f in &failures {
+        println!("{f}");
+    }
+    if failures.is_empty() {
+        println!("All {} cases passed.", cases.len());
+        Ok(())
+    } else {
+        Err(format!("{} of {} cases failed", failures.len(), cases.len()).into())
+    }
+}
+
+async fn self_test(profile: Option<&str>, mappings: Option<&Path>, limit: usize) -> Result<()> {
+    load_mappings(profile, mappings)?;
+    let checks = run_self_test(profile, mappings, limit)
+        .await
+        .map_err(|e| e.to_string())?;
+    for c in &checks {
+        match &c.failure {
+            None => println!("pass  {}", c.name),
+            Some(e) => println!("FAIL  {}: {e}", c.name),
+        }
+    }
+    let failed = checks.iter().filter(|c| c.failure.is_some()).count();
+    if failed == 0 {
+        println!("All {} checks passed.", checks.len());
         Ok(())
+    } else {
+        Err(format!("{failed} of {} checks failed", checks.len()).into())
+    }
+}
+
+async fn loadtest(mut config: LoadTestConfig) -> Result<()> {
+    if config.addresses.is_empty() {
+        // Those of ServerTranslationSet::test_mappings.
+        config.addresses = vec![("/encoder/1".to_string(), 1), ("/key/1".to_string(), 1)];
+    }
+    println!(
+        "Sending {} OSC messages per second to {} for {:?}.",
+        config.rate, config.target, config.duration
+    );
+    let report = run_load_test(&config).await.map_err(|e| e.to_string())?;
+    for line in report.lines() {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+async fn ctl(socket: Option<&Path>, command: &[String]) -> Result<()> {
+    let socket = socket.map_or_else(default_ctl_path, Path::to_path_buf);
+    for line in ctl_request(&socket, &command.join(" ")).await? {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+/// Prints the events in a capture file, one per line.
+fn capture_dump(file: &Path) -> Result<()> {
+    let (start, events) = read_capture(file).map_err(|e| format!("{}: {e}", file.display()))?;
+    if let Ok(t) = start.duration_since(std::time::UNIX_EPOCH) {
+        println!("# started at {:.6} (Unix time)", t.as_secs_f64());
+    }
+    for event in &events {
+        println!("{event}");
     }
+    Ok(())
+}
+
+/// Prints what the MIDI and OSC received in a capture file translate to.
+fn capture_replay(profile: Option<&str>, mappings: Option<&Path>, file: &Path) -> Result<()> {
+    let set = load_mappings(profile, mappings)?;
+    let (_, events) = read_capture(file).map_err(|e| format!("{}: {e}", file.display()))?;
+    for line in replay(&set, &events) {
+        println!("{line}");
+    }
+    Ok(())
 }
@@ -1,25 +1,133 @@
 //! MIDI/OSC translator for Behringer BCR2000
 //!
-//! An OSC server receives OSC packets at a configured UDP port and translates
-//! them to MIDI/BCL messages sent to a BCR2000.
+//! The service receives OSC at one or more UDP ports and translates it to
+//! MIDI for the device, and translates the MIDI the device sends to OSC for
+//! one or more UDP destinations. OSC addressed under `/bcr2kosc/` isn't
+//! translated; it requests device operations, or sets the state that
+//! mappings' conditions are checked against. The service is configured
+//! with a builder, in which every piece is optional, and can translate in
+//! one direction only; see `Directions`.
 //!
-//! An OSC client listens for MIDI/BCL messages from a BCR2000, translates them
-//! to OSC packets, and sends them to one or more configured UDP destinations.
+//! - `admin`: device operations requested over OSC
+//! - `builder`: configuration of the service
+//! - `capture`: capture of all MIDI and OSC to a file, and its replay
+//! - `check`: whether the service could start, without running it
+//! - `coalesce`: coalescing of MIDI under backlog, newest value first
+//! - `ctl`: the local control socket
+//! - `destinations`: checks of OSC destinations when the service starts
+//! - `encode`: encoding of OSC into a reusable buffer
+//! - `failover`: backup OSC destinations
+//! - `format`: formatting of OSC for each destination
+//! - `fragment`: splitting of bundles to fit each destination
+//! - `input`: the sockets on which OSC is received
+//! - `keepalive`: pings of the device
+//! - `latency`: measurement of round-trip latency
+//! - `learn`: learning the ranges of control values
+//! - `loadtest`: throughput under synthetic load
+//! - `monitor`: notification of destinations when ports or the device go
+//!   offline
+//! - `namespace`: further OSC namespaces, with their own MIDI and mappings
+//! - `presets`: switching of mappings with the device's preset
+//! - `self_test`: a self-test of the whole pipeline
+//! - `standby`: a hot standby for a primary instance
+//! - `supervisor`: restarting of failed translation tasks
+//! - `surface`: several devices served as one surface
+//! - `sync`: the state of the controls, for OSC clients that ask
+//! - `timing`: timing of translation, in debug builds
+//! - `trace`: recent translations, kept for inspection
+//! - `unmatched`: counting and logging of messages no mapping handles
+//! - `watchdog`: heartbeats for external watchdogs
 
 use std::error::Error;
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use crate::midi_io::{MidiSink, MidiStream};
-use crate::translator::ServerTranslationSet;
+use crate::translator::{
+    packet_messages, Coercion, LearnedRanges, ServerTranslationSet, SlewLimiter, SlewedMidi,
+    TranslationSetBuilder,
+};
 use crate::PGM;
-use futures::future::join;
-use futures::{pin_mut, select, Future, FutureExt, Sink, SinkExt, Stream, StreamExt};
-use log::{debug, error, info};
-use midi_control::MidiMessage;
-use rosc::encoder::encode;
+use arc_swap::ArcSwap;
+use futures::channel::mpsc;
+use futures::future::{join_all, OptionFuture};
+use futures::{join, pin_mut, select, Future, FutureExt, Sink, SinkExt, Stream, StreamExt};
+use midi_io::{all_messages, MidiMessage, MidiSink, MidiStream, SharedMidiInput};
+use rosc::{OscMessage, OscPacket};
 use tokio::net::UdpSocket;
 use tokio::sync::Notify;
+use tokio::time::MissedTickBehavior;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, debug_span, error, info, info_span, Instrument, Span};
+
+mod admin;
+mod builder;
+mod capture;
+mod check;
+mod coalesce;
+mod ctl;
+mod destinations;
+mod encode;
+mod failover;
+mod format;
+mod fragment;
+mod input;
+mod keepalive;
+mod latency;
+mod learn;
+mod loadtest;
+mod monitor;
+mod namespace;
+mod presets;
+mod self_test;
+mod standby;
+mod supervisor;
+mod surface;
+mod sync;
+#[cfg(test)]
+mod testing;
+mod timing;
+mod trace;
+mod unmatched;
+mod watchdog;
+use admin::Admin;
+pub use builder::BCtlOscSvcBuilder;
+use capture::Capture;
+pub use capture::{read_capture, replay};
+use coalesce::{Coalescer, Priority};
+pub use ctl::{ctl_request, default_ctl_path};
+use ctl::{Control, Reports};
+use encode::encode_into;
+use failover::Destinations;
+pub use failover::FailoverConfig;
+use format::Formats;
+pub use format::OscFormat;
+use input::OscInput;
+use keepalive::Keepalive;
+pub use keepalive::KeepaliveConfig;
+pub use latency::LatencyConfig;
+use latency::LatencyProbe;
+use learn::RangeLearner;
+pub use loadtest::{run_load_test, LoadTestConfig};
+use monitor::Monitor;
+use namespace::Namespace;
+pub use namespace::NamespaceConfig;
+pub use presets::PresetConfig;
+use presets::PresetSwitcher;
+pub use self_test::{run_self_test, Check};
+pub use standby::StandbyConfig;
+use supervisor::supervise;
+use surface::Surface;
+pub use surface::SurfaceMember;
+use sync::ClientSync;
+use timing::TranslationTimes;
+use trace::TraceLog;
+pub use trace::DEFAULT_TRACE_SIZE;
+pub use unmatched::UnmatchedAction;
+use unmatched::UnmatchedLog;
+pub use watchdog::WatchdogConfig;
 
 type Result<T> = std::result::Result<T, Box<dyn Error>>;
 
@@ -28,94 +136,903 @@ type Result<T> = std::result::Result<T, Box<dyn Error>>;
 /// convenient to abstract this while experimenting.
 type StopMechanism = Arc<Notify>;
 
+/// How often ramps of slewed control changes are advanced.
+const SLEW_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The translation set in use, which can be replaced while the service runs.
+type Translations = Arc<ArcSwap<ServerTranslationSet>>;
+
+/// Builds the mappings supplied to a `BCtlOscSvcBuilder`, in place of a
+/// profile and mapping file.
+type CustomMappings = Arc<dyn Fn() -> Result<TranslationSetBuilder> + Send + Sync>;
+
+/// Where the service's mappings come from, so that they can be rebuilt.
+#[derive(Clone)]
+struct MappingSource {
+    /// A built-in profile, if any.
+    profile: Option<String>,
+    /// A mapping file. Without a profile or a file, a small test set is used.
+    file: Option<PathBuf>,
+    /// Mappings supplied by the service's owner, used instead of the profile
+    /// and file.
+    custom: Option<CustomMappings>,
+    /// Mappings added while the service is running.
+    added: Vec<String>,
+    /// The coercion policy of mappings that don't set their own.
+    coercion: Coercion,
+    /// Whether the raw MIDI namespace is added to the mappings.
+    raw_midi: bool,
+    /// Learned ranges by which the mappings are calibrated, if any.
+    ranges_file: Option<PathBuf>,
+}
+
+/// The mapping source, shared by the tasks that change or report it.
+type SharedMappings = Arc<Mutex<MappingSource>>;
+
+impl MappingSource {
+    fn build(&self) -> Result<ServerTranslationSet> {
+        let base = match &self.custom {
+            Some(custom) => custom()?,
+            None => {
+                ServerTranslationSet::configured(self.profile.as_deref(), self.file.as_deref())?
+            }
+        };
+        let mut builder = base.text(&self.added)?;
+        if self.raw_midi {
+            builder = builder.raw_midi();
+        }
+        let mut set = builder.build()?.with_coercion(self.coercion);
+        if let Some(file) = &self.ranges_file {
+            set = set.with_ranges(LearnedRanges::load(file)?);
+        }
+        Ok(set)
+    }
+}
+
+/// What the translation tasks share: where translated OSC goes, and what
+/// they report to.
+#[derive(Clone)]
+struct Pipeline {
+    directions: Directions,
+    destinations: Arc<Destinations>,
+    formats: Arc<Formats>,
+    admin: Arc<Admin>,
+    sync: Arc<ClientSync>,
+    unmatched: Arc<UnmatchedLog>,
+    trace: Arc<TraceLog>,
+    times: Arc<TranslationTimes>,
+    capture: Option<Arc<Capture>>,
+}
+
+/// The directions in which the service translates.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Directions {
+    /// MIDI to OSC, and OSC to MIDI.
+    #[default]
+    Both,
+    /// Only MIDI to OSC. No MIDI is sent, so the device is never changed:
+    /// OSC isn't translated to MIDI, device operations are refused, and
+    /// the MIDI output isn't opened. OSC addressed to the service itself,
+    /// such as requests to sync, is still handled.
+    ToOsc,
+    /// Only OSC to MIDI, for a device that only shows what OSC sets. The
+    /// MIDI input isn't opened, and nothing listens for MIDI. Device
+    /// operations that wait for the device's answer are refused.
+    ToMidi,
+}
+
+impl Directions {
+    /// Returns true if OSC is translated to MIDI.
+    pub fn to_midi(self) -> bool {
+        self != Directions::ToOsc
+    }
+
+    /// Returns true if MIDI is translated to OSC.
+    pub fn to_osc(self) -> bool {
+        self != Directions::ToMidi
+    }
+}
+
+impl std::fmt::Display for Directions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Directions::Both => "MIDI to OSC, OSC to MIDI",
+            Directions::ToOsc => "MIDI to OSC only",
+            Directions::ToMidi => "OSC to MIDI only",
+        })
+    }
+}
+
+/// Where the service receives OSC.
+enum OscIn {
+    /// A socket bound to this address when the service runs.
+    Addr(SocketAddr),
+    /// A socket supplied by the service's owner.
+    Socket(Arc<UdpSocket>),
+}
+
+/// A stream of MIDI to translate.
+type MidiSource = Pin<Box<dyn Stream<Item = MidiMessage> + Send>>;
+
+/// Where the service receives MIDI.
+enum MidiIn {
+    /// The MIDI port, or RTP-MIDI host, with this name.
+    Port(String),
+    /// A stream supplied by the service's owner, which is used up when the
+    /// service runs.
+    Stream(Mutex<Option<MidiSource>>),
+    /// Nothing. No MIDI is translated to OSC.
+    None,
+}
+
+impl MidiIn {
+    fn port_name(&self) -> Option<&str> {
+        match self {
+            MidiIn::Port(name) => Some(name),
+            _ => None,
+        }
+    }
+}
+
+/// Where the service sends MIDI.
+enum MidiOut {
+    /// The MIDI port, or RTP-MIDI host, with this name.
+    Port(String),
+    /// A sink supplied by the service's owner.
+    Sink(MidiSink),
+    /// Nowhere. MIDI translated from OSC is dropped.
+    None,
+}
+
+impl MidiOut {
+    fn port_name(&self) -> Option<&str> {
+        match self {
+            MidiOut::Port(name) => Some(name),
+            _ => None,
+        }
+    }
+}
+
 /// Represents the OSC client/server. The start method starts listeners for OSC
 /// and MIDI traffic. The stop method shuts everything down.
 ///
+/// The service is configured with a `BCtlOscSvcBuilder`, from `builder`.
+///
 /// You should call stop before dropping this object. Otherwise the I/O tasks
 /// will continue running, with no way to stop them.
 ///
 pub struct BCtlOscSvc {
-    pub midi_in_port_name: String,
-    pub midi_out_port_name: String,
-    pub osc_in_addr: SocketAddr,
-    pub osc_out_addrs: Arc<Vec<SocketAddr>>,
+    midi_in: MidiIn,
+    midi_out: MidiOut,
+    osc_in: OscIn,
+    /// Further addresses on which to listen for OSC. Their traffic is
+    /// translated like that received at `osc_in`.
+    osc_in_extra_addrs: Vec<SocketAddr>,
+    osc_out_addrs: Arc<Vec<SocketAddr>>,
+    /// The formats of OSC destinations that don't use the default. See the
+    /// `format` module.
+    osc_formats: Vec<(SocketAddr, OscFormat)>,
+    mapping_file: Option<PathBuf>,
+    /// A built-in set of mappings, to which those in `mapping_file` are
+    /// added. See `ServerTranslationSet::profile`.
+    profile: Option<String>,
+    /// Mappings used instead of the profile and mapping file.
+    custom_mappings: Option<CustomMappings>,
+    /// The control socket, if any.
+    ctl_path: Option<PathBuf>,
+    /// Latency measurement, which is off by default.
+    latency: Option<LatencyConfig>,
+    /// The local address from which translated OSC is sent. By default, it's
+    /// sent from the socket that receives OSC.
+    osc_out_bind: Option<SocketAddr>,
+    /// Whether messages that no mapping handles are logged. They're counted
+    /// regardless.
+    log_unmatched: bool,
+    /// What's done with OSC messages that no mapping handles. They're
+    /// dropped by default.
+    unmatched_osc: UnmatchedAction,
+    /// Whether all MIDI is also translated to and from the raw MIDI
+    /// namespace, under `/midi/`. Off by default. See `RawMidiTranslator`.
+    raw_midi: bool,
+    /// Run as a standby, waiting for a primary's heartbeats to stop before
+    /// starting.
+    standby: Option<StandbyConfig>,
+    /// Where to send heartbeats, if a standby is watching this instance.
+    heartbeat_to: Option<SocketAddr>,
+    /// How often to check the MIDI ports and device, notifying OSC
+    /// destinations of changes. Off by default.
+    status_interval: Option<Duration>,
+    /// Device health pings, which are off by default.
+    keepalive: Option<KeepaliveConfig>,
+    /// The name of the device that's pinged, if it has one.
+    device_name: Option<String>,
+    /// Backup OSC destinations, used when those in `osc_out_addrs` stop
+    /// answering pings. Off by default.
+    failover: Option<FailoverConfig>,
+    /// How OSC arguments of unexpected types are treated, by mappings that
+    /// don't say. Permissive by default.
+    coercion: Coercion,
+    /// The minimum interval between SysEx messages sent to the device, such
+    /// as BCL sent by device operations. Translated control changes are sent
+    /// in between, ahead of queued SysEx. Zero by default.
+    sysex_interval: Duration,
+    /// The interval at which the MIDI output port is reopened after it
+    /// fails, such as when the device is unplugged, until it's back. Off by
+    /// default, which leaves the service unable to send MIDI once the port
+    /// fails.
+    midi_rebind: Option<Duration>,
+    /// Heartbeats for external watchdogs, which are off by default.
+    watchdog: Option<WatchdogConfig>,
+    /// The number of recent translations kept for the `trace` command. Zero
+    /// turns tracing off.
+    trace_size: usize,
+    /// A file of learned ranges, by which mappings are calibrated.
+    ranges_file: Option<PathBuf>,
+    /// Whether the ranges of control values are learned, and saved to
+    /// `ranges_file`.
+    learn_ranges: bool,
+    /// Further OSC namespaces, each with its own MIDI ports and mappings.
+    /// See the `namespace` module.
+    namespaces: Vec<NamespaceConfig>,
+    /// A file to capture all MIDI and OSC to. See the `capture` module.
+    capture_file: Option<PathBuf>,
+    /// Switching of mappings with the device's preset, which is off by
+    /// default. See the `presets` module.
+    presets: Option<PresetConfig>,
+    /// Whether the service starts even if an OSC destination can't be sent
+    /// to. See the `destinations` module.
+    force: bool,
+    /// Further devices that make a surface with the service's own. See the
+    /// `surface` module.
+    surface: Vec<SurfaceMember>,
+    /// The directions in which the service translates. Both by default.
+    directions: Directions,
 
+    /// The translation set in use, shared with the tasks that translate or
+    /// replace it.
+    translations: Translations,
     stopper: StopMechanism,
+    /// Cancels device operations in progress when the service stops.
+    cancel: CancellationToken,
 }
 impl BCtlOscSvc {
-    /// Create a new B-Control OSC service object.
-    ///
-    /// The MIDI input and output ports should be chosen such that MIDI commands
-    /// will reach your B-Control devices, and replies from the controllers will
-    /// make it back to this service.
-    ///
-    pub fn new(
-        midi_in_port_name: &str,
-        midi_out_port_name: &str,
-        osc_in_addr: &SocketAddr,
-        osc_out_addrs: &[SocketAddr],
-    ) -> Self {
-        BCtlOscSvc {
-            midi_in_port_name: midi_in_port_name.to_string(),
-            midi_out_port_name: midi_out_port_name.to_string(),
-            osc_in_addr: osc_in_addr.clone(),
-            osc_out_addrs: Arc::new(osc_out_addrs.to_vec()),
-            stopper: Arc::new(Notify::new()),
-        }
+    /// Returns a builder for a B-Control OSC service.
+    pub fn builder() -> BCtlOscSvcBuilder {
+        BCtlOscSvcBuilder::default()
     }
 
     /// Run the service.
-    pub async fn run(&mut self) -> Result<()> {
-        // We use a single UDP socket for sending and receiving.
-        let udp_socket = Arc::new(UdpSocket::bind(self.osc_in_addr).await?);
-        let xset = Arc::new(ServerTranslationSet::get_test_set()?);
-
-        // MIDI -> OSC
-        let midi_rx = MidiStream::bind(&self.midi_in_port_name)?;
-        info!(
-            "{PGM} is listening for MIDI on \"{}\"",
-            self.midi_in_port_name
-        );
-        let midi_to_osc = self.start_midi_to_osc(midi_rx, &udp_socket, &xset);
+    pub async fn run(&self) -> Result<()> {
+        let started = Instant::now();
+        // A standby takes nothing over until the primary fails.
+        let mut added = vec![];
+        if let Some(config) = self.standby {
+            select! {
+                r = standby::wait_for_failure(config).fuse() => added = r?,
+                _ = wait_on_stopping(self.stopper.clone()).fuse() => return Ok(()),
+            };
+        }
+
+        // Unless told otherwise, we use a single UDP socket for sending and
+        // receiving. Replies to OSC requests and latency probes always come
+        // from the first receiving socket, so that answers come back to it.
+        let first = match &self.osc_in {
+            OscIn::Addr(addr) => OscInput::bind(*addr).await?,
+            OscIn::Socket(socket) => OscInput::from_socket(socket.clone())?,
+        };
+        let mut inputs = vec![Arc::new(first)];
+        for a in &self.osc_in_extra_addrs {
+            inputs.push(Arc::new(OscInput::bind(*a).await?));
+        }
+        let bound: Vec<SocketAddr> = inputs
+            .iter()
+            .filter_map(|i| i.socket.local_addr().ok())
+            .collect();
+        destinations::check(&self.all_destinations(), &bound, self.force)?;
+        let udp_socket = inputs[0].socket.clone();
+        let osc_out_socket = match self.osc_out_bind {
+            Some(addr) => Arc::new(UdpSocket::bind(addr).await?),
+            None => udp_socket.clone(),
+        };
+        let mappings = self.mapping_source(added);
+        self.set_translations(mappings.build()?);
+        let xset = self.translations.clone();
+        let mappings: SharedMappings = Arc::new(Mutex::new(mappings));
+
+        // The MIDI ports are opened once, and shared between translation and
+        // device operations.
+        let midi_rx = self.open_midi_in()?;
+        let midi_in = SharedMidiInput::default();
+        let midi_tx = self.open_midi_out()?;
+        let (surface, midi_rx, midi_tx) = self.open_surface(midi_rx, midi_tx)?;
+        let surface = self.start_surface(surface);
+        let opened = self.open_namespaces()?;
+        let namespaces: Vec<Namespace> = opened.iter().map(|(ns, _, _)| ns.clone()).collect();
+        let trace = Arc::new(TraceLog::new(self.trace_size));
+        let times = Arc::new(TranslationTimes::default());
+        let capture = match &self.capture_file {
+            Some(path) => Some(Arc::new(
+                Capture::create(path).map_err(|e| format!("{}: {e}", path.display()))?,
+            )),
+            None => None,
+        };
+        let admin = Arc::new(Admin::new(
+            midi_in.clone(),
+            midi_tx.clone(),
+            udp_socket.clone(),
+            trace.clone(),
+            self.cancel.clone(),
+            self.directions,
+        ));
+
+        let probe = self.latency.map(|c| Arc::new(LatencyProbe::new(c)));
+        let latency = self.start_latency(&probe, &midi_in, &midi_tx, &udp_socket);
+        let unmatched = Arc::new(UnmatchedLog::new(self.log_unmatched, self.unmatched_osc));
+        let destinations = Arc::new(Destinations::new(
+            self.osc_out_addrs.clone(),
+            self.failover.as_ref(),
+        ));
+        let failover = self.start_failover(&destinations, &udp_socket);
+        let formats = Arc::new(Formats::new(&self.osc_formats));
+        let sync = Arc::new(ClientSync::new(
+            destinations.clone(),
+            formats.clone(),
+            osc_out_socket.clone(),
+        ));
+        let pipeline = Pipeline {
+            directions: self.directions,
+            destinations: destinations.clone(),
+            formats,
+            admin: admin.clone(),
+            sync,
+            unmatched: unmatched.clone(),
+            trace: trace.clone(),
+            times: times.clone(),
+            capture: capture.clone(),
+        };
 
-        // OSC -> MIDI
-        let midi_tx = MidiSink::bind(&self.midi_out_port_name)?;
-        info!("{PGM} will send MIDI to \"{}\".", self.midi_out_port_name);
-        let osc_to_midi = self.start_osc_to_midi(&udp_socket, midi_tx, &xset);
+        // MIDI -> OSC. Without it, nothing listens for MIDI, and without
+        // OSC -> MIDI, nothing sends it.
+        let (to_osc, to_midi) = (self.directions.to_osc(), self.directions.to_midi());
+        let midi_to_osc = self.start_midi_to_osc(&midi_in, &osc_out_socket, &xset, &pipeline);
+        let midi_to_osc = OptionFuture::from(to_osc.then_some(midi_to_osc));
 
-        join(midi_to_osc, osc_to_midi).await;
+        // OSC -> MIDI. Replies to pings go to both the latency probe and
+        // failover.
+        let on_pong = {
+            let (probe, destinations) = (probe.clone(), destinations.clone());
+            move |sender: SocketAddr, msg: &OscMessage| {
+                if let Some(probe) = &probe {
+                    probe.pong(sender, msg);
+                }
+                destinations.pong(sender);
+            }
+        };
+        let outbox = Arc::new(Coalescer::default());
+        let osc_to_midi =
+            self.start_osc_to_midi(&inputs, &outbox, &xset, &namespaces, on_pong, &pipeline);
+        let midi_sender = self.start_midi_sender(&outbox, midi_tx, &capture);
+        let midi_sender = OptionFuture::from(to_midi.then_some(midi_sender));
+
+        // Each namespace distributes, translates and sends its own MIDI.
+        let namespaced = join_all(opened.into_iter().map(|(ns, midi_rx, midi_tx)| {
+            let ns_midi_in = SharedMidiInput::default();
+            let midi_to_osc = run_midi_to_osc(
+                self.stopper.clone(),
+                ns_midi_in.clone(),
+                osc_out_socket.clone(),
+                ns.xset.clone(),
+                Some(ns.prefix.clone()),
+                pipeline.clone(),
+            );
+            let stopper = self.stopper.clone();
+            let sender =
+                run_midi_sender(stopper.clone(), ns.outbox.clone(), midi_tx, capture.clone());
+            let distribution = run_midi_distribution(stopper, midi_rx, ns_midi_in, capture.clone());
+            async move {
+                join!(
+                    OptionFuture::from(to_osc.then_some(distribution)),
+                    OptionFuture::from(to_osc.then_some(midi_to_osc)),
+                    OptionFuture::from(to_midi.then_some(sender))
+                );
+            }
+        }));
+
+        let distribution = self.start_midi_distribution(midi_rx, midi_in.clone(), &capture);
+        let distribution = OptionFuture::from(to_osc.then_some(distribution));
+        let heartbeat = self.start_heartbeat(&udp_socket, &mappings);
+        let keepalive = self
+            .keepalive
+            .map(|c| Arc::new(Keepalive::new(c, self.device_name.clone())));
+        let pings = self.start_keepalive(&keepalive, &admin);
+        let monitor = self.start_monitor(&keepalive, &udp_socket);
+        let watchdog = self.start_watchdog(started, &osc_out_socket);
+        let learner = if self.learn_ranges {
+            let ranges = match &self.ranges_file {
+                Some(file) => LearnedRanges::load(file)?,
+                None => LearnedRanges::default(),
+            };
+            Some(Arc::new(RangeLearner::new(
+                ranges,
+                self.ranges_file.clone(),
+            )))
+        } else {
+            None
+        };
+        let learning = self.start_learning(&learner, &midi_in);
+        let switcher = self
+            .presets
+            .clone()
+            .map(|c| Arc::new(PresetSwitcher::new(c, mappings.clone(), xset.clone())));
+        let switching = self.start_preset_switching(&switcher, &midi_in, &admin);
+
+        // Control socket
+        let reports = Reports {
+            inputs,
+            outbox,
+            unmatched,
+            latency: probe,
+            keepalive,
+            destinations,
+            trace,
+            times,
+            ranges: learner,
+        };
+        let control = Control::new(self.status(), xset.clone(), mappings, admin, reports);
+        let ctl = self.start_ctl(control);
+
+        join!(
+            distribution,
+            midi_to_osc,
+            osc_to_midi,
+            midi_sender,
+            ctl,
+            latency,
+            heartbeat,
+            pings,
+            monitor,
+            watchdog,
+            failover,
+            learning,
+            switching,
+            surface,
+            namespaced
+        );
         Ok(())
     }
 
-    /// Stop the I/O tasks started by start(). Returns after all tasks have
-    /// terminated.
-    pub async fn stop(&mut self) {
+    /// Tells the I/O tasks started by `run` to stop, without waiting for
+    /// them; `run` returns once they have.
+    pub async fn stop(&self) {
+        self.cancel.cancel();
         self.stopper.notify_waiters();
     }
 
+    /// Returns the source of the service's mappings, with `added` mappings
+    /// taken over from a primary.
+    fn mapping_source(&self, added: Vec<String>) -> MappingSource {
+        MappingSource {
+            profile: self.profile.clone(),
+            file: self.mapping_file.clone(),
+            custom: self.custom_mappings.clone(),
+            added,
+            coercion: self.coercion,
+            raw_midi: self.raw_midi,
+            ranges_file: self.ranges_file.clone(),
+        }
+    }
+
+    /// Returns the source of a namespace's mappings.
+    fn namespace_mappings(&self, config: &NamespaceConfig) -> MappingSource {
+        MappingSource {
+            profile: None,
+            file: config.mappings.clone(),
+            custom: None,
+            added: vec![],
+            coercion: self.coercion,
+            raw_midi: false,
+            ranges_file: None,
+        }
+    }
+
+    /// Builds the namespaces' mappings, and opens their MIDI ports.
+    fn open_namespaces(&self) -> Result<Vec<(Namespace, MidiSource, MidiSink)>> {
+        self.namespaces
+            .iter()
+            .map(|config| {
+                let set = self
+                    .namespace_mappings(config)
+                    .build()
+                    .map_err(|e| format!("namespace {}: {e}", config.prefix))?;
+                let midi_rx: MidiSource = if self.directions.to_osc() {
+                    Box::pin(
+                        MidiStream::builder()
+                            .client_name(PGM)
+                            .bind(&config.midi_in)?,
+                    )
+                } else {
+                    Box::pin(futures::stream::pending())
+                };
+                let builder = MidiSink::builder()
+                    .client_name(PGM)
+                    .sysex_interval(self.sysex_interval);
+                let midi_tx = if self.directions.to_midi() {
+                    builder.bind(&config.midi_out)?
+                } else {
+                    builder.discard()
+                };
+                info!(
+                    "{PGM} serves {} with MIDI from \"{}\" and to \"{}\".",
+                    config.prefix, config.midi_in, config.midi_out
+                );
+                let ns = Namespace {
+                    prefix: config.prefix.clone(),
+                    xset: Arc::new(ArcSwap::from_pointee(set)),
+                    outbox: Arc::new(Coalescer::default()),
+                };
+                Ok((ns, midi_rx, midi_tx))
+            })
+            .collect()
+    }
+
+    /// Opens the stream of MIDI to translate.
+    fn open_midi_in(&self) -> Result<MidiSource> {
+        if !self.directions.to_osc() {
+            return Ok(Box::pin(futures::stream::pending()));
+        }
+        Ok(match &self.midi_in {
+            MidiIn::Port(name) => {
+                let stream = MidiStream::builder().client_name(PGM).bind(name)?;
+                info!("{PGM} is listening for MIDI on \"{name}\"");
+                Box::pin(stream)
+            }
+            MidiIn::Stream(stream) => stream
+                .lock()
+                .unwrap()
+                .take()
+                .ok_or("the MIDI stream was already used")?,
+            MidiIn::None => Box::pin(futures::stream::pending()),
+        })
+    }
+
+    /// Opens the sink for translated MIDI and device operations.
+    fn open_midi_out(&self) -> Result<MidiSink> {
+        let builder = MidiSink::builder()
+            .client_name(PGM)
+            .sysex_interval(self.sysex_interval);
+        if !self.directions.to_midi() {
+            return Ok(builder.discard());
+        }
+        Ok(match &self.midi_out {
+            MidiOut::Port(name) => {
+                let builder = match self.midi_rebind {
+                    Some(interval) => builder.rebind(interval),
+                    None => builder,
+                };
+                let sink = builder.bind(name)?;
+                info!("{PGM} will send MIDI to \"{name}\".");
+                sink
+            }
+            MidiOut::Sink(sink) => sink.clone(),
+            MidiOut::None => builder.discard(),
+        })
+    }
+
+    /// Joins the service's own MIDI with that of the surface's other devices,
+    /// if it has any.
+    fn open_surface(
+        &self,
+        midi_rx: MidiSource,
+        midi_tx: MidiSink,
+    ) -> Result<(Option<Surface>, MidiSource, MidiSink)> {
+        if self.surface.is_empty() {
+            return Ok((None, midi_rx, midi_tx));
+        }
+        let (surface, midi_rx, midi_tx) = Surface::open(
+            &self.surface,
+            midi_rx,
+            midi_tx,
+            self.sysex_interval,
+            self.directions,
+        )?;
+        Ok((Some(surface), midi_rx, midi_tx))
+    }
+
+    /// Replaces the translation set. While the service runs, translation
+    /// continues with the new set from the next message. Until the service
+    /// runs, and whenever mappings are reloaded or added through the control
+    /// socket, the set is rebuilt from the configured mappings instead.
+    pub fn set_translations(&self, set: ServerTranslationSet) {
+        self.translations.store(Arc::new(set));
+    }
+
+    /// Returns the OSC destinations, followed by any backups.
+    fn all_destinations(&self) -> Vec<SocketAddr> {
+        let mut all = self.osc_out_addrs.to_vec();
+        if let Some(config) = &self.failover {
+            all.extend(config.backups.iter().flatten());
+        }
+        all
+    }
+
+    /// Describes the service's configuration.
+    fn status(&self) -> Vec<String> {
+        let mut status = vec![
+            format!("translating: {}", self.directions),
+            format!(
+                "MIDI in: {}",
+                match &self.midi_in {
+                    _ if !self.directions.to_osc() => "(none, output only)",
+                    MidiIn::Port(name) => name.as_str(),
+                    MidiIn::Stream(_) => "(stream)",
+                    MidiIn::None => "(none)",
+                }
+            ),
+            format!(
+                "MIDI out: {}",
+                match &self.midi_out {
+                    _ if !self.directions.to_midi() => "(none, read-only)",
+                    MidiOut::Port(name) => name.as_str(),
+                    MidiOut::Sink(_) => "(sink)",
+                    MidiOut::None => "(none)",
+                }
+            ),
+        ];
+        match &self.osc_in {
+            OscIn::Addr(a) => status.push(format!("OSC in: {a}")),
+            OscIn::Socket(socket) => match socket.local_addr() {
+                Ok(a) => status.push(format!("OSC in: {a}")),
+                Err(_) => status.push("OSC in: (socket)".to_string()),
+            },
+        }
+        if let Some(p) = &self.profile {
+            status.push(format!("profile: {p}"));
+        }
+        for a in &self.osc_in_extra_addrs {
+            status.push(format!("OSC in: {a}"));
+        }
+        if let Some(a) = self.heartbeat_to {
+            status.push(format!("heartbeats to: {a}"));
+        }
+        if let Some(a) = self.osc_out_bind {
+            status.push(format!("OSC sent from: {a}"));
+        }
+        status.push(format!("unmatched OSC: {}", self.unmatched_osc));
+        if self.raw_midi {
+            status.push("raw MIDI namespace: /midi/".to_string());
+        }
+        for a in &*self.osc_out_addrs {
+            status.push(format!("OSC out: {a}"));
+        }
+        status.extend(Formats::new(&self.osc_formats).report());
+        if let Some(config) = &self.watchdog {
+            status.push(format!("watchdog heartbeats every {:?}", config.interval));
+        }
+        status.push(format!("trace size: {}", self.trace_size));
+        if let Some(path) = &self.capture_file {
+            status.push(format!("capturing to: {}", path.display()));
+        }
+        if let Some(f) = &self.ranges_file {
+            status.push(format!("ranges file: {}", f.display()));
+        }
+        if self.learn_ranges {
+            status.push("learning control ranges".to_string());
+        }
+        if let Some(config) = &self.presets {
+            for (preset, mappings) in &config.mappings {
+                status.push(format!("preset {preset} mappings: {mappings}"));
+            }
+        }
+        for m in &self.surface {
+            status.push(format!(
+                "surface device: {}, channels {}",
+                m.port,
+                surface::channel_list(&m.channels)
+            ));
+        }
+        for ns in &self.namespaces {
+            let mappings = match &ns.mappings {
+                Some(f) => f.display().to_string(),
+                None => "(test set)".to_string(),
+            };
+            status.push(format!(
+                "namespace {}: MIDI in {}, MIDI out {}, mappings {mappings}",
+                ns.prefix, ns.midi_in, ns.midi_out
+            ));
+        }
+        if let Some(config) = &self.failover {
+            for (i, group) in config.backups.iter().enumerate() {
+                let addrs: Vec<String> = group.iter().map(|a| a.to_string()).collect();
+                status.push(format!("OSC backup {}: {}", i + 1, addrs.join(", ")));
+            }
+        }
+        status
+    }
+
+    fn start_ctl(&self, control: Control) -> impl Future<Output = ()> {
+        run_ctl(
+            self.stopper.clone(),
+            Arc::new(control),
+            self.ctl_path.clone(),
+        )
+    }
+
+    fn start_heartbeat(
+        &self,
+        udp_socket: &Arc<UdpSocket>,
+        mappings: &SharedMappings,
+    ) -> impl Future<Output = ()> {
+        run_heartbeat(
+            self.stopper.clone(),
+            udp_socket.clone(),
+            self.heartbeat_to,
+            mappings.clone(),
+        )
+    }
+
+    fn start_keepalive(
+        &self,
+        keepalive: &Option<Arc<Keepalive>>,
+        admin: &Arc<Admin>,
+    ) -> impl Future<Output = ()> {
+        run_keepalive(self.stopper.clone(), keepalive.clone(), admin.clone())
+    }
+
+    fn start_monitor(
+        &self,
+        keepalive: &Option<Arc<Keepalive>>,
+        udp_socket: &Arc<UdpSocket>,
+    ) -> impl Future<Output = ()> {
+        // Only ports can be watched, and each is only opened if it's
+        // translated in its direction.
+        let midi_in = self
+            .midi_in
+            .port_name()
+            .filter(|_| self.directions.to_osc());
+        let midi_out = self
+            .midi_out
+            .port_name()
+            .filter(|_| self.directions.to_midi());
+        let monitor = self
+            .status_interval
+            .filter(|_| midi_in.is_some() || midi_out.is_some())
+            .map(|interval| Monitor {
+                interval,
+                midi_in_port_name: midi_in.map(str::to_string),
+                midi_out_port_name: midi_out.map(str::to_string),
+                device_name: self.device_name.clone(),
+            });
+        run_monitor(
+            self.stopper.clone(),
+            monitor,
+            keepalive.clone(),
+            udp_socket.clone(),
+            self.osc_out_addrs.clone(),
+        )
+    }
+
+    fn start_watchdog(
+        &self,
+        started: Instant,
+        udp_socket: &Arc<UdpSocket>,
+    ) -> impl Future<Output = ()> {
+        run_watchdog(
+            self.stopper.clone(),
+            self.watchdog.clone(),
+            started,
+            udp_socket.clone(),
+            self.osc_out_addrs.clone(),
+        )
+    }
+
+    fn start_learning(
+        &self,
+        learner: &Option<Arc<RangeLearner>>,
+        midi_in: &SharedMidiInput,
+    ) -> impl Future<Output = ()> {
+        run_learning(self.stopper.clone(), learner.clone(), midi_in.clone())
+    }
+
+    fn start_preset_switching(
+        &self,
+        switcher: &Option<Arc<PresetSwitcher>>,
+        midi_in: &SharedMidiInput,
+        admin: &Arc<Admin>,
+    ) -> impl Future<Output = ()> {
+        run_preset_switching(
+            self.stopper.clone(),
+            switcher.clone(),
+            midi_in.clone(),
+            admin.clone(),
+        )
+    }
+
+    fn start_surface(&self, surface: Option<Surface>) -> impl Future<Output = ()> {
+        run_surface(self.stopper.clone(), surface)
+    }
+
+    fn start_failover(
+        &self,
+        destinations: &Arc<Destinations>,
+        udp_socket: &Arc<UdpSocket>,
+    ) -> impl Future<Output = ()> {
+        run_failover(
+            self.stopper.clone(),
+            destinations.clone(),
+            udp_socket.clone(),
+        )
+    }
+
+    fn start_latency(
+        &self,
+        probe: &Option<Arc<LatencyProbe>>,
+        midi_in: &SharedMidiInput,
+        midi_out: &MidiSink,
+        udp_socket: &Arc<UdpSocket>,
+    ) -> impl Future<Output = ()> {
+        run_latency(
+            self.stopper.clone(),
+            probe.clone(),
+            midi_in.clone(),
+            midi_out.clone(),
+            udp_socket.clone(),
+            self.osc_out_addrs.clone(),
+        )
+    }
+
     fn start_midi_to_osc(
         &self,
-        receiver: impl Stream<Item = MidiMessage> + Send + 'static,
+        midi_in: &SharedMidiInput,
         udp_socket: &Arc<UdpSocket>,
-        xset: &Arc<ServerTranslationSet>,
+        xset: &Translations,
+        pipeline: &Pipeline,
     ) -> impl Future<Output = ()> {
-        let stopper = self.stopper.clone();
         run_midi_to_osc(
-            stopper,
-            receiver,
-            self.osc_out_addrs.clone(),
+            self.stopper.clone(),
+            midi_in.clone(),
             udp_socket.clone(),
             xset.clone(),
+            None,
+            pipeline.clone(),
         )
     }
 
+    fn start_midi_distribution(
+        &self,
+        src: impl Stream<Item = MidiMessage> + Send + 'static,
+        midi_in: SharedMidiInput,
+        capture: &Option<Arc<Capture>>,
+    ) -> impl Future<Output = ()> {
+        run_midi_distribution(self.stopper.clone(), src, midi_in, capture.clone())
+    }
+
     fn start_osc_to_midi(
         &self,
-        udp_socket: &Arc<UdpSocket>,
-        dest: impl Sink<MidiMessage> + Send + 'static,
-        xset: &Arc<ServerTranslationSet>,
+        inputs: &[Arc<OscInput>],
+        outbox: &Arc<Coalescer>,
+        xset: &Translations,
+        namespaces: &[Namespace],
+        on_pong: impl Fn(SocketAddr, &OscMessage) + Clone + Send + Sync + 'static,
+        pipeline: &Pipeline,
     ) -> impl Future<Output = ()> {
-        run_osc_to_midi(self.stopper.clone(), udp_socket.clone(), dest, xset.clone())
+        run_osc_to_midi(
+            self.stopper.clone(),
+            inputs.to_vec(),
+            outbox.clone(),
+            xset.clone(),
+            namespaces.to_vec(),
+            on_pong,
+            pipeline.clone(),
+        )
+    }
+
+    fn start_midi_sender(
+        &self,
+        outbox: &Arc<Coalescer>,
+        dest: impl Sink<MidiMessage> + Clone + Send + 'static,
+        capture: &Option<Arc<Capture>>,
+    ) -> impl Future<Output = ()> {
+        run_midi_sender(self.stopper.clone(), outbox.clone(), dest, capture.clone())
     }
 }
 
@@ -123,112 +1040,503 @@ async fn wait_on_stopping(stopper: StopMechanism) {
     stopper.notified().await;
 }
 
-async fn run_midi_to_osc<SRC>(
+async fn run_ctl(stopper: StopMechanism, control: Arc<Control>, path: Option<PathBuf>) {
+    let Some(path) = path else {
+        return;
+    };
+    select! {
+        r = control.listen(&path).fuse() => {
+            if let Err(e) = r {
+                error!("{PGM} control socket failed: {e}");
+            }
+        },
+        _ = wait_on_stopping(stopper).fuse() => {}
+    };
+    info!("{PGM} control socket stopped.");
+}
+
+async fn run_latency(
+    stopper: StopMechanism,
+    probe: Option<Arc<LatencyProbe>>,
+    midi_in: SharedMidiInput,
+    midi_out: MidiSink,
+    udp_socket: Arc<UdpSocket>,
+    clients: Arc<Vec<SocketAddr>>,
+) {
+    if let Some(probe) = probe {
+        select! {
+            _ = probe.run(midi_in, midi_out, udp_socket, clients).fuse() => {},
+            _ = wait_on_stopping(stopper).fuse() => {}
+        };
+        info!("{PGM} latency probe stopped.");
+    }
+}
+
+async fn run_failover(
+    stopper: StopMechanism,
+    destinations: Arc<Destinations>,
+    udp_socket: Arc<UdpSocket>,
+) {
+    if destinations.has_backups() {
+        select! {
+            _ = destinations.run(udp_socket).fuse() => {},
+            _ = wait_on_stopping(stopper).fuse() => {}
+        };
+        info!("{PGM} failover pings stopped.");
+    }
+}
+
+async fn run_heartbeat(
+    stopper: StopMechanism,
+    udp_socket: Arc<UdpSocket>,
+    standby: Option<SocketAddr>,
+    mappings: SharedMappings,
+) {
+    if let Some(standby) = standby {
+        select! {
+            _ = standby::send_heartbeats(&udp_socket, standby, mappings).fuse() => {},
+            _ = wait_on_stopping(stopper).fuse() => {}
+        };
+        info!("{PGM} heartbeat stopped.");
+    }
+}
+
+async fn run_keepalive(
+    stopper: StopMechanism,
+    keepalive: Option<Arc<Keepalive>>,
+    admin: Arc<Admin>,
+) {
+    if let Some(keepalive) = keepalive {
+        select! {
+            _ = keepalive.run(admin).fuse() => {},
+            _ = wait_on_stopping(stopper).fuse() => {}
+        };
+        info!("{PGM} device pings stopped.");
+    }
+}
+
+async fn run_monitor(
+    stopper: StopMechanism,
+    monitor: Option<Monitor>,
+    keepalive: Option<Arc<Keepalive>>,
+    udp_socket: Arc<UdpSocket>,
+    clients: Arc<Vec<SocketAddr>>,
+) {
+    if let Some(monitor) = monitor {
+        select! {
+            _ = monitor.run(keepalive, udp_socket, clients).fuse() => {},
+            _ = wait_on_stopping(stopper).fuse() => {}
+        };
+        info!("{PGM} status monitor stopped.");
+    }
+}
+
+async fn run_watchdog(
+    stopper: StopMechanism,
+    config: Option<WatchdogConfig>,
+    started: Instant,
+    udp_socket: Arc<UdpSocket>,
+    clients: Arc<Vec<SocketAddr>>,
+) {
+    if let Some(config) = config {
+        select! {
+            _ = watchdog::send_heartbeats(&config, started, udp_socket, clients).fuse() => {},
+            _ = wait_on_stopping(stopper).fuse() => {}
+        };
+        info!("{PGM} watchdog heartbeats stopped.");
+    }
+}
+
+async fn run_learning(
+    stopper: StopMechanism,
+    learner: Option<Arc<RangeLearner>>,
+    midi_in: SharedMidiInput,
+) {
+    if let Some(learner) = learner {
+        select! {
+            _ = learner.run(midi_in).fuse() => {},
+            _ = wait_on_stopping(stopper).fuse() => {}
+        };
+        info!("{PGM} range learning stopped.");
+    }
+}
+
+async fn run_preset_switching(
+    stopper: StopMechanism,
+    switcher: Option<Arc<PresetSwitcher>>,
+    midi_in: SharedMidiInput,
+    admin: Arc<Admin>,
+) {
+    if let Some(switcher) = switcher {
+        select! {
+            _ = switcher.run(midi_in, admin).fuse() => {},
+            _ = wait_on_stopping(stopper).fuse() => {}
+        };
+        info!("{PGM} preset switching stopped.");
+    }
+}
+
+async fn run_surface(stopper: StopMechanism, surface: Option<Surface>) {
+    if let Some(surface) = surface {
+        select! {
+            _ = surface.run().fuse() => {},
+            _ = wait_on_stopping(stopper).fuse() => {}
+        };
+        info!("{PGM} surface routing stopped.");
+    }
+}
+
+async fn run_midi_distribution<SRC>(
     stopper: StopMechanism,
     src: SRC,
-    osc_out_addrs: Arc<Vec<SocketAddr>>,
-    dest: Arc<UdpSocket>,
-    xset: Arc<ServerTranslationSet>,
+    midi_in: SharedMidiInput,
+    capture: Option<Arc<Capture>>,
 ) where
     SRC: Stream<Item = MidiMessage> + Send,
 {
-    let stopper = stopper.clone();
+    let src = src.inspect(move |m| {
+        if let Some(capture) = &capture {
+            capture.midi_in(m);
+        }
+    });
     select! {
-        _ = run_midi_to_osc_loop(src, osc_out_addrs, dest, xset).fuse() => {},
+        _ = midi_in.distribute(src).instrument(info_span!("midi_receive")).fuse() => {},
         _ = wait_on_stopping(stopper).fuse() => {}
     };
+    info!("{PGM} MIDI distribution stopped.");
+}
+
+async fn run_midi_to_osc(
+    stopper: StopMechanism,
+    midi_in: SharedMidiInput,
+    dest: Arc<UdpSocket>,
+    xset: Translations,
+    prefix: Option<String>,
+    pipeline: Pipeline,
+) {
+    supervise("MIDI to OSC translation", stopper, || {
+        run_midi_to_osc_loop(
+            midi_in.subscribe(all_messages),
+            dest.clone(),
+            xset.clone(),
+            prefix.clone(),
+            pipeline.clone(),
+        )
+    })
+    .await;
     info!("{PGM} OSC sender stopped.");
 }
 
 async fn run_midi_to_osc_loop<SRC>(
     src: SRC,
-    osc_out_addrs: Arc<Vec<SocketAddr>>,
     dest: Arc<UdpSocket>,
-    xset: Arc<ServerTranslationSet>,
+    xset: Translations,
+    prefix: Option<String>,
+    pipeline: Pipeline,
 ) where
     SRC: Stream<Item = MidiMessage> + Send,
 {
+    let Pipeline {
+        destinations,
+        formats,
+        sync,
+        unmatched,
+        trace,
+        times,
+        capture,
+        ..
+    } = pipeline;
     pin_mut!(src);
     info!("{PGM} will send OSC from UDP port {:?}.", dest.local_addr());
+    // Each packet is encoded once per destination format, into a buffer
+    // reused for every packet, and the same bytes are sent to all
+    // destinations of that format.
+    let mut buf = Vec::with_capacity(1024);
     while let Some(midi_msg) = src.next().await {
-        if let Some(pkt) = xset.midi_msg_to_osc(midi_msg) {
-            let e = encode(&pkt);
-            match e {
-                Ok(buf) => {
-                    debug!("Sending this OSC packet: {pkt:?}");
-                    for a in &*osc_out_addrs {
-                        if let Err(e) = dest.send_to(&buf, a).await {
-                            error!("OSC send to {a} failed: {e}");
-                        };
+        let received = Instant::now();
+        let span = debug_span!("midi_to_osc", midi = ?midi_msg);
+        let current = xset.load_full();
+        let translated = span.in_scope(|| current.midi_msg_to_osc(&midi_msg));
+        if trace.enabled() {
+            trace.midi(
+                &midi_msg,
+                current.mapping_labels(current.midi_mappings(&midi_msg)),
+                translated.as_ref(),
+            );
+        }
+        match translated {
+            Some(pkt) => {
+                let pkt = match &prefix {
+                    Some(prefix) => namespace::add_prefix(prefix, pkt),
+                    None => pkt,
+                };
+                sync.record(&pkt);
+                debug!("Sending this OSC packet: {pkt:?}");
+                for (format, addrs) in formats.group(&destinations.current()) {
+                    let converted;
+                    let pkt = if format == OscFormat::default() {
+                        &pkt
+                    } else {
+                        match format.apply(&pkt) {
+                            Some(p) => {
+                                converted = p;
+                                &converted
+                            }
+                            None => continue,
+                        }
+                    };
+                    encode_into(pkt, &mut buf);
+                    match format.max_size {
+                        Some(max) if buf.len() > max => {
+                            for part in fragment::split(pkt, max) {
+                                encode_into(&part, &mut buf);
+                                send_encoded(&dest, &buf, &addrs, &capture, &span).await;
+                            }
+                        }
+                        _ => send_encoded(&dest, &buf, &addrs, &capture, &span).await,
                     }
                 }
-                Err(e) => error!("OSC encoding failed: {e}"),
+                times.midi_to_osc(received);
             }
+            None if !current.handles_midi(&midi_msg) => unmatched.midi(&midi_msg),
+            None => {}
         }
     }
     info!("{PGM} OSC sender source exhausted.");
 }
 
-async fn run_osc_to_midi<D>(
+/// Sends an encoded packet to each of `addrs`.
+async fn send_encoded(
+    socket: &UdpSocket,
+    buf: &[u8],
+    addrs: &[SocketAddr],
+    capture: &Option<Arc<Capture>>,
+    span: &Span,
+) {
+    for a in addrs {
+        if let Some(capture) = capture {
+            capture.osc_out(*a, buf);
+        }
+        let send = debug_span!(parent: span, "osc_send", dest = %a);
+        if let Err(e) = socket.send_to(buf, a).instrument(send).await {
+            error!("OSC send to {a} failed: {e}");
+        };
+    }
+}
+
+async fn run_osc_to_midi<P>(
     stopper: StopMechanism,
-    src: Arc<UdpSocket>,
-    dest: D,
-    xset: Arc<ServerTranslationSet>,
+    inputs: Vec<Arc<OscInput>>,
+    outbox: Arc<Coalescer>,
+    xset: Translations,
+    namespaces: Vec<Namespace>,
+    on_pong: P,
+    pipeline: Pipeline,
 ) where
-    D: Sink<MidiMessage>,
+    P: Fn(SocketAddr, &OscMessage) + Clone + Send + Sync + 'static,
 {
-    let stopper = stopper.clone();
-    select! {
-        _ = run_osc_to_midi_loop(src, dest,xset).fuse() => {},
-        _ = wait_on_stopping(stopper).fuse() => {}
-    };
+    supervise("OSC to MIDI translation", stopper, || {
+        let (inputs, outbox, xset) = (inputs.clone(), outbox.clone(), xset.clone());
+        let (namespaces, on_pong, pipeline) =
+            (namespaces.clone(), on_pong.clone(), pipeline.clone());
+        async move {
+            // Packets from all inputs are merged into one stream, from which
+            // messages for namespaces are routed to their own translation.
+            let (tx, rx) = mpsc::unbounded();
+            let receivers = join_all(inputs.iter().map(|i| i.receive(tx.clone())));
+            drop(tx);
+            let (own_tx, own_rx) = mpsc::unbounded();
+            let mut ns_txs = vec![];
+            let mut ns_loops = vec![];
+            for ns in &namespaces {
+                let (ns_tx, ns_rx) = mpsc::unbounded();
+                ns_txs.push(ns_tx);
+                ns_loops.push(run_osc_to_midi_loop(
+                    ns_rx,
+                    ns.outbox.clone(),
+                    ns.xset.clone(),
+                    on_pong.clone(),
+                    pipeline.clone(),
+                ));
+            }
+            let capture = pipeline.capture.clone();
+            let own = run_osc_to_midi_loop(own_rx, outbox, xset, on_pong, pipeline);
+            let routing = route_osc(rx, &namespaces, own_tx, ns_txs, capture);
+            select! {
+                _ = async { join!(routing, own, join_all(ns_loops)) }.fuse() => {},
+                _ = receivers.fuse() => {},
+            };
+        }
+    })
+    .await;
     info!("{PGM} OSC listener stopped.");
 }
 
-async fn run_osc_to_midi_loop<D>(src: Arc<UdpSocket>, dest: D, xset: Arc<ServerTranslationSet>)
-where
-    D: Sink<MidiMessage>,
+async fn run_osc_to_midi_loop<SRC, P>(
+    src: SRC,
+    outbox: Arc<Coalescer>,
+    xset: Translations,
+    on_pong: P,
+    pipeline: Pipeline,
+) where
+    SRC: Stream<Item = (OscPacket, SocketAddr)>,
+    P: Fn(SocketAddr, &OscMessage),
 {
-    info!(
-        "{PGM} listening for OSC on UDP port {:?}.",
-        src.local_addr()
-    );
-    let mut vec = vec![0u8; 1024 * 16];
-    let mut next: usize = 0;
-    pin_mut!(dest);
+    let Pipeline {
+        directions,
+        admin,
+        sync,
+        unmatched,
+        trace,
+        times,
+        ..
+    } = pipeline;
+    let to_midi = directions.to_midi();
+    let mut slew = SlewLimiter::default();
+    let mut slew_timer = tokio::time::interval(SLEW_INTERVAL);
+    slew_timer.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut last_slew = Instant::now();
+    // Reused for each message, so that translation needn't allocate.
+    let mut translated = SlewedMidi::new();
+    pin_mut!(src);
     loop {
-        // TODO: On Windows, we get error 10054 here if the *sender* just tried
-        // to send to an unresponsive port! (Try using distinct send/receive
-        // UdpSockets?)
-        match src.recv_from(&mut vec[next..]).await {
-            Ok((len, sender)) => {
-                let buflen = next + len;
-                match rosc::decoder::decode_udp(&vec[0..buflen]) {
-                    Ok((remainder, pkt)) => {
-                        debug!("Received OSC packet from {sender:?}: {pkt:?}");
-                        let rlen = remainder.len();
-                        if rlen > 0 {
-                            debug!("OSC input remainder {len} bytes.");
-                            vec.copy_within(len..len + rlen, 0);
-                            next = rlen;
+        tokio::select! {
+            received = src.next() => match received {
+                Some((pkt, sender)) => {
+                    let received = Instant::now();
+                    if !slew.is_active() {
+                        last_slew = Instant::now();
+                    }
+                    let current = xset.load_full();
+                    let mut queued = false;
+                    // Messages for the service itself can come in bundles
+                    // too, even bundles of one.
+                    for msg in packet_messages(&pkt) {
+                        if LatencyProbe::is_pong(msg) {
+                            on_pong(sender, msg);
+                            continue;
+                        }
+                        if ClientSync::is_request(msg) {
+                            sync.spawn(msg, sender);
+                            continue;
+                        }
+                        if current.state().apply(msg) {
+                            continue;
+                        }
+                        if admin::is_admin(msg) {
+                            admin.spawn(msg.clone(), sender);
+                            continue;
                         }
-                        for m in xset.osc_pkt_to_midi(&pkt) {
-                            dest.feed(m)
-                                .await
-                                .unwrap_or_else(|_| error!("OSC pkt feed failed."));
+                        if !to_midi {
+                            continue;
+                        }
+                        translated.clear();
+                        debug_span!("osc_to_midi", %sender, addr = %msg.addr)
+                            .in_scope(|| current.osc_msg_to_slewed_midi_into(msg, &mut translated));
+                        if translated.is_empty() && !current.handles_osc(msg) {
+                            unmatched.osc(sender, msg);
+                            match unmatched.osc_action() {
+                                UnmatchedAction::Passthrough => {
+                                    if let Some(m) = unmatched::passthrough(msg) {
+                                        outbox.push(m);
+                                    }
+                                }
+                                UnmatchedAction::Reply => {
+                                    admin.try_reply(&unmatched::error_reply(msg), sender);
+                                }
+                                _ => {}
+                            }
+                        }
+                        if trace.enabled() {
+                            let mappings = current.mapping_labels(current.osc_mappings(msg));
+                            trace.osc(sender, msg, mappings, translated.iter().map(|(m, _)| m));
+                        }
+                        for (m, rate) in translated.drain(..) {
+                            if let Some(m) = slew.submit(m, rate) {
+                                outbox.push(m);
+                                queued = true;
+                            }
                         }
-                        dest.flush()
-                            .await
-                            .unwrap_or_else(|_| error!("OSC pkt flush failed."));
                     }
-                    Err(e) => {
-                        error!("OSC pkt decode error: {e}");
-                        next = 0;
-                        error!("Discarded {buflen} bytes.");
+                    if queued {
+                        times.osc_to_midi(received);
                     }
                 }
+                None => break,
+            },
+            _ = slew_timer.tick(), if slew.is_active() => {
+                let now = Instant::now();
+                for m in slew.advance(now - last_slew) {
+                    outbox.push_with(m, Priority::Low);
+                }
+                last_slew = now;
             }
-            Err(e) => error!("UDP recv error: {e}"),
         }
     }
 }
+
+/// Routes received packets to the translation of the namespaces they're
+/// addressed to, and the rest to that of the service's own mappings.
+async fn route_osc<SRC>(
+    src: SRC,
+    namespaces: &[Namespace],
+    own: mpsc::UnboundedSender<(OscPacket, SocketAddr)>,
+    others: Vec<mpsc::UnboundedSender<(OscPacket, SocketAddr)>>,
+    capture: Option<Arc<Capture>>,
+) where
+    SRC: Stream<Item = (OscPacket, SocketAddr)>,
+{
+    pin_mut!(src);
+    while let Some((pkt, sender)) = src.next().await {
+        if let Some(capture) = &capture {
+            capture.osc_in(sender, &pkt);
+        }
+        let (routed, rest) = namespace::route(namespaces, pkt);
+        for (i, msg) in routed {
+            others[i]
+                .unbounded_send((OscPacket::Message(msg), sender))
+                .ok();
+        }
+        if let Some(pkt) = rest {
+            own.unbounded_send((pkt, sender)).ok();
+        }
+    }
+}
+
+/// Sends MIDI from the coalescing queue to the device, a batch at a time.
+/// While a batch is being written, newer values for its targets replace each
+/// other in the queue.
+async fn run_midi_sender<D>(
+    stopper: StopMechanism,
+    outbox: Arc<Coalescer>,
+    dest: D,
+    capture: Option<Arc<Capture>>,
+) where
+    D: Sink<MidiMessage> + Clone + Send + 'static,
+{
+    supervise("MIDI sender", stopper, || {
+        let (outbox, dest, capture) = (outbox.clone(), dest.clone(), capture.clone());
+        async move {
+            pin_mut!(dest);
+            loop {
+                for m in outbox.take().await {
+                    if let Some(capture) = &capture {
+                        capture.midi_out(&m);
+                    }
+                    dest.feed(m)
+                        .instrument(debug_span!("midi_feed"))
+                        .await
+                        .unwrap_or_else(|_| error!("MIDI feed failed."));
+                }
+                // A flush that doesn't finish shows as a long-lived span in
+                // tokio-console.
+                dest.flush()
+                    .instrument(debug_span!("midi_flush"))
+                    .await
+                    .unwrap_or_else(|_| error!("MIDI flush failed."));
+            }
+        }
+    })
+    .await;
+    info!("{PGM} MIDI sender stopped.");
+}
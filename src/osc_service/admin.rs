@@ -0,0 +1,597 @@
+//! Device administration over OSC.
+//!
+//! While the service runs, messages addressed under `/bcr2kosc/device/`
+//! perform the same device operations as the corresponding commands, using
+//! the service's MIDI ports. Replies are sent to the OSC client that made the
+//! request.
+//!
+//! OSC pattern                       arguments          reply
+//! /bcr2kosc/device/identity         [device]           /bcr2kosc/device/identity device model id
+//! /bcr2kosc/device/preset/select    preset [device]
+//! /bcr2kosc/device/preset/get       preset [device]    /bcr2kosc/device/preset device preset bcl
+//! /bcr2kosc/device/bcl/send         bcl [device]       /bcr2kosc/device/bcl/sent device lines
+//! /bcr2kosc/identify                [device]
+//! /bcr2kosc/trace                   [count]            /bcr2kosc/trace number event, for each
+//!
+//! Device numbers are integers from 1 through 16, and default to 1. Presets
+//! are integers from 1 through 32, or the strings "temp" or "all". BCL is
+//! passed as a single string of newline-separated lines. The trace of recent
+//! translations isn't a device operation, but is requested the same way; see
+//! the `trace` module. `/bcr2kosc/identify` makes the device flash its LEDs,
+//! to tell it apart from others; see `blink` in `b_control`.
+//!
+//! Failures are reported to the client as `/bcr2kosc/error message`. Device
+//! operations always fail while the service is read-only, and all but
+//! selecting a preset fail while it has no MIDI input.
+//!
+//! Device operations share the service's MIDI connections with translation,
+//! which continues while they run. Only one device operation runs at a time,
+//! since replies from a B-Control can't otherwise be told apart. Operations
+//! in progress are cancelled when the service stops.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::channel::mpsc::UnboundedReceiver;
+use futures::SinkExt;
+use midi_io::{sysex_messages, MidiMessage, MidiSink, SharedMidiInput};
+use rosc::encoder::encode;
+use rosc::{OscMessage, OscPacket, OscType};
+use tokio::net::UdpSocket;
+use tokio::sync::Mutex;
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info};
+
+use super::trace::{TraceLog, TRACE_ADDR};
+use super::Directions;
+use crate::b_control::*;
+
+type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Address prefix of messages handled by the service itself rather than by
+/// translators.
+pub const ADMIN_PREFIX: &str = "/bcr2kosc/";
+
+const IDENTITY_TIMEOUT: Duration = Duration::from_secs(2);
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(30);
+const ALL_PRESETS_TIMEOUT: Duration = Duration::from_secs(600);
+const BLINK_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Performs device operations requested over OSC or the control socket, using
+/// MIDI connections shared with the rest of the service.
+pub struct Admin {
+    midi_in: SharedMidiInput,
+    /// Held for the duration of each operation.
+    midi_out: Mutex<MidiSink>,
+    socket: Arc<UdpSocket>,
+    trace: Arc<TraceLog>,
+    /// The time left between lines of BCL sent to each device, by number,
+    /// from the timing in its global setup. Fetched before the first upload
+    /// to a device, and updated by uploads of global setups.
+    pacing: std::sync::Mutex<HashMap<u8, Duration>>,
+    cancel: CancellationToken,
+    /// The directions the service translates in. Device operations are
+    /// refused if they need MIDI that the service doesn't send or receive.
+    directions: Directions,
+}
+
+impl Admin {
+    /// Creates an `Admin` that replies to requests from `socket`. Operations
+    /// fail with `Cancelled` once `cancel` is cancelled, and always fail if
+    /// `directions` leave out the MIDI they need.
+    pub fn new(
+        midi_in: SharedMidiInput,
+        midi_out: MidiSink,
+        socket: Arc<UdpSocket>,
+        trace: Arc<TraceLog>,
+        cancel: CancellationToken,
+        directions: Directions,
+    ) -> Self {
+        Admin {
+            midi_in,
+            midi_out: Mutex::new(midi_out),
+            socket,
+            trace,
+            pacing: Default::default(),
+            cancel,
+            directions,
+        }
+    }
+
+    /// Starts the operation requested by `msg` on a new task. Replies are sent
+    /// to `sender`.
+    pub fn spawn(self: &Arc<Self>, msg: OscMessage, sender: SocketAddr) {
+        let admin = self.clone();
+        tokio::spawn(async move {
+            for reply in admin.handle(&msg).await {
+                match encode(&OscPacket::Message(reply)) {
+                    Ok(buf) => {
+                        if let Err(e) = admin.socket.send_to(&buf, sender).await {
+                            error!("OSC reply to {sender} failed: {e}");
+                        }
+                    }
+                    Err(e) => error!("OSC encoding failed: {e}"),
+                }
+            }
+        });
+    }
+
+    /// Sends a packet to a client without waiting, as translation does when
+    /// it has to reply. A reply that doesn't fit in the socket's buffer
+    /// isn't worth holding translation up for, and is dropped.
+    pub fn try_reply(&self, pkt: &OscPacket, to: SocketAddr) {
+        match encode(pkt) {
+            Ok(buf) => {
+                if let Err(e) = self.socket.try_send_to(&buf, to) {
+                    debug!("OSC reply to {to} dropped: {e}");
+                }
+            }
+            Err(e) => error!("OSC encoding failed: {e}"),
+        }
+    }
+
+    /// Fails if the service sends no MIDI, or, for operations that wait for
+    /// the device's answer, if it receives none.
+    fn check_directions(&self, answered: bool) -> Result<()> {
+        if !self.directions.to_midi() {
+            return Err("the service is read-only, and sends no MIDI".into());
+        }
+        if answered && !self.directions.to_osc() {
+            return Err("the service has no MIDI input to hear the device's answer".into());
+        }
+        Ok(())
+    }
+
+    /// Asks a device to identify itself.
+    pub async fn identity(&self, device: u8) -> Result<(BControlModel, String)> {
+        self.check_directions(true)?;
+        let mut midi_out = self.midi_out.lock().await;
+        let mut midi_in = self.midi_in.subscribe(sysex_messages);
+        timeout(
+            IDENTITY_TIMEOUT,
+            get_identity(device, &mut midi_in, &mut *midi_out, &self.cancel),
+        )
+        .await
+        .map_err(|_| "device did not identify itself")?
+    }
+
+    /// Checks whether a device answers an identity request. Returns `None`,
+    /// without asking, if another device operation is in progress.
+    pub async fn ping(&self, device: u8) -> Option<bool> {
+        if self.check_directions(true).is_err() {
+            return None;
+        }
+        let mut midi_out = self.midi_out.try_lock().ok()?;
+        let mut midi_in = self.midi_in.subscribe(sysex_messages);
+        let answered = timeout(
+            IDENTITY_TIMEOUT,
+            get_identity(device, &mut midi_in, &mut *midi_out, &self.cancel),
+        )
+        .await;
+        Some(matches!(answered, Ok(Ok(_))))
+    }
+
+    /// Selects a stored preset on a device.
+    pub async fn select_preset(&self, device: u8, preset: PresetIndex) -> Result<()> {
+        let index = match preset {
+            PresetIndex::Preset(index) => index,
+            _ => return Err("a specific stored preset must be selected".into()),
+        };
+        self.check_directions(false)?;
+        let mut midi_out = self.midi_out.lock().await;
+        midi_out
+            .send(BControlMessages::device(device).select_preset(index))
+            .await?;
+        Ok(())
+    }
+
+    /// Gets the BCL of a preset from a device.
+    pub async fn get_preset(&self, device: u8, preset: PresetIndex) -> Result<Vec<String>> {
+        let limit = match preset {
+            PresetIndex::All => ALL_PRESETS_TIMEOUT,
+            _ => TRANSFER_TIMEOUT,
+        };
+        self.check_directions(true)?;
+        let mut midi_out = self.midi_out.lock().await;
+        let mut midi_in = self.midi_in.subscribe(sysex_messages);
+        timeout(
+            limit,
+            get_preset_bcl(device, preset, &mut midi_in, &mut *midi_out, &self.cancel),
+        )
+        .await
+        .map_err(|_| "device did not send the preset in time")?
+    }
+
+    /// Gets the BCL of a preset from a device, like `get_preset`. Returns
+    /// `None`, without asking, if another device operation is in progress.
+    pub async fn try_get_preset(
+        &self,
+        device: u8,
+        preset: PresetIndex,
+    ) -> Option<Result<Vec<String>>> {
+        if let Err(e) = self.check_directions(true) {
+            return Some(Err(e));
+        }
+        let mut midi_out = self.midi_out.try_lock().ok()?;
+        let mut midi_in = self.midi_in.subscribe(sysex_messages);
+        let lines = timeout(
+            TRANSFER_TIMEOUT,
+            get_preset_bcl(device, preset, &mut midi_in, &mut *midi_out, &self.cancel),
+        )
+        .await;
+        Some(lines.unwrap_or_else(|_| Err("device did not send the preset in time".into())))
+    }
+
+    /// Sends BCL to a device, after checking that it suits the device's model.
+    pub async fn send_bcl<S: AsRef<str>>(&self, device: u8, lines: &[S]) -> Result<()> {
+        self.check_directions(true)?;
+        let mut midi_out = self.midi_out.lock().await;
+        let mut midi_in = self.midi_in.subscribe(sysex_messages);
+        let (model, _) = timeout(
+            IDENTITY_TIMEOUT,
+            get_identity(device, &mut midi_in, &mut *midi_out, &self.cancel),
+        )
+        .await
+        .map_err(|_| "device did not identify itself")??;
+        bcl::check_model(lines, model)?;
+        let pacing = self
+            .device_pacing(device, &mut midi_in, &mut midi_out)
+            .await?;
+        timeout(
+            TRANSFER_TIMEOUT,
+            send_bcl(
+                device,
+                lines,
+                pacing,
+                &mut midi_in,
+                &mut *midi_out,
+                &self.cancel,
+            ),
+        )
+        .await
+        .map_err(|_| "device did not accept the BCL in time")??;
+        self.adopt_timing(device, lines);
+        Ok(())
+    }
+
+    /// Makes a device flash its LEDs, so that it can be told apart from
+    /// others. See `blink`.
+    pub async fn blink(&self, device: u8) -> Result<()> {
+        self.check_directions(true)?;
+        let mut midi_out = self.midi_out.lock().await;
+        let mut midi_in = self.midi_in.subscribe(sysex_messages);
+        let (model, _) = timeout(
+            IDENTITY_TIMEOUT,
+            get_identity(device, &mut midi_in, &mut *midi_out, &self.cancel),
+        )
+        .await
+        .map_err(|_| "device did not identify itself")??;
+        let pacing = self
+            .device_pacing(device, &mut midi_in, &mut midi_out)
+            .await?;
+        timeout(
+            BLINK_TIMEOUT,
+            blink(
+                device,
+                model,
+                pacing,
+                &mut midi_in,
+                &mut *midi_out,
+                &self.cancel,
+            ),
+        )
+        .await
+        .map_err(|_| "device did not finish flashing in time")?
+    }
+
+    /// Returns the time to leave between lines of BCL sent to a device,
+    /// fetching its global setup the first time.
+    async fn device_pacing(
+        &self,
+        device: u8,
+        midi_in: &mut UnboundedReceiver<MidiMessage>,
+        midi_out: &mut MidiSink,
+    ) -> Result<Duration> {
+        let known = self.pacing.lock().unwrap().get(&device).copied();
+        if let Some(pacing) = known {
+            return Ok(pacing);
+        }
+        let global = timeout(
+            TRANSFER_TIMEOUT,
+            get_global_bcl(device, midi_in, midi_out, &self.cancel),
+        )
+        .await
+        .map_err(|_| "device did not send its global setup in time")??;
+        let pacing = self.adopt_timing(device, &global).unwrap_or_default();
+        self.pacing.lock().unwrap().insert(device, pacing);
+        Ok(pacing)
+    }
+
+    /// Records the pacing of a device from the timing in BCL, if it gives
+    /// any, returning it.
+    fn adopt_timing<S: AsRef<str>>(&self, device: u8, lines: &[S]) -> Option<Duration> {
+        let timing = bcl::DeviceTiming::from_bcl(lines);
+        if !timing.is_known() {
+            return None;
+        }
+        info!(
+            "Device {} has {timing}; BCL is sent {} ms apart.",
+            device + 1,
+            timing.pacing().as_millis()
+        );
+        self.pacing.lock().unwrap().insert(device, timing.pacing());
+        Some(timing.pacing())
+    }
+
+    /// Performs the operation requested by `msg`, returning the replies to
+    /// send to the requester.
+    async fn handle(&self, msg: &OscMessage) -> Vec<OscMessage> {
+        debug!("Performing OSC request {}", msg.addr);
+        match self.perform(msg).await {
+            Ok(replies) => replies,
+            Err(e) => {
+                error!("OSC {} failed: {e}", msg.addr);
+                vec![reply(
+                    "/bcr2kosc/error",
+                    vec![OscType::String(format!("{}: {e}", msg.addr))],
+                )]
+            }
+        }
+    }
+
+    async fn perform(&self, msg: &OscMessage) -> Result<Vec<OscMessage>> {
+        let args = &msg.args;
+        match msg.addr.as_str() {
+            "/bcr2kosc/device/identity" => {
+                let device = device_arg(args, 0)?;
+                let (model, id_string) = self.identity(device).await?;
+                Ok(vec![reply(
+                    "/bcr2kosc/device/identity",
+                    vec![
+                        OscType::Int(device as i32 + 1),
+                        OscType::String(model.to_string()),
+                        OscType::String(id_string),
+                    ],
+                )])
+            }
+            "/bcr2kosc/device/preset/select" => {
+                let device = device_arg(args, 1)?;
+                self.select_preset(device, preset_arg(args)?).await?;
+                Ok(vec![])
+            }
+            "/bcr2kosc/device/preset/get" => {
+                let device = device_arg(args, 1)?;
+                let preset = preset_arg(args)?;
+                let lines = self.get_preset(device, preset).await?;
+                Ok(vec![reply(
+                    "/bcr2kosc/device/preset",
+                    vec![
+                        OscType::Int(device as i32 + 1),
+                        OscType::String(preset_name(preset)),
+                        OscType::String(lines.join("\n")),
+                    ],
+                )])
+            }
+            "/bcr2kosc/device/bcl/send" => {
+                let device = device_arg(args, 1)?;
+                let text = match args.first() {
+                    Some(OscType::String(s)) => s,
+                    _ => return Err("expected BCL text".into()),
+                };
+                let lines: Vec<&str> = text.lines().collect();
+                self.send_bcl(device, &lines).await?;
+                Ok(vec![reply(
+                    "/bcr2kosc/device/bcl/sent",
+                    vec![
+                        OscType::Int(device as i32 + 1),
+                        OscType::Int(lines.len() as i32),
+                    ],
+                )])
+            }
+            "/bcr2kosc/identify" => {
+                self.blink(device_arg(args, 0)?).await?;
+                Ok(vec![])
+            }
+            TRACE_ADDR => {
+                if !self.trace.enabled() {
+                    return Err("tracing is not enabled".into());
+                }
+                let count = match args.first() {
+                    None => None,
+                    Some(OscType::Int(n)) if *n > 0 => Some(*n as usize),
+                    Some(a) => return Err(format!("invalid count {a:?}").into()),
+                };
+                Ok(self
+                    .trace
+                    .report(count)
+                    .into_iter()
+                    .map(|(seq, event)| {
+                        reply(
+                            TRACE_ADDR,
+                            vec![OscType::Int(seq as i32), OscType::String(event)],
+                        )
+                    })
+                    .collect())
+            }
+            _ => Err("unknown address".into()),
+        }
+    }
+}
+
+/// Returns true if the message is addressed to the service itself.
+pub fn is_admin(msg: &OscMessage) -> bool {
+    msg.addr.starts_with(ADMIN_PREFIX)
+}
+
+fn reply(addr: &str, args: Vec<OscType>) -> OscMessage {
+    OscMessage {
+        addr: addr.to_string(),
+        args,
+    }
+}
+
+/// Gets the zero-based device number from an optional argument.
+fn device_arg(args: &[OscType], i: usize) -> Result<u8> {
+    match args.get(i) {
+        None => Ok(0),
+        Some(OscType::Int(n)) if (1..=16).contains(n) => Ok(*n as u8 - 1),
+        Some(a) => Err(format!("invalid device number {a:?}").into()),
+    }
+}
+
+/// Names a preset the way clients specify it, with stored presets numbered
+/// from 1.
+fn preset_name(preset: PresetIndex) -> String {
+    match preset {
+        PresetIndex::Preset(n) => (n as u32 + 1).to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn preset_arg(args: &[OscType]) -> Result<PresetIndex> {
+    match args.first() {
+        Some(OscType::Int(n)) => n
+            .to_string()
+            .parse::<PresetIndex>()
+            .map_err(|e| e.to_string().into()),
+        Some(OscType::String(s)) => s.parse::<PresetIndex>().map_err(|e| e.to_string().into()),
+        _ => Err("expected a preset".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::{self, Harness};
+    use super::super::unmatched::ERROR_ADDR;
+    use super::*;
+
+    fn request(addr: &str, args: Vec<OscType>) -> OscMessage {
+        reply(addr, args)
+    }
+
+    /// A message from device 1, a BCR.
+    fn from_device(command: BControlCommand) -> MidiMessage {
+        MidiMessage::from(&BControlSysEx {
+            device: DeviceID::Device(0),
+            model: BControlModel::BCR,
+            command,
+        })
+    }
+
+    fn only_message(pkt: OscPacket) -> OscMessage {
+        match pkt {
+            OscPacket::Message(m) => m,
+            OscPacket::Bundle(b) => panic!("expected a message, got {b:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn device_operations_are_answered() {
+        let (mut h, builder) = Harness::new().await;
+        let svc = builder.build();
+        testing::run(&svc, async {
+            h.send_osc(request("/bcr2kosc/device/identity", vec![]))
+                .await;
+            let asked = h.recv_midi().await;
+            assert_eq!(asked, BControlMessages::device(0).request_identity());
+            let id_string = "BCR2000 1.10".into();
+            let answer = from_device(BControlCommand::SendIdentity { id_string });
+            h.midi_in.unbounded_send(answer).unwrap();
+            let identity = only_message(h.recv_osc().await);
+            assert_eq!(
+                identity.args,
+                [
+                    OscType::Int(1),
+                    OscType::String("BCR".to_string()),
+                    OscType::String("BCR2000 1.10".to_string()),
+                ]
+            );
+
+            let args = vec![OscType::Int(3), OscType::Int(2)];
+            h.send_osc(request("/bcr2kosc/device/preset/select", args))
+                .await;
+            assert_eq!(
+                h.recv_midi().await,
+                BControlMessages::device(1).select_preset(2)
+            );
+
+            let args = vec![OscType::String("temp".to_string())];
+            h.send_osc(request("/bcr2kosc/device/preset/get", args))
+                .await;
+            assert_eq!(
+                h.recv_midi().await,
+                BControlMessages::device(0).request_preset(PresetIndex::Temporary)
+            );
+            for (i, line) in ["$rev R1", "$preset", "$end"].iter().enumerate() {
+                let text = (*line).into();
+                let msg_index = i as u16;
+                let line = from_device(BControlCommand::SendBclMessage { msg_index, text });
+                h.midi_in.unbounded_send(line).unwrap();
+            }
+            let preset = only_message(h.recv_osc().await);
+            assert_eq!(preset.addr, "/bcr2kosc/device/preset");
+            assert_eq!(
+                preset.args,
+                [
+                    OscType::Int(1),
+                    OscType::String("temp".to_string()),
+                    OscType::String("$rev R1\n$preset\n$end".to_string()),
+                ]
+            );
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn bad_requests_are_reported_to_the_client() {
+        let (h, builder) = Harness::new().await;
+        let svc = builder.build();
+        testing::run(&svc, async {
+            let error = |text: &str| reply(ERROR_ADDR, vec![OscType::String(text.to_string())]);
+            let args = vec![OscType::Int(1), OscType::Int(17)];
+            h.send_osc(request("/bcr2kosc/device/preset/select", args))
+                .await;
+            assert_eq!(
+                only_message(h.recv_osc().await),
+                error("/bcr2kosc/device/preset/select: invalid device number Int(17)")
+            );
+            let args = vec![OscType::String("all".to_string())];
+            h.send_osc(request("/bcr2kosc/device/preset/select", args))
+                .await;
+            assert_eq!(
+                only_message(h.recv_osc().await),
+                error("/bcr2kosc/device/preset/select: a specific stored preset must be selected")
+            );
+            h.send_osc(request("/bcr2kosc/device/reboot", vec![])).await;
+            assert_eq!(
+                only_message(h.recv_osc().await),
+                error("/bcr2kosc/device/reboot: unknown address")
+            );
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn read_only_services_refuse_device_operations() {
+        let (h, builder) = Harness::new().await;
+        let svc = builder.directions(Directions::ToOsc).build();
+        testing::run(&svc, async {
+            let args = vec![OscType::Int(1)];
+            h.send_osc(request("/bcr2kosc/device/preset/select", args))
+                .await;
+            let refused = only_message(h.recv_osc().await);
+            assert_eq!(
+                refused.args,
+                [OscType::String(
+                    "/bcr2kosc/device/preset/select: the service is read-only, and sends no MIDI"
+                        .to_string()
+                )]
+            );
+        })
+        .await;
+    }
+}
@@ -0,0 +1,467 @@
+//! Configuration of a `BCtlOscSvc`.
+//!
+//! Every piece of the service is optional. Without MIDI ports, the service
+//! translates nothing from MIDI, and drops the MIDI it translates from OSC.
+//! Without OSC destinations, it only receives OSC. MIDI can instead come from
+//! any stream, and go to any `MidiSink`, such as one made with
+//! `MidiSinkBuilder::channel`, so that the service can be driven by tests,
+//! or `MidiSinkBuilder::dry_run`, which logs MIDI instead of sending it.
+//!
+//! ```text
+//! let svc = BCtlOscSvc::builder()
+//!     .midi_in("BCR2000")
+//!     .midi_out("BCR2000")
+//!     .osc_in("0.0.0.0:9000".parse()?)
+//!     .osc_out(&["127.0.0.1:8000".parse()?])
+//!     .ctl_path(&default_ctl_path())
+//!     .build();
+//! ```
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use arc_swap::ArcSwap;
+use futures::Stream;
+use midi_io::{MidiMessage, MidiSink};
+use tokio::net::UdpSocket;
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+
+use super::{
+    destinations, BCtlOscSvc, Directions, FailoverConfig, KeepaliveConfig, LatencyConfig, MidiIn,
+    MidiOut, NamespaceConfig, OscFormat, OscIn, PresetConfig, Result, StandbyConfig, SurfaceMember,
+    UnmatchedAction, WatchdogConfig, DEFAULT_TRACE_SIZE,
+};
+use crate::translator::{Coercion, ServerTranslationSet, TranslationSetBuilder};
+
+/// Sets the options of a `BCtlOscSvc`.
+pub struct BCtlOscSvcBuilder {
+    svc: BCtlOscSvc,
+}
+
+impl Default for BCtlOscSvcBuilder {
+    fn default() -> Self {
+        BCtlOscSvcBuilder {
+            svc: BCtlOscSvc {
+                midi_in: MidiIn::None,
+                midi_out: MidiOut::None,
+                osc_in: OscIn::Addr((Ipv4Addr::LOCALHOST, 0).into()),
+                osc_in_extra_addrs: vec![],
+                osc_out_addrs: Arc::new(vec![]),
+                osc_formats: vec![],
+                mapping_file: None,
+                profile: None,
+                custom_mappings: None,
+                ctl_path: None,
+                latency: None,
+                osc_out_bind: None,
+                log_unmatched: false,
+                unmatched_osc: UnmatchedAction::default(),
+                raw_midi: false,
+                standby: None,
+                heartbeat_to: None,
+                status_interval: None,
+                keepalive: None,
+                device_name: None,
+                failover: None,
+                coercion: Coercion::default(),
+                sysex_interval: Duration::ZERO,
+                midi_rebind: None,
+                watchdog: None,
+                trace_size: DEFAULT_TRACE_SIZE,
+                ranges_file: None,
+                learn_ranges: false,
+                namespaces: vec![],
+                capture_file: None,
+                presets: None,
+                force: false,
+                surface: vec![],
+                directions: Directions::default(),
+                translations: Arc::new(ArcSwap::from_pointee(ServerTranslationSet::new(vec![]))),
+                stopper: Arc::new(Notify::new()),
+                cancel: CancellationToken::new(),
+            },
+        }
+    }
+}
+
+impl BCtlOscSvcBuilder {
+    /// Sets the MIDI port, or RTP-MIDI host, from which MIDI is received.
+    /// It should be chosen such that replies from the controllers make it
+    /// back to the service.
+    pub fn midi_in(mut self, port_name: &str) -> Self {
+        self.svc.midi_in = MidiIn::Port(port_name.to_string());
+        self
+    }
+
+    /// Receives MIDI from a stream, instead of a port.
+    pub fn midi_stream(mut self, stream: impl Stream<Item = MidiMessage> + Send + 'static) -> Self {
+        self.svc.midi_in = MidiIn::Stream(Mutex::new(Some(Box::pin(stream))));
+        self
+    }
+
+    /// Sets the MIDI port, or RTP-MIDI host, to which MIDI is sent. It should
+    /// be chosen such that MIDI commands will reach your B-Control devices.
+    pub fn midi_out(mut self, port_name: &str) -> Self {
+        self.svc.midi_out = MidiOut::Port(port_name.to_string());
+        self
+    }
+
+    /// Sends MIDI to a sink, instead of a port.
+    pub fn midi_sink(mut self, sink: MidiSink) -> Self {
+        self.svc.midi_out = MidiOut::Sink(sink);
+        self
+    }
+
+    /// Sets the local address at which OSC is received. By default, it's an
+    /// unused port on the loopback interface.
+    pub fn osc_in(mut self, addr: SocketAddr) -> Self {
+        self.svc.osc_in = OscIn::Addr(addr);
+        self
+    }
+
+    /// Receives OSC on a socket that's already bound, instead of binding
+    /// one. Replies are sent from it, and by default so is translated OSC.
+    pub fn osc_socket(mut self, socket: UdpSocket) -> Self {
+        self.svc.osc_in = OscIn::Socket(Arc::new(socket));
+        self
+    }
+
+    /// Sets further addresses on which to listen for OSC. Their traffic is
+    /// translated like that received at the first.
+    pub fn osc_in_extra(mut self, addrs: &[SocketAddr]) -> Self {
+        self.svc.osc_in_extra_addrs = addrs.to_vec();
+        self
+    }
+
+    /// Sets the destinations of translated OSC. Without any, the service
+    /// only receives OSC. A destination given more than once is sent to
+    /// once.
+    pub fn osc_out(mut self, addrs: &[SocketAddr]) -> Self {
+        self.svc.osc_out_addrs = Arc::new(destinations::dedupe(addrs));
+        self
+    }
+
+    /// Sets the formats of OSC destinations that don't use the default. See
+    /// the `format` module.
+    pub fn osc_formats(mut self, formats: &[(SocketAddr, OscFormat)]) -> Self {
+        self.svc.osc_formats = formats.to_vec();
+        self
+    }
+
+    /// Sets the local address from which translated OSC is sent. By default,
+    /// it's sent from the socket that receives OSC.
+    pub fn osc_out_bind(mut self, addr: Option<SocketAddr>) -> Self {
+        self.svc.osc_out_bind = addr;
+        self
+    }
+
+    /// Sets the file from which mappings are read.
+    pub fn mapping_file(mut self, path: Option<&Path>) -> Self {
+        self.svc.mapping_file = path.map(Path::to_path_buf);
+        self
+    }
+
+    /// Sets a built-in set of mappings, to which those in the mapping file
+    /// are added. See `ServerTranslationSet::profile`.
+    pub fn profile(mut self, profile: Option<&str>) -> Self {
+        self.svc.profile = profile.map(str::to_string);
+        self
+    }
+
+    /// Uses the mappings made by `f` instead of a profile and mapping file.
+    /// It's called again whenever the mappings are reloaded.
+    pub fn translations(
+        mut self,
+        f: impl Fn() -> Result<TranslationSetBuilder> + Send + Sync + 'static,
+    ) -> Self {
+        self.svc.custom_mappings = Some(Arc::new(f));
+        self
+    }
+
+    /// Sets the path of the control socket. Without one, the service has no
+    /// control socket.
+    pub fn ctl_path(mut self, path: &Path) -> Self {
+        self.svc.ctl_path = Some(path.to_path_buf());
+        self
+    }
+
+    /// Sets up latency measurement, which is off by default.
+    pub fn latency(mut self, config: Option<LatencyConfig>) -> Self {
+        self.svc.latency = config;
+        self
+    }
+
+    /// Sets whether messages that no mapping handles are logged. They're
+    /// counted regardless.
+    pub fn log_unmatched(mut self, log: bool) -> Self {
+        self.svc.log_unmatched = log;
+        self
+    }
+
+    /// Sets what's done with OSC messages that no mapping handles. They're
+    /// dropped by default.
+    pub fn unmatched_osc(mut self, action: UnmatchedAction) -> Self {
+        self.svc.unmatched_osc = action;
+        self
+    }
+
+    /// Sets whether all MIDI is also translated to and from the raw MIDI
+    /// namespace, under `/midi/`. Off by default. See `RawMidiTranslator`.
+    pub fn raw_midi(mut self, raw_midi: bool) -> Self {
+        self.svc.raw_midi = raw_midi;
+        self
+    }
+
+    /// Runs the service as a standby, waiting for a primary's heartbeats to
+    /// stop before starting.
+    pub fn standby(mut self, config: Option<StandbyConfig>) -> Self {
+        self.svc.standby = config;
+        self
+    }
+
+    /// Sets where to send heartbeats, if a standby is watching this instance.
+    pub fn heartbeat_to(mut self, addr: Option<SocketAddr>) -> Self {
+        self.svc.heartbeat_to = addr;
+        self
+    }
+
+    /// Sets how often to check the MIDI ports and device, notifying OSC
+    /// destinations of changes. Off by default, and only ports are checked.
+    pub fn status_interval(mut self, interval: Option<Duration>) -> Self {
+        self.svc.status_interval = interval;
+        self
+    }
+
+    /// Sets up device health pings, which are off by default.
+    pub fn keepalive(mut self, config: Option<KeepaliveConfig>) -> Self {
+        self.svc.keepalive = config;
+        self
+    }
+
+    /// Names the device that's pinged, for logs and status notifications.
+    pub fn device_name(mut self, name: Option<&str>) -> Self {
+        self.svc.device_name = name.map(str::to_string);
+        self
+    }
+
+    /// Sets backup OSC destinations, used when the others stop answering
+    /// pings. Off by default.
+    pub fn failover(mut self, config: Option<FailoverConfig>) -> Self {
+        self.svc.failover = config;
+        self
+    }
+
+    /// Sets how OSC arguments of unexpected types are treated, by mappings
+    /// that don't say. Permissive by default.
+    pub fn coercion(mut self, coercion: Coercion) -> Self {
+        self.svc.coercion = coercion;
+        self
+    }
+
+    /// Sets the minimum interval between SysEx messages sent to the device.
+    /// Zero by default.
+    pub fn sysex_interval(mut self, interval: Duration) -> Self {
+        self.svc.sysex_interval = interval;
+        self
+    }
+
+    /// Sets the interval at which the MIDI output port is reopened after it
+    /// fails, until it's back. Off by default.
+    pub fn midi_rebind(mut self, interval: Option<Duration>) -> Self {
+        self.svc.midi_rebind = interval;
+        self
+    }
+
+    /// Sets up heartbeats for external watchdogs, which are off by default.
+    pub fn watchdog(mut self, config: Option<WatchdogConfig>) -> Self {
+        self.svc.watchdog = config;
+        self
+    }
+
+    /// Sets the number of recent translations kept for the `trace` command.
+    /// Zero turns tracing off.
+    pub fn trace_size(mut self, size: usize) -> Self {
+        self.svc.trace_size = size;
+        self
+    }
+
+    /// Sets the file of learned ranges by which mappings are calibrated. See
+    /// the `learn` module.
+    pub fn ranges_file(mut self, path: Option<&Path>) -> Self {
+        self.svc.ranges_file = path.map(Path::to_path_buf);
+        self
+    }
+
+    /// Sets whether the ranges of control values are learned, and saved to
+    /// the ranges file. Off by default.
+    pub fn learn_ranges(mut self, learn: bool) -> Self {
+        self.svc.learn_ranges = learn;
+        self
+    }
+
+    /// Sets further OSC namespaces, each with its own MIDI ports and
+    /// mappings. See the `namespace` module.
+    pub fn namespaces(mut self, namespaces: &[NamespaceConfig]) -> Self {
+        self.svc.namespaces = namespaces.to_vec();
+        self
+    }
+
+    /// Sets a file to capture all MIDI and OSC to, replacing any that's
+    /// there. Off by default. See the `capture` module.
+    pub fn capture_file(mut self, path: Option<&Path>) -> Self {
+        self.svc.capture_file = path.map(Path::to_path_buf);
+        self
+    }
+
+    /// Sets up switching of mappings with the device's preset, which is off
+    /// by default. See the `presets` module.
+    pub fn presets(mut self, config: Option<PresetConfig>) -> Self {
+        self.svc.presets = config;
+        self
+    }
+
+    /// Sets whether the service starts even if an OSC destination can't be
+    /// sent to, such as one with port 0. Off by default. See the
+    /// `destinations` module.
+    pub fn force(mut self, force: bool) -> Self {
+        self.svc.force = force;
+        self
+    }
+
+    /// Adds further devices to the service's own, to be served as one
+    /// surface. None by default. See the `surface` module.
+    pub fn surface(mut self, members: &[SurfaceMember]) -> Self {
+        self.svc.surface = members.to_vec();
+        self
+    }
+
+    /// Sets the directions in which the service translates. Both by
+    /// default. See `Directions`.
+    pub fn directions(mut self, directions: Directions) -> Self {
+        self.svc.directions = directions;
+        self
+    }
+
+    /// Returns the configured service, ready to run.
+    pub fn build(self) -> BCtlOscSvc {
+        self.svc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::channel::mpsc;
+    use futures::{join, StreamExt};
+    use midi_control::{Channel, ControlEvent};
+    use rosc::decoder::decode_udp;
+    use rosc::encoder::encode;
+    use rosc::{OscMessage, OscPacket, OscType};
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::translator::testing::{expect_midi, expect_osc};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn cc(control: u8, value: u8) -> MidiMessage {
+        MidiMessage::ControlChange(Channel::Ch1, ControlEvent { control, value })
+    }
+
+    fn osc(addr: &str, v: f32) -> OscMessage {
+        OscMessage {
+            addr: addr.to_string(),
+            args: vec![OscType::Float(v)],
+        }
+    }
+
+    async fn recv_osc(client: &UdpSocket) -> (OscPacket, SocketAddr) {
+        let mut buf = [0u8; 1024];
+        let (len, from) = timeout(TIMEOUT, client.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        (decode_udp(&buf[..len]).unwrap().1, from)
+    }
+
+    #[tokio::test]
+    async fn service_runs_on_injected_midi_and_socket() {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let svc_addr = socket.local_addr().unwrap();
+        let client = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let (midi_in, stream) = mpsc::unbounded();
+        let (sink, mut midi_out) = mpsc::unbounded();
+        let svc = BCtlOscSvc::builder()
+            .midi_stream(stream)
+            .midi_sink(MidiSink::builder().channel(sink))
+            .osc_socket(socket)
+            .osc_out(&[client.local_addr().unwrap()])
+            .translations(|| Ok(ServerTranslationSet::test_mappings()))
+            .build();
+
+        let checks = async {
+            midi_in.unbounded_send(cc(1, 127)).unwrap();
+            let (pkt, from) = recv_osc(&client).await;
+            assert_eq!(from, svc_addr);
+            expect_osc(Some(&pkt), &osc("/encoder/1", 1.0)).unwrap();
+
+            let pkt = encode(&OscPacket::Message(osc("/key/1", 1.0))).unwrap();
+            client.send_to(&pkt, svc_addr).await.unwrap();
+            let midi = timeout(TIMEOUT, midi_out.next()).await.unwrap().unwrap();
+            expect_midi(&[midi], &cc(65, 127)).unwrap();
+            svc.stop().await;
+        };
+        let (r, ()) = timeout(TIMEOUT, async { join!(svc.run(), checks) })
+            .await
+            .unwrap();
+        r.unwrap();
+    }
+
+    #[tokio::test]
+    async fn replaced_translations_apply_from_the_next_message() {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let svc_addr = socket.local_addr().unwrap();
+        let client = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let (midi_in, stream) = mpsc::unbounded();
+        let (sink, mut midi_out) = mpsc::unbounded();
+        let svc = BCtlOscSvc::builder()
+            .midi_stream(stream)
+            .midi_sink(MidiSink::builder().channel(sink))
+            .osc_socket(socket)
+            .osc_out(&[client.local_addr().unwrap()])
+            .build();
+
+        let checks = async {
+            midi_in.unbounded_send(cc(1, 127)).unwrap();
+            let (pkt, _) = recv_osc(&client).await;
+            expect_osc(Some(&pkt), &osc("/encoder/1", 1.0)).unwrap();
+
+            let set = TranslationSetBuilder::new()
+                .cc(Channel::Ch1, 2)
+                .osc("/fader/2")
+                .build()
+                .unwrap();
+            svc.set_translations(set);
+            midi_in.unbounded_send(cc(1, 0)).unwrap();
+            midi_in.unbounded_send(cc(2, 0)).unwrap();
+            let (pkt, _) = recv_osc(&client).await;
+            expect_osc(Some(&pkt), &osc("/fader/2", 0.0)).unwrap();
+
+            // The old mapping is gone, so the first MIDI sent is the second
+            // message's.
+            for addr in ["/encoder/1", "/fader/2"] {
+                let pkt = encode(&OscPacket::Message(osc(addr, 1.0))).unwrap();
+                client.send_to(&pkt, svc_addr).await.unwrap();
+            }
+            let midi = timeout(TIMEOUT, midi_out.next()).await.unwrap().unwrap();
+            expect_midi(&[midi], &cc(2, 127)).unwrap();
+            svc.stop().await;
+        };
+        let (r, ()) = timeout(TIMEOUT, async { join!(svc.run(), checks) })
+            .await
+            .unwrap();
+        r.unwrap();
+    }
+}
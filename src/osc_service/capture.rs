@@ -0,0 +1,428 @@
+//! Capture of everything a service receives and sends.
+//!
+//! For bug reports, the service can write every MIDI message and OSC packet
+//! that passes through it, in either direction, into one file, with the time
+//! since the capture started. The file is binary, so that MIDI and OSC are
+//! kept exactly as they were; `capture-dump` shows it as text:
+//!
+//! ```text
+//!     0.000000 MIDI in              B0 07 40
+//!     0.000412 OSC out 10.0.0.5:9000 /track/1/volume [Float(0.503937)]
+//!     1.250020 OSC in  10.0.0.5:9000 /track/1/mute [Float(1.0)]
+//!     1.250104 MIDI out             B0 10 7F
+//! ```
+//!
+//! The file starts with `MAGIC` and the start time, in microseconds since
+//! the Unix epoch, then has one record per event: its kind, a byte; its
+//! time, in microseconds since the start; the OSC peer's address, as a
+//! length byte and text, empty for MIDI; and the data, as a 32-bit length
+//! and the bytes. Numbers are little-endian.
+//!
+//! MIDI is captured as it's received from the port and as it's written to
+//! it; OSC as it's received, and as translations send it. Replies to device
+//! operations and other service messages aren't captured.
+//!
+//! A capture can be replayed through mappings offline, with
+//! `capture-replay`, to see what the MIDI and OSC received would be
+//! translated to now, such as after a fix to the mappings. Nothing is sent.
+//! Only the service's own mappings are used, so OSC for namespaces should be
+//! replayed through theirs, with the prefixes left on.
+
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use midi_io::{copy_message, message_from_bytes, message_to_bytes, to_hex, MidiMessage};
+use rosc::OscPacket;
+use tracing::error;
+
+use super::encode::encode_into;
+use crate::translator::ServerTranslationSet;
+
+/// The first bytes of a capture file.
+pub const MAGIC: &[u8; 8] = b"B2KOCAP1";
+
+/// What a captured event was.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureKind {
+    MidiIn,
+    MidiOut,
+    OscIn,
+    OscOut,
+}
+
+impl CaptureKind {
+    fn from_byte(b: u8) -> Option<Self> {
+        match b {
+            0 => Some(CaptureKind::MidiIn),
+            1 => Some(CaptureKind::MidiOut),
+            2 => Some(CaptureKind::OscIn),
+            3 => Some(CaptureKind::OscOut),
+            _ => None,
+        }
+    }
+}
+
+impl Display for CaptureKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CaptureKind::MidiIn => "MIDI in",
+            CaptureKind::MidiOut => "MIDI out",
+            CaptureKind::OscIn => "OSC in",
+            CaptureKind::OscOut => "OSC out",
+        }
+        .fmt(f)
+    }
+}
+
+/// An event read from a capture file.
+#[derive(Debug, Clone)]
+pub struct CaptureEvent {
+    pub kind: CaptureKind,
+    /// The time since the capture started.
+    pub at: Duration,
+    /// The OSC sender or destination.
+    pub peer: Option<SocketAddr>,
+    /// The MIDI message's bytes, or the encoded OSC packet.
+    pub data: Vec<u8>,
+}
+
+impl Display for CaptureEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let peer = self.peer.map(|p| p.to_string()).unwrap_or_default();
+        write!(
+            f,
+            "{:12.6} {:<8} {peer:<21} ",
+            self.at.as_secs_f64(),
+            self.kind.to_string()
+        )?;
+        match self.kind {
+            CaptureKind::MidiIn | CaptureKind::MidiOut => write!(f, "{}", to_hex(&self.data)),
+            CaptureKind::OscIn | CaptureKind::OscOut => {
+                match rosc::decoder::decode_udp(&self.data) {
+                    Ok((_, pkt)) => PacketText(&pkt).fmt(f),
+                    Err(e) => write!(f, "undecodable OSC ({e:?}): {}", to_hex(&self.data)),
+                }
+            }
+        }
+    }
+}
+
+/// Shows an OSC packet's messages, and how they're bundled.
+struct PacketText<'a>(&'a OscPacket);
+
+impl Display for PacketText<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            OscPacket::Message(m) => write!(f, "{} {:?}", m.addr, m.args),
+            OscPacket::Bundle(b) => {
+                write!(f, "bundle [")?;
+                for (i, p) in b.content.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "; ")?;
+                    }
+                    PacketText(p).fmt(f)?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+/// A capture file being written.
+pub struct Capture {
+    start: Instant,
+    file: Mutex<File>,
+}
+
+impl Capture {
+    /// Creates a capture file, replacing any that's there.
+    pub fn create(path: &Path) -> std::io::Result<Self> {
+        let mut file = File::create(path)?;
+        let epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        file.write_all(MAGIC)?;
+        file.write_all(&(epoch.as_micros() as u64).to_le_bytes())?;
+        Ok(Capture {
+            start: Instant::now(),
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Records a MIDI message received from the device.
+    pub fn midi_in(&self, msg: &MidiMessage) {
+        let bytes = message_to_bytes(copy_message(msg));
+        self.record(CaptureKind::MidiIn, None, &bytes);
+    }
+
+    /// Records a MIDI message written to the device.
+    pub fn midi_out(&self, msg: &MidiMessage) {
+        let bytes = message_to_bytes(copy_message(msg));
+        self.record(CaptureKind::MidiOut, None, &bytes);
+    }
+
+    /// Records an OSC packet received.
+    pub fn osc_in(&self, sender: SocketAddr, pkt: &OscPacket) {
+        let mut buf = vec![];
+        encode_into(pkt, &mut buf);
+        self.record(CaptureKind::OscIn, Some(sender), &buf);
+    }
+
+    /// Records an encoded OSC packet sent.
+    pub fn osc_out(&self, dest: SocketAddr, buf: &[u8]) {
+        self.record(CaptureKind::OscOut, Some(dest), buf);
+    }
+
+    fn record(&self, kind: CaptureKind, peer: Option<SocketAddr>, data: &[u8]) {
+        let at = self.start.elapsed().as_micros() as u64;
+        let peer = peer.map(|p| p.to_string()).unwrap_or_default();
+        let mut record = Vec::with_capacity(14 + peer.len() + data.len());
+        record.push(kind as u8);
+        record.extend_from_slice(&at.to_le_bytes());
+        record.push(peer.len() as u8);
+        record.extend_from_slice(peer.as_bytes());
+        record.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        record.extend_from_slice(data);
+        // Each record is written whole, so that a capture cut short by a
+        // crash is readable up to its last event.
+        if let Err(e) = self.file.lock().unwrap().write_all(&record) {
+            error!("Capture write failed: {e}");
+        }
+    }
+}
+
+/// Reads a capture file, returning its start time and its events.
+pub fn read_capture(path: &Path) -> std::io::Result<(SystemTime, Vec<CaptureEvent>)> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut magic = [0u8; 8];
+    file.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not a capture file"));
+    }
+    let start = UNIX_EPOCH + Duration::from_micros(read_u64(&mut file)?);
+    let mut events = vec![];
+    let mut kind = [0u8; 1];
+    while file.read(&mut kind)? > 0 {
+        match read_event(kind[0], &mut file) {
+            Ok(event) => events.push(event),
+            // The last record of a capture that was cut short.
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok((start, events))
+}
+
+fn read_event(kind: u8, r: &mut impl Read) -> std::io::Result<CaptureEvent> {
+    let kind = CaptureKind::from_byte(kind).ok_or_else(|| invalid("bad event kind"))?;
+    let at = Duration::from_micros(read_u64(r)?);
+    let mut len = [0u8; 1];
+    r.read_exact(&mut len)?;
+    let mut peer = vec![0u8; len[0] as usize];
+    r.read_exact(&mut peer)?;
+    let peer = if peer.is_empty() {
+        None
+    } else {
+        let peer = String::from_utf8_lossy(&peer).parse();
+        Some(peer.map_err(|_| invalid("bad peer address"))?)
+    };
+    let mut len = [0u8; 4];
+    r.read_exact(&mut len)?;
+    let mut data = vec![0u8; u32::from_le_bytes(len) as usize];
+    r.read_exact(&mut data)?;
+    Ok(CaptureEvent {
+        kind,
+        at,
+        peer,
+        data,
+    })
+}
+
+fn read_u64(r: &mut impl Read) -> std::io::Result<u64> {
+    let mut b = [0u8; 8];
+    r.read_exact(&mut b)?;
+    Ok(u64::from_le_bytes(b))
+}
+
+fn invalid(what: &str) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, what)
+}
+
+/// Translates the MIDI and OSC received in a capture, describing what each
+/// message would be translated to. What was sent when the capture was made
+/// is skipped.
+pub fn replay(set: &ServerTranslationSet, events: &[CaptureEvent]) -> Vec<String> {
+    let mut lines = vec![];
+    for event in events {
+        let at = event.at.as_secs_f64();
+        match event.kind {
+            CaptureKind::MidiIn => {
+                let msg = message_from_bytes(&event.data);
+                let result = match set.midi_msg_to_osc(&msg) {
+                    Some(pkt) => format!("OSC {}", PacketText(&pkt)),
+                    None if set.handles_midi(&msg) => "nothing sent".to_string(),
+                    None => "no mapping".to_string(),
+                };
+                lines.push(format!(
+                    "{at:12.6} MIDI in {} -> {result}",
+                    to_hex(&event.data)
+                ));
+            }
+            CaptureKind::OscIn => {
+                let pkt = match rosc::decoder::decode_udp(&event.data) {
+                    Ok((_, pkt)) => pkt,
+                    Err(e) => {
+                        lines.push(format!("{at:12.6} OSC in undecodable ({e:?})"));
+                        continue;
+                    }
+                };
+                let sender = event.peer.map(|p| p.to_string()).unwrap_or_default();
+                for msg in super::packet_messages(&pkt) {
+                    let sent: Vec<String> = set
+                        .osc_msg_to_slewed_midi(msg)
+                        .into_iter()
+                        .map(|(m, _)| format!("MIDI {}", to_hex(&message_to_bytes(m))))
+                        .collect();
+                    let result = if !sent.is_empty() {
+                        sent.join("; ")
+                    } else if set.handles_osc(msg) {
+                        "nothing sent".to_string()
+                    } else {
+                        "no mapping".to_string()
+                    };
+                    lines.push(format!(
+                        "{at:12.6} OSC in {sender} {} {:?} -> {result}",
+                        msg.addr, msg.args
+                    ));
+                }
+            }
+            CaptureKind::MidiOut | CaptureKind::OscOut => {}
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use midi_control::{Channel, ControlEvent};
+    use rosc::{OscMessage, OscType};
+
+    use super::*;
+    use crate::PGM;
+
+    fn cc(control: u8, value: u8) -> MidiMessage {
+        MidiMessage::ControlChange(Channel::Ch1, ControlEvent { control, value })
+    }
+
+    fn osc(addr: &str, v: f32) -> OscPacket {
+        OscPacket::Message(OscMessage {
+            addr: addr.to_string(),
+            args: vec![OscType::Float(v)],
+        })
+    }
+
+    #[test]
+    fn capture_reads_back() {
+        let path = std::env::temp_dir().join(format!("{PGM}-{}.cap", std::process::id()));
+        let peer: SocketAddr = "10.0.0.5:9000".parse().unwrap();
+        let pkt = osc("/encoder/1", 0.5);
+        let mut encoded = vec![];
+        encode_into(&pkt, &mut encoded);
+
+        let before = SystemTime::now() - Duration::from_secs(1);
+        let capture = Capture::create(&path).unwrap();
+        capture.midi_in(&cc(1, 64));
+        capture.osc_out(peer, &encoded);
+        capture.osc_in(peer, &pkt);
+        capture.midi_out(&cc(65, 127));
+        drop(capture);
+        let read = read_capture(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let (start, events) = read.unwrap();
+        assert!(start >= before && start <= SystemTime::now());
+        let kinds: Vec<_> = events.iter().map(|e| e.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                CaptureKind::MidiIn,
+                CaptureKind::OscOut,
+                CaptureKind::OscIn,
+                CaptureKind::MidiOut
+            ]
+        );
+        assert!(events.windows(2).all(|w| w[0].at <= w[1].at));
+        assert_eq!(events[0].peer, None);
+        assert_eq!(events[0].data, [0xB0, 1, 64]);
+        assert_eq!(events[1].peer, Some(peer));
+        assert_eq!(events[1].data, encoded);
+        assert_eq!(events[2].data, encoded);
+        assert_eq!(events[3].data, [0xB0, 65, 127]);
+    }
+
+    #[test]
+    fn truncated_capture_reads_to_last_whole_event() {
+        let path = std::env::temp_dir().join(format!("{PGM}-{}-cut.cap", std::process::id()));
+        let capture = Capture::create(&path).unwrap();
+        capture.midi_in(&cc(1, 64));
+        capture.midi_in(&cc(1, 65));
+        drop(capture);
+        let len = std::fs::metadata(&path).unwrap().len();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 1)
+            .unwrap();
+        let read = read_capture(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let (_, events) = read.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].data, [0xB0, 1, 64]);
+    }
+
+    fn event(kind: CaptureKind, millis: u64, data: Vec<u8>) -> CaptureEvent {
+        let peer = match kind {
+            CaptureKind::OscIn | CaptureKind::OscOut => Some("10.0.0.5:9000".parse().unwrap()),
+            CaptureKind::MidiIn | CaptureKind::MidiOut => None,
+        };
+        CaptureEvent {
+            kind,
+            at: Duration::from_millis(millis),
+            peer,
+            data,
+        }
+    }
+
+    #[test]
+    fn replay_keeps_times_and_skips_what_was_sent() {
+        let set = ServerTranslationSet::test_mappings().build().unwrap();
+        let mut key = vec![];
+        encode_into(&osc("/key/1", 1.0), &mut key);
+        let mut other = vec![];
+        encode_into(&osc("/other", 1.0), &mut other);
+        let events = [
+            event(CaptureKind::MidiIn, 0, vec![0xB0, 1, 127]),
+            event(CaptureKind::OscOut, 1, other.clone()),
+            event(CaptureKind::OscIn, 1250, key),
+            event(CaptureKind::MidiOut, 1251, vec![0xB0, 65, 127]),
+            event(CaptureKind::MidiIn, 2500, vec![0xB0, 2, 0]),
+            event(CaptureKind::OscIn, 3000, other),
+        ];
+        assert_eq!(
+            replay(&set, &events),
+            [
+                "    0.000000 MIDI in B0 01 7F -> OSC /encoder/1 [Float(1.0)]",
+                "    1.250000 OSC in 10.0.0.5:9000 /key/1 [Float(1.0)] -> MIDI B0 41 7F",
+                "    2.500000 MIDI in B0 02 00 -> no mapping",
+                "    3.000000 OSC in 10.0.0.5:9000 /other [Float(1.0)] -> no mapping",
+            ]
+        );
+    }
+}
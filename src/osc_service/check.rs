@@ -0,0 +1,194 @@
+//! A check that the service could start, for provisioning scripts.
+//!
+//! The check does what the service does when it starts, without translating
+//! anything: it builds the mappings, including those of namespaces, binds
+//! the OSC sockets, checks that the OSC destinations can be sent to, opens
+//! the MIDI ports, asks the device to identify itself, and checks that no
+//! other instance has the control socket. A step that fails doesn't stop those
+//! after it, so that one run reports everything that needs fixing.
+//!
+//! MIDI streams supplied by the service's owner aren't read, since the
+//! service can only use them once, so the device isn't asked when the
+//! service has one. Nor is it asked when the service only translates in one
+//! direction, since the MIDI port of the other direction isn't opened.
+
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use midi_io::{MidiSink, MidiStream};
+use tokio::net::UdpSocket;
+use tokio::time::{timeout_at, Instant};
+
+use super::ctl::in_use;
+use super::{destinations, BCtlOscSvc, Check, MidiIn, MidiOut, MidiSource, OscIn};
+use crate::b_control::{BControlCommand, BControlMessages, BControlSysEx};
+use crate::config::device_label;
+
+/// How long the device is given to identify itself.
+const IDENTITY_TIMEOUT: Duration = Duration::from_secs(2);
+
+impl BCtlOscSvc {
+    /// Checks that the service could start, without starting it. Nothing
+    /// opened by the check is kept open.
+    pub async fn check(&self) -> Vec<Check> {
+        let mut checks = vec![Check {
+            name: "mappings are valid".to_string(),
+            failure: self
+                .mapping_source(vec![])
+                .build()
+                .err()
+                .map(|e| e.to_string()),
+        }];
+        if let OscIn::Addr(addr) = &self.osc_in {
+            checks.push(bind("OSC in", *addr).await);
+        }
+        for a in &self.osc_in_extra_addrs {
+            checks.push(bind("OSC in", *a).await);
+        }
+        if let Some(addr) = self.osc_out_bind {
+            checks.push(bind("OSC out", addr).await);
+        }
+        let invalid = destinations::invalid(&self.all_destinations());
+        checks.push(Check {
+            name: "OSC destinations can be sent to".to_string(),
+            failure: (!invalid.is_empty() && !self.force).then(|| invalid.join("; ")),
+        });
+
+        let midi_in = match &self.midi_in {
+            _ if !self.directions.to_osc() => None,
+            MidiIn::Port(name) => opened(&mut checks, "MIDI in", name, self.open_midi_in()),
+            _ => None,
+        };
+        let midi_out = match &self.midi_out {
+            _ if !self.directions.to_midi() => None,
+            MidiOut::Port(name) => opened(&mut checks, "MIDI out", name, self.open_midi_out()),
+            MidiOut::Sink(_) => self.open_midi_out().ok(),
+            _ => None,
+        };
+        if let (Some(midi_in), Some(midi_out)) = (midi_in, midi_out) {
+            let device = self.keepalive.map(|k| k.device);
+            checks.push(Check {
+                name: match device {
+                    Some(d) => format!(
+                        "{} identifies itself",
+                        device_label(d + 1, self.device_name.as_deref())
+                    ),
+                    None => "a device identifies itself".to_string(),
+                },
+                failure: identify(midi_in, midi_out, device).await.err(),
+            });
+        }
+
+        for m in &self.surface {
+            if self.directions.to_osc() {
+                opened(
+                    &mut checks,
+                    "surface MIDI in",
+                    &m.port,
+                    MidiStream::bind(&m.port),
+                );
+            }
+            if self.directions.to_midi() {
+                opened(
+                    &mut checks,
+                    "surface MIDI out",
+                    &m.port,
+                    MidiSink::bind(&m.port),
+                );
+            }
+        }
+
+        for ns in &self.namespaces {
+            checks.push(Check {
+                name: format!("namespace {} mappings are valid", ns.prefix),
+                failure: self
+                    .namespace_mappings(ns)
+                    .build()
+                    .err()
+                    .map(|e| e.to_string()),
+            });
+            if self.directions.to_osc() {
+                let what = format!("namespace {} MIDI in", ns.prefix);
+                opened(
+                    &mut checks,
+                    &what,
+                    &ns.midi_in,
+                    MidiStream::bind(&ns.midi_in),
+                );
+            }
+            if self.directions.to_midi() {
+                let what = format!("namespace {} MIDI out", ns.prefix);
+                opened(
+                    &mut checks,
+                    &what,
+                    &ns.midi_out,
+                    MidiSink::bind(&ns.midi_out),
+                );
+            }
+        }
+
+        if let Some(path) = &self.ctl_path {
+            checks.push(Check {
+                name: format!("control socket {} is free", path.display()),
+                failure: in_use(path)
+                    .await
+                    .then(|| "it's in use by another instance".to_string()),
+            });
+        }
+        checks
+    }
+}
+
+/// Checks that a UDP socket can be bound to `addr`.
+async fn bind(what: &str, addr: SocketAddr) -> Check {
+    Check {
+        name: format!("{what} binds {addr}"),
+        failure: UdpSocket::bind(addr).await.err().map(|e| e.to_string()),
+    }
+}
+
+/// Records whether a MIDI port opened, returning it if it did.
+fn opened<T, E: Display>(
+    checks: &mut Vec<Check>,
+    what: &str,
+    name: &str,
+    port: std::result::Result<T, E>,
+) -> Option<T> {
+    checks.push(Check {
+        name: format!("{what} opens \"{name}\""),
+        failure: port.as_ref().err().map(|e| e.to_string()),
+    });
+    port.ok()
+}
+
+/// Asks a device, or any device, to identify itself, and waits for an
+/// answer.
+async fn identify(
+    mut midi_in: MidiSource,
+    mut midi_out: MidiSink,
+    device: Option<u8>,
+) -> std::result::Result<(), String> {
+    let messages = device.map_or_else(BControlMessages::default, BControlMessages::device);
+    midi_out
+        .send(messages.request_identity())
+        .await
+        .map_err(|e| e.to_string())?;
+    let deadline = Instant::now() + IDENTITY_TIMEOUT;
+    loop {
+        match timeout_at(deadline, midi_in.next()).await {
+            Err(_) => return Err("no device answered".to_string()),
+            Ok(None) => return Err("the MIDI port closed".to_string()),
+            Ok(Some(m)) => {
+                if let Ok(BControlSysEx {
+                    command: BControlCommand::SendIdentity { .. },
+                    ..
+                }) = BControlSysEx::try_from(&m)
+                {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,225 @@
+//! Coalescing of MIDI sent to the device.
+//!
+//! OSC can arrive faster than the device takes MIDI, for instance while a
+//! fader is dragged in a DAW. Sending every value in order would hold the
+//! final position behind a backlog of stale ones. Instead, translated MIDI
+//! waits in a queue that holds at most one message per target: a control
+//! change per channel and control, poly pressure per channel and key, and a
+//! program change, channel pressure or pitch bend per channel. A newer
+//! message for a target replaces the queued one, keeping its place in line.
+//!
+//! Notes and SysEx are events rather than values, so they're queued in
+//! order and never dropped. Without a backlog, nothing waits long enough to
+//! be replaced.
+//!
+//! Messages have a priority. The steps of a slew ramp are `Low`, so that
+//! under backlog the values a host sets directly, and their feedback to the
+//! device's LEDs and motors, go ahead of a ramp's intermediate values. A
+//! batch is sent in order of priority, and in queue order within each. A
+//! message that replaces a queued one takes its own priority.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use midi_control::MidiMessage;
+use tokio::sync::Notify;
+
+/// How soon a queued message is sent, relative to the others.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Values set directly, and events.
+    #[default]
+    Normal,
+    /// Values that can wait, such as the steps of a slew ramp.
+    Low,
+}
+
+/// The kind of a message, as its status byte without the channel, with its
+/// channel and control or key number.
+type Target = (u8, u8, u8);
+
+#[derive(Default)]
+struct Queue {
+    messages: VecDeque<(Option<Target>, Priority, MidiMessage)>,
+    /// The number of messages queued, including those later replaced.
+    pushed: u64,
+    /// The number of messages taken to be sent.
+    taken: u64,
+    /// The number of messages replaced by newer ones.
+    replaced: u64,
+}
+
+/// MIDI messages waiting to be sent, at most one per target.
+#[derive(Default)]
+pub struct Coalescer {
+    queue: Mutex<Queue>,
+    ready: Notify,
+}
+
+impl Coalescer {
+    /// Queues a message at `Priority::Normal`, replacing any queued message
+    /// for the same target.
+    pub fn push(&self, msg: MidiMessage) {
+        self.push_with(msg, Priority::Normal);
+    }
+
+    /// Queues a message with a priority, replacing any queued message for
+    /// the same target.
+    pub fn push_with(&self, msg: MidiMessage, priority: Priority) {
+        let target = target(&msg);
+        let mut guard = self.queue.lock().unwrap();
+        let queue = &mut *guard;
+        queue.pushed += 1;
+        let queued = match target {
+            Some(t) => queue.messages.iter_mut().find(|(q, _, _)| *q == Some(t)),
+            None => None,
+        };
+        match queued {
+            Some(slot) => {
+                slot.1 = priority;
+                slot.2 = msg;
+                queue.replaced += 1;
+            }
+            None => {
+                queue.messages.push_back((target, priority, msg));
+                self.ready.notify_one();
+            }
+        }
+    }
+
+    /// Takes every queued message, in order of priority, waiting for one if
+    /// there are none.
+    pub async fn take(&self) -> Vec<MidiMessage> {
+        loop {
+            {
+                let mut queue = self.queue.lock().unwrap();
+                if !queue.messages.is_empty() {
+                    queue.taken += queue.messages.len() as u64;
+                    // The sort is stable, keeping queue order within each
+                    // priority.
+                    queue.messages.make_contiguous().sort_by_key(|(_, p, _)| *p);
+                    return queue.messages.drain(..).map(|(_, _, m)| m).collect();
+                }
+            }
+            self.ready.notified().await;
+        }
+    }
+
+    /// Summarizes the queue's statistics.
+    pub fn report(&self) -> String {
+        let queue = self.queue.lock().unwrap();
+        format!(
+            "MIDI out: {} translated, {} sent, {} queued, {} stale values replaced",
+            queue.pushed,
+            queue.taken,
+            queue.messages.len(),
+            queue.replaced
+        )
+    }
+
+    /// The queue's statistics, by name.
+    pub fn counters(&self) -> Vec<(&'static str, u64)> {
+        let queue = self.queue.lock().unwrap();
+        vec![
+            ("midi_translated", queue.pushed),
+            ("midi_sent", queue.taken),
+            ("midi_queued", queue.messages.len() as u64),
+            ("midi_replaced", queue.replaced),
+        ]
+    }
+}
+
+fn target(msg: &MidiMessage) -> Option<Target> {
+    match msg {
+        MidiMessage::ControlChange(ch, e) => Some((0xB0, *ch as u8, e.control)),
+        MidiMessage::PolyKeyPressure(ch, e) => Some((0xA0, *ch as u8, e.key)),
+        MidiMessage::ProgramChange(ch, _) => Some((0xC0, *ch as u8, 0)),
+        MidiMessage::ChannelPressure(ch, _) => Some((0xD0, *ch as u8, 0)),
+        MidiMessage::PitchBend(ch, _, _) => Some((0xE0, *ch as u8, 0)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use midi_control::{Channel, ControlEvent, KeyEvent};
+
+    fn cc(control: u8, value: u8) -> MidiMessage {
+        MidiMessage::ControlChange(Channel::Ch1, ControlEvent { control, value })
+    }
+
+    fn note(key: u8, value: u8) -> MidiMessage {
+        MidiMessage::NoteOn(Channel::Ch1, KeyEvent { key, value })
+    }
+
+    #[test]
+    fn latest_value_wins() {
+        let outbox = Coalescer::default();
+        outbox.push(cc(7, 1));
+        outbox.push(cc(7, 2));
+        outbox.push(cc(8, 5));
+        outbox.push(cc(7, 3));
+        assert_eq!(block_on(outbox.take()), vec![cc(7, 3), cc(8, 5)]);
+        assert!(outbox.counters().contains(&("midi_replaced", 2)));
+        assert!(outbox.counters().contains(&("midi_sent", 2)));
+    }
+
+    #[test]
+    fn channels_are_separate_targets() {
+        let outbox = Coalescer::default();
+        let ch2 = || {
+            MidiMessage::ControlChange(
+                Channel::Ch2,
+                ControlEvent {
+                    control: 7,
+                    value: 9,
+                },
+            )
+        };
+        outbox.push(cc(7, 1));
+        outbox.push(ch2());
+        assert_eq!(block_on(outbox.take()), vec![cc(7, 1), ch2()]);
+    }
+
+    #[test]
+    fn events_are_kept() {
+        let outbox = Coalescer::default();
+        outbox.push(note(60, 100));
+        outbox.push(note(60, 0));
+        outbox.push(note(60, 100));
+        assert_eq!(
+            block_on(outbox.take()),
+            vec![note(60, 100), note(60, 0), note(60, 100)]
+        );
+    }
+
+    #[test]
+    fn higher_priority_is_sent_first() {
+        let outbox = Coalescer::default();
+        outbox.push_with(cc(1, 1), Priority::Low);
+        outbox.push(cc(2, 2));
+        outbox.push_with(cc(3, 3), Priority::Low);
+        outbox.push(note(60, 100));
+        assert_eq!(
+            block_on(outbox.take()),
+            vec![cc(2, 2), note(60, 100), cc(1, 1), cc(3, 3)]
+        );
+    }
+
+    #[test]
+    fn replacement_takes_its_own_priority() {
+        let outbox = Coalescer::default();
+        outbox.push_with(cc(1, 1), Priority::Low);
+        outbox.push_with(cc(2, 2), Priority::Low);
+        outbox.push(cc(3, 3));
+        outbox.push(cc(2, 4));
+        assert_eq!(block_on(outbox.take()), vec![cc(2, 4), cc(3, 3), cc(1, 1)]);
+
+        outbox.push(cc(1, 1));
+        outbox.push(cc(2, 2));
+        outbox.push_with(cc(1, 5), Priority::Low);
+        assert_eq!(block_on(outbox.take()), vec![cc(2, 2), cc(1, 5)]);
+    }
+}
@@ -0,0 +1,488 @@
+//! Local control socket for a running service.
+//!
+//! The service listens on a Unix-domain socket, or a named pipe on Windows,
+//! for commands from `bcr2kosc ctl`. Each command is a single line. The reply
+//! is any number of data lines, each starting with `| `, followed by a line
+//! that is either `ok` or `error: ` and a message.
+//!
+//! Command                          reply
+//! status                           a description of the service
+//! reload                           mappings are rebuilt from the mapping file
+//! set-mapping MAPPING              the mapping is added
+//! identity [DEVICE]                model and identity string
+//! identify [DEVICE]                the device flashes its LEDs
+//! select-preset PRESET [DEVICE]
+//! get-preset PRESET [DEVICE]       BCL
+//! latency                          latency measurements, if enabled
+//! capabilities                     MIDI messages the current mappings cover
+//! mappings                         the current mappings, numbered, with
+//!                                  their descriptions and what they cover
+//! stats                            OSC input, MIDI output, unmatched
+//!                                  message and device ping counts, and in
+//!                                  debug builds, translation times
+//! counters                         the counts from stats that tools read,
+//!                                  as NAME VALUE, summed over OSC inputs
+//! trace [COUNT]                    the last COUNT translations, or all
+//!                                  that are kept
+//! ranges                           learned ranges of control values, as
+//!                                  CHANNEL CONTROL LOW HIGH
+//!
+//! Mappings added by `set-mapping` are discarded by `reload`.
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tracing::{debug, error, info};
+
+use super::admin::Admin;
+use super::coalesce::Coalescer;
+use super::failover::Destinations;
+use super::input::OscInput;
+use super::keepalive::Keepalive;
+use super::latency::LatencyProbe;
+use super::learn::RangeLearner;
+use super::timing::TranslationTimes;
+use super::trace::TraceLog;
+use super::unmatched::UnmatchedLog;
+use super::{MappingSource, SharedMappings, Translations};
+use crate::b_control::PresetIndex;
+use crate::PGM;
+
+type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Prefix of reply lines that carry data.
+const DATA_PREFIX: &str = "| ";
+
+/// Returns the control socket path used when none is specified.
+pub fn default_ctl_path() -> PathBuf {
+    #[cfg(windows)]
+    {
+        PathBuf::from(format!(r"\\.\pipe\{PGM}"))
+    }
+    #[cfg(not(windows))]
+    {
+        std::env::temp_dir().join(format!("{PGM}.sock"))
+    }
+}
+
+/// Sends a command to a running service, returning the data lines of the
+/// reply.
+pub async fn ctl_request(path: &Path, command: &str) -> Result<Vec<String>> {
+    let stream = connect(path).await?;
+    let (rd, mut wr) = tokio::io::split(stream);
+    wr.write_all(format!("{command}\n").as_bytes()).await?;
+    let mut lines = BufReader::new(rd).lines();
+    let mut data = vec![];
+    while let Some(line) = lines.next_line().await? {
+        if let Some(d) = line.strip_prefix(DATA_PREFIX) {
+            data.push(d.to_string());
+        } else if line == "ok" {
+            return Ok(data);
+        } else if let Some(e) = line.strip_prefix("error: ") {
+            return Err(e.into());
+        } else {
+            return Err(format!("unexpected reply: {line}").into());
+        }
+    }
+    Err("connection closed without a reply".into())
+}
+
+/// The parts of the service that the `stats`, `counters`, `latency`,
+/// `trace` and `ranges` commands report on.
+pub struct Reports {
+    /// The OSC input sockets.
+    pub inputs: Vec<Arc<OscInput>>,
+    /// The queue of MIDI translated from OSC.
+    pub outbox: Arc<Coalescer>,
+    /// Counts of unmatched messages.
+    pub unmatched: Arc<UnmatchedLog>,
+    /// The latency probe, if latency is measured.
+    pub latency: Option<Arc<LatencyProbe>>,
+    /// The device health pings, if enabled.
+    pub keepalive: Option<Arc<Keepalive>>,
+    /// The destinations of translated OSC.
+    pub destinations: Arc<Destinations>,
+    /// Recent translations.
+    pub trace: Arc<TraceLog>,
+    /// How long translation takes.
+    pub times: Arc<TranslationTimes>,
+    /// The range learner, if ranges are learned.
+    pub ranges: Option<Arc<RangeLearner>>,
+}
+
+impl Reports {
+    /// The counts of OSC received, MIDI translated and sent, and unmatched
+    /// messages, by name. Those of the OSC inputs are summed.
+    fn counters(&self) -> Vec<(&'static str, u64)> {
+        let mut counters: Vec<(&'static str, u64)> = vec![];
+        for (name, value) in self.inputs.iter().flat_map(|i| i.counters()) {
+            match counters.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, total)) => *total += value,
+                None => counters.push((name, value)),
+            }
+        }
+        counters.extend(self.outbox.counters());
+        counters.extend(self.unmatched.counters());
+        counters
+    }
+}
+
+/// Executes commands received on the control socket.
+pub struct Control {
+    status: Vec<String>,
+    translations: Translations,
+    mappings: SharedMappings,
+    admin: Arc<Admin>,
+    reports: Reports,
+}
+
+impl Control {
+    /// Creates a `Control`. The `status` lines are included in the reply to
+    /// the `status` command.
+    pub fn new(
+        status: Vec<String>,
+        translations: Translations,
+        mappings: SharedMappings,
+        admin: Arc<Admin>,
+        reports: Reports,
+    ) -> Self {
+        Control {
+            status,
+            translations,
+            mappings,
+            admin,
+            reports,
+        }
+    }
+
+    /// Accepts connections on the control socket until an error occurs.
+    pub async fn listen(self: Arc<Self>, path: &Path) -> Result<()> {
+        info!("{PGM} control socket is {}", path.display());
+        listen(self, path).await
+    }
+
+    async fn serve_connection<S>(&self, stream: S)
+    where
+        S: AsyncRead + AsyncWrite,
+    {
+        let (rd, mut wr) = tokio::io::split(stream);
+        let mut lines = BufReader::new(rd).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            debug!("Control command: {line}");
+            let mut reply = String::new();
+            match self.execute(line.trim()).await {
+                Ok(data) => {
+                    for d in data {
+                        reply.push_str(&format!("{DATA_PREFIX}{d}\n"));
+                    }
+                    reply.push_str("ok\n");
+                }
+                Err(e) => {
+                    error!("Control command \"{line}\" failed: {e}");
+                    reply.push_str(&format!("error: {e}\n"));
+                }
+            }
+            if let Err(e) = wr.write_all(reply.as_bytes()).await {
+                error!("Control reply failed: {e}");
+                break;
+            }
+        }
+    }
+
+    async fn execute(&self, line: &str) -> Result<Vec<String>> {
+        let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let args: Vec<&str> = rest.split_whitespace().collect();
+        match command {
+            "status" => {
+                let mut data = self.status.clone();
+                let mappings = self.mappings.lock().unwrap();
+                if let Some(f) = &mappings.file {
+                    data.push(format!("mapping file: {}", f.display()));
+                }
+                data.push(format!("translators: {}", self.translations.load().len()));
+                data.push(format!("added mappings: {}", mappings.added.len()));
+                Ok(data)
+            }
+            "reload" => {
+                let mut mappings = self.mappings.lock().unwrap();
+                let reloaded = MappingSource {
+                    added: vec![],
+                    ..mappings.clone()
+                };
+                self.install(&reloaded)?;
+                *mappings = reloaded;
+                Ok(vec![])
+            }
+            "set-mapping" => {
+                let mut mappings = self.mappings.lock().unwrap();
+                let mut updated = mappings.clone();
+                updated.added.push(rest.trim().to_string());
+                self.install(&updated)?;
+                *mappings = updated;
+                Ok(vec![])
+            }
+            "identity" => {
+                let device = device_arg(&args, 0)?;
+                let (model, id_string) = self.admin.identity(device).await?;
+                Ok(vec![model.to_string(), id_string])
+            }
+            "identify" => {
+                self.admin.blink(device_arg(&args, 0)?).await?;
+                Ok(vec![])
+            }
+            "select-preset" => {
+                let device = device_arg(&args, 1)?;
+                self.admin.select_preset(device, preset_arg(&args)?).await?;
+                Ok(vec![])
+            }
+            "get-preset" => {
+                let device = device_arg(&args, 1)?;
+                self.admin.get_preset(device, preset_arg(&args)?).await
+            }
+            "capabilities" => Ok(self.translations.load().coverage_report()),
+            "mappings" => Ok(self.translations.load().mapping_report()),
+            "stats" => {
+                let reports = &self.reports;
+                let mut data: Vec<String> = reports.inputs.iter().map(|i| i.report()).collect();
+                data.push(reports.outbox.report());
+                data.extend(reports.unmatched.report());
+                if let Some(k) = &reports.keepalive {
+                    data.extend(k.report());
+                }
+                data.extend(reports.destinations.report());
+                data.extend(reports.times.report());
+                Ok(data)
+            }
+            "trace" => {
+                if !self.reports.trace.enabled() {
+                    return Err("tracing is not enabled".into());
+                }
+                let count = match args.first() {
+                    Some(a) => Some(
+                        a.parse::<usize>()
+                            .map_err(|_| format!("invalid count \"{a}\""))?,
+                    ),
+                    None => None,
+                };
+                Ok(self
+                    .reports
+                    .trace
+                    .report(count)
+                    .into_iter()
+                    .map(|(seq, event)| format!("#{seq} {event}"))
+                    .collect())
+            }
+            "counters" => Ok(self
+                .reports
+                .counters()
+                .into_iter()
+                .map(|(name, value)| format!("{name} {value}"))
+                .collect()),
+            "latency" => match &self.reports.latency {
+                Some(probe) => Ok(probe.report()),
+                None => Err("latency measurement is not enabled".into()),
+            },
+            "ranges" => match &self.reports.ranges {
+                Some(learner) => Ok(learner.report()),
+                None => Err("range learning is not enabled".into()),
+            },
+            _ => Err(format!("unknown command \"{command}\"").into()),
+        }
+    }
+
+    /// Builds a translation set and makes it current.
+    fn install(&self, mappings: &MappingSource) -> Result<()> {
+        let set = mappings.build().map_err(|e| e.to_string())?;
+        info!("{PGM} installed {} translators.", set.len());
+        self.translations.store(Arc::new(set));
+        Ok(())
+    }
+}
+
+/// Gets the zero-based device number from an optional argument.
+fn device_arg(args: &[&str], i: usize) -> Result<u8> {
+    match args.get(i) {
+        None => Ok(0),
+        Some(a) => match a.parse::<u8>() {
+            Ok(n) if (1..=16).contains(&n) => Ok(n - 1),
+            _ => Err(format!("invalid device number \"{a}\"").into()),
+        },
+    }
+}
+
+fn preset_arg(args: &[&str]) -> Result<PresetIndex> {
+    match args.first() {
+        Some(a) => a.parse::<PresetIndex>().map_err(|e| e.to_string().into()),
+        None => Err("expected a preset".into()),
+    }
+}
+
+#[cfg(unix)]
+async fn connect(path: &Path) -> Result<tokio::net::UnixStream> {
+    Ok(tokio::net::UnixStream::connect(path).await?)
+}
+
+#[cfg(unix)]
+async fn listen(control: Arc<Control>, path: &Path) -> Result<()> {
+    use tokio::net::UnixListener;
+    // A socket file left behind by an instance that exited uncleanly can be
+    // replaced, but not one that belongs to a running instance.
+    if path.exists() {
+        if in_use(path).await {
+            return Err(format!("{} is in use by another instance", path.display()).into());
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    let _file = SocketFile(path);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let control = control.clone();
+        tokio::spawn(async move { control.serve_connection(stream).await });
+    }
+}
+
+/// Removes a control socket's file when dropped, along with the listener, so
+/// that the file is only removed by the instance that made it.
+#[cfg(unix)]
+struct SocketFile<'a>(&'a Path);
+
+#[cfg(unix)]
+impl Drop for SocketFile<'_> {
+    fn drop(&mut self) {
+        std::fs::remove_file(self.0).ok();
+    }
+}
+
+/// Whether a control socket belongs to a running instance.
+pub async fn in_use(path: &Path) -> bool {
+    connect(path).await.is_ok()
+}
+
+#[cfg(windows)]
+async fn connect(path: &Path) -> Result<tokio::net::windows::named_pipe::NamedPipeClient> {
+    use tokio::net::windows::named_pipe::ClientOptions;
+    Ok(ClientOptions::new().open(path)?)
+}
+
+#[cfg(windows)]
+async fn listen(control: Arc<Control>, path: &Path) -> Result<()> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+    let mut server = ServerOptions::new()
+        .first_pipe_instance(true)
+        .create(path)?;
+    loop {
+        server.connect().await?;
+        let connected = server;
+        server = ServerOptions::new().create(path)?;
+        let control = control.clone();
+        tokio::spawn(async move { control.serve_connection(connected).await });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::{self, cc, ctl, osc, Harness};
+    use crate::translator::testing::expect_osc;
+
+    fn count(lines: &[String], name: &str) -> u64 {
+        lines
+            .iter()
+            .find_map(|l| l.strip_prefix(&format!("{name} "))?.parse().ok())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn mappings_are_added_and_reloaded() {
+        let path = testing::ctl_path("ctl-mappings");
+        let (h, builder) = Harness::new().await;
+        let svc = builder.ctl_path(&path).build();
+        testing::run(&svc, async {
+            let status = ctl(&path, "status").await.unwrap();
+            assert!(status.contains(&"translators: 2".to_string()), "{status:?}");
+            assert!(status.contains(&"added mappings: 0".to_string()));
+
+            ctl(&path, "set-mapping cc 1 7 /volume # Master")
+                .await
+                .unwrap();
+            let mappings = ctl(&path, "mappings").await.unwrap();
+            assert_eq!(mappings.len(), 3);
+            assert!(mappings[2].contains("Master"), "{mappings:?}");
+            h.midi_in.unbounded_send(cc(7, 127)).unwrap();
+            expect_osc(Some(&h.recv_osc().await), &osc("/volume", 1.0)).unwrap();
+
+            let e = ctl(&path, "set-mapping cc 17 7 /volume").await.unwrap_err();
+            // Added mappings are numbered as lines after those added before.
+            assert_eq!(e, "line 2: invalid MIDI channel \"17\"");
+            let status = ctl(&path, "status").await.unwrap();
+            assert!(status.contains(&"added mappings: 1".to_string()));
+
+            ctl(&path, "reload").await.unwrap();
+            assert_eq!(ctl(&path, "mappings").await.unwrap().len(), 2);
+        })
+        .await;
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn counters_count_osc_and_midi() {
+        let path = testing::ctl_path("ctl-counters");
+        let (mut h, builder) = Harness::new().await;
+        let svc = builder.ctl_path(&path).build();
+        testing::run(&svc, async {
+            h.send_osc(osc("/key/1", 1.0)).await;
+            h.recv_midi().await;
+            h.send_osc(osc("/nowhere", 1.0)).await;
+            h.send_osc(osc("/key/1", 0.0)).await;
+            h.recv_midi().await;
+            let counters = ctl(&path, "counters").await.unwrap();
+            assert_eq!(count(&counters, "osc_packets"), 3);
+            assert_eq!(count(&counters, "midi_translated"), 2);
+            assert_eq!(count(&counters, "midi_sent"), 2);
+            assert_eq!(count(&counters, "unmatched_osc"), 1);
+            let stats = ctl(&path, "stats").await.unwrap();
+            assert!(stats.contains(&"unmatched OSC messages: 1".to_string()));
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn bad_commands_are_errors() {
+        let path = testing::ctl_path("ctl-errors");
+        let (_h, builder) = Harness::new().await;
+        let svc = builder.ctl_path(&path).build();
+        testing::run(&svc, async {
+            for (command, error) in [
+                ("frobnicate", "unknown command \"frobnicate\""),
+                ("identity 17", "invalid device number \"17\""),
+                ("get-preset", "expected a preset"),
+                ("latency", "latency measurement is not enabled"),
+                ("ranges", "range learning is not enabled"),
+                ("trace x", "invalid count \"x\""),
+            ] {
+                assert_eq!(ctl(&path, command).await.unwrap_err(), error);
+            }
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn sockets_in_use_are_refused() {
+        let path = testing::ctl_path("ctl-in-use");
+        let (_h, builder) = Harness::new().await;
+        let first = builder.ctl_path(&path).build();
+        let (_h2, builder) = Harness::new().await;
+        let second = builder.ctl_path(&path).build();
+        testing::run(&first, async {
+            ctl(&path, "status").await.unwrap();
+            // Give the second service time to try the socket.
+            let tried = tokio::time::sleep(std::time::Duration::from_millis(100));
+            testing::run(&second, tried).await;
+            // The second service left the first's socket alone.
+            ctl(&path, "status").await.unwrap();
+        })
+        .await;
+    }
+}
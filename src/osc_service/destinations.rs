@@ -0,0 +1,83 @@
+//! Checks of the OSC destinations, made when the service starts.
+//!
+//! A destination given more than once is only sent to once; the builder
+//! drops the repeats. When the service starts, each destination, including
+//! backups, is compared with the sockets that OSC is received on. Sending to
+//! one of them would loop translated OSC back into the service, where it
+//! would be translated again, so a warning is logged.
+//!
+//! A destination that can't be sent to at all, with an unspecified address
+//! such as 0.0.0.0 or with port 0, is refused, and the service doesn't
+//! start. It can be forced to start anyway, in which case these are warnings
+//! too.
+
+use std::net::SocketAddr;
+
+use tracing::warn;
+
+use super::Result;
+
+/// Drops destinations given more than once, keeping the first of each.
+pub fn dedupe(addrs: &[SocketAddr]) -> Vec<SocketAddr> {
+    let mut unique: Vec<SocketAddr> = Vec::with_capacity(addrs.len());
+    for a in addrs {
+        if unique.contains(a) {
+            warn!("OSC destination {a} is given more than once.");
+        } else {
+            unique.push(*a);
+        }
+    }
+    unique
+}
+
+/// Describes why each destination that can't be sent to can't be.
+pub fn invalid(destinations: &[SocketAddr]) -> Vec<String> {
+    destinations
+        .iter()
+        .filter_map(|d| {
+            if d.ip().is_unspecified() {
+                Some(format!("OSC destination {d} has no address to send to"))
+            } else if d.port() == 0 {
+                Some(format!("OSC destination {d} has no port to send to"))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Returns the destinations that are sockets the service receives OSC on.
+pub fn loops(destinations: &[SocketAddr], inputs: &[SocketAddr]) -> Vec<SocketAddr> {
+    destinations
+        .iter()
+        .filter(|d| inputs.iter().any(|i| reaches(d, i)))
+        .copied()
+        .collect()
+}
+
+/// Returns true if OSC sent to `destination` would be received by a socket
+/// bound to `input`. Only addresses that are plainly the same host are
+/// recognized: the same address, or a loopback address when the socket is
+/// bound to one, or to all interfaces.
+fn reaches(destination: &SocketAddr, input: &SocketAddr) -> bool {
+    let (d, i) = (destination.ip(), input.ip());
+    destination.port() == input.port()
+        && (d == i || (d.is_loopback() && (i.is_loopback() || i.is_unspecified())))
+}
+
+/// Checks the destinations against the inputs, logging what's wrong. Unless
+/// forced, fails if any destination can't be sent to.
+pub fn check(destinations: &[SocketAddr], inputs: &[SocketAddr], force: bool) -> Result<()> {
+    for d in loops(destinations, inputs) {
+        warn!("OSC destination {d} receives OSC for this service, so OSC sent to it loops back.");
+    }
+    let invalid = invalid(destinations);
+    if force || invalid.is_empty() {
+        for e in invalid {
+            warn!("{e}.");
+        }
+        Ok(())
+    } else {
+        Err(format!("{}; use --force to start anyway", invalid.join("; ")).into())
+    }
+}
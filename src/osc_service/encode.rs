@@ -0,0 +1,233 @@
+//! Encoding of OSC packets into a reusable buffer.
+//!
+//! `rosc::encoder::encode` returns a new vector for each packet, and allocates
+//! more for each of its parts along the way. The service encodes every packet
+//! it sends, so it uses this encoder instead, which writes into a buffer that
+//! is reused from one packet to the next. The bytes produced are the same.
+
+use rosc::{OscBundle, OscMessage, OscPacket, OscTime, OscType};
+
+/// Encodes a packet into `buf`, replacing its contents.
+pub fn encode_into(packet: &OscPacket, buf: &mut Vec<u8>) {
+    buf.clear();
+    encode_packet(packet, buf);
+}
+
+fn encode_packet(packet: &OscPacket, buf: &mut Vec<u8>) {
+    match packet {
+        OscPacket::Message(msg) => encode_message(msg, buf),
+        OscPacket::Bundle(bundle) => encode_bundle(bundle, buf),
+    }
+}
+
+fn encode_message(msg: &OscMessage, buf: &mut Vec<u8>) {
+    encode_str(&msg.addr, buf);
+    buf.push(b',');
+    for arg in &msg.args {
+        encode_tag(arg, buf);
+    }
+    buf.push(0);
+    pad(buf);
+    for arg in &msg.args {
+        encode_arg(arg, buf);
+    }
+}
+
+fn encode_bundle(bundle: &OscBundle, buf: &mut Vec<u8>) {
+    encode_str("#bundle", buf);
+    encode_time(bundle.timetag, buf);
+    for packet in &bundle.content {
+        // Each element is preceded by its size, which is filled in afterward.
+        let at = buf.len();
+        buf.extend_from_slice(&[0; 4]);
+        encode_packet(packet, buf);
+        let size = (buf.len() - at - 4) as u32;
+        buf[at..at + 4].copy_from_slice(&size.to_be_bytes());
+    }
+}
+
+fn encode_tag(arg: &OscType, buf: &mut Vec<u8>) {
+    let tag = match arg {
+        OscType::Int(_) => b'i',
+        OscType::Float(_) => b'f',
+        OscType::String(_) => b's',
+        OscType::Blob(_) => b'b',
+        OscType::Time(_) => b't',
+        OscType::Long(_) => b'h',
+        OscType::Double(_) => b'd',
+        OscType::Char(_) => b'c',
+        OscType::Color(_) => b'r',
+        OscType::Midi(_) => b'm',
+        OscType::Bool(true) => b'T',
+        OscType::Bool(false) => b'F',
+        OscType::Nil => b'N',
+        OscType::Inf => b'I',
+        OscType::Array(a) => {
+            buf.push(b'[');
+            for v in &a.content {
+                encode_tag(v, buf);
+            }
+            b']'
+        }
+    };
+    buf.push(tag);
+}
+
+fn encode_arg(arg: &OscType, buf: &mut Vec<u8>) {
+    match arg {
+        OscType::Int(x) => buf.extend_from_slice(&x.to_be_bytes()),
+        OscType::Float(x) => buf.extend_from_slice(&x.to_be_bytes()),
+        OscType::String(s) => encode_str(s, buf),
+        OscType::Blob(b) => {
+            buf.extend_from_slice(&(b.len() as i32).to_be_bytes());
+            buf.extend_from_slice(b);
+            pad(buf);
+        }
+        OscType::Time(t) => encode_time(*t, buf),
+        OscType::Long(x) => buf.extend_from_slice(&x.to_be_bytes()),
+        OscType::Double(x) => buf.extend_from_slice(&x.to_be_bytes()),
+        OscType::Char(c) => buf.extend_from_slice(&(*c as u32).to_be_bytes()),
+        OscType::Color(c) => buf.extend_from_slice(&[c.red, c.green, c.blue, c.alpha]),
+        OscType::Midi(m) => buf.extend_from_slice(&[m.port, m.status, m.data1, m.data2]),
+        OscType::Bool(_) | OscType::Nil | OscType::Inf => {}
+        OscType::Array(a) => {
+            for v in &a.content {
+                encode_arg(v, buf);
+            }
+        }
+    }
+}
+
+fn encode_time(time: OscTime, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&time.seconds.to_be_bytes());
+    buf.extend_from_slice(&time.fractional.to_be_bytes());
+}
+
+/// Encodes a string with its terminating null.
+fn encode_str(s: &str, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(s.as_bytes());
+    buf.push(0);
+    pad(buf);
+}
+
+/// Pads the buffer to a multiple of four bytes. Since packets start at the
+/// beginning of the buffer, and every part of a packet is a multiple of four
+/// bytes long, this aligns each part correctly.
+fn pad(buf: &mut Vec<u8>) {
+    while !buf.len().is_multiple_of(4) {
+        buf.push(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rosc::{OscArray, OscColor, OscMidiMessage};
+
+    fn assert_same(packet: OscPacket) {
+        let mut buf = vec![0xAA; 3];
+        encode_into(&packet, &mut buf);
+        let expected = rosc::encoder::encode(&packet).unwrap();
+        assert_eq!(buf, expected, "{packet:?}");
+    }
+
+    fn msg(addr: &str, args: Vec<OscType>) -> OscPacket {
+        OscPacket::Message(OscMessage {
+            addr: addr.to_string(),
+            args,
+        })
+    }
+
+    fn every_type() -> Vec<OscType> {
+        vec![
+            OscType::Int(-2),
+            OscType::Float(0.5),
+            OscType::String("level".to_string()),
+            OscType::Blob(vec![1, 2, 3]),
+            OscType::Time(OscTime {
+                seconds: 3,
+                fractional: 0x8000_0000,
+            }),
+            OscType::Long(-1 << 40),
+            OscType::Double(-0.25),
+            OscType::Char('x'),
+            OscType::Color(OscColor {
+                red: 1,
+                green: 2,
+                blue: 3,
+                alpha: 4,
+            }),
+            OscType::Midi(OscMidiMessage {
+                port: 0,
+                status: 0xB0,
+                data1: 7,
+                data2: 100,
+            }),
+            OscType::Bool(true),
+            OscType::Bool(false),
+            OscType::Nil,
+            OscType::Inf,
+            OscType::Array(OscArray {
+                content: vec![
+                    OscType::Int(1),
+                    OscType::Array(OscArray {
+                        content: vec![OscType::String("a".to_string())],
+                    }),
+                ],
+            }),
+        ]
+    }
+
+    #[test]
+    fn every_type_alone() {
+        for arg in every_type() {
+            assert_same(msg("/x", vec![arg]));
+        }
+    }
+
+    #[test]
+    fn every_type_together() {
+        assert_same(msg("/all/types", every_type()));
+        assert_same(msg("/none", vec![]));
+    }
+
+    #[test]
+    fn padding() {
+        for n in 0..=8 {
+            let s = "s".repeat(n);
+            assert_same(msg(&format!("/{s}"), vec![]));
+            assert_same(msg("/s", vec![OscType::String(s)]));
+            assert_same(msg("/b", vec![OscType::Blob(vec![0xFF; n])]));
+        }
+        // Enough arguments that the type tags need padding at every length.
+        for n in 0..=8 {
+            assert_same(msg("/i", vec![OscType::Int(1); n]));
+        }
+    }
+
+    #[test]
+    fn bundles() {
+        let time = OscTime {
+            seconds: 1,
+            fractional: 2,
+        };
+        let inner = OscPacket::Bundle(OscBundle {
+            timetag: time,
+            content: vec![
+                msg("/inner", vec![OscType::Blob(vec![1])]),
+                OscPacket::Bundle(OscBundle {
+                    timetag: time,
+                    content: vec![],
+                }),
+            ],
+        });
+        assert_same(OscPacket::Bundle(OscBundle {
+            timetag: time,
+            content: vec![],
+        }));
+        assert_same(OscPacket::Bundle(OscBundle {
+            timetag: time,
+            content: vec![msg("/a", every_type()), inner, msg("/bb", vec![])],
+        }));
+    }
+}
@@ -0,0 +1,324 @@
+//! Failover between groups of OSC destinations.
+//!
+//! Destinations can be given in priority groups: the usual destinations form
+//! the primary group, and each backup group follows in order. Translated OSC
+//! goes to a single group at a time, the first one with a destination that
+//! is answering.
+//!
+//! To find out which destinations are answering, each is sent
+//! `/bcr2kosc/ping id` at regular intervals, as for latency measurement, and
+//! is expected to reply with `/bcr2kosc/pong id`. Any reply counts, whatever
+//! its id. A destination that hasn't replied within the timeout is taken to
+//! be down. When every group is down, OSC goes to the primary group.
+//!
+//! Every destination starts out as answering, so OSC goes to the primary
+//! group until it has had a chance to miss its replies.
+//!
+//! Clients that register themselves are sent translated OSC too, whichever
+//! group is in use; see the `sync` module. They aren't pinged.
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rosc::encoder::encode;
+use rosc::{OscMessage, OscPacket, OscType};
+use tokio::net::UdpSocket;
+use tokio::time::MissedTickBehavior;
+use tracing::{error, info, warn};
+
+use super::latency::PING_ADDR;
+use crate::PGM;
+
+/// The number of pings sent to each destination per timeout.
+const PINGS_PER_TIMEOUT: u32 = 4;
+
+/// Configures failover to backup destinations.
+#[derive(Clone, Debug)]
+pub struct FailoverConfig {
+    /// Groups of backup destinations, in priority order.
+    pub backups: Vec<Vec<SocketAddr>>,
+    /// How long a destination can go without replying before it's taken to
+    /// be down.
+    pub timeout: Duration,
+}
+
+struct State {
+    /// The index of the group that OSC is sent to.
+    active: usize,
+    /// When each destination last replied.
+    last_reply: BTreeMap<SocketAddr, Instant>,
+    switches: u32,
+    /// Clients that registered themselves.
+    registered: Vec<SocketAddr>,
+    /// The active group and the registered clients.
+    current: Arc<Vec<SocketAddr>>,
+}
+
+impl State {
+    fn refresh(&mut self, groups: &[Arc<Vec<SocketAddr>>]) {
+        let group = &groups[self.active];
+        let mut current = group.to_vec();
+        current.extend(self.registered.iter().filter(|a| !group.contains(a)));
+        self.current = Arc::new(current);
+    }
+}
+
+/// The destinations of translated OSC.
+pub struct Destinations {
+    groups: Vec<Arc<Vec<SocketAddr>>>,
+    timeout: Duration,
+    state: Mutex<State>,
+}
+
+impl Destinations {
+    /// Creates the destinations, with `primary` as the first group, followed
+    /// by the backups in `failover`, if any.
+    pub fn new(primary: Arc<Vec<SocketAddr>>, failover: Option<&FailoverConfig>) -> Self {
+        let mut groups = vec![primary];
+        let mut timeout = Duration::ZERO;
+        if let Some(config) = failover {
+            groups.extend(config.backups.iter().cloned().map(Arc::new));
+            timeout = config.timeout;
+        }
+        let now = Instant::now();
+        let last_reply = groups
+            .iter()
+            .flat_map(|g| g.iter())
+            .map(|a| (*a, now))
+            .collect();
+        let current = groups[0].clone();
+        Destinations {
+            groups,
+            timeout,
+            state: Mutex::new(State {
+                active: 0,
+                last_reply,
+                switches: 0,
+                registered: vec![],
+                current,
+            }),
+        }
+    }
+
+    /// Returns the destinations that OSC is currently sent to.
+    pub fn current(&self) -> Arc<Vec<SocketAddr>> {
+        self.state.lock().unwrap().current.clone()
+    }
+
+    /// Adds a client to the destinations for as long as the service runs.
+    /// Returns false if it was already registered.
+    pub fn register(&self, client: SocketAddr) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.registered.contains(&client) {
+            return false;
+        }
+        info!("{PGM} registered OSC client {client}.");
+        state.registered.push(client);
+        state.refresh(&self.groups);
+        true
+    }
+
+    /// Returns true if there are backup groups to fail over to.
+    pub fn has_backups(&self) -> bool {
+        self.groups.len() > 1
+    }
+
+    /// Records a reply to a ping.
+    pub fn pong(&self, sender: SocketAddr) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(t) = state.last_reply.get_mut(&sender) {
+            *t = Instant::now();
+        }
+    }
+
+    /// Describes the groups, and which one is in use, and the registered
+    /// clients.
+    pub fn report(&self) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        let mut report: Vec<String> = state
+            .registered
+            .iter()
+            .map(|a| format!("registered OSC client: {a}"))
+            .collect();
+        if !self.has_backups() {
+            return report;
+        }
+        report.push(format!(
+            "OSC destination group {} in use, {} switches",
+            state.active + 1,
+            state.switches
+        ));
+        for (i, group) in self.groups.iter().enumerate() {
+            let members: Vec<String> = group
+                .iter()
+                .map(|a| {
+                    let ago = state.last_reply[a].elapsed().as_secs_f64();
+                    format!("{a} ({ago:.1} s)")
+                })
+                .collect();
+            report.push(format!("group {}: {}", i + 1, members.join(", ")));
+        }
+        report
+    }
+
+    /// Pings every destination, switching groups as they stop or start
+    /// answering. Runs until cancelled.
+    pub async fn run(&self, socket: Arc<UdpSocket>) {
+        let mut timer = tokio::time::interval(self.timeout / PINGS_PER_TIMEOUT);
+        timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut id: i32 = 0;
+        loop {
+            timer.tick().await;
+            self.choose();
+            id = id.wrapping_add(1);
+            let ping = OscPacket::Message(OscMessage {
+                addr: PING_ADDR.to_string(),
+                args: vec![OscType::Int(id)],
+            });
+            let buf = match encode(&ping) {
+                Ok(buf) => buf,
+                Err(e) => {
+                    error!("OSC encoding failed: {e}");
+                    continue;
+                }
+            };
+            for a in self.groups.iter().flat_map(|g| g.iter()) {
+                if let Err(e) = socket.send_to(&buf, a).await {
+                    error!("OSC ping to {a} failed: {e}");
+                }
+            }
+        }
+    }
+
+    /// Makes the first group with an answering destination the active one.
+    fn choose(&self) {
+        let mut state = self.state.lock().unwrap();
+        let answering = |a: &SocketAddr| state.last_reply[a].elapsed() < self.timeout;
+        let found = self.groups.iter().position(|g| g.iter().any(answering));
+        let active = found.unwrap_or(0);
+        if active != state.active {
+            let addrs: Vec<String> = self.groups[active].iter().map(|a| a.to_string()).collect();
+            if found.is_none() {
+                warn!(
+                    "{PGM} no OSC destination group is answering; sending to {}.",
+                    addrs.join(", ")
+                );
+            } else if active > state.active {
+                warn!(
+                    "{PGM} OSC destination group {} stopped answering; sending to {}.",
+                    state.active + 1,
+                    addrs.join(", ")
+                );
+            } else {
+                info!(
+                    "{PGM} OSC destination group {} is answering; sending to {}.",
+                    active + 1,
+                    addrs.join(", ")
+                );
+            }
+            state.active = active;
+            state.switches += 1;
+            state.refresh(&self.groups);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::super::latency::PONG_ADDR;
+    use super::super::testing::{self, cc, osc, recv_osc, Harness};
+    use super::*;
+    use crate::translator::testing::expect_osc;
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    fn addr(port: u16) -> SocketAddr {
+        (Ipv4Addr::LOCALHOST, port).into()
+    }
+
+    fn destinations() -> Destinations {
+        let config = FailoverConfig {
+            backups: vec![vec![addr(2), addr(3)], vec![addr(4)]],
+            timeout: TIMEOUT,
+        };
+        Destinations::new(Arc::new(vec![addr(1)]), Some(&config))
+    }
+
+    #[test]
+    fn first_answering_group_is_used() {
+        let d = destinations();
+        assert!(d.has_backups());
+        assert_eq!(*d.current(), [addr(1)]);
+        std::thread::sleep(TIMEOUT);
+        d.pong(addr(3));
+        d.pong(addr(4));
+        d.choose();
+        assert_eq!(*d.current(), [addr(2), addr(3)]);
+        d.pong(addr(1));
+        d.choose();
+        assert_eq!(*d.current(), [addr(1)]);
+        assert!(d.report()[0].starts_with("OSC destination group 1 in use, 2 switches"));
+    }
+
+    #[test]
+    fn primary_is_used_when_nothing_answers() {
+        let d = destinations();
+        std::thread::sleep(TIMEOUT);
+        d.pong(addr(4));
+        d.choose();
+        assert_eq!(*d.current(), [addr(4)]);
+        std::thread::sleep(TIMEOUT);
+        d.choose();
+        assert_eq!(*d.current(), [addr(1)]);
+    }
+
+    #[test]
+    fn registered_clients_follow_every_group() {
+        let d = destinations();
+        assert!(d.register(addr(9)));
+        assert!(!d.register(addr(9)));
+        assert_eq!(*d.current(), [addr(1), addr(9)]);
+        std::thread::sleep(TIMEOUT);
+        d.pong(addr(4));
+        d.choose();
+        assert_eq!(*d.current(), [addr(4), addr(9)]);
+        assert!(!Destinations::new(Arc::new(vec![addr(1)]), None).has_backups());
+    }
+
+    #[tokio::test]
+    async fn osc_fails_over_to_a_backup_that_answers() {
+        let (h, builder) = Harness::new().await;
+        let backup = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let config = FailoverConfig {
+            backups: vec![vec![backup.local_addr().unwrap()]],
+            timeout: TIMEOUT,
+        };
+        let svc = builder.failover(Some(config)).build();
+        testing::run(&svc, async {
+            // The backup answers pings until it's sent translated OSC; the
+            // primary, the harness's client, never does.
+            loop {
+                let (pkt, from) = recv_osc(&backup).await;
+                let OscPacket::Message(msg) = pkt else {
+                    continue;
+                };
+                if msg.addr == PING_ADDR {
+                    let pong = OscPacket::Message(OscMessage {
+                        addr: PONG_ADDR.to_string(),
+                        args: msg.args,
+                    });
+                    backup.send_to(&encode(&pong).unwrap(), from).await.unwrap();
+                    h.midi_in.unbounded_send(cc(1, 127)).unwrap();
+                } else {
+                    expect_osc(Some(&OscPacket::Message(msg)), &osc("/encoder/1", 1.0)).unwrap();
+                    break;
+                }
+            }
+        })
+        .await;
+    }
+}
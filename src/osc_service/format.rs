@@ -0,0 +1,215 @@
+//! Per-destination formatting of translated OSC.
+//!
+//! Hosts disagree on how switches are represented. REAPER expects floats,
+//! 1.0 for on and 0.0 for off, where others want OSC 1.1 true and false, or
+//! integers. Mappings send OSC 1.1 booleans where they're switches, with
+//! `output=bool`; see the `output` module of `translator`. Each destination
+//! can then be given a format, which converts them as it wants:
+//!
+//! Option               effect
+//! bools=bool           true and false are sent as they are
+//! bools=float          1.0 and 0.0
+//! bools=int            1 and 0
+//! strings=keep         strings are sent as they are
+//! strings=ascii        characters outside ASCII are replaced with `?`
+//! strings=drop         messages with string arguments aren't sent
+//! max-size=BYTES       packets larger than this are split, where they can
+//!                      be; see the `fragment` module
+//!
+//! A format is a comma-separated list of options and the names of presets:
+//! `generic`, the default, which changes nothing, or `reaper`, which is
+//! `bools=float`. Later options override earlier ones.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use rosc::{OscBundle, OscMessage, OscPacket, OscType};
+
+use super::fragment::MIN_MAX_SIZE;
+
+/// How a destination wants booleans.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum BoolFormat {
+    /// OSC 1.1 true and false.
+    #[default]
+    Bool,
+    /// Floats, 1.0 and 0.0.
+    Float,
+    /// Integers, 1 and 0.
+    Int,
+}
+
+/// How a destination wants strings.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum StringFormat {
+    /// As they are.
+    #[default]
+    Keep,
+    /// With characters outside ASCII replaced by `?`.
+    Ascii,
+    /// Not at all; messages with string arguments aren't sent.
+    Drop,
+}
+
+/// The format of OSC sent to a destination.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct OscFormat {
+    /// How booleans are sent.
+    pub bools: BoolFormat,
+    /// How strings are sent.
+    pub strings: StringFormat,
+    /// The largest packet, in bytes, that is sent, if there's a limit.
+    pub max_size: Option<usize>,
+}
+
+impl OscFormat {
+    /// Converts a packet to the format, returning `None` if nothing is left
+    /// to send.
+    pub fn apply(&self, packet: &OscPacket) -> Option<OscPacket> {
+        match packet {
+            OscPacket::Message(m) => {
+                let args = m
+                    .args
+                    .iter()
+                    .map(|a| self.convert(a))
+                    .collect::<Option<Vec<_>>>()?;
+                Some(OscPacket::Message(OscMessage {
+                    addr: m.addr.clone(),
+                    args,
+                }))
+            }
+            OscPacket::Bundle(b) => {
+                let content: Vec<OscPacket> =
+                    b.content.iter().filter_map(|p| self.apply(p)).collect();
+                if content.is_empty() {
+                    None
+                } else {
+                    Some(OscPacket::Bundle(OscBundle {
+                        timetag: b.timetag,
+                        content,
+                    }))
+                }
+            }
+        }
+    }
+
+    /// Converts an argument, returning `None` if its message shouldn't be
+    /// sent.
+    fn convert(&self, arg: &OscType) -> Option<OscType> {
+        Some(match (arg, self.bools, self.strings) {
+            (OscType::Bool(b), BoolFormat::Float, _) => OscType::Float(if *b { 1.0 } else { 0.0 }),
+            (OscType::Bool(b), BoolFormat::Int, _) => OscType::Int(*b as i32),
+            (OscType::String(_), _, StringFormat::Drop) => return None,
+            (OscType::String(s), _, StringFormat::Ascii) => OscType::String(
+                s.chars()
+                    .map(|c| if c.is_ascii() { c } else { '?' })
+                    .collect(),
+            ),
+            (other, _, _) => other.clone(),
+        })
+    }
+}
+
+impl FromStr for OscFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut format = OscFormat::default();
+        for option in s.split(',') {
+            match option {
+                "generic" => {
+                    format.bools = BoolFormat::Bool;
+                    format.strings = StringFormat::Keep;
+                    continue;
+                }
+                "reaper" => {
+                    format.bools = BoolFormat::Float;
+                    format.strings = StringFormat::Keep;
+                    continue;
+                }
+                _ => {}
+            }
+            match option.split_once('=') {
+                Some(("bools", "bool")) => format.bools = BoolFormat::Bool,
+                Some(("bools", "float")) => format.bools = BoolFormat::Float,
+                Some(("bools", "int")) => format.bools = BoolFormat::Int,
+                Some(("strings", "keep")) => format.strings = StringFormat::Keep,
+                Some(("strings", "ascii")) => format.strings = StringFormat::Ascii,
+                Some(("strings", "drop")) => format.strings = StringFormat::Drop,
+                Some(("max-size", n)) => match n.parse::<usize>() {
+                    Ok(n) if n >= MIN_MAX_SIZE => format.max_size = Some(n),
+                    _ => {
+                        return Err(format!(
+                            "invalid max-size \"{n}\"; it must be at least {MIN_MAX_SIZE} bytes"
+                        ))
+                    }
+                },
+                _ => return Err(format!("unknown OSC format option \"{option}\"")),
+            }
+        }
+        Ok(format)
+    }
+}
+
+impl Display for OscFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bools = match self.bools {
+            BoolFormat::Bool => "bool",
+            BoolFormat::Float => "float",
+            BoolFormat::Int => "int",
+        };
+        let strings = match self.strings {
+            StringFormat::Keep => "keep",
+            StringFormat::Ascii => "ascii",
+            StringFormat::Drop => "drop",
+        };
+        write!(f, "bools={bools},strings={strings}")?;
+        if let Some(n) = self.max_size {
+            write!(f, ",max-size={n}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The formats of destinations that don't use the default.
+#[derive(Clone, Debug, Default)]
+pub struct Formats(HashMap<SocketAddr, OscFormat>);
+
+impl Formats {
+    /// Creates the formats of destinations.
+    pub fn new(formats: &[(SocketAddr, OscFormat)]) -> Self {
+        Formats(formats.iter().copied().collect())
+    }
+
+    /// Returns the format of a destination.
+    pub fn get(&self, addr: SocketAddr) -> OscFormat {
+        self.0.get(&addr).copied().unwrap_or_default()
+    }
+
+    /// Groups destinations by format, so that each packet is converted and
+    /// encoded once per format.
+    pub fn group(&self, addrs: &[SocketAddr]) -> Vec<(OscFormat, Vec<SocketAddr>)> {
+        let mut groups: Vec<(OscFormat, Vec<SocketAddr>)> = vec![];
+        for a in addrs {
+            let format = self.get(*a);
+            match groups.iter_mut().find(|(f, _)| *f == format) {
+                Some((_, group)) => group.push(*a),
+                None => groups.push((format, vec![*a])),
+            }
+        }
+        groups
+    }
+
+    /// Describes the destinations' formats.
+    pub fn report(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self
+            .0
+            .iter()
+            .map(|(a, f)| format!("OSC format for {a}: {f}"))
+            .collect();
+        lines.sort();
+        lines
+    }
+}
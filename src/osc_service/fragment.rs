@@ -0,0 +1,235 @@
+//! Splitting of OSC bundles too large for a destination.
+//!
+//! A destination's format can limit the size of the packets it's sent, with
+//! the `max-size` option; see the `format` module. Packets are usually small,
+//! but mappings that send several messages at once, and the state sent to
+//! clients that sync, are sent as bundles, which can exceed the network's MTU
+//! or what a receiver is willing to read. A bundle whose encoding is larger
+//! than the limit is split into bundles that fit, each with the same time
+//! tag, and the messages in the same order.
+//!
+//! Every part of a split bundle is itself a bundle with the bundle's time tag,
+//! so that a receiver schedules each message as it would have in the whole.
+//! A bundle nested in one that's split is kept whole if it fits, and is
+//! otherwise split in the same way, each of its parts being wrapped in a
+//! bundle of its own with the outer time tag. A message can't be split, so one
+//! that's too large by itself is sent anyway, alone in its bundle, with a
+//! warning.
+
+use rosc::{OscBundle, OscPacket};
+use tracing::warn;
+
+use super::encode::encode_into;
+
+/// The smallest limit allowed, which leaves room in a bundle for a message
+/// with a short address and a few arguments.
+pub const MIN_MAX_SIZE: usize = 64;
+
+/// The size of a bundle's header: `#bundle` and the time tag.
+const BUNDLE_HEADER: usize = 16;
+
+/// The size of the length that precedes each element of a bundle.
+const ELEMENT_HEADER: usize = 4;
+
+/// Splits a packet into packets whose encodings are at most `max` bytes,
+/// where they can be.
+pub fn split(packet: &OscPacket, max: usize) -> Vec<OscPacket> {
+    let mut parts = vec![];
+    let mut buf = Vec::with_capacity(max);
+    split_into(packet, max, &mut buf, &mut parts);
+    parts
+}
+
+fn split_into(packet: &OscPacket, max: usize, buf: &mut Vec<u8>, parts: &mut Vec<OscPacket>) {
+    let bundle = match packet {
+        OscPacket::Message(msg) => {
+            encode_into(packet, buf);
+            warn_if_too_large(&msg.addr, buf.len(), max);
+            parts.push(packet.clone());
+            return;
+        }
+        OscPacket::Bundle(b) => b,
+    };
+    let mut content = vec![];
+    let mut size = BUNDLE_HEADER;
+    for p in &bundle.content {
+        encode_into(p, buf);
+        let element = ELEMENT_HEADER + buf.len();
+        if BUNDLE_HEADER + element > max {
+            // It doesn't fit in a bundle with anything else.
+            flush(bundle, &mut content, parts);
+            size = BUNDLE_HEADER;
+            match p {
+                OscPacket::Message(msg) => {
+                    warn_if_too_large(&msg.addr, BUNDLE_HEADER + element, max);
+                    content.push(p.clone());
+                    flush(bundle, &mut content, parts);
+                }
+                OscPacket::Bundle(_) => {
+                    let inner_max = max.saturating_sub(BUNDLE_HEADER + ELEMENT_HEADER);
+                    let mut inner = vec![];
+                    split_into(p, inner_max, buf, &mut inner);
+                    for part in inner {
+                        content.push(part);
+                        flush(bundle, &mut content, parts);
+                    }
+                }
+            }
+            continue;
+        }
+        if size + element > max {
+            flush(bundle, &mut content, parts);
+            size = BUNDLE_HEADER;
+        }
+        content.push(p.clone());
+        size += element;
+    }
+    flush(bundle, &mut content, parts);
+}
+
+fn warn_if_too_large(addr: &str, size: usize, max: usize) {
+    if size > max {
+        warn!("OSC message to {addr} is {size} bytes, more than the {max} allowed.");
+    }
+}
+
+/// Adds a bundle of the content gathered so far, if there is any.
+fn flush(bundle: &OscBundle, content: &mut Vec<OscPacket>, parts: &mut Vec<OscPacket>) {
+    if !content.is_empty() {
+        parts.push(OscPacket::Bundle(OscBundle {
+            timetag: bundle.timetag,
+            content: std::mem::take(content),
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rosc::{OscMessage, OscTime, OscType};
+
+    const OUTER: OscTime = OscTime {
+        seconds: 1,
+        fractional: 0,
+    };
+    const INNER: OscTime = OscTime {
+        seconds: 2,
+        fractional: 0,
+    };
+
+    fn msg(n: i32) -> OscPacket {
+        OscPacket::Message(OscMessage {
+            addr: format!("/ch/{n}/level"),
+            args: vec![OscType::Int(n)],
+        })
+    }
+
+    fn bundle(timetag: OscTime, content: Vec<OscPacket>) -> OscPacket {
+        OscPacket::Bundle(OscBundle { timetag, content })
+    }
+
+    fn size(packet: &OscPacket) -> usize {
+        let mut buf = vec![];
+        encode_into(packet, &mut buf);
+        buf.len()
+    }
+
+    /// The messages in a packet, in order.
+    fn messages(packet: &OscPacket, found: &mut Vec<OscPacket>) {
+        match packet {
+            OscPacket::Message(_) => found.push(packet.clone()),
+            OscPacket::Bundle(b) => b.content.iter().for_each(|p| messages(p, found)),
+        }
+    }
+
+    fn all_messages(packets: &[OscPacket]) -> Vec<OscPacket> {
+        let mut found = vec![];
+        packets.iter().for_each(|p| messages(p, &mut found));
+        found
+    }
+
+    fn timetag(packet: &OscPacket) -> OscTime {
+        match packet {
+            OscPacket::Bundle(b) => b.timetag,
+            OscPacket::Message(m) => panic!("message to {} sent bare", m.addr),
+        }
+    }
+
+    #[test]
+    fn message_is_kept() {
+        let big = OscPacket::Message(OscMessage {
+            addr: "/big".to_string(),
+            args: vec![OscType::Blob(vec![0; 100])],
+        });
+        assert_eq!(split(&big, MIN_MAX_SIZE), vec![big]);
+    }
+
+    #[test]
+    fn bundle_is_split_in_order() {
+        let packet = bundle(OUTER, (0..20).map(msg).collect());
+        for max in [MIN_MAX_SIZE, 100, 200] {
+            let parts = split(&packet, max);
+            assert!(parts.len() > 1);
+            for part in &parts {
+                assert!(size(part) <= max, "{part:?} is larger than {max}");
+                assert_eq!(timetag(part), OUTER);
+            }
+            assert_eq!(
+                all_messages(&parts),
+                all_messages(std::slice::from_ref(&packet))
+            );
+        }
+    }
+
+    #[test]
+    fn large_message_is_sent_in_a_bundle() {
+        let big = OscPacket::Message(OscMessage {
+            addr: "/big".to_string(),
+            args: vec![OscType::Blob(vec![0; 100])],
+        });
+        let packet = bundle(OUTER, vec![msg(1), big.clone(), msg(2)]);
+        let parts = split(&packet, MIN_MAX_SIZE);
+        assert_eq!(
+            parts,
+            vec![
+                bundle(OUTER, vec![msg(1)]),
+                bundle(OUTER, vec![big]),
+                bundle(OUTER, vec![msg(2)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_bundle_that_fits_is_kept_whole() {
+        let inner = bundle(INNER, vec![msg(1)]);
+        let packet = bundle(OUTER, vec![inner.clone(), msg(2), msg(3)]);
+        let parts = split(&packet, MIN_MAX_SIZE);
+        assert_eq!(parts[0], bundle(OUTER, vec![inner]));
+        assert_eq!(all_messages(&parts), all_messages(&[packet]));
+    }
+
+    #[test]
+    fn nested_bundle_is_split_and_wrapped() {
+        let inner = bundle(INNER, (1..10).map(msg).collect());
+        let packet = bundle(OUTER, vec![msg(0), inner, msg(10)]);
+        let max = 100;
+        let parts = split(&packet, max);
+        assert!(parts.len() > 3);
+        for part in &parts {
+            assert!(size(part) <= max, "{part:?} is larger than {max}");
+            assert_eq!(timetag(part), OUTER);
+        }
+        assert_eq!(parts.first(), Some(&bundle(OUTER, vec![msg(0)])));
+        assert_eq!(parts.last(), Some(&bundle(OUTER, vec![msg(10)])));
+        for part in &parts[1..parts.len() - 1] {
+            match part {
+                OscPacket::Bundle(b) => {
+                    assert_eq!(b.content.len(), 1);
+                    assert_eq!(timetag(&b.content[0]), INNER);
+                }
+                _ => unreachable!(),
+            }
+        }
+        assert_eq!(all_messages(&parts), all_messages(&[packet]));
+    }
+}
@@ -0,0 +1,125 @@
+//! OSC input sockets.
+//!
+//! The service can listen for OSC on several UDP sockets. Each decodes the
+//! packets it receives and passes them, with their senders, to the one
+//! translation pipeline. Each keeps its own statistics, reported by the
+//! control socket's `stats` command.
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use futures::channel::mpsc::UnboundedSender;
+use rosc::OscPacket;
+use tokio::net::UdpSocket;
+use tracing::{debug, error, info};
+
+use crate::PGM;
+
+#[derive(Default)]
+struct Stats {
+    packets: u64,
+    bytes: u64,
+    decode_errors: u64,
+    recv_errors: u64,
+}
+
+/// A socket on which OSC is received.
+pub struct OscInput {
+    /// The socket, which can also be used to reply to senders.
+    pub socket: Arc<UdpSocket>,
+    addr: SocketAddr,
+    stats: Mutex<Stats>,
+}
+
+impl OscInput {
+    /// Binds a socket to the given local address.
+    pub async fn bind(addr: SocketAddr) -> std::io::Result<Self> {
+        let socket = Arc::new(UdpSocket::bind(addr).await?);
+        Ok(OscInput {
+            socket,
+            addr,
+            stats: Mutex::new(Stats::default()),
+        })
+    }
+
+    /// Uses a socket that's already bound, such as one shared with other
+    /// software.
+    pub fn from_socket(socket: Arc<UdpSocket>) -> std::io::Result<Self> {
+        let addr = socket.local_addr()?;
+        Ok(OscInput {
+            socket,
+            addr,
+            stats: Mutex::new(Stats::default()),
+        })
+    }
+
+    /// Receives and decodes packets, sending them and their senders to `tx`.
+    /// Runs until cancelled, or until `tx` is closed.
+    pub async fn receive(&self, tx: UnboundedSender<(OscPacket, SocketAddr)>) {
+        info!(
+            "{PGM} listening for OSC on UDP port {:?}.",
+            self.socket.local_addr()
+        );
+        let mut vec = vec![0u8; 1024 * 16];
+        let mut next: usize = 0;
+        loop {
+            // TODO: On Windows, we get error 10054 here if the *sender* just
+            // tried to send to an unresponsive port! (Try using distinct
+            // send/receive UdpSockets?)
+            match self.socket.recv_from(&mut vec[next..]).await {
+                Ok((len, sender)) => {
+                    let buflen = next + len;
+                    match rosc::decoder::decode_udp(&vec[0..buflen]) {
+                        Ok((remainder, pkt)) => {
+                            debug!("Received OSC packet from {sender:?}: {pkt:?}");
+                            {
+                                let mut stats = self.stats.lock().unwrap();
+                                stats.packets += 1;
+                                stats.bytes += len as u64;
+                            }
+                            let rlen = remainder.len();
+                            if rlen > 0 {
+                                debug!("OSC input remainder {len} bytes.");
+                                vec.copy_within(len..len + rlen, 0);
+                                next = rlen;
+                            }
+                            if tx.unbounded_send((pkt, sender)).is_err() {
+                                return;
+                            }
+                        }
+                        Err(e) => {
+                            self.stats.lock().unwrap().decode_errors += 1;
+                            error!("OSC pkt decode error: {e}");
+                            next = 0;
+                            error!("Discarded {buflen} bytes.");
+                        }
+                    }
+                }
+                Err(e) => {
+                    self.stats.lock().unwrap().recv_errors += 1;
+                    error!("UDP recv error: {e}");
+                }
+            }
+        }
+    }
+
+    /// Summarizes the socket's statistics.
+    pub fn report(&self) -> String {
+        let stats = self.stats.lock().unwrap();
+        format!(
+            "OSC in {}: {} packets, {} bytes, {} decode errors, {} receive errors",
+            self.addr, stats.packets, stats.bytes, stats.decode_errors, stats.recv_errors
+        )
+    }
+
+    /// The socket's statistics, by name.
+    pub fn counters(&self) -> Vec<(&'static str, u64)> {
+        let stats = self.stats.lock().unwrap();
+        vec![
+            ("osc_packets", stats.packets),
+            ("osc_bytes", stats.bytes),
+            ("osc_decode_errors", stats.decode_errors),
+            ("osc_recv_errors", stats.recv_errors),
+        ]
+    }
+}
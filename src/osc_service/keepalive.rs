@@ -0,0 +1,118 @@
+//! Device health pings.
+//!
+//! When enabled, the service asks the device to identify itself at regular
+//! intervals, and tracks whether it answers. A device that stops answering,
+//! for example because it was switched off, is noticed within one interval
+//! plus the identity timeout. Changes are logged, and passed to the status
+//! monitor so that OSC clients can be told of them.
+//!
+//! A ping is skipped while another device operation is in progress, since
+//! the device is busy answering that.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::watch;
+use tokio::time::MissedTickBehavior;
+use tracing::{info, warn};
+
+use super::admin::Admin;
+use crate::config::device_label;
+use crate::PGM;
+
+/// Configures device health pings.
+#[derive(Clone, Copy, Debug)]
+pub struct KeepaliveConfig {
+    /// Time between pings.
+    pub interval: Duration,
+    /// The zero-based number of the device to ping.
+    pub device: u8,
+}
+
+#[derive(Default)]
+struct State {
+    sent: u64,
+    answered: u64,
+    last_answer: Option<Instant>,
+}
+
+/// Pings a device and tracks its answers.
+pub struct Keepalive {
+    config: KeepaliveConfig,
+    /// The device's name, if it has one.
+    name: Option<String>,
+    state: Mutex<State>,
+    /// Whether the device answered the last ping; `None` until it's known.
+    alive: watch::Sender<Option<bool>>,
+}
+
+impl Keepalive {
+    /// Creates a keepalive for a device with an optional name. Pings aren't
+    /// sent until `run` is called.
+    pub fn new(config: KeepaliveConfig, name: Option<String>) -> Self {
+        Keepalive {
+            config,
+            name,
+            state: Mutex::new(State::default()),
+            alive: watch::channel(None).0,
+        }
+    }
+
+    /// Returns a receiver that is notified whenever the device starts or
+    /// stops answering.
+    pub fn subscribe(&self) -> watch::Receiver<Option<bool>> {
+        self.alive.subscribe()
+    }
+
+    /// Summarizes the pings so far.
+    pub fn report(&self) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        let device = self.label();
+        let last = match state.last_answer {
+            Some(t) => format!("last answer {:.1} s ago", t.elapsed().as_secs_f64()),
+            None => "never answered".to_string(),
+        };
+        vec![format!(
+            "{device}: {} of {} pings answered, {last}",
+            state.answered, state.sent
+        )]
+    }
+
+    /// Pings the device every interval. Runs until cancelled.
+    pub async fn run(self: Arc<Self>, admin: Arc<Admin>) {
+        let mut timer = tokio::time::interval(self.config.interval);
+        timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            timer.tick().await;
+            let answered = match admin.ping(self.config.device).await {
+                Some(answered) => answered,
+                None => continue,
+            };
+            {
+                let mut state = self.state.lock().unwrap();
+                state.sent += 1;
+                if answered {
+                    state.answered += 1;
+                    state.last_answer = Some(Instant::now());
+                }
+            }
+            self.alive.send_if_modified(|alive| {
+                if *alive == Some(answered) {
+                    return false;
+                }
+                let device = self.label();
+                if answered {
+                    info!("{PGM} {device} is answering.");
+                } else {
+                    warn!("{PGM} {device} stopped answering.");
+                }
+                *alive = Some(answered);
+                true
+            });
+        }
+    }
+
+    fn label(&self) -> String {
+        device_label(self.config.device + 1, self.name.as_deref())
+    }
+}
@@ -0,0 +1,237 @@
+//! Round-trip latency measurement.
+//!
+//! When enabled, the service periodically probes the paths it relays between:
+//!
+//! * MIDI: a marker control change is sent to the device, and timed until it
+//!   is echoed back. This requires a device, or a MIDI loop, that echoes the
+//!   marker control; choose a control that isn't otherwise mapped.
+//! * OSC: each destination is sent `/bcr2kosc/ping id`, and timed until it
+//!   replies with `/bcr2kosc/pong id`. Clients that don't reply are simply
+//!   reported as such.
+//!
+//! Measurements are logged at debug level, and summarized by the control
+//! socket's `latency` command.
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::{SinkExt, StreamExt};
+use midi_control::{Channel, ControlEvent};
+use midi_io::{MidiMessage, MidiSink, SharedMidiInput};
+use rosc::encoder::encode;
+use rosc::{OscMessage, OscPacket, OscType};
+use tokio::net::UdpSocket;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, error};
+
+/// Address of the latency probes sent to OSC destinations.
+pub const PING_ADDR: &str = "/bcr2kosc/ping";
+
+/// Address of the replies expected to latency probes.
+pub const PONG_ADDR: &str = "/bcr2kosc/pong";
+
+/// Configures latency measurement.
+#[derive(Clone, Copy, Debug)]
+pub struct LatencyConfig {
+    /// Time between probes.
+    pub interval: Duration,
+    /// The channel and control number of the MIDI marker, if MIDI latency is
+    /// measured.
+    pub marker: Option<(Channel, u8)>,
+}
+
+/// Statistics for one path.
+#[derive(Default)]
+struct Stats {
+    sent: u32,
+    received: u32,
+    last: Duration,
+    min: Duration,
+    max: Duration,
+    total: Duration,
+}
+
+impl Stats {
+    fn record(&mut self, d: Duration) {
+        if self.received == 0 || d < self.min {
+            self.min = d;
+        }
+        self.max = self.max.max(d);
+        self.last = d;
+        self.total += d;
+        self.received += 1;
+    }
+
+    fn describe(&self) -> String {
+        if self.received == 0 {
+            return format!("no replies to {} probes", self.sent);
+        }
+        format!(
+            "last {}, min {}, mean {}, max {}, {} of {} probes answered",
+            ms(self.last),
+            ms(self.min),
+            ms(self.total / self.received),
+            ms(self.max),
+            self.received,
+            self.sent
+        )
+    }
+}
+
+fn ms(d: Duration) -> String {
+    format!("{:.1} ms", d.as_secs_f64() * 1000.0)
+}
+
+#[derive(Default)]
+struct State {
+    sequence: i32,
+    midi_pending: Option<(u8, Instant)>,
+    osc_pending: BTreeMap<SocketAddr, (i32, Instant)>,
+    midi: Stats,
+    osc: BTreeMap<SocketAddr, Stats>,
+}
+
+/// Sends latency probes and collects the results.
+pub struct LatencyProbe {
+    config: LatencyConfig,
+    state: Mutex<State>,
+}
+
+impl LatencyProbe {
+    /// Creates a probe. Probes aren't sent until `run` is called.
+    pub fn new(config: LatencyConfig) -> Self {
+        LatencyProbe {
+            config,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Returns true if the message is a reply to a latency probe.
+    pub fn is_pong(msg: &OscMessage) -> bool {
+        msg.addr == PONG_ADDR
+    }
+
+    /// Records a reply to a latency probe.
+    pub fn pong(&self, sender: SocketAddr, msg: &OscMessage) {
+        let now = Instant::now();
+        let id = match msg.args.first() {
+            Some(OscType::Int(id)) => *id,
+            _ => return,
+        };
+        let mut state = self.state.lock().unwrap();
+        match state.osc_pending.get(&sender) {
+            Some((pending, sent)) if *pending == id => {
+                let d = now - *sent;
+                state.osc_pending.remove(&sender);
+                debug!("OSC round trip to {sender}: {}", ms(d));
+                state.osc.entry(sender).or_default().record(d);
+            }
+            _ => debug!("Ignoring stale or unexpected pong from {sender}."),
+        }
+    }
+
+    /// Summarizes the measurements so far.
+    pub fn report(&self) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        let mut report = vec![];
+        if self.config.marker.is_some() {
+            report.push(format!("MIDI: {}", state.midi.describe()));
+        }
+        for (a, stats) in &state.osc {
+            report.push(format!("OSC {a}: {}", stats.describe()));
+        }
+        report
+    }
+
+    /// Sends probes every interval, and watches for the MIDI marker's echo.
+    /// Runs until cancelled.
+    pub async fn run(
+        &self,
+        midi_in: SharedMidiInput,
+        mut midi_out: MidiSink,
+        socket: Arc<UdpSocket>,
+        clients: Arc<Vec<SocketAddr>>,
+    ) {
+        let mut echoes = midi_in.subscribe(is_control_change);
+        let mut timer = tokio::time::interval(self.config.interval);
+        timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = timer.tick() => self.probe(&mut midi_out, &socket, &clients).await,
+                Some(msg) = echoes.next() => self.echo(&msg),
+            }
+        }
+    }
+
+    async fn probe(&self, midi_out: &mut MidiSink, socket: &UdpSocket, clients: &[SocketAddr]) {
+        let now = Instant::now();
+        let id = {
+            let mut state = self.state.lock().unwrap();
+            state.sequence = state.sequence.wrapping_add(1);
+            let id = state.sequence;
+            if self.config.marker.is_some() {
+                state.midi_pending = Some(((id & 0x7f) as u8, now));
+                state.midi.sent += 1;
+            }
+            for a in clients {
+                state.osc_pending.insert(*a, (id, now));
+                state.osc.entry(*a).or_default().sent += 1;
+            }
+            id
+        };
+        if let Some((channel, control)) = self.config.marker {
+            let marker = MidiMessage::ControlChange(
+                channel,
+                ControlEvent {
+                    control,
+                    value: (id & 0x7f) as u8,
+                },
+            );
+            if let Err(e) = midi_out.send(marker).await {
+                error!("Latency probe MIDI send failed: {e}");
+            }
+        }
+        let ping = OscPacket::Message(OscMessage {
+            addr: PING_ADDR.to_string(),
+            args: vec![OscType::Int(id)],
+        });
+        match encode(&ping) {
+            Ok(buf) => {
+                for a in clients {
+                    if let Err(e) = socket.send_to(&buf, a).await {
+                        error!("Latency probe OSC send to {a} failed: {e}");
+                    }
+                }
+            }
+            Err(e) => error!("OSC encoding failed: {e}"),
+        }
+    }
+
+    fn echo(&self, msg: &MidiMessage) {
+        let now = Instant::now();
+        let (channel, control) = match self.config.marker {
+            Some(marker) => marker,
+            None => return,
+        };
+        if let MidiMessage::ControlChange(ch, ControlEvent { control: c, value }) = msg {
+            if *ch != channel || *c != control {
+                return;
+            }
+            let mut state = self.state.lock().unwrap();
+            if let Some((pending, sent)) = state.midi_pending {
+                if pending == *value {
+                    let d = now - sent;
+                    state.midi_pending = None;
+                    debug!("MIDI round trip: {}", ms(d));
+                    state.midi.record(d);
+                }
+            }
+        }
+    }
+}
+
+fn is_control_change(msg: &MidiMessage) -> bool {
+    matches!(msg, MidiMessage::ControlChange(..))
+}
@@ -0,0 +1,87 @@
+//! Learning of the ranges of control values.
+//!
+//! When enabled, the service watches the control changes it receives, and
+//! widens the range learned for each control to include every value it
+//! sends. The ranges are saved to the ranges file, if there is one, a few
+//! seconds after they change, and they're reported by the control socket's
+//! `ranges` command.
+//!
+//! Mappings are calibrated with the ranges in the file when they're built, at
+//! startup and on `reload`, not as ranges are learned, so that translation
+//! doesn't shift while a control is being explored. See the `ranges` module
+//! of `translator`.
+
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use futures::StreamExt;
+use midi_io::{MidiMessage, SharedMidiInput};
+use tokio::time::MissedTickBehavior;
+use tracing::{error, info};
+
+use crate::translator::LearnedRanges;
+use crate::PGM;
+
+/// How often learned ranges are saved, if they've changed.
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Learns the ranges of control values.
+pub struct RangeLearner {
+    ranges: Mutex<LearnedRanges>,
+    /// Where the ranges are saved, if anywhere.
+    file: Option<PathBuf>,
+}
+
+impl RangeLearner {
+    /// Creates a learner that starts from `ranges`, saving them to `file`.
+    pub fn new(ranges: LearnedRanges, file: Option<PathBuf>) -> Self {
+        RangeLearner {
+            ranges: Mutex::new(ranges),
+            file,
+        }
+    }
+
+    /// Learns from the control changes received, until cancelled or the
+    /// input ends.
+    pub async fn run(&self, midi_in: SharedMidiInput) {
+        let mut src = midi_in.subscribe(control_changes);
+        let mut timer = tokio::time::interval(SAVE_INTERVAL);
+        timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut changed = false;
+        loop {
+            tokio::select! {
+                received = src.next() => match received {
+                    Some(msg) => changed |= self.ranges.lock().unwrap().observe(&msg),
+                    None => break,
+                },
+                _ = timer.tick(), if changed => {
+                    self.save();
+                    changed = false;
+                }
+            }
+        }
+        if changed {
+            self.save();
+        }
+    }
+
+    /// The learned ranges, as they're written to the file.
+    pub fn report(&self) -> Vec<String> {
+        self.ranges.lock().unwrap().lines()
+    }
+
+    fn save(&self) {
+        if let Some(file) = &self.file {
+            let ranges = self.ranges.lock().unwrap().clone();
+            match ranges.save(file) {
+                Ok(()) => info!("{PGM} saved learned ranges to {}.", file.display()),
+                Err(e) => error!("{PGM} can't save learned ranges to {}: {e}", file.display()),
+            }
+        }
+    }
+}
+
+fn control_changes(msg: &MidiMessage) -> bool {
+    matches!(msg, MidiMessage::ControlChange(..))
+}
@@ -0,0 +1,301 @@
+//! Load testing of a running service.
+//!
+//! The load test acts as a demanding OSC client. It sends a running service
+//! synthetic OSC at a steady rate, to a weighted mix of addresses, each with
+//! a float argument that ramps from 0 to 1 so that successive values differ.
+//! The service's counters are read from its control socket before and after,
+//! and compared with what was sent:
+//!
+//! - Packets sent but not received were dropped, by the network or by the
+//!   service's socket buffer.
+//! - Messages translated, against those received, and MIDI sent to the
+//!   device, against those translated, show how much of the load got
+//!   through, and how much was coalesced under backlog.
+//! - Messages received that no mapping handles are counted separately, since
+//!   they point at the address mix rather than at performance.
+//!
+//! The counters also count any other traffic to the service during the
+//! test, so the test is best run against an otherwise quiet instance.
+
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use rosc::{OscMessage, OscPacket, OscType};
+use tokio::net::UdpSocket;
+use tokio::time::{Instant, MissedTickBehavior};
+
+use super::ctl::ctl_request;
+use super::encode::encode_into;
+use super::Result;
+
+/// How often batches of messages are sent.
+const TICK: Duration = Duration::from_millis(5);
+
+/// How long the service is given to finish with the last messages sent,
+/// before its counters are read.
+const SETTLE: Duration = Duration::from_millis(500);
+
+/// The number of steps in each address's ramp of values.
+const STEPS: u32 = 128;
+
+/// Configures a load test.
+#[derive(Clone, Debug)]
+pub struct LoadTestConfig {
+    /// The address and port on which the service receives OSC.
+    pub target: SocketAddr,
+    /// The path of the service's control socket.
+    pub ctl_path: PathBuf,
+    /// OSC messages per second.
+    pub rate: u32,
+    /// How long to send for.
+    pub duration: Duration,
+    /// The OSC addresses to send to, each with its share of the messages.
+    pub addresses: Vec<(String, u32)>,
+}
+
+/// The outcome of a load test.
+pub struct LoadTestReport {
+    /// How long messages were sent for.
+    pub elapsed: Duration,
+    /// The number of messages sent, one per packet.
+    pub sent: u64,
+    /// Changes in the service's counters over the test, by name, as
+    /// reported by the `counters` control command.
+    pub counted: HashMap<String, u64>,
+}
+
+impl LoadTestReport {
+    /// The change in one of the service's counters.
+    pub fn counted(&self, name: &str) -> u64 {
+        self.counted.get(name).copied().unwrap_or_default()
+    }
+
+    /// Describes the outcome.
+    pub fn lines(&self) -> Vec<String> {
+        let secs = self.elapsed.as_secs_f64().max(f64::EPSILON);
+        let received = self.counted("osc_packets");
+        let dropped = self.sent.saturating_sub(received);
+        let translated = self.counted("midi_translated");
+        let midi_sent = self.counted("midi_sent");
+        let percent = |n: u64, of: u64| {
+            if of == 0 {
+                0.0
+            } else {
+                n as f64 * 100.0 / of as f64
+            }
+        };
+        vec![
+            format!(
+                "sent:       {} messages in {:.1}s, {:.0}/s",
+                self.sent,
+                secs,
+                self.sent as f64 / secs
+            ),
+            format!(
+                "received:   {received}, {dropped} dropped ({:.2}%)",
+                percent(dropped, self.sent)
+            ),
+            format!(
+                "translated: {translated} MIDI messages, {:.0}/s",
+                translated as f64 / secs
+            ),
+            format!(
+                "MIDI sent:  {midi_sent}, {:.0}/s, {} stale values replaced",
+                midi_sent as f64 / secs,
+                self.counted("midi_replaced")
+            ),
+            format!("unmatched:  {} OSC messages", self.counted("unmatched_osc")),
+            format!(
+                "errors:     {} decode errors",
+                self.counted("osc_decode_errors")
+            ),
+        ]
+    }
+}
+
+/// Runs a load test against a running service.
+pub async fn run_load_test(config: &LoadTestConfig) -> Result<LoadTestReport> {
+    if config.addresses.iter().all(|(_, weight)| *weight == 0) {
+        return Err("no OSC addresses to send to".into());
+    }
+    let before = read_counters(&config.ctl_path).await?;
+
+    let local: SocketAddr = if config.target.is_ipv4() {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(local).await?;
+    let mix = Mix::new(&config.addresses);
+    let mut buf = vec![];
+    let mut sent: u64 = 0;
+    let mut timer = tokio::time::interval(TICK);
+    timer.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let start = Instant::now();
+    let end = start + config.duration;
+    loop {
+        let now = timer.tick().await;
+        let elapsed = now.min(end) - start;
+        // Catch up with the rate, however late the tick.
+        let due = (elapsed.as_secs_f64() * config.rate as f64) as u64;
+        while sent < due {
+            let msg = OscPacket::Message(mix.message(sent));
+            encode_into(&msg, &mut buf);
+            socket.send_to(&buf, config.target).await?;
+            sent += 1;
+        }
+        if now >= end {
+            break;
+        }
+    }
+    let elapsed = start.elapsed();
+
+    tokio::time::sleep(SETTLE).await;
+    let after = read_counters(&config.ctl_path).await?;
+    let counted = after
+        .into_iter()
+        .map(|(name, value)| {
+            let base = before.get(&name).copied().unwrap_or_default();
+            (name, value.saturating_sub(base))
+        })
+        .collect();
+    Ok(LoadTestReport {
+        elapsed,
+        sent,
+        counted,
+    })
+}
+
+async fn read_counters(ctl_path: &Path) -> Result<HashMap<String, u64>> {
+    let lines = ctl_request(ctl_path, "counters")
+        .await
+        .map_err(|e| format!("service counters unavailable: {e}"))?;
+    Ok(lines
+        .iter()
+        .filter_map(|line| {
+            let (name, value) = line.split_once(' ')?;
+            Some((name.to_string(), value.trim().parse().ok()?))
+        })
+        .collect())
+}
+
+/// The addresses to send to, each repeated by its weight, in turn.
+struct Mix {
+    schedule: Vec<usize>,
+    addresses: Vec<String>,
+}
+
+impl Mix {
+    fn new(addresses: &[(String, u32)]) -> Self {
+        let mut schedule = vec![];
+        for (i, (_, weight)) in addresses.iter().enumerate() {
+            schedule.extend(std::iter::repeat_n(i, *weight as usize));
+        }
+        Mix {
+            schedule,
+            addresses: addresses.iter().map(|(a, _)| a.clone()).collect(),
+        }
+    }
+
+    /// The `n`th message sent.
+    fn message(&self, n: u64) -> OscMessage {
+        let len = self.schedule.len() as u64;
+        let index = self.schedule[(n % len) as usize];
+        // Each address steps through its ramp once per pass of the schedule.
+        let step = (n / len) as u32 % STEPS;
+        OscMessage {
+            addr: self.addresses[index].clone(),
+            args: vec![OscType::Float(step as f32 / (STEPS - 1) as f32)],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::{self, ctl, Harness};
+    use super::*;
+
+    fn mix(addresses: &[(&str, u32)]) -> Mix {
+        let addresses: Vec<(String, u32)> =
+            addresses.iter().map(|(a, w)| (a.to_string(), *w)).collect();
+        Mix::new(&addresses)
+    }
+
+    #[test]
+    fn mix_follows_the_weights() {
+        let mix = mix(&[("/a", 3), ("/b", 1), ("/none", 0)]);
+        let addrs: Vec<String> = (0..8).map(|n| mix.message(n).addr).collect();
+        assert_eq!(addrs, ["/a", "/a", "/a", "/b", "/a", "/a", "/a", "/b"]);
+    }
+
+    #[test]
+    fn values_ramp_per_pass() {
+        let mix = mix(&[("/a", 1), ("/b", 1)]);
+        let value = |n| mix.message(n).args[0].clone();
+        assert_eq!(value(0), OscType::Float(0.0));
+        assert_eq!(value(1), OscType::Float(0.0));
+        assert_eq!(value(2), OscType::Float(1.0 / 127.0));
+        assert_eq!(value(2 * 127), OscType::Float(1.0));
+        assert_eq!(value(2 * 128), OscType::Float(0.0));
+    }
+
+    #[test]
+    fn report_compares_counters_with_what_was_sent() {
+        let counted = [
+            ("osc_packets", 90),
+            ("midi_translated", 60),
+            ("midi_sent", 50),
+            ("unmatched_osc", 30),
+        ];
+        let report = LoadTestReport {
+            elapsed: Duration::from_secs(2),
+            sent: 100,
+            counted: counted.iter().map(|(n, v)| (n.to_string(), *v)).collect(),
+        };
+        let lines = report.lines();
+        assert_eq!(lines[0], "sent:       100 messages in 2.0s, 50/s");
+        assert_eq!(lines[1], "received:   90, 10 dropped (10.00%)");
+        assert_eq!(lines[2], "translated: 60 MIDI messages, 30/s");
+        assert_eq!(lines[3], "MIDI sent:  50, 25/s, 0 stale values replaced");
+        assert_eq!(lines[4], "unmatched:  30 OSC messages");
+    }
+
+    #[tokio::test]
+    async fn load_test_counts_a_running_service() {
+        let ctl_path = testing::ctl_path("loadtest");
+        let (h, builder) = Harness::new().await;
+        let svc = builder.ctl_path(&ctl_path).build();
+        let config = LoadTestConfig {
+            target: h.svc_addr,
+            ctl_path: ctl_path.clone(),
+            rate: 500,
+            duration: Duration::from_millis(200),
+            addresses: vec![("/encoder/1".to_string(), 1), ("/nowhere".to_string(), 1)],
+        };
+        testing::run(&svc, async {
+            ctl(&ctl_path, "status").await.unwrap();
+            let report = run_load_test(&config).await.unwrap();
+            assert_eq!(report.sent, 100);
+            assert_eq!(report.counted("osc_packets"), report.sent);
+            assert_eq!(report.counted("unmatched_osc"), report.sent / 2);
+            assert_eq!(report.counted("midi_translated"), report.sent / 2);
+            assert_eq!(report.counted("osc_decode_errors"), 0);
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn load_test_needs_an_address() {
+        let config = LoadTestConfig {
+            target: (Ipv4Addr::LOCALHOST, 9).into(),
+            ctl_path: testing::ctl_path("no-addresses"),
+            rate: 1,
+            duration: Duration::from_millis(1),
+            addresses: vec![("/a".to_string(), 0)],
+        };
+        let e = run_load_test(&config).await.err().unwrap();
+        assert_eq!(e.to_string(), "no OSC addresses to send to");
+    }
+}
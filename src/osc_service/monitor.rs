@@ -0,0 +1,121 @@
+//! Connection status notifications.
+//!
+//! When enabled, the service periodically checks that its MIDI ports are
+//! still present. If device health pings are enabled too, it also follows
+//! whether the B-Control answers them; see the `keepalive` module. OSC
+//! destinations are told of the initial state, and of every change:
+//!
+//! OSC address                  arguments
+//! /bcr2kosc/status/midi        1 if the MIDI ports are present, else 0
+//! /bcr2kosc/status/device      1 if the device answers, else 0, and the
+//!                              device's name, if it has one
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use midi_io::{Direction, Port};
+use rosc::encoder::encode;
+use rosc::{OscMessage, OscPacket, OscType};
+use tokio::net::UdpSocket;
+use tokio::sync::watch;
+use tokio::time::MissedTickBehavior;
+use tracing::{error, info};
+
+use super::keepalive::Keepalive;
+use crate::PGM;
+
+/// Address of MIDI port status notifications.
+pub const STATUS_MIDI_ADDR: &str = "/bcr2kosc/status/midi";
+
+/// Address of device status notifications.
+pub const STATUS_DEVICE_ADDR: &str = "/bcr2kosc/status/device";
+
+/// Watches the MIDI ports and device, notifying OSC destinations of changes.
+pub struct Monitor {
+    /// Time between checks.
+    pub interval: Duration,
+    /// The MIDI input port that should be present, if the service has one.
+    pub midi_in_port_name: Option<String>,
+    /// The MIDI output port that should be present, if the service has one.
+    pub midi_out_port_name: Option<String>,
+    /// The name of the device, if it has one.
+    pub device_name: Option<String>,
+}
+
+impl Monitor {
+    /// Checks the ports every interval, and passes on changes in device
+    /// status from `keepalive`, if any. Runs until cancelled.
+    pub async fn run(
+        &self,
+        keepalive: Option<Arc<Keepalive>>,
+        socket: Arc<UdpSocket>,
+        clients: Arc<Vec<SocketAddr>>,
+    ) {
+        let mut timer = tokio::time::interval(self.interval);
+        timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        // Without a keepalive, the sender is dropped, and the device status
+        // never changes.
+        let mut device_status = match &keepalive {
+            Some(k) => k.subscribe(),
+            None => watch::channel(None).1,
+        };
+        let mut midi_status = None;
+        loop {
+            tokio::select! {
+                _ = timer.tick() => {
+                    let midi = self.ports_present();
+                    if midi_status != Some(midi) {
+                        info!(
+                            "{PGM} MIDI ports are {}.",
+                            if midi { "present" } else { "missing" }
+                        );
+                        notify(&socket, &clients, STATUS_MIDI_ADDR, midi, None).await;
+                        midi_status = Some(midi);
+                    }
+                }
+                Ok(()) = device_status.changed() => {
+                    let device = *device_status.borrow();
+                    if let Some(device) = device {
+                        let name = self.device_name.as_deref();
+                        notify(&socket, &clients, STATUS_DEVICE_ADDR, device, name).await;
+                    }
+                }
+            }
+        }
+    }
+
+    fn ports_present(&self) -> bool {
+        let present = |name: &Option<String>, direction| {
+            name.as_ref()
+                .is_none_or(|n| Port::find(n, direction).is_ok())
+        };
+        present(&self.midi_in_port_name, Direction::Input)
+            && present(&self.midi_out_port_name, Direction::Output)
+    }
+}
+
+async fn notify(
+    socket: &UdpSocket,
+    clients: &[SocketAddr],
+    addr: &str,
+    up: bool,
+    name: Option<&str>,
+) {
+    let mut args = vec![OscType::Int(up as i32)];
+    args.extend(name.map(|n| OscType::String(n.to_string())));
+    let msg = OscPacket::Message(OscMessage {
+        addr: addr.to_string(),
+        args,
+    });
+    match encode(&msg) {
+        Ok(buf) => {
+            for a in clients {
+                if let Err(e) = socket.send_to(&buf, a).await {
+                    error!("Status notification to {a} failed: {e}");
+                }
+            }
+        }
+        Err(e) => error!("OSC encoding failed: {e}"),
+    }
+}
@@ -0,0 +1,137 @@
+//! Further OSC namespaces, each with its own MIDI ports and mappings.
+//!
+//! Besides its own mappings and MIDI ports, a service can serve namespaces
+//! under address prefixes, such as `/daw` for one controller and `/synth`
+//! for another. OSC addressed under a namespace's prefix is routed to it,
+//! with the prefix removed, before any mapping sees it, and the OSC that its
+//! mappings translate MIDI to is sent with the prefix added. So a namespace's
+//! mapping file is written as if it were served alone. All other OSC goes to
+//! the service's own mappings.
+//!
+//! A namespace is written `PREFIX=INPUT,OUTPUT[,MAPPINGS]`, naming its MIDI
+//! ports and, optionally, its mapping file. Without one, it has the small
+//! test set of mappings. Namespaces share the service's OSC sockets,
+//! destinations and device operations, but mappings added or reloaded
+//! through the control socket are the service's own.
+
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use rosc::{OscBundle, OscMessage, OscPacket};
+
+use super::coalesce::Coalescer;
+use super::Translations;
+
+/// Configures a namespace.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NamespaceConfig {
+    /// The address prefix, such as `/synth`, without a trailing `/`.
+    pub prefix: String,
+    /// The MIDI port that the namespace's MIDI comes from.
+    pub midi_in: String,
+    /// The MIDI port that the namespace's MIDI goes to.
+    pub midi_out: String,
+    /// The namespace's mapping file, if any.
+    pub mappings: Option<PathBuf>,
+}
+
+impl FromStr for NamespaceConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (prefix, ports) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected PREFIX=INPUT,OUTPUT[,MAPPINGS], got \"{s}\""))?;
+        let prefix = prefix.trim_end_matches('/');
+        if !prefix.starts_with('/') || prefix.len() < 2 {
+            return Err(format!("\"{prefix}\" is not an OSC address prefix"));
+        }
+        let mut parts = ports.splitn(3, ',');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(midi_in), Some(midi_out), mappings) => Ok(NamespaceConfig {
+                prefix: prefix.to_string(),
+                midi_in: midi_in.to_string(),
+                midi_out: midi_out.to_string(),
+                mappings: mappings.map(PathBuf::from),
+            }),
+            _ => Err(format!("expected INPUT,OUTPUT port names, got \"{ports}\"")),
+        }
+    }
+}
+
+/// A running namespace: where OSC routed to it is translated, and where the
+/// MIDI that results goes.
+#[derive(Clone)]
+pub struct Namespace {
+    pub prefix: String,
+    pub xset: Translations,
+    pub outbox: Arc<Coalescer>,
+}
+
+/// Splits a packet between namespaces. Returns the messages routed to each
+/// namespace, by its index in `namespaces`, with their prefixes removed, and
+/// the packet left for the service's own mappings, if any. A packet with
+/// nothing to route is left whole.
+pub fn route(
+    namespaces: &[Namespace],
+    pkt: OscPacket,
+) -> (Vec<(usize, OscMessage)>, Option<OscPacket>) {
+    let mut routed = vec![];
+    let rest = split(namespaces, pkt, &mut routed);
+    (routed, rest)
+}
+
+fn split(
+    namespaces: &[Namespace],
+    pkt: OscPacket,
+    routed: &mut Vec<(usize, OscMessage)>,
+) -> Option<OscPacket> {
+    match pkt {
+        OscPacket::Message(msg) => {
+            for (i, ns) in namespaces.iter().enumerate() {
+                if let Some(addr) = strip(&ns.prefix, &msg.addr) {
+                    let addr = addr.to_string();
+                    routed.push((i, OscMessage { addr, ..msg }));
+                    return None;
+                }
+            }
+            Some(OscPacket::Message(msg))
+        }
+        OscPacket::Bundle(b) => {
+            let content: Vec<OscPacket> = b
+                .content
+                .into_iter()
+                .filter_map(|p| split(namespaces, p, routed))
+                .collect();
+            (!content.is_empty()).then_some(OscPacket::Bundle(OscBundle {
+                timetag: b.timetag,
+                content,
+            }))
+        }
+    }
+}
+
+/// Returns the rest of an address under a prefix, starting with `/`.
+fn strip<'a>(prefix: &str, addr: &'a str) -> Option<&'a str> {
+    addr.strip_prefix(prefix)
+        .filter(|rest| rest.starts_with('/'))
+}
+
+/// Adds a prefix to the address of every message in a packet.
+pub fn add_prefix(prefix: &str, pkt: OscPacket) -> OscPacket {
+    match pkt {
+        OscPacket::Message(msg) => OscPacket::Message(OscMessage {
+            addr: format!("{prefix}{}", msg.addr),
+            ..msg
+        }),
+        OscPacket::Bundle(b) => OscPacket::Bundle(OscBundle {
+            timetag: b.timetag,
+            content: b
+                .content
+                .into_iter()
+                .map(|p| add_prefix(prefix, p))
+                .collect(),
+        }),
+    }
+}
@@ -0,0 +1,158 @@
+//! Switching of mappings with the device's preset.
+//!
+//! The configuration file can name the mappings to use with each of the
+//! device's presets, by number or by name; see the `config` module. The
+//! service follows the preset in either of two ways:
+//!
+//! - By the program change that the device, or a control on it, sends when a
+//!   preset is selected. Program 0 is preset 1.
+//! - By reading the name of the device's current preset at an interval. The
+//!   poll is skipped while another device operation is in progress.
+//!
+//! When the preset changes, the mappings are rebuilt from its profile or
+//! mapping file, as by the control socket's `reload`, and mappings added with
+//! `set-mapping` are discarded. A preset that the configuration doesn't list
+//! brings back the mappings the service was started with. Mappings are only
+//! rebuilt when they change, so presets that share mappings share their
+//! state too.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::StreamExt;
+use midi_io::{MidiMessage, SharedMidiInput};
+use tokio::time::MissedTickBehavior;
+use tracing::{error, info, warn};
+
+use super::admin::Admin;
+use super::{MappingSource, SharedMappings, Translations};
+use crate::b_control::PresetIndex;
+use crate::config::PresetKey;
+use crate::translator::PROFILES;
+use crate::PGM;
+
+/// The period of the poll's timer when there's no poll, in which case the
+/// timer is ignored.
+const IDLE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Configures switching of mappings with the device's preset.
+#[derive(Clone, Debug)]
+pub struct PresetConfig {
+    /// The mappings for each preset: a profile name or a mapping file.
+    pub mappings: Vec<(PresetKey, String)>,
+    /// How often the current preset's name is read from the device, if at
+    /// all.
+    pub poll: Option<Duration>,
+    /// The zero-based number of the device whose preset is read.
+    pub device: u8,
+}
+
+/// Switches mappings when the device's preset changes.
+pub struct PresetSwitcher {
+    config: PresetConfig,
+    /// The mappings the service was started with.
+    initial: MappingSource,
+    mappings: SharedMappings,
+    translations: Translations,
+    /// The mappings switched to, or `None` for the initial mappings.
+    current: Mutex<Option<String>>,
+}
+
+impl PresetSwitcher {
+    pub fn new(config: PresetConfig, mappings: SharedMappings, translations: Translations) -> Self {
+        let initial = mappings.lock().unwrap().clone();
+        PresetSwitcher {
+            config,
+            initial,
+            mappings,
+            translations,
+            current: Mutex::new(None),
+        }
+    }
+
+    /// Follows the device's preset, until cancelled or the input ends.
+    pub async fn run(&self, midi_in: SharedMidiInput, admin: Arc<Admin>) {
+        let mut src = midi_in.subscribe(program_changes);
+        let mut timer = tokio::time::interval(self.config.poll.unwrap_or(IDLE_INTERVAL));
+        timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                received = src.next() => match received {
+                    Some(MidiMessage::ProgramChange(_, program)) if program < 32 => {
+                        self.switch(&PresetKey::Number(program + 1));
+                    }
+                    Some(_) => {}
+                    None => break,
+                },
+                _ = timer.tick(), if self.config.poll.is_some() => {
+                    let preset = PresetIndex::Temporary;
+                    match admin.try_get_preset(self.config.device, preset).await {
+                        Some(Ok(lines)) => match preset_name(&lines) {
+                            Some(name) => self.switch(&PresetKey::Name(name)),
+                            None => warn!("{PGM} found no name in the current preset."),
+                        },
+                        Some(Err(e)) => warn!("{PGM} couldn't read the current preset: {e}"),
+                        None => {}
+                    }
+                }
+            }
+        }
+    }
+
+    /// Switches to the mappings for a preset, if they aren't in use.
+    fn switch(&self, preset: &PresetKey) {
+        let wanted = self
+            .config
+            .mappings
+            .iter()
+            .find(|(k, _)| k == preset)
+            .map(|(_, m)| m.clone());
+        let mut current = self.current.lock().unwrap();
+        if *current == wanted {
+            return;
+        }
+        let source = match &wanted {
+            Some(m) if PROFILES.contains(&m.as_str()) => MappingSource {
+                profile: Some(m.clone()),
+                file: None,
+                custom: None,
+                added: vec![],
+                ..self.initial.clone()
+            },
+            Some(m) => MappingSource {
+                profile: None,
+                file: Some(m.into()),
+                custom: None,
+                added: vec![],
+                ..self.initial.clone()
+            },
+            None => self.initial.clone(),
+        };
+        let mut mappings = self.mappings.lock().unwrap();
+        match source.build() {
+            Ok(set) => {
+                info!(
+                    "{PGM} switched to the mappings for preset {preset}, {} translators.",
+                    set.len()
+                );
+                self.translations.store(Arc::new(set));
+                *mappings = source;
+                *current = wanted;
+            }
+            Err(e) => error!("{PGM} couldn't switch mappings for preset {preset}: {e}"),
+        }
+    }
+}
+
+/// Finds the name of a preset in its BCL.
+fn preset_name(lines: &[String]) -> Option<String> {
+    lines.iter().find_map(|line| {
+        let name = line.trim().strip_prefix(".name")?.trim();
+        let name = name.strip_prefix('\'')?.strip_suffix('\'')?;
+        Some(name.trim_end().to_string())
+    })
+}
+
+fn program_changes(msg: &MidiMessage) -> bool {
+    matches!(msg, MidiMessage::ProgramChange(..))
+}
@@ -0,0 +1,257 @@
+//! A self-test of the whole translation pipeline.
+//!
+//! The self-test runs the service with its MIDI connected to the test, in
+//! process, and UDP sockets on the loopback interface. For each mapping, up
+//! to a limit, it sends the service a synthetic MIDI message and checks that
+//! the OSC the mapping translates it to comes out. It then sends that OSC
+//! back, and checks that the MIDI it translates to comes out.
+//!
+//! Expected translations come from the same mappings, translated in-process,
+//! so a failure points at the service or the network rather than at the
+//! mappings; `verify` checks those. No MIDI ports are opened, so the test
+//! needs no MIDI system, and doesn't check it.
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::time::Duration;
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::{select, FutureExt, StreamExt};
+use midi_control::{ControlEvent, KeyEvent};
+use midi_io::{copy_message, MidiMessage, MidiSink};
+use rosc::decoder::decode_udp;
+use rosc::encoder::encode;
+use rosc::{OscMessage, OscPacket};
+use tokio::net::UdpSocket;
+use tokio::time::{sleep, timeout_at, Instant};
+
+use super::{BCtlOscSvc, Result};
+use crate::translator::testing::{expect_midi, expect_osc};
+use crate::translator::{MidiFamily, ServerTranslationSet};
+use crate::PGM;
+
+/// How long the service is given to start its tasks, which don't receive
+/// MIDI sent before they do.
+const STARTUP: Duration = Duration::from_millis(500);
+
+/// How long to wait for each translation.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The outcome of one check.
+pub struct Check {
+    /// What was checked.
+    pub name: String,
+    /// Why the check failed, if it did.
+    pub failure: Option<String>,
+}
+
+/// Runs the service with the configured mappings, checking translations for
+/// up to `limit` mappings.
+pub async fn run_self_test(
+    profile: Option<&str>,
+    file: Option<&Path>,
+    limit: usize,
+) -> Result<Vec<Check>> {
+    let set = ServerTranslationSet::configured(profile, file)?.build()?;
+    let cases = synthetic_cases(&set, limit);
+    if cases.is_empty() {
+        return Err("the mappings translate no MIDI messages to test".into());
+    }
+
+    let (midi_tx, svc_in) = mpsc::unbounded();
+    let (svc_out, mut midi_rx) = mpsc::unbounded();
+    let osc = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+    // The service is given its socket already bound, so that nothing can
+    // take the port in between.
+    let svc_socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+    let svc_addr = svc_socket.local_addr()?;
+    let (profile, file) = (profile.map(str::to_string), file.map(Path::to_path_buf));
+
+    let svc = BCtlOscSvc::builder()
+        .midi_stream(svc_in)
+        .midi_sink(MidiSink::builder().client_name(PGM).channel(svc_out))
+        .osc_socket(svc_socket)
+        .osc_out(&[osc.local_addr()?])
+        .translations(move || ServerTranslationSet::configured(profile.as_deref(), file.as_deref()))
+        .build();
+    let checks = select! {
+        r = svc.run().fuse() => match r {
+            Err(e) => Err(format!("the service failed: {e}").into()),
+            Ok(()) => Err("the service stopped".into()),
+        },
+        checks = run_checks(
+            &set,
+            &cases,
+            &midi_tx,
+            &mut midi_rx,
+            &osc,
+            svc_addr,
+        ).fuse() => Ok(checks),
+    };
+    svc.stop().await;
+    checks
+}
+
+/// A synthetic MIDI message, and the first OSC message it translates to.
+type Case = (MidiMessage, OscMessage);
+
+/// Makes a case for each mapping that describes the MIDI it handles, using
+/// its lowest channel and number, and a full-scale value.
+fn synthetic_cases(set: &ServerTranslationSet, limit: usize) -> Vec<Case> {
+    set.coverage()
+        .into_iter()
+        .filter_map(|c| {
+            let channel = c.channels.iter().next()?;
+            let n = c.numbers.first()?;
+            let midi = match c.family {
+                MidiFamily::ControlChange => MidiMessage::ControlChange(
+                    channel,
+                    ControlEvent {
+                        control: n,
+                        value: 127,
+                    },
+                ),
+                MidiFamily::Note => MidiMessage::NoteOn(channel, KeyEvent { key: n, value: 127 }),
+            };
+            let osc = first_message(set.midi_msg_to_osc(&midi)?)?;
+            Some((midi, osc))
+        })
+        .take(limit)
+        .collect()
+}
+
+fn first_message(packet: OscPacket) -> Option<OscMessage> {
+    match packet {
+        OscPacket::Message(m) => Some(m),
+        OscPacket::Bundle(b) => b.content.into_iter().find_map(first_message),
+    }
+}
+
+async fn run_checks(
+    set: &ServerTranslationSet,
+    cases: &[Case],
+    midi_tx: &UnboundedSender<MidiMessage>,
+    midi_rx: &mut UnboundedReceiver<MidiMessage>,
+    osc: &UdpSocket,
+    svc_addr: SocketAddr,
+) -> Vec<Check> {
+    sleep(STARTUP).await;
+    let mut checks = vec![];
+    for (midi, expected) in cases {
+        checks.push(Check {
+            name: format!("MIDI {} to OSC {}", describe(midi), expected.addr),
+            failure: midi_to_osc(midi, expected, midi_tx, osc).await.err(),
+        });
+        // With feedback to another address, the OSC sent may not translate
+        // back to MIDI.
+        let midi_out: Vec<MidiMessage> = set
+            .osc_msg_to_slewed_midi(expected)
+            .into_iter()
+            .map(|(m, _)| m)
+            .collect();
+        if !midi_out.is_empty() {
+            checks.push(Check {
+                name: format!("OSC {} to MIDI {}", expected.addr, describe(&midi_out[0])),
+                failure: osc_to_midi(expected, &midi_out, midi_rx, osc, svc_addr)
+                    .await
+                    .err(),
+            });
+        }
+    }
+    checks
+}
+
+/// Sends MIDI to the service, and waits for it to send the expected OSC.
+async fn midi_to_osc(
+    midi: &MidiMessage,
+    expected: &OscMessage,
+    midi_tx: &UnboundedSender<MidiMessage>,
+    osc: &UdpSocket,
+) -> std::result::Result<(), String> {
+    let mut buf = vec![0u8; 1024 * 16];
+    while osc.try_recv_from(&mut buf).is_ok() {}
+    midi_tx
+        .unbounded_send(copy_message(midi))
+        .map_err(|e| e.to_string())?;
+    let deadline = Instant::now() + TIMEOUT;
+    let mut failure = format!("expected {expected:?}, got nothing");
+    loop {
+        let len = match timeout_at(deadline, osc.recv_from(&mut buf)).await {
+            Err(_) => return Err(failure),
+            Ok(Err(e)) => return Err(e.to_string()),
+            Ok(Ok((len, _))) => len,
+        };
+        if let Ok((_, packet)) = decode_udp(&buf[..len]) {
+            match expect_osc(Some(&packet), expected) {
+                Ok(()) => return Ok(()),
+                Err(e) => failure = e,
+            }
+        }
+    }
+}
+
+/// Sends OSC to the service, and waits for it to send the expected MIDI.
+async fn osc_to_midi(
+    msg: &OscMessage,
+    expected: &[MidiMessage],
+    midi_rx: &mut UnboundedReceiver<MidiMessage>,
+    osc: &UdpSocket,
+    svc_addr: SocketAddr,
+) -> std::result::Result<(), String> {
+    while let Some(Some(_)) = midi_rx.next().now_or_never() {}
+    let buf = encode(&OscPacket::Message(msg.clone())).map_err(|e| e.to_string())?;
+    osc.send_to(&buf, svc_addr)
+        .await
+        .map_err(|e| e.to_string())?;
+    let deadline = Instant::now() + TIMEOUT;
+    let mut received = vec![];
+    loop {
+        let missing = expected
+            .iter()
+            .map(|m| expect_midi(&received, m))
+            .find_map(|r| r.err());
+        let Some(failure) = missing else {
+            return Ok(());
+        };
+        match timeout_at(deadline, midi_rx.next()).await {
+            Err(_) => return Err(failure),
+            Ok(None) => return Err("the service's MIDI output closed".to_string()),
+            Ok(Some(m)) => received.push(m),
+        }
+    }
+}
+
+/// Describes a MIDI message as a golden file would.
+fn describe(midi: &MidiMessage) -> String {
+    match midi {
+        MidiMessage::ControlChange(ch, e) => {
+            format!("cc {} {} {}", *ch as u8 + 1, e.control, e.value)
+        }
+        MidiMessage::NoteOn(ch, e) => format!("note-on {} {} {}", *ch as u8 + 1, e.key, e.value),
+        MidiMessage::NoteOff(ch, e) => format!("note-off {} {} {}", *ch as u8 + 1, e.key, e.value),
+        other => format!("{other:?}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_mappings_pass() {
+        let checks = run_self_test(None, None, 10).await.unwrap();
+        let names: Vec<&str> = checks.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "MIDI cc 1 1 127 to OSC /encoder/1",
+                "OSC /encoder/1 to MIDI cc 1 1 127",
+                "MIDI cc 1 65 127 to OSC /key/1",
+                "OSC /key/1 to MIDI cc 1 65 127",
+            ]
+        );
+        for check in &checks {
+            assert_eq!(check.failure, None, "{}", check.name);
+        }
+    }
+}
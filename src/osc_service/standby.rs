@@ -0,0 +1,233 @@
+//! Hot standby.
+//!
+//! Two instances of the service can be run as a primary and a standby. The
+//! primary sends a heartbeat, `/bcr2kosc/heartbeat`, to the standby at
+//! regular intervals. The standby opens no MIDI ports and sends no OSC while
+//! heartbeats arrive. When they stop for longer than its timeout, or never
+//! arrive, the standby takes over: it starts the service as usual.
+//!
+//! The heartbeat carries the mappings added to the primary while it runs, so
+//! that the standby starts with the same translations. Its arguments are a
+//! sequence number followed by one string per added mapping.
+//!
+//! The standby needs its own MIDI path to the device, such as a MIDI
+//! splitter or network MIDI, and should send OSC to the same destinations as
+//! the primary. A primary that recovers doesn't reclaim control; stop the
+//! instance that took over first.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use rosc::encoder::encode;
+use rosc::{OscMessage, OscPacket, OscType};
+use tokio::net::UdpSocket;
+use tokio::time::{timeout, MissedTickBehavior};
+use tracing::{debug, error, info, warn};
+
+use super::{Result, SharedMappings};
+use crate::PGM;
+
+/// Address of heartbeat messages.
+pub const HEARTBEAT_ADDR: &str = "/bcr2kosc/heartbeat";
+
+/// Time between heartbeats.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Configures an instance as a standby.
+#[derive(Clone, Copy, Debug)]
+pub struct StandbyConfig {
+    /// The local address on which heartbeats are received.
+    pub listen: SocketAddr,
+    /// How long heartbeats can be missing before the standby takes over.
+    pub timeout: Duration,
+}
+
+/// Waits until the primary's heartbeats stop, returning the mappings last
+/// added to the primary.
+pub async fn wait_for_failure(config: StandbyConfig) -> Result<Vec<String>> {
+    let socket = UdpSocket::bind(config.listen).await?;
+    info!(
+        "{PGM} is on standby, listening for heartbeats on {}.",
+        config.listen
+    );
+    let mut buf = vec![0u8; 1024 * 64];
+    let mut added = vec![];
+    let mut last_seq = None;
+    loop {
+        let (len, sender) = match timeout(config.timeout, socket.recv_from(&mut buf)).await {
+            Ok(r) => r?,
+            Err(_) => break,
+        };
+        let msg = match rosc::decoder::decode_udp(&buf[..len]) {
+            Ok((_, OscPacket::Message(msg))) if msg.addr == HEARTBEAT_ADDR => msg,
+            Ok(_) => {
+                debug!("Ignoring OSC from {sender} while on standby.");
+                continue;
+            }
+            Err(e) => {
+                error!("OSC pkt decode error: {e}");
+                continue;
+            }
+        };
+        let mut args = msg.args.into_iter();
+        let seq = match args.next() {
+            Some(OscType::Int(seq)) => seq,
+            _ => {
+                error!("Malformed heartbeat from {sender}.");
+                continue;
+            }
+        };
+        if last_seq.is_none() {
+            info!("{PGM} received first heartbeat from {sender}.");
+        }
+        last_seq = Some(seq);
+        added = args
+            .filter_map(|a| match a {
+                OscType::String(s) => Some(s),
+                _ => None,
+            })
+            .collect();
+    }
+    match last_seq {
+        Some(seq) => warn!(
+            "{PGM} heartbeat lost after {seq}; taking over with {} added mappings.",
+            added.len()
+        ),
+        None => warn!("{PGM} received no heartbeat; taking over."),
+    }
+    Ok(added)
+}
+
+/// Sends heartbeats to a standby. Runs until cancelled.
+pub async fn send_heartbeats(socket: &UdpSocket, standby: SocketAddr, mappings: SharedMappings) {
+    info!("{PGM} will send heartbeats to {standby}.");
+    let mut timer = tokio::time::interval(HEARTBEAT_INTERVAL);
+    timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut seq: i32 = 0;
+    loop {
+        timer.tick().await;
+        seq = seq.wrapping_add(1);
+        let mut args = vec![OscType::Int(seq)];
+        args.extend(
+            mappings
+                .lock()
+                .unwrap()
+                .added
+                .iter()
+                .map(|m| OscType::String(m.clone())),
+        );
+        let heartbeat = OscPacket::Message(OscMessage {
+            addr: HEARTBEAT_ADDR.to_string(),
+            args,
+        });
+        match encode(&heartbeat) {
+            Ok(buf) => {
+                if let Err(e) = socket.send_to(&buf, standby).await {
+                    error!("Heartbeat send to {standby} failed: {e}");
+                }
+            }
+            Err(e) => error!("OSC encoding failed: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::super::testing::{self, cc, osc, Harness};
+    use super::*;
+    use crate::translator::testing::expect_osc;
+
+    /// A local address that's free to listen on.
+    fn free_addr() -> SocketAddr {
+        let socket = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        socket.local_addr().unwrap()
+    }
+
+    fn config() -> StandbyConfig {
+        StandbyConfig {
+            listen: free_addr(),
+            timeout: Duration::from_millis(300),
+        }
+    }
+
+    async fn send(socket: &UdpSocket, to: SocketAddr, addr: &str, args: Vec<OscType>) {
+        let pkt = OscPacket::Message(OscMessage {
+            addr: addr.to_string(),
+            args,
+        });
+        socket.send_to(&encode(&pkt).unwrap(), to).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn takes_over_when_no_heartbeat_arrives() {
+        let added = wait_for_failure(config()).await.unwrap();
+        assert!(added.is_empty());
+    }
+
+    #[tokio::test]
+    async fn takes_over_with_the_last_heartbeats_mappings() {
+        let config = config();
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let primary = async {
+            // Let the standby start listening.
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let to = config.listen;
+            let mapping = |s: &str| OscType::String(s.to_string());
+            send(&socket, to, HEARTBEAT_ADDR, vec![OscType::Int(1)]).await;
+            let two = vec![OscType::Int(2), mapping("cc 1 7 /a"), mapping("cc 1 8 /b")];
+            send(&socket, to, HEARTBEAT_ADDR, two).await;
+            // None of these replace the mappings of the last heartbeat.
+            send(&socket, to, "/other", vec![mapping("cc 1 9 /c")]).await;
+            send(&socket, to, HEARTBEAT_ADDR, vec![mapping("cc 1 9 /c")]).await;
+            socket.send_to(b"not OSC", to).await.unwrap();
+        };
+        let (added, ()) = futures::join!(wait_for_failure(config), primary);
+        assert_eq!(added.unwrap(), ["cc 1 7 /a", "cc 1 8 /b"]);
+    }
+
+    #[tokio::test]
+    async fn standby_service_takes_over_a_primarys_added_mappings() {
+        // Long enough for the primary to start before the standby gives up.
+        let config = StandbyConfig {
+            timeout: Duration::from_secs(2),
+            ..config()
+        };
+        let primary_path = testing::ctl_path("standby-primary");
+        let (_primary_h, builder) = Harness::new().await;
+        let primary = builder
+            .ctl_path(&primary_path)
+            .heartbeat_to(Some(config.listen))
+            .build();
+        let (standby_h, builder) = Harness::new().await;
+        let standby_path = testing::ctl_path("standby-standby");
+        let standby = builder
+            .ctl_path(&standby_path)
+            .standby(Some(config))
+            .build();
+        testing::run(&standby, async {
+            testing::run(&primary, async {
+                testing::ctl(&primary_path, "set-mapping cc 1 7 /volume")
+                    .await
+                    .unwrap();
+                // Wait for a heartbeat sent after the mapping was added.
+                tokio::time::sleep(HEARTBEAT_INTERVAL + Duration::from_millis(200)).await;
+            })
+            .await;
+            // The standby opens its control socket once it has taken over.
+            let status = testing::ctl(&standby_path, "status").await.unwrap();
+            assert!(
+                status.contains(&"added mappings: 1".to_string()),
+                "{status:?}"
+            );
+            standby_h.midi_in.unbounded_send(cc(7, 127)).unwrap();
+            let out = standby_h.recv_osc().await;
+            expect_osc(Some(&out), &osc("/volume", 1.0)).unwrap();
+            standby_h.midi_in.unbounded_send(cc(1, 127)).unwrap();
+            let out = standby_h.recv_osc().await;
+            expect_osc(Some(&out), &osc("/encoder/1", 1.0)).unwrap();
+        })
+        .await;
+    }
+}
@@ -0,0 +1,81 @@
+//! Restarting of translation tasks that fail.
+//!
+//! A bug in a translator, or an unexpected end of its input, would otherwise
+//! stop one direction of translation while the rest of the service carries
+//! on, which can go unnoticed until a show. A supervised task runs on its own
+//! tokio task, so that a panic is caught rather than taking the service down.
+//! When it panics or ends before the service stops, the failure is logged and
+//! the task is started afresh, after a delay that doubles with each
+//! consecutive failure, up to a limit. A task that ran for a while before
+//! failing is restarted after the shortest delay again.
+
+use std::any::Any;
+use std::time::{Duration, Instant};
+
+use futures::{pin_mut, select, Future, FutureExt};
+use tracing::{error, info_span, warn, Instrument};
+
+use super::{wait_on_stopping, StopMechanism};
+use crate::PGM;
+
+/// The delay before restarting a task after its first failure.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+
+/// The longest delay before restarting a task.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How long a task has to run before a failure no longer counts as
+/// consecutive with the previous one.
+const STABLE: Duration = Duration::from_secs(60);
+
+/// Runs the tasks made by `start`, one at a time, restarting each one that
+/// fails, until the service stops. `name` describes the task in logs.
+pub async fn supervise<F, T>(name: &str, stopper: StopMechanism, mut start: F)
+where
+    F: FnMut() -> T,
+    T: Future<Output = ()> + Send + 'static,
+{
+    // The same wait is used throughout, so that a stop isn't missed between
+    // restarts.
+    let stopping = wait_on_stopping(stopper).fuse();
+    pin_mut!(stopping);
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        let started = Instant::now();
+        let mut task = tokio::spawn(start().instrument(info_span!("task", name)));
+        let result = select! {
+            r = (&mut task).fuse() => r,
+            _ = stopping => {
+                task.abort();
+                return;
+            }
+        };
+        match result {
+            Ok(()) => error!("{PGM} {name} stopped unexpectedly."),
+            Err(e) if e.is_panic() => {
+                error!("{PGM} {name} panicked: {}", panic_message(e.into_panic()))
+            }
+            Err(e) => error!("{PGM} {name} failed: {e}"),
+        }
+        if started.elapsed() >= STABLE {
+            backoff = INITIAL_BACKOFF;
+        }
+        warn!("{PGM} restarting {name} in {backoff:?}.");
+        select! {
+            _ = tokio::time::sleep(backoff).fuse() => {},
+            _ = stopping => return,
+        }
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// Gets the message of a panic, which is usually a string.
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    if let Some(s) = panic.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = panic.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "no message".to_string()
+    }
+}
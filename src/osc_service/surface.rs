@@ -0,0 +1,185 @@
+//! Several devices presented as one surface.
+//!
+//! A BCF2000's faders and a BCR2000's encoders can be served as one surface,
+//! with one set of mappings, one OSC namespace and one state, so that the
+//! encoder group or shift button of one device applies to the controls of
+//! both. The service's own MIDI ports are the surface's first device, and
+//! further devices are added with the channels they use, which the
+//! configuration file gives; see the `config` module.
+//!
+//! MIDI from every device is translated as if it came from one. MIDI
+//! translated from OSC is sent to the service's own device, and to each
+//! other device that uses its channel, so feedback reaches whichever device
+//! has the control. A control on a channel that two devices use is kept in
+//! step on both: MIDI from one device is also sent to the others that use
+//! its channel, with the service's own device counting as using every
+//! channel.
+//!
+//! Device operations, such as reading presets, are only the service's own
+//! device's. SysEx from the other devices isn't translated.
+
+use std::time::Duration;
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::stream::select_all;
+use futures::{select, SinkExt, StreamExt};
+use midi_io::{copy_message, MidiMessage, MidiSink, MidiStream};
+use tracing::{error, info};
+
+use super::{Directions, MidiSource, Result};
+use crate::PGM;
+
+/// A device added to the service's own to make a surface.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SurfaceMember {
+    /// The name of the device's MIDI ports, for both input and output.
+    pub port: String,
+    /// The MIDI channels the device uses, numbered from 1.
+    pub channels: Vec<u8>,
+}
+
+/// Where MIDI for the surface goes.
+struct Device {
+    /// The channels the device is sent, or `None` for every channel.
+    channels: Option<Vec<u8>>,
+    sink: MidiSink,
+}
+
+/// Routes MIDI between the devices of a surface and the service.
+pub struct Surface {
+    inputs: Vec<MidiSource>,
+    devices: Vec<Device>,
+    /// Where MIDI from the devices goes, to be translated.
+    received: UnboundedSender<MidiMessage>,
+    /// MIDI translated from OSC, or sent by device operations.
+    outgoing: UnboundedReceiver<MidiMessage>,
+}
+
+impl Surface {
+    /// Opens the members' ports, and joins them with the service's own MIDI.
+    /// Returns the surface, the MIDI to translate, and the sink for MIDI to
+    /// the surface, which replace the service's own. Only the members' ports
+    /// for the service's `directions` are opened.
+    pub fn open(
+        members: &[SurfaceMember],
+        midi_rx: MidiSource,
+        midi_tx: MidiSink,
+        sysex_interval: Duration,
+        directions: Directions,
+    ) -> Result<(Surface, MidiSource, MidiSink)> {
+        let builder = || {
+            MidiSink::builder()
+                .client_name(PGM)
+                .sysex_interval(sysex_interval)
+        };
+        let mut inputs = vec![midi_rx];
+        let mut devices = vec![Device {
+            channels: None,
+            sink: midi_tx,
+        }];
+        for m in members {
+            let sink = if directions.to_midi() {
+                builder().bind(&m.port)?
+            } else {
+                builder().discard()
+            };
+            info!(
+                "{PGM} adds \"{}\" to the surface, on channels {}.",
+                m.port,
+                channel_list(&m.channels)
+            );
+            if directions.to_osc() {
+                let stream = MidiStream::builder().client_name(PGM).bind(&m.port)?;
+                inputs.push(Box::pin(stream.filter(|m| {
+                    futures::future::ready(!matches!(m, MidiMessage::SysEx(_)))
+                })));
+            }
+            devices.push(Device {
+                channels: Some(m.channels.clone()),
+                sink,
+            });
+        }
+        let (received, midi_rx) = mpsc::unbounded();
+        let (tx, outgoing) = mpsc::unbounded();
+        let surface = Surface {
+            inputs,
+            devices,
+            received,
+            outgoing,
+        };
+        Ok((surface, Box::pin(midi_rx), builder().channel(tx)))
+    }
+
+    /// Routes MIDI until the devices' input ends or the service's sink is
+    /// dropped.
+    pub async fn run(self) {
+        let Surface {
+            inputs,
+            mut devices,
+            received,
+            mut outgoing,
+        } = self;
+        let mut inputs = select_all(
+            inputs
+                .into_iter()
+                .enumerate()
+                .map(|(i, s)| s.map(move |m| (i, m))),
+        );
+        loop {
+            select! {
+                m = inputs.next() => match m {
+                    Some((from, m)) => {
+                        send(&mut devices, Some(from), &m).await;
+                        if received.unbounded_send(m).is_err() {
+                            break;
+                        }
+                    }
+                    None => break,
+                },
+                m = outgoing.next() => match m {
+                    Some(m) => send(&mut devices, None, &m).await,
+                    None => break,
+                },
+            }
+        }
+    }
+}
+
+/// Sends a message to the devices that use its channel, except the one it
+/// came from, if any. Messages without a channel only go to the service's
+/// own device, and only from the service.
+async fn send(devices: &mut [Device], from: Option<usize>, m: &MidiMessage) {
+    let channel = channel_number(m);
+    for (i, d) in devices.iter_mut().enumerate() {
+        let wanted = match (channel, &d.channels) {
+            _ if from == Some(i) => false,
+            (Some(c), Some(channels)) => channels.contains(&c),
+            (Some(_), None) => true,
+            (None, channels) => from.is_none() && channels.is_none(),
+        };
+        if wanted && d.sink.send(copy_message(m)).await.is_err() {
+            error!("MIDI send to a device of the surface failed.");
+        }
+    }
+}
+
+/// The channel of a channel message, numbered from 1.
+fn channel_number(midi: &MidiMessage) -> Option<u8> {
+    use MidiMessage::*;
+    match midi {
+        ControlChange(ch, _)
+        | NoteOn(ch, _)
+        | NoteOff(ch, _)
+        | PolyKeyPressure(ch, _)
+        | ProgramChange(ch, _)
+        | ChannelPressure(ch, _)
+        | PitchBend(ch, _, _) => Some(*ch as u8 + 1),
+        _ => None,
+    }
+}
+
+/// Describes a list of channels, as numbers separated by commas.
+pub fn channel_list(channels: &[u8]) -> String {
+    let channels: Vec<String> = channels.iter().map(|c| c.to_string()).collect();
+    channels.join(",")
+}
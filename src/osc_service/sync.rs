@@ -0,0 +1,134 @@
+//! The state of the controls, for OSC clients that start up late.
+//!
+//! The service remembers the last OSC message translated from MIDI for each
+//! address. A client that sends `/bcr2kosc/sync` is sent them all, so that
+//! its UI shows where the controls are without waiting for each to move. A
+//! client that sends `/bcr2kosc/register` is also added to the destinations
+//! of translated OSC, for as long as the service runs.
+//!
+//! OSC address            arguments
+//! /bcr2kosc/sync
+//! /bcr2kosc/register
+//!
+//! The state is sparse: only controls that have sent MIDI since the service
+//! started are known. It's sent as bundles of at most `BUNDLE_SIZE` messages,
+//! so that each fits in a datagram, in the client's OSC format. Bundles are
+//! split further if the format limits the size of packets.
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use rosc::{OscBundle, OscMessage, OscPacket, OscTime};
+use tokio::net::UdpSocket;
+use tracing::{debug, error};
+
+use super::encode::encode_into;
+use super::failover::Destinations;
+use super::format::{Formats, OscFormat};
+use super::fragment;
+
+/// The address of requests for the state.
+pub const SYNC_ADDR: &str = "/bcr2kosc/sync";
+
+/// The address of requests to be sent translated OSC, and the state.
+pub const REGISTER_ADDR: &str = "/bcr2kosc/register";
+
+/// The most messages sent in one bundle.
+const BUNDLE_SIZE: usize = 64;
+
+/// The last OSC sent for each address, and where to send it on request.
+pub struct ClientSync {
+    values: Mutex<BTreeMap<String, OscMessage>>,
+    destinations: Arc<Destinations>,
+    formats: Arc<Formats>,
+    socket: Arc<UdpSocket>,
+}
+
+impl ClientSync {
+    /// Creates an empty state, sent from `socket`, and registering clients
+    /// with `destinations`.
+    pub fn new(
+        destinations: Arc<Destinations>,
+        formats: Arc<Formats>,
+        socket: Arc<UdpSocket>,
+    ) -> Self {
+        ClientSync {
+            values: Mutex::new(BTreeMap::new()),
+            destinations,
+            formats,
+            socket,
+        }
+    }
+
+    /// Records the messages in a packet translated from MIDI.
+    pub fn record(&self, pkt: &OscPacket) {
+        let mut values = self.values.lock().unwrap();
+        for msg in super::packet_messages(pkt) {
+            match values.get_mut(&msg.addr) {
+                Some(m) => m.args.clone_from(&msg.args),
+                None => {
+                    values.insert(msg.addr.clone(), msg.clone());
+                }
+            }
+        }
+    }
+
+    /// Returns true if the message is a request handled here.
+    pub fn is_request(msg: &OscMessage) -> bool {
+        msg.addr == SYNC_ADDR || msg.addr == REGISTER_ADDR
+    }
+
+    /// Handles a request from `sender`, sending it the state on a new task.
+    pub fn spawn(self: &Arc<Self>, msg: &OscMessage, sender: SocketAddr) {
+        if msg.addr == REGISTER_ADDR {
+            self.destinations.register(sender);
+        }
+        let format = self.formats.get(sender);
+        let bundles = self.bundles(format);
+        let socket = self.socket.clone();
+        tokio::spawn(async move {
+            debug!(
+                "Sending the state to {sender} in {} bundles.",
+                bundles.len()
+            );
+            let mut buf = Vec::with_capacity(1024);
+            for b in &bundles {
+                encode_into(b, &mut buf);
+                if let Err(e) = socket.send_to(&buf, sender).await {
+                    error!("OSC state for {sender} failed: {e}");
+                    return;
+                }
+            }
+        });
+    }
+
+    /// Returns the state as bundles, in a destination's format.
+    fn bundles(&self, format: OscFormat) -> Vec<OscPacket> {
+        let values = self.values.lock().unwrap();
+        let messages: Vec<OscPacket> = values
+            .values()
+            .map(|m| OscPacket::Message(m.clone()))
+            .filter_map(|p| {
+                if format == OscFormat::default() {
+                    Some(p)
+                } else {
+                    format.apply(&p)
+                }
+            })
+            .collect();
+        let bundles = messages.chunks(BUNDLE_SIZE).map(|content| {
+            OscPacket::Bundle(OscBundle {
+                timetag: OscTime {
+                    seconds: 0,
+                    fractional: 0,
+                },
+                content: content.to_vec(),
+            })
+        });
+        match format.max_size {
+            Some(max) => bundles.flat_map(|b| fragment::split(&b, max)).collect(),
+            None => bundles.collect(),
+        }
+    }
+}
@@ -0,0 +1,136 @@
+#![allow(dead_code)]
+//! A service driven by channels and local sockets, for tests.
+//!
+//! `Harness::new` makes the MIDI channels and OSC sockets, and a builder
+//! already connected to them and given the test mappings, for a test to add
+//! the options it tests. `run` runs the service alongside the test's checks,
+//! stopping it when they're done.
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::{join, Future, StreamExt};
+use midi_control::{Channel, ControlEvent};
+use midi_io::{MidiMessage, MidiSink};
+use rosc::decoder::decode_udp;
+use rosc::encoder::encode;
+use rosc::{OscMessage, OscPacket, OscType};
+use tokio::net::UdpSocket;
+use tokio::time::{timeout, Instant};
+
+use super::ctl::{ctl_request, in_use};
+use super::{BCtlOscSvc, BCtlOscSvcBuilder};
+use crate::translator::ServerTranslationSet;
+use crate::PGM;
+
+/// How long a test waits for anything.
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The ends of a service's MIDI and OSC connections that a test holds.
+pub struct Harness {
+    /// Sends MIDI to the service, as if from the device.
+    pub midi_in: UnboundedSender<MidiMessage>,
+    /// Receives the MIDI the service sends to the device.
+    pub midi_out: UnboundedReceiver<MidiMessage>,
+    /// An OSC client, which the service sends translated OSC to.
+    pub client: UdpSocket,
+    /// Where the service receives OSC.
+    pub svc_addr: SocketAddr,
+}
+
+impl Harness {
+    /// Makes the connections, and a builder for a service using them.
+    pub async fn new() -> (Harness, BCtlOscSvcBuilder) {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let svc_addr = socket.local_addr().unwrap();
+        let client = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let (midi_in, stream) = mpsc::unbounded();
+        let (sink, midi_out) = mpsc::unbounded();
+        let builder = BCtlOscSvc::builder()
+            .midi_stream(stream)
+            .midi_sink(MidiSink::builder().channel(sink))
+            .osc_socket(socket)
+            .osc_out(&[client.local_addr().unwrap()])
+            .translations(|| Ok(ServerTranslationSet::test_mappings()));
+        let harness = Harness {
+            midi_in,
+            midi_out,
+            client,
+            svc_addr,
+        };
+        (harness, builder)
+    }
+
+    /// Sends an OSC message to the service from the client.
+    pub async fn send_osc(&self, msg: OscMessage) {
+        let pkt = encode(&OscPacket::Message(msg)).unwrap();
+        self.client.send_to(&pkt, self.svc_addr).await.unwrap();
+    }
+
+    /// Receives the next OSC packet sent to the client.
+    pub async fn recv_osc(&self) -> OscPacket {
+        recv_osc(&self.client).await.0
+    }
+
+    /// Receives the next MIDI message sent to the device.
+    pub async fn recv_midi(&mut self) -> MidiMessage {
+        timeout(TIMEOUT, self.midi_out.next())
+            .await
+            .expect("no MIDI from the service")
+            .unwrap()
+    }
+}
+
+/// Receives the next OSC packet on a socket, and who sent it.
+pub async fn recv_osc(socket: &UdpSocket) -> (OscPacket, SocketAddr) {
+    let mut buf = [0u8; 4096];
+    let (len, from) = timeout(TIMEOUT, socket.recv_from(&mut buf))
+        .await
+        .expect("no OSC from the service")
+        .unwrap();
+    (decode_udp(&buf[..len]).unwrap().1, from)
+}
+
+/// Runs the service until `checks` are done, then stops it, failing if
+/// either takes too long or the service fails.
+pub async fn run(svc: &BCtlOscSvc, checks: impl Future<Output = ()>) {
+    let checks = async {
+        checks.await;
+        svc.stop().await;
+    };
+    let (r, ()) = timeout(TIMEOUT * 2, async { join!(svc.run(), checks) })
+        .await
+        .expect("the service didn't stop");
+    r.unwrap();
+}
+
+/// A control socket path of its own for a test.
+pub fn ctl_path(test: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("{PGM}-{}-{test}.sock", std::process::id()))
+}
+
+/// Sends a command to the service's control socket, waiting for the socket
+/// to open if the service has only just started.
+pub async fn ctl(path: &Path, command: &str) -> Result<Vec<String>, String> {
+    let start = Instant::now();
+    while !in_use(path).await {
+        assert!(start.elapsed() < TIMEOUT, "the control socket didn't open");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    ctl_request(path, command).await.map_err(|e| e.to_string())
+}
+
+/// A control change on channel 1.
+pub fn cc(control: u8, value: u8) -> MidiMessage {
+    MidiMessage::ControlChange(Channel::Ch1, ControlEvent { control, value })
+}
+
+/// An OSC message with a float argument.
+pub fn osc(addr: &str, v: f32) -> OscMessage {
+    OscMessage {
+        addr: addr.to_string(),
+        args: vec![OscType::Float(v)],
+    }
+}
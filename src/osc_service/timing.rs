@@ -0,0 +1,120 @@
+//! Histograms of how long translation takes, in debug builds.
+//!
+//! To measure the effect of changes to how translation is done, debug
+//! builds time each message through the service: from when the translation
+//! task takes a MIDI message until the OSC translated from it has been sent
+//! to every destination, and from when it takes an OSC packet until the MIDI
+//! translated from it has been queued for the device. Time spent writing the
+//! MIDI to the port isn't included, since that depends on the device.
+//!
+//! The times are kept in buckets whose bounds double, from 1 µs to about
+//! 30 s, and the control socket's `stats` command reports percentiles of
+//! each, as the upper bounds of the buckets they fall in. Messages that
+//! aren't translated aren't timed. Release builds keep no times.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Whether times are kept.
+const ENABLED: bool = cfg!(debug_assertions);
+
+/// The number of buckets. Bucket `i` holds times under 2^i µs.
+const BUCKETS: usize = 25;
+
+/// The percentiles reported.
+const PERCENTILES: [u32; 4] = [50, 90, 99, 100];
+
+/// Counts of times, by bucket.
+struct Histogram {
+    counts: [u64; BUCKETS],
+    max: Duration,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            counts: [0; BUCKETS],
+            max: Duration::ZERO,
+        }
+    }
+}
+
+impl Histogram {
+    fn record(&mut self, d: Duration) {
+        let micros = d.as_micros().max(1);
+        let bucket = (u128::BITS - micros.leading_zeros()) as usize;
+        self.counts[bucket.min(BUCKETS - 1)] += 1;
+        self.max = self.max.max(d);
+    }
+
+    fn describe(&self, what: &str) -> String {
+        let total: u64 = self.counts.iter().sum();
+        if total == 0 {
+            return format!("{what}: no messages timed");
+        }
+        let percentiles: Vec<String> = PERCENTILES
+            .iter()
+            .map(|p| match p {
+                100 => format!("max {} µs", self.max.as_micros()),
+                p => format!("p{p} < {} µs", self.percentile(total, *p)),
+            })
+            .collect();
+        format!("{what}: {total} messages, {}", percentiles.join(", "))
+    }
+
+    /// Returns the upper bound, in µs, of the bucket that the percentile
+    /// falls in.
+    fn percentile(&self, total: u64, p: u32) -> u64 {
+        let rank = (total * p as u64).div_ceil(100);
+        let mut seen = 0;
+        for (i, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return 1 << i;
+            }
+        }
+        1 << (BUCKETS - 1)
+    }
+}
+
+/// Times of translation in each direction.
+#[derive(Default)]
+pub struct TranslationTimes {
+    midi_to_osc: Mutex<Histogram>,
+    osc_to_midi: Mutex<Histogram>,
+}
+
+impl TranslationTimes {
+    /// Records the time since MIDI that has been translated and sent was
+    /// received.
+    pub fn midi_to_osc(&self, received: Instant) {
+        if ENABLED {
+            self.midi_to_osc.lock().unwrap().record(received.elapsed());
+        }
+    }
+
+    /// Records the time since OSC that has been translated and queued was
+    /// taken for translation.
+    pub fn osc_to_midi(&self, received: Instant) {
+        if ENABLED {
+            self.osc_to_midi.lock().unwrap().record(received.elapsed());
+        }
+    }
+
+    /// Describes the times, if they're kept.
+    pub fn report(&self) -> Vec<String> {
+        if !ENABLED {
+            return vec![];
+        }
+        vec![
+            self.midi_to_osc
+                .lock()
+                .unwrap()
+                .describe("MIDI to OSC translation"),
+            self.osc_to_midi
+                .lock()
+                .unwrap()
+                .describe("OSC to MIDI translation"),
+        ]
+    }
+}
@@ -0,0 +1,162 @@
+//! A trace of recent translations.
+//!
+//! The service keeps the last few translation events in memory, so that a
+//! user can ask what became of a knob turn after the fact, without having
+//! had debug logging enabled. Each event records the message received, the
+//! mappings that handled it, with their descriptions, if they have them, and
+//! what was sent for it, if anything:
+//!
+//! ```text
+//! #41 0.8s ago MIDI B0 07 40 -> mapping 3 "Vocals" -> OSC /track/1/volume [Float(0.503937)]
+//! #42 0.2s ago OSC 10.0.0.5:9000 /track/1/mute [Float(1.0)] -> no mapping
+//! ```
+//!
+//! MIDI sent for OSC is recorded as translated, before slewing and
+//! coalescing. The trace is reported by the control socket's `trace`
+//! command, and to OSC clients that send `/bcr2kosc/trace`, optionally with
+//! the number of events wanted. OSC clients are sent one `/bcr2kosc/trace`
+//! message per event, with its number and description, oldest first.
+
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use midi_io::{copy_message, message_to_bytes, to_hex, MidiMessage};
+use rosc::{OscMessage, OscPacket};
+
+/// The address of trace requests, and of their replies.
+pub const TRACE_ADDR: &str = "/bcr2kosc/trace";
+
+/// The number of events kept when not configured otherwise.
+pub const DEFAULT_TRACE_SIZE: usize = 64;
+
+struct Event {
+    seq: u64,
+    at: Instant,
+    input: String,
+    /// The mappings that handled the message, as labelled by
+    /// `ServerTranslationSet::mapping_label`.
+    mappings: Vec<String>,
+    output: Vec<String>,
+}
+
+#[derive(Default)]
+struct State {
+    next_seq: u64,
+    events: VecDeque<Event>,
+}
+
+/// A ring buffer of the last translation events.
+pub struct TraceLog {
+    capacity: usize,
+    state: Mutex<State>,
+}
+
+impl TraceLog {
+    /// Creates a trace that keeps the last `capacity` events. A capacity of
+    /// zero disables tracing.
+    pub fn new(capacity: usize) -> Self {
+        TraceLog {
+            capacity,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Returns true if events are recorded. Callers can skip working out what
+    /// to record when they aren't.
+    pub fn enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Records a MIDI message, the mappings that handled it, and the OSC
+    /// sent for it.
+    pub fn midi(&self, msg: &MidiMessage, mappings: Vec<String>, output: Option<&OscPacket>) {
+        let bytes = message_to_bytes(copy_message(msg));
+        let mut sent = vec![];
+        if let Some(pkt) = output {
+            packet_lines(pkt, &mut sent);
+        }
+        self.record(format!("MIDI {}", to_hex(&bytes)), mappings, sent);
+    }
+
+    /// Records an OSC message, the mappings that handled it, and the MIDI
+    /// sent for it.
+    pub fn osc<'a>(
+        &self,
+        sender: SocketAddr,
+        msg: &OscMessage,
+        mappings: Vec<String>,
+        output: impl IntoIterator<Item = &'a MidiMessage>,
+    ) {
+        let sent = output
+            .into_iter()
+            .map(|m| format!("MIDI {}", to_hex(&message_to_bytes(copy_message(m)))))
+            .collect();
+        let input = format!("OSC {sender} {} {:?}", msg.addr, msg.args);
+        self.record(input, mappings, sent);
+    }
+
+    fn record(&self, input: String, mappings: Vec<String>, output: Vec<String>) {
+        if !self.enabled() {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.next_seq += 1;
+        let event = Event {
+            seq: state.next_seq,
+            at: Instant::now(),
+            input,
+            mappings,
+            output,
+        };
+        if state.events.len() == self.capacity {
+            state.events.pop_front();
+        }
+        state.events.push_back(event);
+    }
+
+    /// Describes the last `count` events, or all of them, oldest first, each
+    /// with its number.
+    pub fn report(&self, count: Option<usize>) -> Vec<(u64, String)> {
+        let state = self.state.lock().unwrap();
+        let count = count.unwrap_or(state.events.len());
+        let skip = state.events.len().saturating_sub(count);
+        let now = Instant::now();
+        state
+            .events
+            .iter()
+            .skip(skip)
+            .map(|e| (e.seq, describe(e, now)))
+            .collect()
+    }
+}
+
+fn describe(event: &Event, now: Instant) -> String {
+    let age = now.saturating_duration_since(event.at).as_secs_f32();
+    let mut s = format!("{age:.1}s ago {}", event.input);
+    if event.mappings.is_empty() {
+        s.push_str(" -> no mapping");
+        return s;
+    }
+    let noun = if event.mappings.len() == 1 {
+        "mapping"
+    } else {
+        "mappings"
+    };
+    s.push_str(&format!(" -> {noun} {}", event.mappings.join(", ")));
+    if event.output.is_empty() {
+        s.push_str(" -> nothing sent");
+    } else {
+        s.push_str(&format!(" -> {}", event.output.join("; ")));
+    }
+    s
+}
+
+/// Describes the messages in a packet, including those in nested bundles.
+fn packet_lines(pkt: &OscPacket, lines: &mut Vec<String>) {
+    match pkt {
+        OscPacket::Message(m) => lines.push(format!("OSC {} {:?}", m.addr, m.args)),
+        OscPacket::Bundle(b) => b.content.iter().for_each(|p| packet_lines(p, lines)),
+    }
+}
@@ -0,0 +1,345 @@
+//! Reporting of messages that no translator handles.
+//!
+//! Counts of unmatched MIDI and OSC messages are always kept, and reported by
+//! the control socket's `stats` command. When logging is enabled, unmatched
+//! messages are also logged, with a hex dump, at info level. Logging is rate
+//! limited so that a turning encoder doesn't flood the log; the number of
+//! messages left out is logged when logging resumes.
+//!
+//! Beyond that, unmatched OSC can be handled in one of these ways:
+//!
+//! - `drop`: nothing more is done. This is the default.
+//! - `log`: it's logged, even if logging of unmatched messages isn't enabled.
+//! - `passthrough`: messages addressed `/cc/CHANNEL/CONTROL`, with a channel
+//!   from 1 through 16 and a control number from 0 through 127, are sent as
+//!   control changes without a mapping. An integer argument is sent as the
+//!   value, and a float from 0 to 1 is scaled to 0 through 127. Others are
+//!   dropped.
+//! - `reply`: the sender is sent `/bcr2kosc/error`, with a message naming
+//!   the address, as for failed device operations.
+
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use midi_control::{Channel, ControlEvent};
+use midi_io::{copy_message, message_to_bytes, to_hex, MidiMessage};
+use rosc::{OscMessage, OscPacket, OscType};
+use tracing::info;
+
+use super::encode::encode_into;
+
+/// The period over which logged messages are limited.
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// The number of messages logged in each period.
+const LOGS_PER_WINDOW: u32 = 10;
+
+/// The number of bytes of an OSC message that are dumped.
+const MAX_DUMP: usize = 64;
+
+/// What's done with OSC messages that no mapping handles, beyond counting
+/// them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UnmatchedAction {
+    #[default]
+    Drop,
+    Log,
+    Passthrough,
+    Reply,
+}
+
+impl FromStr for UnmatchedAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(UnmatchedAction::Drop),
+            "log" => Ok(UnmatchedAction::Log),
+            "passthrough" => Ok(UnmatchedAction::Passthrough),
+            "reply" => Ok(UnmatchedAction::Reply),
+            _ => Err(format!("unknown action for unmatched OSC \"{s}\"")),
+        }
+    }
+}
+
+impl Display for UnmatchedAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UnmatchedAction::Drop => "drop",
+            UnmatchedAction::Log => "log",
+            UnmatchedAction::Passthrough => "passthrough",
+            UnmatchedAction::Reply => "reply",
+        }
+        .fmt(f)
+    }
+}
+
+/// The address prefix of control changes passed through without a mapping.
+const PASSTHROUGH_PREFIX: &str = "/cc/";
+
+/// The address of replies about unmatched messages.
+pub const ERROR_ADDR: &str = "/bcr2kosc/error";
+
+#[derive(Default)]
+struct State {
+    midi: u64,
+    osc: u64,
+    window_start: Option<Instant>,
+    logged: u32,
+    suppressed: u32,
+}
+
+/// Counts, and optionally logs, unmatched messages.
+pub struct UnmatchedLog {
+    enabled: bool,
+    osc_action: UnmatchedAction,
+    state: Mutex<State>,
+}
+
+impl UnmatchedLog {
+    /// Creates a log. Messages are only counted unless `enabled` is true, or,
+    /// for OSC, `osc_action` is `Log`.
+    pub fn new(enabled: bool, osc_action: UnmatchedAction) -> Self {
+        UnmatchedLog {
+            enabled,
+            osc_action,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// What's done with unmatched OSC messages.
+    pub fn osc_action(&self) -> UnmatchedAction {
+        self.osc_action
+    }
+
+    /// Records a MIDI message that no translator handled. System exclusive
+    /// messages aren't recorded; they're device traffic, not controls.
+    pub fn midi(&self, msg: &MidiMessage) {
+        if let MidiMessage::SysEx(_) = msg {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.midi += 1;
+        if self.enabled && admit(&mut state) {
+            let bytes = message_to_bytes(copy_message(msg));
+            info!("Unmatched MIDI: {} ({msg:?})", to_hex(&bytes));
+        }
+    }
+
+    /// Records an OSC message that no translator handled.
+    pub fn osc(&self, sender: SocketAddr, msg: &OscMessage) {
+        let mut state = self.state.lock().unwrap();
+        state.osc += 1;
+        let enabled = self.enabled || self.osc_action == UnmatchedAction::Log;
+        if enabled && admit(&mut state) {
+            let mut buf = vec![];
+            encode_into(&OscPacket::Message(msg.clone()), &mut buf);
+            let more = if buf.len() > MAX_DUMP { " ..." } else { "" };
+            info!(
+                "Unmatched OSC from {sender}: {} {:?} [{}{more}]",
+                msg.addr,
+                msg.args,
+                to_hex(&buf[..buf.len().min(MAX_DUMP)])
+            );
+        }
+    }
+
+    /// Summarizes the counts.
+    pub fn report(&self) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        vec![
+            format!("unmatched MIDI messages: {}", state.midi),
+            format!("unmatched OSC messages: {}", state.osc),
+        ]
+    }
+
+    /// The counts, by name.
+    pub fn counters(&self) -> Vec<(&'static str, u64)> {
+        let state = self.state.lock().unwrap();
+        vec![("unmatched_midi", state.midi), ("unmatched_osc", state.osc)]
+    }
+}
+
+/// Translates a message addressed `/cc/CHANNEL/CONTROL` to a control change,
+/// for the `passthrough` action.
+pub fn passthrough(msg: &OscMessage) -> Option<MidiMessage> {
+    let (channel, control) = msg.addr.strip_prefix(PASSTHROUGH_PREFIX)?.split_once('/')?;
+    let channel: u8 = channel.parse().ok().filter(|c| (1..=16).contains(c))?;
+    let control: u8 = control.parse().ok().filter(|c| *c < 128)?;
+    let value = match msg.args.first()? {
+        OscType::Int(v) if (0..128).contains(v) => *v as u8,
+        OscType::Float(v) if (0.0..=1.0).contains(v) => (v * 127.0).round() as u8,
+        OscType::Double(v) if (0.0..=1.0).contains(v) => (v * 127.0).round() as u8,
+        _ => return None,
+    };
+    Some(MidiMessage::ControlChange(
+        Channel::from(channel - 1),
+        ControlEvent { control, value },
+    ))
+}
+
+/// The reply to a sender of an unmatched message, for the `reply` action.
+pub fn error_reply(msg: &OscMessage) -> OscPacket {
+    OscPacket::Message(OscMessage {
+        addr: ERROR_ADDR.to_string(),
+        args: vec![OscType::String(format!("{}: no mapping", msg.addr))],
+    })
+}
+
+/// Decides whether a message can be logged in the current period, starting a
+/// new period if the last one is over.
+fn admit(state: &mut State) -> bool {
+    let now = Instant::now();
+    match state.window_start {
+        Some(start) if now - start < WINDOW => {}
+        _ => {
+            if state.suppressed > 0 {
+                info!(
+                    "{} more unmatched messages were not logged.",
+                    state.suppressed
+                );
+            }
+            state.window_start = Some(now);
+            state.logged = 0;
+            state.suppressed = 0;
+        }
+    }
+    if state.logged < LOGS_PER_WINDOW {
+        state.logged += 1;
+        true
+    } else {
+        state.suppressed += 1;
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use midi_control::SysExEvent;
+
+    use super::super::testing::{self, Harness};
+    use super::*;
+    use crate::translator::testing::expect_osc;
+
+    fn cc(control: u8, value: u8) -> MidiMessage {
+        MidiMessage::ControlChange(Channel::Ch1, ControlEvent { control, value })
+    }
+
+    fn osc(addr: &str, args: Vec<OscType>) -> OscMessage {
+        OscMessage {
+            addr: addr.to_string(),
+            args,
+        }
+    }
+
+    #[test]
+    fn messages_are_counted() {
+        let log = UnmatchedLog::new(true, UnmatchedAction::Drop);
+        let sender = (Ipv4Addr::LOCALHOST, 9000).into();
+        log.midi(&cc(1, 2));
+        log.midi(&cc(1, 3));
+        log.midi(&MidiMessage::SysEx(SysExEvent::new_non_realtime(
+            0,
+            [6, 1],
+            &[],
+        )));
+        log.osc(sender, &osc("/nowhere", vec![OscType::Int(1)]));
+        assert_eq!(
+            log.report(),
+            ["unmatched MIDI messages: 2", "unmatched OSC messages: 1"]
+        );
+        assert_eq!(
+            log.counters(),
+            [("unmatched_midi", 2), ("unmatched_osc", 1)]
+        );
+    }
+
+    #[test]
+    fn logging_is_limited_in_each_period() {
+        let mut state = State::default();
+        for _ in 0..LOGS_PER_WINDOW {
+            assert!(admit(&mut state));
+        }
+        assert!(!admit(&mut state));
+        assert!(!admit(&mut state));
+        assert_eq!(state.suppressed, 2);
+
+        // Once the period is over, logging resumes.
+        state.window_start = Some(Instant::now() - WINDOW);
+        assert!(admit(&mut state));
+        assert_eq!((state.logged, state.suppressed), (1, 0));
+    }
+
+    #[test]
+    fn actions_are_parsed_and_shown() {
+        for s in ["drop", "log", "passthrough", "reply"] {
+            assert_eq!(s.parse::<UnmatchedAction>().unwrap().to_string(), s);
+        }
+        assert_eq!(
+            "bounce".parse::<UnmatchedAction>(),
+            Err("unknown action for unmatched OSC \"bounce\"".to_string())
+        );
+    }
+
+    #[test]
+    fn passthrough_sends_control_changes() {
+        let msg = |addr, arg| passthrough(&osc(addr, vec![arg]));
+        let ch16 = |control, value| {
+            MidiMessage::ControlChange(Channel::Ch16, ControlEvent { control, value })
+        };
+        assert_eq!(msg("/cc/1/7", OscType::Int(100)), Some(cc(7, 100)));
+        assert_eq!(msg("/cc/16/0", OscType::Float(1.0)), Some(ch16(0, 127)));
+        assert_eq!(msg("/cc/1/127", OscType::Double(0.5)), Some(cc(127, 64)));
+        for (addr, arg) in [
+            ("/cc/0/7", OscType::Int(1)),
+            ("/cc/17/7", OscType::Int(1)),
+            ("/cc/1/128", OscType::Int(1)),
+            ("/cc/1", OscType::Int(1)),
+            ("/nrpn/1/7", OscType::Int(1)),
+            ("/cc/1/7", OscType::Int(128)),
+            ("/cc/1/7", OscType::Float(1.5)),
+            ("/cc/1/7", OscType::String("1".to_string())),
+        ] {
+            assert_eq!(msg(addr, arg.clone()), None, "{addr} {arg:?}");
+        }
+        assert_eq!(passthrough(&osc("/cc/1/7", vec![])), None);
+    }
+
+    #[tokio::test]
+    async fn service_passes_through_unmatched_osc() {
+        let (mut h, builder) = Harness::new().await;
+        let svc = builder.unmatched_osc(UnmatchedAction::Passthrough).build();
+        testing::run(&svc, async {
+            h.send_osc(osc("/cc/2/7", vec![OscType::Int(5)])).await;
+            let expected = MidiMessage::ControlChange(
+                Channel::Ch2,
+                ControlEvent {
+                    control: 7,
+                    value: 5,
+                },
+            );
+            assert_eq!(h.recv_midi().await, expected);
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn service_replies_to_unmatched_osc() {
+        let (h, builder) = Harness::new().await;
+        let svc = builder.unmatched_osc(UnmatchedAction::Reply).build();
+        testing::run(&svc, async {
+            h.send_osc(osc("/nowhere", vec![OscType::Int(1)])).await;
+            let reply = osc(
+                ERROR_ADDR,
+                vec![OscType::String("/nowhere: no mapping".to_string())],
+            );
+            expect_osc(Some(&h.recv_osc().await), &reply).unwrap();
+        })
+        .await;
+    }
+}
@@ -0,0 +1,73 @@
+//! Heartbeats for external watchdogs.
+//!
+//! When enabled, the service sends `/bcr2kosc/heartbeat` at a configured
+//! interval, so that a watchdog, such as a show-control script or a service
+//! manager, can restart the bridge when the heartbeats stop. Its arguments
+//! are a counter, which starts at 1 and goes up by one with each heartbeat,
+//! and the service's uptime in whole seconds, both integers. A counter that
+//! starts again from 1 shows that the service was restarted.
+//!
+//! Heartbeats go to the OSC destinations, unless others are configured, and
+//! are sent from the socket that sends translated OSC. The address is the
+//! one a primary sends to a hot standby, which takes either as a sign of
+//! life; see the `standby` module.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rosc::{OscMessage, OscPacket, OscType};
+use tokio::net::UdpSocket;
+use tokio::time::MissedTickBehavior;
+use tracing::{error, info};
+
+use super::encode::encode_into;
+use super::standby::HEARTBEAT_ADDR;
+use crate::PGM;
+
+/// Configures heartbeats for watchdogs.
+#[derive(Clone, Debug)]
+pub struct WatchdogConfig {
+    /// Time between heartbeats.
+    pub interval: Duration,
+    /// Where heartbeats go. If empty, they go to the OSC destinations.
+    pub destinations: Vec<SocketAddr>,
+}
+
+/// Sends heartbeats to watchdogs. `started` is when the service started.
+/// Runs until cancelled.
+pub async fn send_heartbeats(
+    config: &WatchdogConfig,
+    started: Instant,
+    socket: Arc<UdpSocket>,
+    clients: Arc<Vec<SocketAddr>>,
+) {
+    let destinations: &[SocketAddr] = if config.destinations.is_empty() {
+        &clients
+    } else {
+        &config.destinations
+    };
+    info!(
+        "{PGM} will send watchdog heartbeats every {:?}.",
+        config.interval
+    );
+    let mut timer = tokio::time::interval(config.interval);
+    timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut buf = vec![];
+    let mut count: i32 = 0;
+    loop {
+        timer.tick().await;
+        count = count.wrapping_add(1);
+        let uptime = started.elapsed().as_secs().min(i32::MAX as u64) as i32;
+        let heartbeat = OscPacket::Message(OscMessage {
+            addr: HEARTBEAT_ADDR.to_string(),
+            args: vec![OscType::Int(count), OscType::Int(uptime)],
+        });
+        encode_into(&heartbeat, &mut buf);
+        for a in destinations {
+            if let Err(e) = socket.send_to(&buf, a).await {
+                error!("Watchdog heartbeat to {a} failed: {e}");
+            }
+        }
+    }
+}
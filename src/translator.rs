@@ -2,51 +2,194 @@
 //!
 //! Notes:
 //! * OSC 1.0 supports only these data types: Int, Float, String, Blob, and Time.
-//! * Reaper expects Float(1.0) for Boolean true, Float(0.0) for false.
+//! * Reaper expects Float(1.0) for Boolean true, Float(0.0) for false. Other
+//!   hosts want OSC 1.1 types; see the `output` module, and the `format`
+//!   module of `osc_service` for formatting each destination's OSC.
 //!
 
+// Translators' constructors return them boxed, ready to go in a set.
+#![allow(clippy::new_ret_no_self)]
+
 use std::error::Error;
-use std::iter;
 
-use log::error;
 use midi_control::*;
 use rosc::address::{Matcher, OscAddress};
 use rosc::{OscBundle, OscMessage, OscPacket, OscTime, OscType};
+use smallvec::SmallVec;
+use tracing::error;
 
+mod builder;
 mod ccx;
+mod coerce;
+mod condition;
+mod coverage;
+mod feedback;
+mod gesture;
+mod group;
+mod info;
+mod notex;
+mod output;
+mod profile;
+mod quantize;
+mod ranges;
+mod raw;
+mod shift;
+mod slew;
+mod spec;
+mod template;
+pub mod testing;
+mod threshold;
+mod velocity;
+pub use crate::translator::builder::*;
 pub use crate::translator::ccx::*;
+pub use crate::translator::coerce::*;
+pub use crate::translator::condition::*;
+pub use crate::translator::coverage::*;
+pub use crate::translator::feedback::*;
+pub use crate::translator::gesture::*;
+pub use crate::translator::group::*;
+pub use crate::translator::info::*;
+pub use crate::translator::notex::*;
+pub use crate::translator::output::*;
+pub use crate::translator::profile::*;
+pub use crate::translator::quantize::*;
+pub use crate::translator::ranges::*;
+pub use crate::translator::raw::*;
+pub use crate::translator::shift::*;
+pub use crate::translator::slew::*;
+pub use crate::translator::template::*;
+pub use crate::translator::threshold::*;
+pub use crate::translator::velocity::*;
 
 
 type Result<T> = std::result::Result<T, Box<dyn Error>>;
 
 /// Specifies a set of translations between OSC and MIDI messages.
-pub struct ServerTranslationSet(Vec<Box<dyn Translator>>);
+pub struct ServerTranslationSet {
+    translators: Vec<Box<dyn Translator>>,
+    /// What each translator's mapping means to people, if known.
+    infos: Vec<MappingInfo>,
+    /// The coercion policy of mappings that don't set their own.
+    coercion: Coercion,
+    /// The learned ranges by which control changes are calibrated.
+    ranges: LearnedRanges,
+    /// The state that mappings' conditions are checked against.
+    state: BridgeState,
+    /// The prefix added to addresses while the controls are shifted, if the
+    /// set has a shift button.
+    shift: Option<String>,
+}
 
-pub type MMIterator = Box<dyn Iterator<Item = MidiMessage>>;
+/// MIDI messages translated from OSC, each paired with the slew rate of the
+/// mapping that produced it. Most OSC translates to a message or two, which
+/// are kept without allocating.
+pub type SlewedMidi = SmallVec<[(MidiMessage, Option<f32>); 4]>;
+
+/// The messages in an OSC packet. A message, or a bundle of a few, is listed
+/// without allocating.
+pub type PacketMessages<'a> = SmallVec<[&'a OscMessage; 4]>;
+
+/// Lists the messages in a packet, including those in nested bundles, in
+/// order. Bundles are walked without recursion, so that a client can't
+/// exhaust the stack by nesting them deeply.
+pub fn packet_messages(pkt: &OscPacket) -> PacketMessages<'_> {
+    let mut messages = PacketMessages::new();
+    let mut bundles: SmallVec<[std::slice::Iter<OscPacket>; 4]> = SmallVec::new();
+    match pkt {
+        OscPacket::Message(m) => messages.push(m),
+        OscPacket::Bundle(b) => bundles.push(b.content.iter()),
+    }
+    while let Some(contents) = bundles.last_mut() {
+        match contents.next() {
+            Some(OscPacket::Message(m)) => messages.push(m),
+            Some(OscPacket::Bundle(b)) => bundles.push(b.content.iter()),
+            None => {
+                bundles.pop();
+            }
+        }
+    }
+    messages
+}
 
 impl ServerTranslationSet {
     /// Create a new ServerTranslationSet from a vector of translators.
     pub fn new(set: Vec<Box<dyn Translator>>) -> ServerTranslationSet {
-        ServerTranslationSet(set)
+        ServerTranslationSet {
+            translators: set,
+            infos: vec![],
+            coercion: Coercion::default(),
+            ranges: LearnedRanges::default(),
+            state: BridgeState::default(),
+            shift: None,
+        }
+    }
+
+    /// Sets the coercion policy of mappings that don't set their own. The
+    /// default is permissive.
+    pub fn with_coercion(mut self, coercion: Coercion) -> Self {
+        self.coercion = coercion;
+        self
     }
 
-    pub fn get_test_set() -> Result<ServerTranslationSet> {
-        Ok(Self::new(vec![
-            ControlChangeRangeTranslator::new(Channel::Ch1, 1, 0, 127, "/encoder/1")?,
-            ControlChangeBoolTranslator::new(Channel::Ch1, 65, 0, 127, "/key/1")?,
-        ]))
+    /// Calibrates control changes by learned ranges. See the `ranges`
+    /// module.
+    pub fn with_ranges(mut self, ranges: LearnedRanges) -> Self {
+        self.ranges = ranges;
+        self
+    }
+
+    /// Gives what each translator's mapping means to people, in order. See
+    /// the `info` module.
+    pub fn with_info(mut self, infos: Vec<MappingInfo>) -> Self {
+        self.infos = infos;
+        self
+    }
+
+    /// Checks mappings' conditions against this state, which their
+    /// translators share. See the `condition` module.
+    pub fn with_state(mut self, state: BridgeState) -> Self {
+        self.state = state;
+        self
+    }
+
+    /// Shifts addresses under this prefix while the shift flag is set. See
+    /// the `shift` module.
+    pub fn with_shift(mut self, prefix: Option<String>) -> Self {
+        self.shift = prefix;
+        self
+    }
+
+    /// Returns the state that mappings' conditions are checked against.
+    pub fn state(&self) -> &BridgeState {
+        &self.state
+    }
+
+    /// The mappings used when none are configured.
+    pub fn test_mappings() -> TranslationSetBuilder {
+        TranslationSetBuilder::new()
+            .cc(Channel::Ch1, 1)
+            .osc("/encoder/1")
+            .cc(Channel::Ch1, 65)
+            .toggle()
+            .osc("/key/1")
+    }
+
+    /// Returns the number of translators in the set.
+    pub fn len(&self) -> usize {
+        self.translators.len()
     }
 
     /// Translates a MIDI msg to an OSC packet, if there is at least one valid
     /// mapping to an OSC message. The packet may contain multiple messages.
-    pub fn midi_msg_to_osc(&self, midi_msg: MidiMessage) -> Option<OscPacket> {
+    pub fn midi_msg_to_osc(&self, midi_msg: &MidiMessage) -> Option<OscPacket> {
+        let stretched = self.ranges.stretch(midi_msg);
+        let midi_msg = stretched.as_ref().unwrap_or(midi_msg);
         let msgs: Vec<OscPacket> = self
-            .0
+            .translators
             .iter()
-            .map(|x| x.midi_to_osc(&midi_msg))
-            .filter_map(|i| i)
+            .filter_map(|x| x.midi_to_osc(midi_msg))
             .collect();
-        if msgs.is_empty() {
+        let pkt = if msgs.is_empty() {
             None
         } else if msgs.len() == 1 {
             Some(msgs.into_iter().last().unwrap())
@@ -58,50 +201,199 @@ impl ServerTranslationSet {
                 },
                 content: msgs,
             }))
+        };
+        match &self.shift {
+            Some(prefix) if self.state.flag(SHIFT_FLAG) => pkt.map(|p| shift_packet(prefix, p)),
+            _ => pkt,
         }
     }
 
-    pub fn osc_pkt_to_midi(&self, op: &OscPacket) -> MMIterator {
-        match op {
-            OscPacket::Message(om) => {
-                let matcher = Matcher::new(&om.addr);
-                if matcher.is_err() {
-                    error!(
-                        "Failed to create OSC matcher for incoming address: {}",
-                        &om.addr
-                    );
-                    return Box::new(iter::empty());
-                }
-                let matcher = matcher.unwrap();
-                let v: Vec<MidiMessage> = self
-                    .0
-                    .iter()
-                    .filter_map(|x| x.osc_to_midi(&matcher, &om.args))
-                    .collect();
-                Box::new(v.into_iter())
-            }
-            OscPacket::Bundle(b) => {
-                let sub = b
-                    .content
-                    .iter()
-                    .map(|p| self.osc_pkt_to_midi(p))
-                    .collect::<Vec<MMIterator>>();
-                Box::new(sub.into_iter().flatten())
+    /// Returns true if any mapping handles the MIDI message, even if none
+    /// sends anything for it, as when its value hasn't changed enough.
+    pub fn handles_midi(&self, midi_msg: &MidiMessage) -> bool {
+        self.translators.iter().any(|x| x.handles_midi(midi_msg))
+    }
+
+    /// Returns true if any mapping handles the OSC message, even if none
+    /// sends anything for it.
+    pub fn handles_osc(&self, om: &OscMessage) -> bool {
+        let addr = match &self.shift {
+            Some(prefix) => unshifted(prefix, &om.addr).unwrap_or(&om.addr),
+            None => &om.addr,
+        };
+        let Ok(matcher) = Matcher::new(addr) else {
+            return false;
+        };
+        let permissive = coerce_args(&om.args, Coercion::Permissive);
+        self.translators
+            .iter()
+            .any(|x| x.handles_osc(&matcher, self.args_for(x.as_ref(), &permissive, &om.args)))
+    }
+
+    /// Returns the positions in the set, counting from 1, of the mappings
+    /// that handle the MIDI message. Mappings are numbered in the order
+    /// they're given: the profile's, then the mapping file's, then those
+    /// added while the service runs, then the raw MIDI namespace.
+    pub fn midi_mappings(&self, midi_msg: &MidiMessage) -> Vec<usize> {
+        self.translators
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| x.handles_midi(midi_msg))
+            .map(|(i, _)| i + 1)
+            .collect()
+    }
+
+    /// Returns the positions in the set, counting from 1, of the mappings
+    /// that handle the OSC message. See `midi_mappings`.
+    pub fn osc_mappings(&self, om: &OscMessage) -> Vec<usize> {
+        let Some(addr) = self.shifted_route(&om.addr) else {
+            return vec![];
+        };
+        let Ok(matcher) = Matcher::new(addr) else {
+            return vec![];
+        };
+        let permissive = coerce_args(&om.args, Coercion::Permissive);
+        self.translators
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| {
+                x.handles_osc(&matcher, self.args_for(x.as_ref(), &permissive, &om.args))
+            })
+            .map(|(i, _)| i + 1)
+            .collect()
+    }
+
+    /// Returns the description of the mapping at a position, counting from
+    /// 1, if it has one.
+    pub fn description(&self, position: usize) -> Option<&str> {
+        self.info(position)?.description.as_deref()
+    }
+
+    /// Returns what the mapping at a position, counting from 1, means to
+    /// people, if known.
+    pub fn info(&self, position: usize) -> Option<&MappingInfo> {
+        self.infos.get(position.checked_sub(1)?)
+    }
+
+    /// Names the mapping at a position for people: its number, followed by
+    /// its description in quotes, if it has one.
+    pub fn mapping_label(&self, position: usize) -> String {
+        match self.description(position) {
+            Some(d) => format!("{position} \"{d}\""),
+            None => position.to_string(),
+        }
+    }
+
+    /// Labels the mappings at these positions, as `mapping_label` does.
+    pub fn mapping_labels(&self, positions: Vec<usize>) -> Vec<String> {
+        positions
+            .into_iter()
+            .map(|p| self.mapping_label(p))
+            .collect()
+    }
+
+    /// Translates an OSC message to MIDI messages, each paired with the slew
+    /// rate of the mapping that produced it.
+    pub fn osc_msg_to_slewed_midi(&self, om: &OscMessage) -> SlewedMidi {
+        let mut out = SlewedMidi::new();
+        self.osc_msg_to_slewed_midi_into(om, &mut out);
+        out
+    }
+
+    /// Translates an OSC message like `osc_msg_to_slewed_midi`, adding the
+    /// MIDI to `out`, so that it can be reused from one message to the next.
+    pub fn osc_msg_to_slewed_midi_into(&self, om: &OscMessage, out: &mut SlewedMidi) {
+        let Some(addr) = self.shifted_route(&om.addr) else {
+            return;
+        };
+        let matcher = match Matcher::new(addr) {
+            Ok(m) => m,
+            Err(_) => {
+                error!(
+                    "Failed to create OSC matcher for incoming address: {}",
+                    &om.addr
+                );
+                return;
             }
+        };
+        let permissive = coerce_args(&om.args, Coercion::Permissive);
+        for x in &self.translators {
+            let rate = x.slew_rate();
+            let args = self.args_for(x.as_ref(), &permissive, &om.args);
+            out.extend(
+                x.osc_to_midi(&matcher, args)
+                    .into_iter()
+                    .map(|m| (self.ranges.narrow(m), rate)),
+            );
+        }
+    }
+
+    /// Returns the address that OSC received on `addr` is translated on, or
+    /// `None` if it's for the layer of the shift button that isn't in use.
+    fn shifted_route<'a>(&self, addr: &'a str) -> Option<&'a str> {
+        let Some(prefix) = &self.shift else {
+            return Some(addr);
+        };
+        match (unshifted(prefix, addr), self.state.flag(SHIFT_FLAG)) {
+            (Some(rest), true) => Some(rest),
+            (None, false) => Some(addr),
+            _ => None,
+        }
+    }
+
+    /// Chooses the arguments a translator is given, under its coercion
+    /// policy: those coerced permissively, or those received.
+    fn args_for<'a>(
+        &self,
+        translator: &dyn Translator,
+        permissive: &'a [OscType],
+        received: &'a [OscType],
+    ) -> &'a [OscType] {
+        match translator.coercion().unwrap_or(self.coercion) {
+            Coercion::Permissive => permissive,
+            Coercion::Strict => received,
         }
     }
 }
 
-pub trait Translator {
+pub trait Translator: Send + Sync {
     fn midi_to_osc(&self, midi: &MidiMessage) -> Option<OscPacket>;
-    fn osc_to_midi(&self, addr_matcher: &Matcher, args: &[OscType]) -> Option<MidiMessage>;
+    /// Translates an OSC message to MIDI. An address pattern can match more
+    /// than one of a translator's addresses, producing several messages.
+    fn osc_to_midi(&self, addr_matcher: &Matcher, args: &[OscType]) -> Vec<MidiMessage>;
+    /// The rate, in control steps per second, at which control changes sent
+    /// by this translator are ramped toward new values, if they are slewed.
+    fn slew_rate(&self) -> Option<f32> {
+        None
+    }
+    /// The coercion policy of OSC arguments given to this translator, if it
+    /// overrides that of its set.
+    fn coercion(&self) -> Option<Coercion> {
+        None
+    }
+    /// Returns true if the translator handles the MIDI message, even if it
+    /// sends nothing for it this time. See the `threshold` module.
+    fn handles_midi(&self, midi: &MidiMessage) -> bool {
+        self.midi_to_osc(midi).is_some()
+    }
+    /// Returns true if the translator handles the OSC message, even if it
+    /// sends nothing for it this time.
+    fn handles_osc(&self, addr_matcher: &Matcher, args: &[OscType]) -> bool {
+        !self.osc_to_midi(addr_matcher, args).is_empty()
+    }
+    /// Describes the MIDI messages the translator handles. Translators that
+    /// don't describe themselves return an empty vector.
+    fn coverage(&self) -> Vec<Coverage> {
+        vec![]
+    }
 }
 
 //struct NoteOnTranslator(Channel, MidiNote, String);
 
 /// Translate a MIDI control value to a normalized float (0.0 thru 1.0).
+/// Values outside the range are taken to be at its nearer end.
 fn cv_to_normalized_float(v: u8, low: u8, high: u8) -> f32 {
-    (v - low) as f32 / (high - low) as f32
+    (v.clamp(low, high) - low) as f32 / (high - low) as f32
 }
 
 /// Translate a normalized float (0.0 thru 1.0) to a MIDI control value.
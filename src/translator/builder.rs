@@ -0,0 +1,543 @@
+//! A fluent builder for `ServerTranslationSet`.
+//!
+//! Mapping files and profiles are built with it, and tests use it to make
+//! sets of their own.
+//!
+//! Each mapping is started with a method naming the MIDI message it
+//! translates, optionally refined, and completed by giving it an OSC address:
+//!
+//! ```ignore
+//! let set = TranslationSetBuilder::new()
+//!     .cc(Channel::Ch1, 7).range(0..=127).osc("/vol")
+//!     .note(Channel::Ch1, 60).osc("/play")
+//!     .bank(Channel::Ch2, 1..=8).osc("/encoder")
+//!     .cc_wildcard(Channels::ANY, None).osc("/ch/{c}/cc/{n}")
+//!     .cc_indexed(IndexTarget::Channel, Channel::Ch1, 7).osc("/track/{1-16}/volume")
+//!     .cc_groups(vec![(Channel::Ch1, 1), (Channel::Ch1, 9)]).osc("/encoder/1/group/{1-2}")
+//!     .cc(Channel::Ch3, 20).steps(4).slew(200.0).osc("/filter/type")
+//!     .cc(Channel::Ch3, 21).feedback("/filter/cutoff/value").osc("/filter/cutoff")
+//!     .cc(Channel::Ch1, 66).toggle().output(OutputType::Bool).osc("/mute")
+//!     .cc(Channel::Ch1, 67).states(vec![(StateKey::Name("rec".into()), 127)]).osc("/arm")
+//!     .cc(Channel::Ch1, 68).coercion(Coercion::Strict).osc("/pan")
+//!     .cc(Channel::Ch1, 69).threshold(0.01).osc("/send")
+//!     .cc(Channel::Ch1, 70).toggle().long_press(Duration::from_millis(500)).osc("/play")
+//!     .group_button(Button::Cc(Channel::Ch1, 110), 2)
+//!     .cc(Channel::Ch1, 1).group(2).osc("/encoder/1/alt")
+//!     .cc(Channel::Ch1, 2).when("shift".parse()?).osc("/encoder/2/fine")
+//!     .cc(Channel::Ch1, 4).unit("dB").scale(-60.0, 6.0).color(Color(255, 128, 0)).osc("/vol")
+//!     .shift_button(Button::Note(Channel::Ch1, 0), "/shift")
+//!     .raw_midi()
+//!     .build()?;
+//! ```
+
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+use super::*;
+
+/// Builds a `ServerTranslationSet` from mappings described in code.
+///
+/// Errors in individual mappings, such as invalid OSC addresses, are reported
+/// by `build`.
+#[derive(Default)]
+pub struct TranslationSetBuilder {
+    translators: Vec<Box<dyn Translator>>,
+    /// What each translator's mapping means to people.
+    infos: Vec<MappingInfo>,
+    error: Option<Box<dyn Error>>,
+    state: BridgeState,
+    shift: Option<String>,
+}
+
+impl TranslationSetBuilder {
+    /// Create a builder with no mappings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start a mapping for a single control change number.
+    pub fn cc(self, channel: Channel, control: u8) -> Mapping<Cc> {
+        Mapping::new(
+            self,
+            Cc {
+                channel,
+                control,
+                toggle: false,
+                states: None,
+            },
+        )
+    }
+
+    /// Start a mapping for control changes on a set of channels. A `control`
+    /// of `None` matches all control numbers. The mapping's OSC address is a
+    /// template; see `AddressTemplate`.
+    pub fn cc_wildcard(
+        self,
+        channels: impl Into<Channels>,
+        control: Option<u8>,
+    ) -> Mapping<CcWildcard> {
+        Mapping::new(
+            self,
+            CcWildcard {
+                channels: channels.into(),
+                control,
+            },
+        )
+    }
+
+    /// Start a mapping for a run of control changes addressed by a template
+    /// with a range placeholder, e.g. `/track/{1-16}/volume`. The index in
+    /// the address selects the channel or the control number, counting up
+    /// from the ones given here.
+    pub fn cc_indexed(
+        self,
+        target: IndexTarget,
+        channel: Channel,
+        control: u8,
+    ) -> Mapping<CcIndexed> {
+        Mapping::new(
+            self,
+            CcIndexed {
+                target,
+                channel,
+                control,
+            },
+        )
+    }
+
+    /// Start a mapping for one encoder in each of a B-Control's encoder
+    /// groups, given as the channel and control that it sends in each group,
+    /// in order. The group is selected by the index in the mapping's address
+    /// template, e.g. `/encoder/1/group/{1-4}`.
+    pub fn cc_groups(self, groups: Vec<(Channel, u8)>) -> Mapping<CcGroups> {
+        Mapping::new(self, CcGroups { groups })
+    }
+
+    /// Start a mapping for a single note.
+    pub fn note(self, channel: Channel, key: MidiNote) -> Mapping<Note> {
+        Mapping::new(
+            self,
+            Note {
+                channel,
+                key,
+                curve: VelocityCurve::default(),
+            },
+        )
+    }
+
+    /// Start a mapping for a run of consecutive control change numbers, which
+    /// are given consecutive OSC addresses.
+    pub fn bank(self, channel: Channel, controls: RangeInclusive<u8>) -> Mapping<Bank> {
+        Mapping::new(self, Bank { channel, controls })
+    }
+
+    /// Add a group button, which selects a group when it's pressed. See the
+    /// `group` module.
+    pub fn group_button(self, button: Button, group: u8) -> Self {
+        let translator = GroupSwitchTranslator::new(button, group, self.state.group().clone());
+        self.add(translator)
+    }
+
+    /// Add a shift button, which adds `prefix`, such as `/shift`, to the
+    /// addresses of all mappings while it's held. A set's shift buttons
+    /// share the prefix of the last one added. See the `shift` module.
+    pub fn shift_button(mut self, button: Button, prefix: &str) -> Self {
+        let prefix = prefix.trim_end_matches('/');
+        if !prefix.starts_with('/') || prefix.len() < 2 {
+            let e = format!("\"{prefix}\" is not an OSC address prefix");
+            return self.add(Err(e.into()));
+        }
+        self.shift = Some(prefix.to_string());
+        let translator = ShiftTranslator::new(button, self.state.clone());
+        self.add(translator)
+    }
+
+    /// Add the raw MIDI namespace, which translates all control changes,
+    /// notes and pitch bends under `/midi/`. See the `raw` module.
+    pub fn raw_midi(self) -> Self {
+        self.translator(Box::new(RawMidiTranslator))
+    }
+
+    /// Add a translator that was constructed elsewhere.
+    pub fn translator(self, translator: Box<dyn Translator>) -> Self {
+        self.add(Ok(translator))
+    }
+
+    /// Returns the number of translators added so far.
+    pub(super) fn translator_count(&self) -> usize {
+        self.translators.len()
+    }
+
+    /// Describe the translators added since there were `start` of them, as
+    /// the mappings of a line of text are. See `Mapping::description`.
+    pub(super) fn describe_from(mut self, start: usize, description: &str) -> Self {
+        for info in self.infos.iter_mut().skip(start) {
+            info.description = Some(description.to_string());
+        }
+        self
+    }
+
+    /// Create the translation set, or report the first error encountered
+    /// while adding mappings.
+    pub fn build(self) -> Result<ServerTranslationSet> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(ServerTranslationSet::new(self.translators)
+                .with_info(self.infos)
+                .with_state(self.state)
+                .with_shift(self.shift)),
+        }
+    }
+
+    fn add(self, translator: Result<Box<dyn Translator>>) -> Self {
+        self.add_with_info(translator, MappingInfo::default())
+    }
+
+    fn add_with_info(mut self, translator: Result<Box<dyn Translator>>, info: MappingInfo) -> Self {
+        match translator {
+            Ok(t) => {
+                self.translators.push(t);
+                self.infos.push(info);
+            }
+            Err(e) => {
+                if self.error.is_none() {
+                    self.error = Some(e);
+                }
+            }
+        }
+        self
+    }
+}
+
+/// A mapping under construction. The kind of mapping determines how it is
+/// completed; the options here apply to all kinds.
+pub struct Mapping<K> {
+    set: TranslationSetBuilder,
+    kind: K,
+    range: RangeInclusive<u8>,
+    slew: Option<f32>,
+    quantize: Option<Quantize>,
+    feedback: Option<String>,
+    output: OutputType,
+    coercion: Option<Coercion>,
+    threshold: Option<f32>,
+    long_press: Option<Duration>,
+    double_press: Option<Duration>,
+    conditions: Vec<Condition>,
+    info: MappingInfo,
+}
+
+impl<K> Mapping<K> {
+    fn new(set: TranslationSetBuilder, kind: K) -> Self {
+        Mapping {
+            set,
+            kind,
+            range: 0..=127,
+            slew: None,
+            quantize: None,
+            feedback: None,
+            output: OutputType::Float,
+            coercion: None,
+            threshold: None,
+            long_press: None,
+            double_press: None,
+            conditions: vec![],
+            info: MappingInfo::default(),
+        }
+    }
+
+    /// Set the range of control values, which is mapped to 0.0 through 1.0.
+    /// The default is 0 through 127.
+    pub fn range(mut self, range: RangeInclusive<u8>) -> Self {
+        self.range = range;
+        self
+    }
+
+    /// Ramp large jumps in values sent to MIDI, at the given rate in control
+    /// steps per second. See `SlewLimiter`.
+    pub fn slew(mut self, rate: f32) -> Self {
+        self.slew = Some(rate);
+        self
+    }
+
+    /// Snap values to this many evenly spaced steps.
+    pub fn steps(mut self, steps: u8) -> Self {
+        self.quantize = Some(Quantize::Steps(steps));
+        self
+    }
+
+    /// Snap control values to the nearest of these values.
+    pub fn values(mut self, values: Vec<u8>) -> Self {
+        self.quantize = Some(Quantize::Values(values));
+        self
+    }
+
+    /// Send OSC translated from MIDI to this address, rather than the one OSC
+    /// is received on. See the `feedback` module.
+    pub fn feedback(mut self, address: &str) -> Self {
+        self.feedback = Some(address.to_string());
+        self
+    }
+
+    /// Send OSC arguments of this type. See the `output` module.
+    pub fn output(mut self, output: OutputType) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Treat OSC arguments of unexpected types under this policy, rather
+    /// than that of the set. See the `coerce` module.
+    pub fn coercion(mut self, coercion: Coercion) -> Self {
+        self.coercion = Some(coercion);
+        self
+    }
+
+    /// Drop values that differ from the last one passed by less than this,
+    /// in the normalized range of 0.0 through 1.0. See the `threshold`
+    /// module.
+    pub fn threshold(mut self, threshold: f32) -> Self {
+        self.threshold = Some(threshold);
+        self
+    }
+
+    /// Also send OSC to `ADDRESS/long` when a button held for at least this
+    /// long is released. See the `gesture` module.
+    pub fn long_press(mut self, time: Duration) -> Self {
+        self.long_press = Some(time);
+        self
+    }
+
+    /// Also send OSC to `ADDRESS/double` when a button is pressed twice
+    /// within this time. See the `gesture` module.
+    pub fn double_press(mut self, time: Duration) -> Self {
+        self.double_press = Some(time);
+        self
+    }
+
+    /// Translate only while this encoder group is active. See the `group`
+    /// module.
+    pub fn group(self, group: u8) -> Self {
+        self.when(Condition::Group(group))
+    }
+
+    /// Translate only while this condition holds, and any others given. See
+    /// the `condition` module.
+    pub fn when(mut self, condition: Condition) -> Self {
+        self.conditions.push(condition);
+        self
+    }
+
+    /// Give the unit of the value the mapping controls, such as "dB". See
+    /// the `info` module.
+    pub fn unit(mut self, unit: &str) -> Self {
+        self.info.unit = Some(unit.to_string());
+        self
+    }
+
+    /// Give the values that the low and high ends of the range stand for.
+    /// See the `info` module.
+    pub fn scale(mut self, low: f32, high: f32) -> Self {
+        self.info.scale = Some((low, high));
+        self
+    }
+
+    /// Give the color of the mapping's control. See the `info` module.
+    pub fn color(mut self, color: Color) -> Self {
+        self.info.color = Some(color);
+        self
+    }
+
+    fn bounds(&self) -> (u8, u8) {
+        (*self.range.start(), *self.range.end())
+    }
+
+    /// Apply the options that are implemented by wrapping a translator.
+    fn wrap(&self, translator: Result<Box<dyn Translator>>) -> Result<Box<dyn Translator>> {
+        let mut translator = translator?;
+        if let Some(q) = &self.quantize {
+            translator = Quantized::wrap(translator, q.clone());
+        }
+        if let Some(address) = &self.feedback {
+            translator = Feedback::wrap(translator, address)?;
+        }
+        if self.output != OutputType::Float {
+            translator = Typed::wrap(translator, self.output);
+        }
+        if let Some(rate) = self.slew {
+            translator = Slewed::wrap(translator, rate);
+        }
+        if let Some(coercion) = self.coercion {
+            translator = Coerced::wrap(translator, coercion);
+        }
+        if self.long_press.is_some() || self.double_press.is_some() {
+            translator = Gestured::wrap(translator, self.long_press, self.double_press);
+        }
+        if let Some(threshold) = self.threshold {
+            translator = Thresholded::wrap(translator, threshold);
+        }
+        if !self.conditions.is_empty() {
+            translator =
+                Conditional::wrap(translator, self.conditions.clone(), self.set.state.clone());
+        }
+        Ok(translator)
+    }
+
+    fn finish(self, translator: Result<Box<dyn Translator>>) -> TranslationSetBuilder {
+        let translator = self.wrap(translator);
+        self.set.add_with_info(translator, self.info)
+    }
+}
+
+/// A single control change.
+pub struct Cc {
+    channel: Channel,
+    control: u8,
+    toggle: bool,
+    states: Option<Vec<(StateKey, u8)>>,
+}
+
+impl Mapping<Cc> {
+    /// Treat the control as an on/off switch, using the ends of the range as
+    /// the off and on values.
+    pub fn toggle(mut self) -> Self {
+        self.kind.toggle = true;
+        self
+    }
+
+    /// Translate an enumeration of OSC values, each to its own control value,
+    /// rather than a continuous range. See `ControlChangeStatesTranslator`.
+    pub fn states(mut self, states: Vec<(StateKey, u8)>) -> Self {
+        self.kind.states = Some(states);
+        self
+    }
+
+    /// Complete the mapping by giving it an OSC address.
+    pub fn osc(mut self, address: &str) -> TranslationSetBuilder {
+        let (low, high) = self.bounds();
+        let Cc {
+            channel,
+            control,
+            toggle,
+            ..
+        } = self.kind;
+        let translator = if let Some(states) = self.kind.states.take() {
+            ControlChangeStatesTranslator::new(channel, control, states, address)
+        } else if toggle {
+            ControlChangeBoolTranslator::new(channel, control, low, high, address)
+        } else {
+            ControlChangeRangeTranslator::new(channel, control, low, high, address)
+        };
+        self.finish(translator)
+    }
+}
+
+/// Control changes on a set of channels, with an address template.
+pub struct CcWildcard {
+    channels: Channels,
+    control: Option<u8>,
+}
+
+impl Mapping<CcWildcard> {
+    /// Complete the mapping by giving it an OSC address template, e.g.
+    /// `/ch/{c}/cc/{n}`.
+    pub fn osc(self, template: &str) -> TranslationSetBuilder {
+        let (low, high) = self.bounds();
+        let CcWildcard { channels, control } = self.kind;
+        let translator =
+            ControlChangeTemplateTranslator::new(channels, control, low, high, template);
+        self.finish(translator)
+    }
+}
+
+/// A run of control changes with an indexed address template.
+pub struct CcIndexed {
+    target: IndexTarget,
+    channel: Channel,
+    control: u8,
+}
+
+impl Mapping<CcIndexed> {
+    /// Complete the mapping by giving it an OSC address template with a range
+    /// placeholder.
+    pub fn osc(self, template: &str) -> TranslationSetBuilder {
+        let (low, high) = self.bounds();
+        let CcIndexed {
+            target,
+            channel,
+            control,
+        } = self.kind;
+        let translator =
+            ControlChangeIndexedTranslator::new(target, channel, control, low, high, template);
+        self.finish(translator)
+    }
+}
+
+/// One encoder's control changes in each encoder group.
+pub struct CcGroups {
+    groups: Vec<(Channel, u8)>,
+}
+
+impl Mapping<CcGroups> {
+    /// Complete the mapping by giving it an OSC address template with a range
+    /// placeholder for the group.
+    pub fn osc(mut self, template: &str) -> TranslationSetBuilder {
+        let (low, high) = self.bounds();
+        let groups = std::mem::take(&mut self.kind.groups);
+        let translator = ControlChangeGroupsTranslator::new(groups, low, high, template);
+        self.finish(translator)
+    }
+}
+
+/// A single note.
+pub struct Note {
+    channel: Channel,
+    key: MidiNote,
+    curve: VelocityCurve,
+}
+
+impl Mapping<Note> {
+    /// Translate velocity under this curve. By default the note is a
+    /// switch. See the `velocity` module.
+    pub fn velocity(mut self, curve: VelocityCurve) -> Self {
+        self.kind.curve = curve;
+        self
+    }
+
+    /// Complete the mapping by giving it an OSC address.
+    pub fn osc(self, address: &str) -> TranslationSetBuilder {
+        let Note {
+            channel,
+            key,
+            curve,
+        } = self.kind;
+        let translator = NoteTranslator::new(channel, key, curve, address);
+        self.finish(translator)
+    }
+}
+
+/// A run of consecutive control change numbers.
+pub struct Bank {
+    channel: Channel,
+    controls: RangeInclusive<u8>,
+}
+
+impl Mapping<Bank> {
+    /// Complete the mapping by giving it an OSC address prefix. Each control
+    /// in the bank is addressed by appending its position in the bank,
+    /// starting with 1, e.g. `/encoder/1`.
+    pub fn osc(self, prefix: &str) -> TranslationSetBuilder {
+        let (low, high) = self.bounds();
+        let channel = self.kind.channel;
+        let mut translators = vec![];
+        for (i, control) in self.kind.controls.clone().enumerate() {
+            let address = format!("{prefix}/{}", i + 1);
+            translators.push(self.wrap(ControlChangeRangeTranslator::new(
+                channel, control, low, high, &address,
+            )));
+        }
+        let info = self.info;
+        translators
+            .into_iter()
+            .fold(self.set, |set, t| set.add_with_info(t, info.clone()))
+    }
+}
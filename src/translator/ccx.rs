@@ -1,5 +1,7 @@
 //! `Translator` implementations for Control Change MIDI.
 
+use std::ops::RangeInclusive;
+
 use super::*;
 
 pub struct ControlChangeRangeTranslator {
@@ -45,21 +47,28 @@ impl Translator for ControlChangeRangeTranslator {
         None
     }
 
-    fn osc_to_midi(&self, addr_matcher: &Matcher, args: &[OscType]) -> Option<MidiMessage> {
-        if addr_matcher.match_address(&self.address) {
-            return Some(MidiMessage::ControlChange(
+    fn osc_to_midi(&self, addr_matcher: &Matcher, args: &[OscType]) -> Vec<MidiMessage> {
+        if !addr_matcher.match_address(&self.address) {
+            return vec![];
+        }
+        match args.first().cloned().and_then(OscType::float) {
+            Some(v) => vec![MidiMessage::ControlChange(
                 self.channel,
                 ControlEvent {
                     control: self.control,
-                    value: normalized_float_to_cv(
-                        OscType::float(args[0].clone()).unwrap(),
-                        self.low,
-                        self.high,
-                    ),
+                    value: normalized_float_to_cv(v, self.low, self.high),
                 },
-            ));
+            )],
+            None => vec![],
         }
-        None
+    }
+
+    fn coverage(&self) -> Vec<Coverage> {
+        vec![Coverage::new(
+            MidiFamily::ControlChange,
+            self.channel,
+            Numbers::single(self.control),
+        )]
     }
 }
 
@@ -129,16 +138,522 @@ impl Translator for ControlChangeBoolTranslator {
         None
     }
 
-    fn osc_to_midi(&self, addr_matcher: &Matcher, args: &[OscType]) -> Option<MidiMessage> {
-        if addr_matcher.match_address(&self.address) {
-            return Some(MidiMessage::ControlChange(
+    fn osc_to_midi(&self, addr_matcher: &Matcher, args: &[OscType]) -> Vec<MidiMessage> {
+        if !addr_matcher.match_address(&self.address) {
+            return vec![];
+        }
+        match args.first().cloned().and_then(OscType::float) {
+            Some(v) => vec![MidiMessage::ControlChange(
                 self.channel,
                 ControlEvent {
                     control: self.control,
-                    value: self.float_to_cv(OscType::float(args[0].clone()).unwrap()),
+                    value: self.float_to_cv(v),
                 },
-            ));
+            )],
+            None => vec![],
+        }
+    }
+
+    fn coverage(&self) -> Vec<Coverage> {
+        vec![Coverage::new(
+            MidiFamily::ControlChange,
+            self.channel,
+            Numbers::single(self.control),
+        )]
+    }
+}
+
+/// Translates control changes on a set of channels, and optionally on any
+/// control number, using an address template that carries the channel and
+/// control number in the OSC address.
+pub struct ControlChangeTemplateTranslator {
+    channels: Channels,
+    control: Option<u8>,
+    low: u8,
+    high: u8,
+    template: AddressTemplate,
+}
+
+impl ControlChangeTemplateTranslator {
+    /// Create a new translator. A `control` of `None` matches all control
+    /// numbers.
+    ///
+    /// The template must have a `{c}` placeholder if more than one channel is
+    /// matched, and an `{n}` placeholder if more than one control is matched.
+    pub fn new(
+        channels: Channels,
+        control: Option<u8>,
+        low: u8,
+        high: u8,
+        template: &str,
+    ) -> Result<Box<dyn Translator>> {
+        let template = AddressTemplate::new(template)?;
+        if channels.single().is_none() && !template.has_channel() {
+            return Err(format!("\"{template}\" needs a {{c}} placeholder for the channel").into());
+        }
+        if control.is_none() && !template.has_number() {
+            return Err(
+                format!("\"{template}\" needs an {{n}} placeholder for the control").into(),
+            );
+        }
+        Ok(Box::new(Self {
+            channels,
+            control,
+            low,
+            high,
+            template,
+        }))
+    }
+}
+
+impl Translator for ControlChangeTemplateTranslator {
+    fn midi_to_osc(&self, midi: &MidiMessage) -> Option<OscPacket> {
+        use MidiMessage::*;
+        if let ControlChange(ch, ControlEvent { control, value }) = midi {
+            if self.channels.contains(*ch) && self.control.is_none_or(|c| c == *control) {
+                let values = TemplateValues {
+                    channel: Some(*ch),
+                    number: Some(*control),
+                    index: None,
+                };
+                return Some(OscPacket::Message(OscMessage {
+                    addr: self.template.render(&values),
+                    args: vec![OscType::Float(cv_to_normalized_float(
+                        *value, self.low, self.high,
+                    ))],
+                }));
+            }
+        }
+        None
+    }
+
+    fn osc_to_midi(&self, addr_matcher: &Matcher, args: &[OscType]) -> Vec<MidiMessage> {
+        let value = match args.first().cloned().and_then(OscType::float) {
+            Some(v) => normalized_float_to_cv(v, self.low, self.high),
+            None => return vec![],
+        };
+        let controls = match self.control {
+            Some(c) => c..=c,
+            None => 0..=127,
+        };
+        self.template
+            .matches(addr_matcher, self.channels, controls)
+            .into_iter()
+            .filter_map(|v| {
+                let channel = v.channel.or_else(|| self.channels.single())?;
+                let control = v.number.or(self.control)?;
+                if !self.channels.contains(channel) || self.control.is_some_and(|c| c != control) {
+                    return None;
+                }
+                Some(MidiMessage::ControlChange(
+                    channel,
+                    ControlEvent { control, value },
+                ))
+            })
+            .collect()
+    }
+
+    fn coverage(&self) -> Vec<Coverage> {
+        let numbers = self.control.map_or(Numbers::ALL, Numbers::single);
+        vec![Coverage::new(
+            MidiFamily::ControlChange,
+            self.channels,
+            numbers,
+        )]
+    }
+}
+
+/// Specifies what the index in an address template like
+/// `/track/{1-16}/volume` selects.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexTarget {
+    /// The index selects the channel, counting up from the mapping's channel.
+    Channel,
+    /// The index selects the control number, counting up from the mapping's
+    /// control.
+    Control,
+}
+
+/// Translates a run of control changes, on consecutive channels or with
+/// consecutive control numbers, to an address template whose range
+/// placeholder holds the position in the run.
+pub struct ControlChangeIndexedTranslator {
+    target: IndexTarget,
+    channel: Channel,
+    control: u8,
+    low: u8,
+    high: u8,
+    indexes: RangeInclusive<u8>,
+    template: AddressTemplate,
+}
+
+impl ControlChangeIndexedTranslator {
+    /// Create a new translator. The template's lowest index corresponds to
+    /// `channel` and `control`.
+    pub fn new(
+        target: IndexTarget,
+        channel: Channel,
+        control: u8,
+        low: u8,
+        high: u8,
+        template: &str,
+    ) -> Result<Box<dyn Translator>> {
+        let template = AddressTemplate::new(template)?;
+        let indexes = template
+            .index_range()
+            .ok_or_else(|| format!("\"{template}\" needs a range placeholder, e.g. {{1-16}}"))?;
+        let count = indexes.end() - indexes.start();
+        let (first, max) = match target {
+            IndexTarget::Channel => (channel as u8, 15),
+            IndexTarget::Control => (control, 127),
+        };
+        if first.saturating_add(count) > max {
+            return Err(format!("\"{template}\" has more indexes than the MIDI range").into());
+        }
+        Ok(Box::new(Self {
+            target,
+            channel,
+            control,
+            low,
+            high,
+            indexes,
+            template,
+        }))
+    }
+
+    fn index_of(&self, ch: Channel, control: u8) -> Option<u8> {
+        let offset = match self.target {
+            IndexTarget::Channel if control == self.control => {
+                (ch as u8).checked_sub(self.channel as u8)?
+            }
+            IndexTarget::Control if ch == self.channel => control.checked_sub(self.control)?,
+            _ => return None,
+        };
+        let index = self.indexes.start().checked_add(offset)?;
+        if self.indexes.contains(&index) {
+            Some(index)
+        } else {
+            None
+        }
+    }
+}
+
+impl Translator for ControlChangeIndexedTranslator {
+    fn midi_to_osc(&self, midi: &MidiMessage) -> Option<OscPacket> {
+        use MidiMessage::*;
+        if let ControlChange(ch, ControlEvent { control, value }) = midi {
+            let index = self.index_of(*ch, *control)?;
+            let values = TemplateValues {
+                index: Some(index),
+                ..Default::default()
+            };
+            return Some(OscPacket::Message(OscMessage {
+                addr: self.template.render(&values),
+                args: vec![OscType::Float(cv_to_normalized_float(
+                    *value, self.low, self.high,
+                ))],
+            }));
         }
         None
     }
+
+    fn osc_to_midi(&self, addr_matcher: &Matcher, args: &[OscType]) -> Vec<MidiMessage> {
+        let value = match args.first().cloned().and_then(OscType::float) {
+            Some(v) => normalized_float_to_cv(v, self.low, self.high),
+            None => return vec![],
+        };
+        self.template
+            .matches(addr_matcher, Channels::from(self.channel), 0..=0)
+            .into_iter()
+            .filter_map(|v| {
+                let offset = v.index? - self.indexes.start();
+                let (channel, control) = match self.target {
+                    IndexTarget::Channel => {
+                        (Channel::from(self.channel as u8 + offset), self.control)
+                    }
+                    IndexTarget::Control => (self.channel, self.control + offset),
+                };
+                Some(MidiMessage::ControlChange(
+                    channel,
+                    ControlEvent { control, value },
+                ))
+            })
+            .collect()
+    }
+
+    fn coverage(&self) -> Vec<Coverage> {
+        let count = self.indexes.end() - self.indexes.start();
+        let coverage = match self.target {
+            IndexTarget::Channel => Coverage::new(
+                MidiFamily::ControlChange,
+                (self.channel as u8..=self.channel as u8 + count)
+                    .map(Channel::from)
+                    .collect::<Channels>(),
+                Numbers::single(self.control),
+            ),
+            IndexTarget::Control => Coverage::new(
+                MidiFamily::ControlChange,
+                self.channel,
+                Numbers::range(self.control..=self.control + count),
+            ),
+        };
+        vec![coverage]
+    }
+}
+
+/// Translates the control changes that one encoder sends in each of a
+/// B-Control's encoder groups to a single address template, whose range
+/// placeholder holds the group. For example, an encoder that sends control 1
+/// in group 1 and control 9 in group 2 can be addressed as
+/// `/encoder/1/group/{1-2}`.
+///
+/// The groups' control changes can differ in channel, control number or
+/// both, as they're programmed on the device.
+pub struct ControlChangeGroupsTranslator {
+    groups: Vec<(Channel, u8)>,
+    low: u8,
+    high: u8,
+    indexes: RangeInclusive<u8>,
+    template: AddressTemplate,
+}
+
+impl ControlChangeGroupsTranslator {
+    /// Create a new translator. The template's lowest index corresponds to
+    /// the first group.
+    pub fn new(
+        groups: Vec<(Channel, u8)>,
+        low: u8,
+        high: u8,
+        template: &str,
+    ) -> Result<Box<dyn Translator>> {
+        let template = AddressTemplate::new(template)?;
+        let indexes = template
+            .index_range()
+            .ok_or_else(|| format!("\"{template}\" needs a range placeholder, e.g. {{1-4}}"))?;
+        if groups.len() != (indexes.end() - indexes.start()) as usize + 1 {
+            return Err(format!(
+                "\"{template}\" has {} indexes for {} groups",
+                indexes.end() - indexes.start() + 1,
+                groups.len()
+            )
+            .into());
+        }
+        Ok(Box::new(Self {
+            groups,
+            low,
+            high,
+            indexes,
+            template,
+        }))
+    }
+}
+
+impl Translator for ControlChangeGroupsTranslator {
+    fn midi_to_osc(&self, midi: &MidiMessage) -> Option<OscPacket> {
+        use MidiMessage::*;
+        if let ControlChange(ch, ControlEvent { control, value }) = midi {
+            let group = self
+                .groups
+                .iter()
+                .position(|(c, n)| c == ch && n == control)?;
+            let values = TemplateValues {
+                index: Some(self.indexes.start() + group as u8),
+                ..Default::default()
+            };
+            return Some(OscPacket::Message(OscMessage {
+                addr: self.template.render(&values),
+                args: vec![OscType::Float(cv_to_normalized_float(
+                    *value, self.low, self.high,
+                ))],
+            }));
+        }
+        None
+    }
+
+    fn osc_to_midi(&self, addr_matcher: &Matcher, args: &[OscType]) -> Vec<MidiMessage> {
+        let value = match args.first().cloned().and_then(OscType::float) {
+            Some(v) => normalized_float_to_cv(v, self.low, self.high),
+            None => return vec![],
+        };
+        self.template
+            .matches(addr_matcher, Channels::ANY, 0..=0)
+            .into_iter()
+            .filter_map(|v| {
+                let (channel, control) = self.groups[(v.index? - self.indexes.start()) as usize];
+                Some(MidiMessage::ControlChange(
+                    channel,
+                    ControlEvent { control, value },
+                ))
+            })
+            .collect()
+    }
+
+    fn coverage(&self) -> Vec<Coverage> {
+        self.groups
+            .iter()
+            .map(|(channel, control)| {
+                Coverage::new(
+                    MidiFamily::ControlChange,
+                    *channel,
+                    Numbers::single(*control),
+                )
+            })
+            .collect()
+    }
+}
+
+/// An OSC value that names one state of a control.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StateKey {
+    /// An integer, sent as an OSC int. Floats with an integral value match it
+    /// too, since hosts often send numbers as floats.
+    Int(i32),
+    /// A name, sent as an OSC string.
+    Name(String),
+}
+
+impl StateKey {
+    fn matches(&self, arg: &OscType) -> bool {
+        match (self, arg) {
+            (StateKey::Int(i), OscType::Float(f)) => *f == *i as f32,
+            (StateKey::Int(i), OscType::Int(j)) => i == j,
+            (StateKey::Name(n), OscType::String(s)) => n == s,
+            _ => false,
+        }
+    }
+
+    fn to_osc(&self) -> OscType {
+        match self {
+            StateKey::Int(i) => OscType::Int(*i),
+            StateKey::Name(n) => OscType::String(n.clone()),
+        }
+    }
+}
+
+/// Translates between a control change and an enumeration of OSC values, each
+/// sent as a particular control value. This drives controls with several
+/// visual states, such as B-Control button LEDs, from host state like "mute"
+/// or "record armed".
+///
+/// Control values that aren't in the enumeration aren't translated to OSC.
+pub struct ControlChangeStatesTranslator {
+    channel: Channel,
+    control: u8,
+    states: Vec<(StateKey, u8)>,
+    address: OscAddress,
+}
+
+impl ControlChangeStatesTranslator {
+    pub fn new(
+        channel: Channel,
+        control: u8,
+        states: Vec<(StateKey, u8)>,
+        address: &str,
+    ) -> Result<Box<dyn Translator>> {
+        if states.is_empty() {
+            return Err("a states mapping needs at least one state".into());
+        }
+        let address = OscAddress::new(address.to_string())?;
+        Ok(Box::new(Self {
+            channel,
+            control,
+            states,
+            address,
+        }))
+    }
+}
+
+impl Translator for ControlChangeStatesTranslator {
+    fn midi_to_osc(&self, midi: &MidiMessage) -> Option<OscPacket> {
+        use MidiMessage::*;
+        if let ControlChange(ch, ControlEvent { control, value }) = midi {
+            if (&self.channel == ch) && (self.control == *control) {
+                let (key, _) = self.states.iter().find(|(_, v)| v == value)?;
+                return Some(OscPacket::Message(OscMessage {
+                    addr: self.address.to_string(),
+                    args: vec![key.to_osc()],
+                }));
+            }
+        }
+        None
+    }
+
+    fn osc_to_midi(&self, addr_matcher: &Matcher, args: &[OscType]) -> Vec<MidiMessage> {
+        if !addr_matcher.match_address(&self.address) {
+            return vec![];
+        }
+        let value = match args.first() {
+            Some(arg) => match self.states.iter().find(|(k, _)| k.matches(arg)) {
+                Some((_, value)) => *value,
+                None => return vec![],
+            },
+            None => return vec![],
+        };
+        vec![MidiMessage::ControlChange(
+            self.channel,
+            ControlEvent {
+                control: self.control,
+                value,
+            },
+        )]
+    }
+
+    fn coverage(&self) -> Vec<Coverage> {
+        vec![Coverage::new(
+            MidiFamily::ControlChange,
+            self.channel,
+            Numbers::single(self.control),
+        )]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cc(control: u8, value: u8) -> MidiMessage {
+        MidiMessage::ControlChange(Channel::Ch1, ControlEvent { control, value })
+    }
+
+    fn osc(addr: &str, args: Vec<OscType>) -> OscMessage {
+        OscMessage {
+            addr: addr.to_string(),
+            args,
+        }
+    }
+
+    fn set(coercion: Coercion) -> ServerTranslationSet {
+        ServerTranslationSet::new(vec![
+            ControlChangeRangeTranslator::new(Channel::Ch1, 7, 0, 127, "/vol").unwrap(),
+            ControlChangeBoolTranslator::new(Channel::Ch1, 8, 0, 127, "/mute").unwrap(),
+        ])
+        .with_coercion(coercion)
+    }
+
+    #[test]
+    fn strict_set_ignores_other_types() {
+        let set = set(Coercion::Strict);
+        for addr in ["/vol", "/mute"] {
+            for args in [
+                vec![OscType::Int(1)],
+                vec![OscType::String("1".to_string())],
+                vec![],
+            ] {
+                let om = osc(addr, args);
+                assert!(set.osc_msg_to_slewed_midi(&om).is_empty(), "{om:?}");
+                assert!(!set.handles_osc(&om), "{om:?}");
+            }
+        }
+        let om = osc("/vol", vec![OscType::Float(1.0)]);
+        assert_eq!(set.osc_msg_to_slewed_midi(&om)[0].0, cc(7, 127));
+    }
+
+    #[test]
+    fn permissive_set_converts_ints() {
+        let set = set(Coercion::Permissive);
+        let om = osc("/vol", vec![OscType::Int(1)]);
+        assert_eq!(set.osc_msg_to_slewed_midi(&om)[0].0, cc(7, 127));
+        let om = osc("/mute", vec![OscType::Int(1)]);
+        assert_eq!(set.osc_msg_to_slewed_midi(&om)[0].0, cc(8, 127));
+    }
 }
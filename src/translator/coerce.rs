@@ -0,0 +1,96 @@
+//! Coercion of OSC arguments received from senders.
+//!
+//! Translators take their values from float arguments. Not every OSC sender
+//! sends floats: some send integers or doubles, and some send numbers as
+//! strings. The coercion policy decides what happens to such arguments:
+//!
+//! Policy      arguments given to translators
+//! permissive  converted to floats where they can be; see `coerce_args`
+//! strict      as sent, so that mismatched types are ignored
+//!
+//! Under either policy, a mapping accepts the type of argument it sends
+//! itself; see the `output` module. The policy is set for a whole set of
+//! mappings, and can be overridden by individual mappings.
+
+use std::str::FromStr;
+
+use super::*;
+
+/// How OSC arguments of unexpected types are treated.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Coercion {
+    /// Arguments are converted to floats where they can be.
+    #[default]
+    Permissive,
+    /// Arguments are given to translators as they were sent.
+    Strict,
+}
+
+impl FromStr for Coercion {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "permissive" => Ok(Coercion::Permissive),
+            "strict" => Ok(Coercion::Strict),
+            _ => Err(format!("unknown coercion policy \"{s}\"")),
+        }
+    }
+}
+
+/// Wraps a translator, overriding the coercion policy of its set.
+pub struct Coerced {
+    inner: Box<dyn Translator>,
+    coercion: Coercion,
+}
+
+impl Coerced {
+    /// Wrap a translator.
+    pub fn wrap(inner: Box<dyn Translator>, coercion: Coercion) -> Box<dyn Translator> {
+        Box::new(Coerced { inner, coercion })
+    }
+}
+
+impl Translator for Coerced {
+    fn midi_to_osc(&self, midi: &MidiMessage) -> Option<OscPacket> {
+        self.inner.midi_to_osc(midi)
+    }
+
+    fn osc_to_midi(&self, addr_matcher: &Matcher, args: &[OscType]) -> Vec<MidiMessage> {
+        self.inner.osc_to_midi(addr_matcher, args)
+    }
+
+    fn coverage(&self) -> Vec<Coverage> {
+        self.inner.coverage()
+    }
+
+    fn slew_rate(&self) -> Option<f32> {
+        self.inner.slew_rate()
+    }
+
+    fn coercion(&self) -> Option<Coercion> {
+        Some(self.coercion)
+    }
+}
+
+/// Converts incoming arguments under a coercion policy. A permissive policy
+/// normalizes the types that mappings send, as `normalize_args` does, and
+/// also converts doubles, 64-bit integers, and strings that parse as numbers.
+/// Strings that aren't numbers are left alone, since they can select states.
+pub fn coerce_args(args: &[OscType], coercion: Coercion) -> Vec<OscType> {
+    match coercion {
+        Coercion::Strict => args.to_vec(),
+        Coercion::Permissive => normalize_args(args)
+            .into_iter()
+            .map(|a| match a {
+                OscType::Double(d) => OscType::Float(d as f32),
+                OscType::Long(l) => OscType::Float(l as f32),
+                OscType::String(s) => match s.trim().parse::<f32>() {
+                    Ok(v) => OscType::Float(v),
+                    Err(_) => OscType::String(s),
+                },
+                other => other,
+            })
+            .collect(),
+    }
+}
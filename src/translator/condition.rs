@@ -0,0 +1,220 @@
+//! Mappings that translate only under conditions on the bridge's state.
+//!
+//! Besides the active encoder group (see the `group` module), the state has
+//! an active bank and named flags, which OSC clients set:
+//!
+//! ```text
+//! OSC address            arguments
+//! /bcr2kosc/bank         bank, counting from 1
+//! /bcr2kosc/flag/NAME    1 or true to set the flag, 0 or false to clear it
+//! ```
+//!
+//! Bank 1 is active, and no flags are set, until a client says otherwise.
+//! A mapping with conditions only translates while they all hold, in either
+//! direction, so several mappings of the same control can act as layers. For
+//! example, a host can set a `shift` flag while a key is held, and each
+//! control can have one mapping for `shift` and one for `!shift`.
+//!
+//! Conditions are checked as each message is translated. Like the group, the
+//! state belongs to one set of mappings, and starts over when they're
+//! reloaded.
+
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::*;
+
+/// The address that sets the active bank.
+pub const BANK_ADDR: &str = "/bcr2kosc/bank";
+
+/// The prefix of addresses that set flags.
+pub const FLAG_PREFIX: &str = "/bcr2kosc/flag/";
+
+/// The state that conditions are checked against, shared by a set's
+/// mappings.
+#[derive(Clone, Debug)]
+pub struct BridgeState {
+    group: ActiveGroup,
+    bank: Arc<AtomicU8>,
+    flags: Arc<Mutex<HashSet<String>>>,
+}
+
+impl Default for BridgeState {
+    fn default() -> Self {
+        BridgeState {
+            group: ActiveGroup::default(),
+            bank: Arc::new(AtomicU8::new(1)),
+            flags: Arc::default(),
+        }
+    }
+}
+
+impl BridgeState {
+    /// Returns the active encoder group.
+    pub fn group(&self) -> &ActiveGroup {
+        &self.group
+    }
+
+    /// Returns the active bank, counting from 1.
+    pub fn bank(&self) -> u8 {
+        self.bank.load(Ordering::Relaxed)
+    }
+
+    /// Returns true if the named flag is set.
+    pub fn flag(&self, name: &str) -> bool {
+        self.flags.lock().unwrap().contains(name)
+    }
+
+    /// Sets or clears the named flag.
+    pub fn set_flag(&self, name: &str, set: bool) {
+        let mut flags = self.flags.lock().unwrap();
+        if set {
+            flags.insert(name.to_string());
+        } else {
+            flags.remove(name);
+        }
+    }
+
+    /// Applies a message that sets the state. Returns false if the message
+    /// isn't one, or its argument is missing or out of range.
+    pub fn apply(&self, msg: &OscMessage) -> bool {
+        let value = match msg.args.first() {
+            Some(OscType::Int(i)) => *i,
+            Some(OscType::Float(f)) => f.round() as i32,
+            Some(OscType::Bool(b)) => *b as i32,
+            _ => return false,
+        };
+        if msg.addr == BANK_ADDR {
+            match u8::try_from(value) {
+                Ok(bank) if bank > 0 => {
+                    self.bank.store(bank, Ordering::Relaxed);
+                    true
+                }
+                _ => false,
+            }
+        } else if let Some(name) = msg.addr.strip_prefix(FLAG_PREFIX) {
+            self.set_flag(name, value != 0);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// A condition on the bridge's state.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Condition {
+    /// The encoder group is active.
+    Group(u8),
+    /// The bank is active.
+    Bank(u8),
+    /// The named flag is set, or if false, isn't.
+    Flag(String, bool),
+}
+
+impl Condition {
+    fn holds(&self, state: &BridgeState) -> bool {
+        match self {
+            Condition::Group(g) => state.group.get() == *g,
+            Condition::Bank(b) => state.bank() == *b,
+            Condition::Flag(name, set) => state.flag(name) == *set,
+        }
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    /// Parses `group:N`, `bank:N`, `NAME` or `!NAME`.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let number = |n: &str| match n.parse::<u8>() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(format!("invalid number \"{n}\" in condition \"{s}\"")),
+        };
+        if let Some(n) = s.strip_prefix("group:") {
+            Ok(Condition::Group(number(n)?))
+        } else if let Some(n) = s.strip_prefix("bank:") {
+            Ok(Condition::Bank(number(n)?))
+        } else {
+            let (name, set) = match s.strip_prefix('!') {
+                Some(name) => (name, false),
+                None => (s, true),
+            };
+            let valid = !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+            if valid {
+                Ok(Condition::Flag(name.to_string(), set))
+            } else {
+                Err(format!("invalid condition \"{s}\""))
+            }
+        }
+    }
+}
+
+/// Wraps a translator, translating only while its conditions hold.
+pub struct Conditional {
+    inner: Box<dyn Translator>,
+    conditions: Vec<Condition>,
+    state: BridgeState,
+}
+
+impl Conditional {
+    /// Wrap a translator.
+    pub fn wrap(
+        inner: Box<dyn Translator>,
+        conditions: Vec<Condition>,
+        state: BridgeState,
+    ) -> Box<dyn Translator> {
+        Box::new(Conditional {
+            inner,
+            conditions,
+            state,
+        })
+    }
+
+    fn holds(&self) -> bool {
+        self.conditions.iter().all(|c| c.holds(&self.state))
+    }
+}
+
+impl Translator for Conditional {
+    fn midi_to_osc(&self, midi: &MidiMessage) -> Option<OscPacket> {
+        if self.holds() {
+            self.inner.midi_to_osc(midi)
+        } else {
+            None
+        }
+    }
+
+    fn osc_to_midi(&self, addr_matcher: &Matcher, args: &[OscType]) -> Vec<MidiMessage> {
+        if self.holds() {
+            self.inner.osc_to_midi(addr_matcher, args)
+        } else {
+            vec![]
+        }
+    }
+
+    fn coverage(&self) -> Vec<Coverage> {
+        self.inner.coverage()
+    }
+
+    fn slew_rate(&self) -> Option<f32> {
+        self.inner.slew_rate()
+    }
+
+    fn coercion(&self) -> Option<Coercion> {
+        self.inner.coercion()
+    }
+
+    fn handles_midi(&self, midi: &MidiMessage) -> bool {
+        self.holds() && self.inner.handles_midi(midi)
+    }
+
+    fn handles_osc(&self, addr_matcher: &Matcher, args: &[OscType]) -> bool {
+        self.holds() && self.inner.handles_osc(addr_matcher, args)
+    }
+}
@@ -0,0 +1,211 @@
+//! Descriptions of the MIDI messages that translators handle.
+//!
+//! These help explain why a message isn't being translated: either no
+//! translator implementation handles its kind of message, or no mapping
+//! covers its channel and number. `mapping_report` lists what each mapping
+//! covers, with its description, unit, scale and color, so that a large set
+//! of mappings can be followed.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::ops::RangeInclusive;
+
+use super::*;
+
+/// Kinds of MIDI message handled by translators.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MidiFamily {
+    /// Control Change messages, identified by control number.
+    ControlChange,
+    /// Note On and Note Off messages, identified by key.
+    Note,
+}
+
+impl MidiFamily {
+    /// The families that translator implementations exist for.
+    pub const HANDLED: [MidiFamily; 2] = [MidiFamily::ControlChange, MidiFamily::Note];
+
+    /// Kinds of MIDI message that no translator implementation handles.
+    pub const UNHANDLED: [&'static str; 5] = [
+        "Program Change",
+        "Channel Pressure",
+        "Poly Key Pressure",
+        "Pitch Bend",
+        "SysEx",
+    ];
+}
+
+impl Display for MidiFamily {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MidiFamily::ControlChange => "Control Change",
+            MidiFamily::Note => "Note On/Off",
+        }
+        .fmt(f)
+    }
+}
+
+/// A set of MIDI control or key numbers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Numbers(u128);
+
+impl Numbers {
+    /// All numbers, 0 through 127.
+    pub const ALL: Numbers = Numbers(u128::MAX);
+
+    /// A set of consecutive numbers.
+    pub fn range(range: RangeInclusive<u8>) -> Self {
+        range
+            .filter(|n| *n < 128)
+            .fold(Numbers(0), |s, n| s.with(n))
+    }
+
+    /// A set holding one number.
+    pub fn single(n: u8) -> Self {
+        Numbers(0).with(n)
+    }
+
+    /// The lowest number in the set, if any.
+    pub fn first(&self) -> Option<u8> {
+        (self.0 != 0).then(|| self.0.trailing_zeros() as u8)
+    }
+
+    fn with(self, n: u8) -> Self {
+        Numbers(self.0 | 1 << (n & 0x7f))
+    }
+
+    fn union(self, other: Numbers) -> Self {
+        Numbers(self.0 | other.0)
+    }
+}
+
+impl Display for Numbers {
+    /// Lists the numbers as runs, e.g. "0-7, 9, 65".
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut runs = vec![];
+        let mut n = 0u32;
+        while n < 128 {
+            if self.0 & 1 << n == 0 {
+                n += 1;
+                continue;
+            }
+            let start = n;
+            while n < 128 && self.0 & 1 << n != 0 {
+                n += 1;
+            }
+            runs.push(match n - 1 - start {
+                0 => format!("{start}"),
+                _ => format!("{start}-{}", n - 1),
+            });
+        }
+        runs.join(", ").fmt(f)
+    }
+}
+
+/// MIDI messages handled by a translator.
+#[derive(Clone, Copy, Debug)]
+pub struct Coverage {
+    /// The kind of message.
+    pub family: MidiFamily,
+    /// The channels on which messages are handled.
+    pub channels: Channels,
+    /// The control or key numbers handled.
+    pub numbers: Numbers,
+}
+
+impl Coverage {
+    /// Describes a single channel and a set of numbers.
+    pub fn new(family: MidiFamily, channels: impl Into<Channels>, numbers: Numbers) -> Self {
+        Coverage {
+            family,
+            channels: channels.into(),
+            numbers,
+        }
+    }
+}
+
+impl Display for Coverage {
+    /// Describes the coverage, e.g. "Control Change, channel 1: 0-7".
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let channels: Vec<String> = self
+            .channels
+            .iter()
+            .map(|ch| (ch as u8 + 1).to_string())
+            .collect();
+        let channels = match channels.len() {
+            1 => format!("channel {}", channels[0]),
+            16 => "any channel".to_string(),
+            _ => format!("channels {}", channels.join(", ")),
+        };
+        write!(f, "{}, {channels}: {}", self.family, self.numbers)
+    }
+}
+
+impl ServerTranslationSet {
+    /// Describes the MIDI messages handled by each translator that describes
+    /// itself.
+    pub fn coverage(&self) -> Vec<Coverage> {
+        self.translators.iter().flat_map(|t| t.coverage()).collect()
+    }
+
+    /// Describes the MIDI messages that translators can handle, and those
+    /// that this set's mappings cover.
+    pub fn coverage_report(&self) -> Vec<String> {
+        let mut report = vec!["Translators handle these MIDI messages:".to_string()];
+        for family in MidiFamily::HANDLED {
+            report.push(format!("  {family}"));
+        }
+        report.push(format!(
+            "They don't handle {}.",
+            MidiFamily::UNHANDLED.join(", ")
+        ));
+
+        let mut covered: BTreeMap<(MidiFamily, u8), Numbers> = BTreeMap::new();
+        let mut undescribed = 0;
+        for t in &self.translators {
+            let coverage = t.coverage();
+            if coverage.is_empty() {
+                undescribed += 1;
+            }
+            for c in coverage {
+                for ch in c.channels.iter() {
+                    let numbers = covered.entry((c.family, ch as u8)).or_default();
+                    *numbers = numbers.union(c.numbers);
+                }
+            }
+        }
+        report.push(String::new());
+        report.push("The mappings translate:".to_string());
+        for ((family, ch), numbers) in covered {
+            report.push(format!("  {family}, channel {}: {numbers}", ch + 1));
+        }
+        if undescribed > 0 {
+            report.push(format!(
+                "  {undescribed} translators that don't describe their coverage"
+            ));
+        }
+        report
+    }
+    /// Lists the mappings in order, each with its number, its description,
+    /// if it has one, the MIDI messages it covers, and its unit, scale and
+    /// color, if it has them.
+    pub fn mapping_report(&self) -> Vec<String> {
+        self.translators
+            .iter()
+            .enumerate()
+            .map(|(i, t)| {
+                let coverage: Vec<String> = t.coverage().iter().map(|c| c.to_string()).collect();
+                let coverage = if coverage.is_empty() {
+                    "coverage not described".to_string()
+                } else {
+                    coverage.join("; ")
+                };
+                let label = self.mapping_label(i + 1);
+                match self.info(i + 1).filter(|info| !info.is_plain()) {
+                    Some(info) => format!("{label}: {coverage} ({info})"),
+                    None => format!("{label}: {coverage}"),
+                }
+            })
+            .collect()
+    }
+}
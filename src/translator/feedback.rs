@@ -0,0 +1,63 @@
+//! Feedback sent to a different OSC address than the one OSC is received on.
+//!
+//! Some hosts echo whatever arrives at a control's address back to it, or
+//! expect feedback at an address of its own, e.g. receiving on `/fader1` and
+//! displaying `/fader1/value`. A mapping with a feedback address sends its
+//! MIDI translations there instead.
+//!
+//! In a feedback address, `{addr}` stands for the address the mapping would
+//! otherwise have used. This allows mappings with several addresses, such as
+//! banks and templates, to give each its own feedback address, e.g.
+//! `{addr}/value`.
+
+use rosc::address::verify_address;
+
+use super::*;
+
+/// Wraps a translator, sending its OSC output to a feedback address.
+pub struct Feedback {
+    inner: Box<dyn Translator>,
+    address: String,
+}
+
+impl Feedback {
+    /// Wrap a translator.
+    pub fn wrap(inner: Box<dyn Translator>, address: &str) -> Result<Box<dyn Translator>> {
+        verify_address(&address.replace("{addr}", "/addr"))?;
+        Ok(Box::new(Feedback {
+            inner,
+            address: address.to_string(),
+        }))
+    }
+
+    fn readdress(&self, packet: OscPacket) -> OscPacket {
+        match packet {
+            OscPacket::Message(m) => OscPacket::Message(OscMessage {
+                addr: self.address.replace("{addr}", &m.addr),
+                args: m.args,
+            }),
+            OscPacket::Bundle(b) => OscPacket::Bundle(OscBundle {
+                timetag: b.timetag,
+                content: b.content.into_iter().map(|p| self.readdress(p)).collect(),
+            }),
+        }
+    }
+}
+
+impl Translator for Feedback {
+    fn midi_to_osc(&self, midi: &MidiMessage) -> Option<OscPacket> {
+        self.inner.midi_to_osc(midi).map(|p| self.readdress(p))
+    }
+
+    fn osc_to_midi(&self, addr_matcher: &Matcher, args: &[OscType]) -> Vec<MidiMessage> {
+        self.inner.osc_to_midi(addr_matcher, args)
+    }
+
+    fn coverage(&self) -> Vec<Coverage> {
+        self.inner.coverage()
+    }
+
+    fn slew_rate(&self) -> Option<f32> {
+        self.inner.slew_rate()
+    }
+}
@@ -0,0 +1,328 @@
+//! Long presses and double presses of buttons.
+//!
+//! A mapping with gestures tells presses of a button apart by their timing,
+//! so that one button can do several things. A control value of 0, or a
+//! note off, releases the button; any other value presses it. Besides the
+//! usual OSC for each press and release:
+//!
+//! * A button held for at least the long-press time is sent to
+//!   `ADDRESS/long` when it's released.
+//! * A button pressed again within the double-press time of the last press
+//!   is sent to `ADDRESS/double` on the second press. A third press starts
+//!   over.
+//!
+//! `ADDRESS` is the address the press was sent to, and the gesture has the
+//! arguments of the press. Gestures are only recognized once a press or
+//! release arrives, so a long press isn't sent while the button is held.
+//! OSC to the gesture addresses isn't translated to MIDI.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::*;
+
+/// The state of one button, by the address its presses are sent to.
+#[derive(Default)]
+struct Button {
+    down_since: Option<Instant>,
+    last_press: Option<Instant>,
+    args: Vec<OscType>,
+}
+
+/// Wraps a translator, adding OSC for long and double presses.
+pub struct Gestured {
+    inner: Box<dyn Translator>,
+    long_press: Option<Duration>,
+    double_press: Option<Duration>,
+    buttons: Mutex<HashMap<String, Button>>,
+}
+
+impl Gestured {
+    /// Wrap a translator. A gesture without a time isn't recognized.
+    pub fn wrap(
+        inner: Box<dyn Translator>,
+        long_press: Option<Duration>,
+        double_press: Option<Duration>,
+    ) -> Box<dyn Translator> {
+        Box::new(Gestured {
+            inner,
+            long_press,
+            double_press,
+            buttons: Mutex::default(),
+        })
+    }
+
+    /// Returns the gestures completed by a press or release of the buttons
+    /// addressed in a packet.
+    fn gestures(&self, packet: &OscPacket, pressed: bool, now: Instant) -> Vec<OscPacket> {
+        let mut gestures = vec![];
+        let mut buttons = self.buttons.lock().unwrap();
+        for msg in messages(packet) {
+            let button = buttons.entry(msg.addr.clone()).or_default();
+            if pressed {
+                let double = self
+                    .double_press
+                    .zip(button.last_press)
+                    .is_some_and(|(within, last)| now - last <= within);
+                if double {
+                    gestures.push(gesture(&msg.addr, "double", msg.args.clone()));
+                    button.last_press = None;
+                } else {
+                    button.last_press = Some(now);
+                }
+                button.down_since = Some(now);
+                button.args.clone_from(&msg.args);
+            } else if let Some(down) = button.down_since.take() {
+                if self.long_press.is_some_and(|long| now - down >= long) {
+                    gestures.push(gesture(&msg.addr, "long", button.args.clone()));
+                }
+            }
+        }
+        gestures
+    }
+}
+
+/// Returns true for a press of a button, false for a release, and `None` for
+/// other messages.
+fn is_press(midi: &MidiMessage) -> Option<bool> {
+    match midi {
+        MidiMessage::ControlChange(_, ControlEvent { value, .. }) => Some(*value > 0),
+        MidiMessage::NoteOn(_, KeyEvent { value, .. }) => Some(*value > 0),
+        MidiMessage::NoteOff(..) => Some(false),
+        _ => None,
+    }
+}
+
+fn messages(packet: &OscPacket) -> Vec<&OscMessage> {
+    match packet {
+        OscPacket::Message(m) => vec![m],
+        OscPacket::Bundle(b) => b.content.iter().flat_map(messages).collect(),
+    }
+}
+
+fn gesture(addr: &str, name: &str, args: Vec<OscType>) -> OscPacket {
+    OscPacket::Message(OscMessage {
+        addr: format!("{addr}/{name}"),
+        args,
+    })
+}
+
+impl Translator for Gestured {
+    fn midi_to_osc(&self, midi: &MidiMessage) -> Option<OscPacket> {
+        let packet = self.inner.midi_to_osc(midi)?;
+        let gestures = match is_press(midi) {
+            Some(pressed) => self.gestures(&packet, pressed, Instant::now()),
+            None => vec![],
+        };
+        if gestures.is_empty() {
+            return Some(packet);
+        }
+        let mut content = vec![packet];
+        content.extend(gestures);
+        Some(OscPacket::Bundle(OscBundle {
+            timetag: OscTime {
+                seconds: 0,
+                fractional: 0,
+            },
+            content,
+        }))
+    }
+
+    fn osc_to_midi(&self, addr_matcher: &Matcher, args: &[OscType]) -> Vec<MidiMessage> {
+        self.inner.osc_to_midi(addr_matcher, args)
+    }
+
+    fn coverage(&self) -> Vec<Coverage> {
+        self.inner.coverage()
+    }
+
+    fn slew_rate(&self) -> Option<f32> {
+        self.inner.slew_rate()
+    }
+
+    fn coercion(&self) -> Option<Coercion> {
+        self.inner.coercion()
+    }
+
+    fn handles_midi(&self, midi: &MidiMessage) -> bool {
+        self.inner.handles_midi(midi)
+    }
+
+    fn handles_osc(&self, addr_matcher: &Matcher, args: &[OscType]) -> bool {
+        self.inner.handles_osc(addr_matcher, args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LONG: Duration = Duration::from_millis(500);
+    const DOUBLE: Duration = Duration::from_millis(300);
+
+    fn button() -> Box<dyn Translator> {
+        ControlChangeRangeTranslator::new(Channel::Ch1, 20, 0, 127, "/button").unwrap()
+    }
+
+    fn gestured(long_press: Option<Duration>, double_press: Option<Duration>) -> Gestured {
+        Gestured {
+            inner: button(),
+            long_press,
+            double_press,
+            buttons: Mutex::default(),
+        }
+    }
+
+    fn press(addr: &str, value: f32) -> OscPacket {
+        OscPacket::Message(OscMessage {
+            addr: addr.to_string(),
+            args: vec![OscType::Float(value)],
+        })
+    }
+
+    /// Presses (`true`) and releases of `/button` at times in milliseconds,
+    /// and the gestures each completes.
+    fn timeline(g: &Gestured, events: &[(u64, bool)]) -> Vec<Vec<OscPacket>> {
+        let start = Instant::now();
+        events
+            .iter()
+            .map(|(ms, pressed)| {
+                let value = if *pressed { 1.0 } else { 0.0 };
+                let now = start + Duration::from_millis(*ms);
+                g.gestures(&press("/button", value), *pressed, now)
+            })
+            .collect()
+    }
+
+    fn long() -> Vec<OscPacket> {
+        vec![press("/button/long", 1.0)]
+    }
+
+    fn double() -> Vec<OscPacket> {
+        vec![press("/button/double", 1.0)]
+    }
+
+    #[test]
+    fn short_press() {
+        let g = gestured(Some(LONG), Some(DOUBLE));
+        assert_eq!(
+            timeline(&g, &[(0, true), (100, false)]),
+            vec![vec![], vec![]]
+        );
+    }
+
+    #[test]
+    fn long_press() {
+        let g = gestured(Some(LONG), Some(DOUBLE));
+        assert_eq!(
+            timeline(&g, &[(0, true), (499, false), (1000, true), (1500, false)]),
+            vec![vec![], vec![], vec![], long()]
+        );
+    }
+
+    #[test]
+    fn long_press_has_the_press_arguments() {
+        let g = gestured(Some(LONG), None);
+        let start = Instant::now();
+        assert!(g.gestures(&press("/button", 0.5), true, start).is_empty());
+        assert_eq!(
+            g.gestures(&press("/button", 0.0), false, start + LONG),
+            vec![press("/button/long", 0.5)]
+        );
+    }
+
+    #[test]
+    fn release_without_press() {
+        let g = gestured(Some(LONG), Some(DOUBLE));
+        assert_eq!(timeline(&g, &[(1000, false)]), vec![vec![]]);
+        // A second release doesn't repeat a long press.
+        assert_eq!(
+            timeline(&g, &[(0, true), (600, false), (700, false)]),
+            vec![vec![], long(), vec![]]
+        );
+    }
+
+    #[test]
+    fn double_press() {
+        let g = gestured(Some(LONG), Some(DOUBLE));
+        assert_eq!(
+            timeline(&g, &[(0, true), (50, false), (300, true), (350, false)]),
+            vec![vec![], vec![], double(), vec![]]
+        );
+        let g = gestured(Some(LONG), Some(DOUBLE));
+        assert_eq!(
+            timeline(&g, &[(0, true), (50, false), (301, true)]),
+            vec![vec![], vec![], vec![]]
+        );
+    }
+
+    #[test]
+    fn third_press_starts_over() {
+        let g = gestured(None, Some(DOUBLE));
+        assert_eq!(
+            timeline(&g, &[(0, true), (100, true), (200, true), (250, true)]),
+            vec![vec![], double(), vec![], double()]
+        );
+    }
+
+    #[test]
+    fn long_then_double() {
+        // A release can end a long press, and the next press still be a
+        // double press, if the long press is shorter than the double press.
+        let g = gestured(Some(Duration::from_millis(200)), Some(DOUBLE));
+        assert_eq!(
+            timeline(&g, &[(0, true), (250, false), (280, true), (300, false)]),
+            vec![vec![], long(), double(), vec![]]
+        );
+    }
+
+    #[test]
+    fn double_then_long() {
+        let g = gestured(Some(LONG), Some(DOUBLE));
+        assert_eq!(
+            timeline(&g, &[(0, true), (50, false), (100, true), (700, false)]),
+            vec![vec![], vec![], double(), long()]
+        );
+    }
+
+    #[test]
+    fn buttons_are_separate() {
+        let g = gestured(Some(LONG), Some(DOUBLE));
+        let start = Instant::now();
+        assert!(g.gestures(&press("/a", 1.0), true, start).is_empty());
+        assert!(g.gestures(&press("/b", 1.0), true, start).is_empty());
+        assert_eq!(
+            g.gestures(&press("/a", 1.0), true, start + DOUBLE),
+            vec![press("/a/double", 1.0)]
+        );
+    }
+
+    #[test]
+    fn gestures_are_bundled_with_the_press() {
+        let g = Gestured::wrap(button(), None, Some(Duration::from_secs(60)));
+        let cc =
+            |value| MidiMessage::ControlChange(Channel::Ch1, ControlEvent { control: 20, value });
+        assert_eq!(g.midi_to_osc(&cc(127)), Some(press("/button", 1.0)));
+        assert_eq!(g.midi_to_osc(&cc(0)), Some(press("/button", 0.0)));
+        assert_eq!(
+            g.midi_to_osc(&cc(127)),
+            Some(OscPacket::Bundle(OscBundle {
+                timetag: OscTime {
+                    seconds: 0,
+                    fractional: 0,
+                },
+                content: vec![press("/button", 1.0), press("/button/double", 1.0)],
+            }))
+        );
+    }
+
+    #[test]
+    fn no_gestures_without_times() {
+        let g = gestured(None, None);
+        assert_eq!(
+            timeline(&g, &[(0, true), (10, true), (5000, false)]),
+            vec![vec![], vec![], vec![]]
+        );
+    }
+}
@@ -0,0 +1,236 @@
+//! The active encoder group.
+//!
+//! A BCR2000's encoder groups give its push encoders four sets of controls,
+//! but the device doesn't say which group is active. If its group buttons
+//! are programmed to send control changes or notes, mappings can follow the
+//! group: a press of a group button selects its group, which is published as
+//! an OSC int on `/bcr2kosc/group`, and mappings with a group only translate
+//! while their group is active, in either direction. The group is sent like
+//! any other translated OSC, so clients that sync are sent it too.
+//!
+//! Group 1 is active until a group button is pressed. The group is part of
+//! the state of one set of mappings, so it starts over at group 1 when
+//! mappings are reloaded. A mapping's group is a condition on that state;
+//! see the `condition` module.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+use super::*;
+
+/// The address the active group is sent to.
+pub const GROUP_ADDR: &str = "/bcr2kosc/group";
+
+/// The active group, shared by a set's group buttons and grouped mappings.
+/// See `BridgeState`.
+#[derive(Clone, Debug)]
+pub struct ActiveGroup(Arc<AtomicU8>);
+
+impl Default for ActiveGroup {
+    fn default() -> Self {
+        ActiveGroup(Arc::new(AtomicU8::new(1)))
+    }
+}
+
+impl ActiveGroup {
+    /// Returns the active group, counting from 1.
+    pub fn get(&self) -> u8 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn set(&self, group: u8) {
+        self.0.store(group, Ordering::Relaxed);
+    }
+}
+
+/// What a button sends when it's pressed, such as a group or shift button.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Button {
+    /// A control change with a non-zero value.
+    Cc(Channel, u8),
+    /// A note on with a non-zero velocity.
+    Note(Channel, MidiNote),
+}
+
+impl Button {
+    pub(super) fn is_pressed_by(&self, midi: &MidiMessage) -> bool {
+        match (self, midi) {
+            (
+                Button::Cc(channel, control),
+                MidiMessage::ControlChange(ch, ControlEvent { control: c, value }),
+            ) => channel == ch && control == c && *value > 0,
+            (Button::Note(channel, key), MidiMessage::NoteOn(ch, KeyEvent { key: k, value })) => {
+                channel == ch && key == k && *value > 0
+            }
+            _ => false,
+        }
+    }
+
+    pub(super) fn is_sent_by(&self, midi: &MidiMessage) -> bool {
+        match (self, midi) {
+            (Button::Cc(channel, control), MidiMessage::ControlChange(ch, e)) => {
+                channel == ch && *control == e.control
+            }
+            (Button::Note(channel, key), MidiMessage::NoteOn(ch, e))
+            | (Button::Note(channel, key), MidiMessage::NoteOff(ch, e)) => {
+                channel == ch && *key == e.key
+            }
+            _ => false,
+        }
+    }
+
+    /// Describes the MIDI messages the button sends.
+    pub(super) fn coverage(&self) -> Coverage {
+        match self {
+            Button::Cc(channel, control) => Coverage::new(
+                MidiFamily::ControlChange,
+                *channel,
+                Numbers::single(*control),
+            ),
+            Button::Note(channel, key) => {
+                Coverage::new(MidiFamily::Note, *channel, Numbers::single(*key))
+            }
+        }
+    }
+}
+
+/// Selects a group when its button is pressed, and publishes it.
+pub struct GroupSwitchTranslator {
+    button: Button,
+    group: u8,
+    active: ActiveGroup,
+}
+
+impl GroupSwitchTranslator {
+    pub fn new(button: Button, group: u8, active: ActiveGroup) -> Result<Box<dyn Translator>> {
+        if group == 0 {
+            return Err("groups are numbered from 1".into());
+        }
+        Ok(Box::new(Self {
+            button,
+            group,
+            active,
+        }))
+    }
+}
+
+impl Translator for GroupSwitchTranslator {
+    fn midi_to_osc(&self, midi: &MidiMessage) -> Option<OscPacket> {
+        if !self.button.is_pressed_by(midi) {
+            return None;
+        }
+        self.active.set(self.group);
+        Some(OscPacket::Message(OscMessage {
+            addr: GROUP_ADDR.to_string(),
+            args: vec![OscType::Int(self.group as i32)],
+        }))
+    }
+
+    fn osc_to_midi(&self, _addr_matcher: &Matcher, _args: &[OscType]) -> Vec<MidiMessage> {
+        vec![]
+    }
+
+    fn handles_midi(&self, midi: &MidiMessage) -> bool {
+        self.button.is_sent_by(midi)
+    }
+
+    fn coverage(&self) -> Vec<Coverage> {
+        vec![self.button.coverage()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::translator::testing::{expect_midi, expect_osc};
+
+    fn cc(control: u8, value: u8) -> MidiMessage {
+        MidiMessage::ControlChange(Channel::Ch1, ControlEvent { control, value })
+    }
+
+    fn osc(addr: &str, v: f32) -> OscMessage {
+        OscMessage {
+            addr: addr.to_string(),
+            args: vec![OscType::Float(v)],
+        }
+    }
+
+    fn group_msg(group: i32) -> OscMessage {
+        OscMessage {
+            addr: GROUP_ADDR.to_string(),
+            args: vec![OscType::Int(group)],
+        }
+    }
+
+    fn grouped_set() -> ServerTranslationSet {
+        TranslationSetBuilder::new()
+            .text(&[
+                "group-cc 1 105 1",
+                "group-note 1 36 2",
+                "cc 1 1 /group/1/encoder group=1",
+                "cc 1 1 /group/2/encoder group=2",
+            ])
+            .and_then(TranslationSetBuilder::build)
+            .unwrap()
+    }
+
+    fn midi_for(set: &ServerTranslationSet, msg: &OscMessage) -> Vec<MidiMessage> {
+        set.osc_msg_to_slewed_midi(msg)
+            .into_iter()
+            .map(|(m, _)| m)
+            .collect()
+    }
+
+    #[test]
+    fn group_buttons_select_and_publish_the_group() {
+        let set = grouped_set();
+        let encoder = |addr: &str| {
+            let out = set.midi_msg_to_osc(&cc(1, 127));
+            expect_osc(out.as_ref(), &osc(addr, 1.0))
+        };
+        encoder("/group/1/encoder").unwrap();
+
+        let press = MidiMessage::NoteOn(
+            Channel::Ch1,
+            KeyEvent {
+                key: 36,
+                value: 127,
+            },
+        );
+        expect_osc(set.midi_msg_to_osc(&press).as_ref(), &group_msg(2)).unwrap();
+        encoder("/group/2/encoder").unwrap();
+        assert!(encoder("/group/1/encoder").is_err());
+
+        // Releasing a button leaves the group as it is.
+        assert_eq!(set.midi_msg_to_osc(&cc(105, 0)), None);
+        encoder("/group/2/encoder").unwrap();
+        expect_osc(set.midi_msg_to_osc(&cc(105, 127)).as_ref(), &group_msg(1)).unwrap();
+        encoder("/group/1/encoder").unwrap();
+    }
+
+    #[test]
+    fn osc_translates_only_in_its_group() {
+        let set = grouped_set();
+        assert!(midi_for(&set, &osc("/group/2/encoder", 1.0)).is_empty());
+        expect_midi(&midi_for(&set, &osc("/group/1/encoder", 1.0)), &cc(1, 127)).unwrap();
+
+        set.midi_msg_to_osc(&cc(105, 127));
+        let press = MidiMessage::NoteOn(Channel::Ch1, KeyEvent { key: 36, value: 1 });
+        set.midi_msg_to_osc(&press);
+        assert!(midi_for(&set, &osc("/group/1/encoder", 1.0)).is_empty());
+        expect_midi(&midi_for(&set, &osc("/group/2/encoder", 0.0)), &cc(1, 0)).unwrap();
+    }
+
+    #[test]
+    fn groups_count_from_one() {
+        let active = ActiveGroup::default();
+        assert_eq!(active.get(), 1);
+        assert!(GroupSwitchTranslator::new(Button::Cc(Channel::Ch1, 105), 0, active).is_err());
+        assert!(TranslationSetBuilder::new()
+            .line("group-cc 1 105 0")
+            .is_err());
+        assert!(TranslationSetBuilder::new()
+            .line("cc 1 1 /a group=0")
+            .is_err());
+    }
+}
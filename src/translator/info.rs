@@ -0,0 +1,93 @@
+//! What mappings mean to people, as opposed to how they translate.
+//!
+//! A mapping can carry a description, the unit of the value it controls, the
+//! values shown at the ends of its range, and a color. None of these change
+//! what is translated; they're kept so that tools that present the mappings,
+//! such as `show-mappings`, can label the controls:
+//!
+//! ```text
+//! cc 1 7 /track/1/volume unit=dB scale=-60,6 color=ff8000 # Lead vocal level
+//! ```
+//!
+//! The scale gives the values that the ends of the mapping's range stand
+//! for, such as -60 and 6 for a fader marked in decibels. Colors are given as
+//! six hexadecimal digits, `RRGGBB`, since `#` starts a description.
+//!
+//! There's no OSCQuery server or TouchOSC layout generator yet, so
+//! descriptions are shown only by `show-mappings` and in traces, and units,
+//! scales and colors only by `show-mappings`.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// A color, as red, green and blue components.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Color(pub u8, pub u8, pub u8);
+
+impl FromStr for Color {
+    type Err = String;
+
+    /// Parses six hexadecimal digits, `RRGGBB`.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || format!("invalid color \"{s}\", expected RRGGBB");
+        if s.len() != 6 || !s.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let component = |i: usize| u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| invalid());
+        Ok(Color(component(0)?, component(2)?, component(4)?))
+    }
+}
+
+impl Display for Color {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.0, self.1, self.2)
+    }
+}
+
+/// What a mapping means to people. Every part is optional.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MappingInfo {
+    /// What the mapping is for.
+    pub description: Option<String>,
+    /// The unit of the value the mapping controls, such as "dB".
+    pub unit: Option<String>,
+    /// The values that the low and high ends of the mapping's range stand
+    /// for.
+    pub scale: Option<(f32, f32)>,
+    /// The color of the mapping's control.
+    pub color: Option<Color>,
+}
+
+impl MappingInfo {
+    /// Returns true if the mapping has no unit, scale or color. The
+    /// description isn't counted.
+    pub fn is_plain(&self) -> bool {
+        self.unit.is_none() && self.scale.is_none() && self.color.is_none()
+    }
+}
+
+impl Display for MappingInfo {
+    /// Lists the unit, scale and color, e.g. "-60 to 6 dB, color #ff8000".
+    /// The description isn't shown.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = vec![];
+        match (self.scale, &self.unit) {
+            (Some((low, high)), Some(unit)) => parts.push(format!("{low} to {high} {unit}")),
+            (Some((low, high)), None) => parts.push(format!("{low} to {high}")),
+            (None, Some(unit)) => parts.push(format!("in {unit}")),
+            (None, None) => {}
+        }
+        if let Some(color) = self.color {
+            parts.push(format!("color {color}"));
+        }
+        parts.join(", ").fmt(f)
+    }
+}
+
+/// Parses a scale, `LOW,HIGH`.
+pub fn parse_scale(s: &str) -> std::result::Result<(f32, f32), String> {
+    let invalid = || format!("invalid scale \"{s}\", expected LOW,HIGH");
+    let (low, high) = s.split_once(',').ok_or_else(invalid)?;
+    let value = |v: &str| v.parse::<f32>().map_err(|_| invalid());
+    Ok((value(low)?, value(high)?))
+}
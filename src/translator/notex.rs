@@ -0,0 +1,82 @@
+//! `Translator` implementations for Note On/Off MIDI.
+
+use super::*;
+
+/// Translates a note to an OSC value, by its velocity curve. Note Off or Note
+/// On with zero velocity is 0.0. Under the default curve, Note On with a
+/// non-zero velocity is 1.0. See the `velocity` module.
+pub struct NoteTranslator {
+    channel: Channel,
+    key: MidiNote,
+    curve: VelocityCurve,
+    address: OscAddress,
+}
+
+impl NoteTranslator {
+    pub fn new(
+        channel: Channel,
+        key: MidiNote,
+        curve: VelocityCurve,
+        address: &str,
+    ) -> Result<Box<dyn Translator>> {
+        let address = OscAddress::new(address.to_string())?;
+        Ok(Box::new(Self {
+            channel,
+            key,
+            curve,
+            address,
+        }))
+    }
+}
+
+impl Translator for NoteTranslator {
+    fn midi_to_osc(&self, midi: &MidiMessage) -> Option<OscPacket> {
+        use MidiMessage::*;
+        let value = match midi {
+            NoteOn(ch, KeyEvent { key, value }) if (&self.channel == ch) && (self.key == *key) => {
+                self.curve.to_value(*value)
+            }
+            NoteOff(ch, KeyEvent { key, .. }) if (&self.channel == ch) && (self.key == *key) => 0.0,
+            _ => return None,
+        };
+        Some(OscPacket::Message(OscMessage {
+            addr: self.address.to_string(),
+            args: vec![OscType::Float(value)],
+        }))
+    }
+
+    fn osc_to_midi(&self, addr_matcher: &Matcher, args: &[OscType]) -> Vec<MidiMessage> {
+        if !addr_matcher.match_address(&self.address) {
+            return vec![];
+        }
+        let velocity = match args.first().cloned().and_then(OscType::float) {
+            Some(v) => self.curve.to_velocity(v),
+            None => return vec![],
+        };
+        vec![if let Some(velocity) = velocity {
+            MidiMessage::NoteOn(
+                self.channel,
+                KeyEvent {
+                    key: self.key,
+                    value: velocity,
+                },
+            )
+        } else {
+            MidiMessage::NoteOff(
+                self.channel,
+                KeyEvent {
+                    key: self.key,
+                    value: 0,
+                },
+            )
+        }]
+    }
+
+    fn coverage(&self) -> Vec<Coverage> {
+        vec![Coverage::new(
+            MidiFamily::Note,
+            self.channel,
+            Numbers::single(self.key),
+        )]
+    }
+}
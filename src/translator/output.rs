@@ -0,0 +1,158 @@
+//! Typing of OSC arguments sent by translators.
+//!
+//! Translators produce float arguments, normalized to 0.0 through 1.0. Some
+//! hosts want other types, particularly for switches. A mapping's output type
+//! converts the value:
+//!
+//! Type      OSC   value sent
+//! float     f     the value, unchanged
+//! int       i     0 or 1
+//! bool      T/F   true if the value is at least 0.5
+//! nil       N     sent only when the value is at least 0.5
+//! impulse   I     sent only when the value is at least 0.5
+//!
+//! Any of these types is accepted on input; see `normalize_args`. Under a
+//! strict coercion policy, a mapping accepts floats and its own type only;
+//! see the `coerce` module.
+
+use std::str::FromStr;
+
+use super::*;
+
+/// The type of argument a mapping sends.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputType {
+    /// A float, 0.0 through 1.0.
+    #[default]
+    Float,
+    /// An integer, 0 or 1.
+    Int,
+    /// An OSC 1.1 true or false.
+    Bool,
+    /// An OSC 1.1 nil, sent only for "on" values.
+    Nil,
+    /// An OSC 1.1 impulse (infinitum), sent only for "on" values.
+    Impulse,
+}
+
+impl OutputType {
+    /// Converts a float argument, returning `None` if nothing should be sent.
+    fn convert(&self, arg: OscType) -> Option<OscType> {
+        let v = match arg {
+            OscType::Float(v) => v,
+            other => return Some(other),
+        };
+        let on = v >= 0.5;
+        match self {
+            OutputType::Float => Some(OscType::Float(v)),
+            OutputType::Int => Some(OscType::Int(on as i32)),
+            OutputType::Bool => Some(OscType::Bool(on)),
+            OutputType::Nil => on.then_some(OscType::Nil),
+            OutputType::Impulse => on.then_some(OscType::Inf),
+        }
+    }
+
+    /// Converts an incoming argument of this type back to a float. Arguments
+    /// of other types are returned unchanged.
+    fn accept(&self, arg: &OscType) -> OscType {
+        match (self, arg) {
+            (OutputType::Int, OscType::Int(i)) => OscType::Float(*i as f32),
+            (OutputType::Bool, OscType::Bool(b)) => OscType::Float(if *b { 1.0 } else { 0.0 }),
+            (OutputType::Nil, OscType::Nil) | (OutputType::Impulse, OscType::Inf) => {
+                OscType::Float(1.0)
+            }
+            (_, other) => other.clone(),
+        }
+    }
+}
+
+impl FromStr for OutputType {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "float" => Ok(OutputType::Float),
+            "int" => Ok(OutputType::Int),
+            "bool" => Ok(OutputType::Bool),
+            "nil" => Ok(OutputType::Nil),
+            "impulse" => Ok(OutputType::Impulse),
+            _ => Err(format!("unknown output type \"{s}\"")),
+        }
+    }
+}
+
+/// Wraps a translator, converting the arguments it sends to OSC.
+pub struct Typed {
+    inner: Box<dyn Translator>,
+    output: OutputType,
+}
+
+impl Typed {
+    /// Wrap a translator.
+    pub fn wrap(inner: Box<dyn Translator>, output: OutputType) -> Box<dyn Translator> {
+        Box::new(Typed { inner, output })
+    }
+
+    fn convert_packet(&self, packet: OscPacket) -> Option<OscPacket> {
+        match packet {
+            OscPacket::Message(m) => {
+                let args = m
+                    .args
+                    .into_iter()
+                    .map(|a| self.output.convert(a))
+                    .collect::<Option<Vec<_>>>()?;
+                Some(OscPacket::Message(OscMessage { addr: m.addr, args }))
+            }
+            OscPacket::Bundle(b) => {
+                let content: Vec<OscPacket> = b
+                    .content
+                    .into_iter()
+                    .filter_map(|p| self.convert_packet(p))
+                    .collect();
+                if content.is_empty() {
+                    None
+                } else {
+                    Some(OscPacket::Bundle(OscBundle {
+                        timetag: b.timetag,
+                        content,
+                    }))
+                }
+            }
+        }
+    }
+}
+
+impl Translator for Typed {
+    fn midi_to_osc(&self, midi: &MidiMessage) -> Option<OscPacket> {
+        self.inner
+            .midi_to_osc(midi)
+            .and_then(|p| self.convert_packet(p))
+    }
+
+    fn osc_to_midi(&self, addr_matcher: &Matcher, args: &[OscType]) -> Vec<MidiMessage> {
+        let args: Vec<OscType> = args.iter().map(|a| self.output.accept(a)).collect();
+        self.inner.osc_to_midi(addr_matcher, &args)
+    }
+
+    fn coverage(&self) -> Vec<Coverage> {
+        self.inner.coverage()
+    }
+
+    fn slew_rate(&self) -> Option<f32> {
+        self.inner.slew_rate()
+    }
+}
+
+/// Converts incoming arguments of the types that mappings can send to the
+/// floats that translators expect. Nil and impulse arguments are taken as
+/// "on", since they're sent only for "on" values.
+pub fn normalize_args(args: &[OscType]) -> Vec<OscType> {
+    args.iter()
+        .map(|a| match a {
+            OscType::Int(i) => OscType::Float(*i as f32),
+            OscType::Bool(b) => OscType::Float(if *b { 1.0 } else { 0.0 }),
+            OscType::Nil | OscType::Inf => OscType::Float(1.0),
+            other => other.clone(),
+        })
+        .collect()
+}
@@ -0,0 +1,136 @@
+//! Built-in mappings for common OSC hosts.
+//!
+//! Each profile assumes a BCR2000 running its first factory preset, which
+//! sends everything on channel 1:
+//!
+//! ```text
+//! controls   BCR2000 element
+//! 1-8        top row of push encoders
+//! 65-72      first row of buttons
+//! 73-80      second row of buttons
+//! 81-88      first row of encoders
+//! 89-96      second row of encoders
+//! 97-104     third row of encoders
+//! 105-108    the four user buttons at the lower right
+//! ```
+//!
+//! A profile can be extended by a mapping file; the file's mappings are added
+//! to the profile's.
+
+use std::path::Path;
+
+use super::*;
+
+/// The names of the built-in profiles.
+pub const PROFILES: &[&str] = &["reaper", "ardour", "qlc+"];
+
+impl ServerTranslationSet {
+    /// Returns the mappings of the named profile.
+    pub fn profile(name: &str) -> Result<TranslationSetBuilder> {
+        match name {
+            "reaper" => Ok(Self::reaper()),
+            "ardour" => Ok(Self::ardour()),
+            "qlc+" => Ok(Self::qlc_plus()),
+            _ => Err(format!(
+                "unknown profile \"{name}\"; the profiles are {}",
+                PROFILES.join(", ")
+            )
+            .into()),
+        }
+    }
+
+    /// Returns the mappings of a profile, with those of a mapping file added.
+    /// Without either, the test mappings are returned.
+    pub fn configured(profile: Option<&str>, file: Option<&Path>) -> Result<TranslationSetBuilder> {
+        let set = match (profile, file) {
+            (Some(name), _) => Self::profile(name)?,
+            (None, Some(_)) => TranslationSetBuilder::new(),
+            (None, None) => Self::test_mappings(),
+        };
+        match file {
+            Some(f) => set.file(f),
+            None => Ok(set),
+        }
+    }
+
+    /// Mappings for REAPER's default OSC pattern configuration, controlling a
+    /// bank of eight tracks and the transport.
+    pub fn reaper() -> TranslationSetBuilder {
+        TranslationSetBuilder::new()
+            .cc_indexed(IndexTarget::Control, Channel::Ch1, 1)
+            .osc("/track/{1-8}/pan")
+            .cc_indexed(IndexTarget::Control, Channel::Ch1, 65)
+            .osc("/track/{1-8}/mute")
+            .cc_indexed(IndexTarget::Control, Channel::Ch1, 73)
+            .osc("/track/{1-8}/solo")
+            .cc_indexed(IndexTarget::Control, Channel::Ch1, 81)
+            .osc("/track/{1-8}/volume")
+            .cc_indexed(IndexTarget::Control, Channel::Ch1, 89)
+            .osc("/track/{1-8}/send/1/volume")
+            .cc_indexed(IndexTarget::Control, Channel::Ch1, 97)
+            .osc("/track/{1-8}/send/2/volume")
+            .cc(Channel::Ch1, 105)
+            .toggle()
+            .osc("/play")
+            .cc(Channel::Ch1, 106)
+            .toggle()
+            .osc("/stop")
+            .cc(Channel::Ch1, 107)
+            .toggle()
+            .osc("/record")
+            .cc(Channel::Ch1, 108)
+            .toggle()
+            .osc("/repeat")
+    }
+
+    /// Mappings for Ardour's OSC surface, controlling the selected strip, the
+    /// master fader, and the transport. Ardour addresses other strips by an
+    /// argument rather than the address, which mappings can't express.
+    pub fn ardour() -> TranslationSetBuilder {
+        TranslationSetBuilder::new()
+            .cc(Channel::Ch1, 81)
+            .osc("/select/fader")
+            .cc(Channel::Ch1, 82)
+            .osc("/master/fader")
+            .cc(Channel::Ch1, 83)
+            .osc("/select/pan_stereo_position")
+            .cc(Channel::Ch1, 84)
+            .osc("/select/pan_stereo_width")
+            .cc(Channel::Ch1, 65)
+            .toggle()
+            .osc("/select/mute")
+            .cc(Channel::Ch1, 66)
+            .toggle()
+            .osc("/select/solo")
+            .cc(Channel::Ch1, 67)
+            .toggle()
+            .osc("/select/recenable")
+            .cc(Channel::Ch1, 105)
+            .toggle()
+            .osc("/transport_play")
+            .cc(Channel::Ch1, 106)
+            .toggle()
+            .osc("/transport_stop")
+            .cc(Channel::Ch1, 107)
+            .toggle()
+            .osc("/rec_enable_toggle")
+            .cc(Channel::Ch1, 108)
+            .toggle()
+            .osc("/goto_start")
+    }
+
+    /// Mappings for QLC+, which lets any OSC address drive an input channel.
+    /// Encoders become faders and buttons become switches, numbered in the
+    /// order of the BCR2000's rows.
+    pub fn qlc_plus() -> TranslationSetBuilder {
+        TranslationSetBuilder::new()
+            .cc_indexed(IndexTarget::Control, Channel::Ch1, 81)
+            .osc("/bcr/fader/{1-24}")
+            .cc_indexed(IndexTarget::Control, Channel::Ch1, 1)
+            .osc("/bcr/knob/{1-8}")
+            .cc_indexed(IndexTarget::Control, Channel::Ch1, 65)
+            .osc("/bcr/button/{1-16}")
+            .cc_indexed(IndexTarget::Control, Channel::Ch1, 105)
+            .osc("/bcr/user/{1-4}")
+    }
+}
@@ -0,0 +1,228 @@
+//! Quantization of translated values.
+//!
+//! A continuous encoder driving an enumerated parameter, such as a filter
+//! type, should only produce the values the parameter accepts. A quantized
+//! mapping snaps values in both directions, either to a number of evenly
+//! spaced steps or to an explicit list of control values.
+
+use super::*;
+
+/// How a mapping's values are snapped.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Quantize {
+    /// Snap OSC values to this many evenly spaced steps from 0.0 to 1.0.
+    Steps(u8),
+    /// Snap MIDI control values to the nearest value in this list.
+    Values(Vec<u8>),
+}
+
+impl Quantize {
+    fn snap_float(&self, v: f32) -> f32 {
+        match self {
+            Quantize::Steps(n) if *n > 1 => {
+                let n = (*n - 1) as f32;
+                (v.clamp(0.0, 1.0) * n).round() / n
+            }
+            _ => v,
+        }
+    }
+
+    fn snap_cv(&self, v: u8) -> u8 {
+        match self {
+            Quantize::Values(values) => values
+                .iter()
+                .copied()
+                .min_by_key(|x| (*x as i16 - v as i16).abs())
+                .unwrap_or(v),
+            _ => v,
+        }
+    }
+
+    fn snap_midi(&self, midi: &MidiMessage) -> Option<MidiMessage> {
+        match midi {
+            MidiMessage::ControlChange(ch, ControlEvent { control, value }) => {
+                Some(MidiMessage::ControlChange(
+                    *ch,
+                    ControlEvent {
+                        control: *control,
+                        value: self.snap_cv(*value),
+                    },
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    fn snap_packet(&self, pkt: OscPacket) -> OscPacket {
+        match pkt {
+            OscPacket::Message(mut m) => {
+                m.args = self.snap_args(&m.args);
+                OscPacket::Message(m)
+            }
+            OscPacket::Bundle(mut b) => {
+                b.content = b.content.into_iter().map(|p| self.snap_packet(p)).collect();
+                OscPacket::Bundle(b)
+            }
+        }
+    }
+
+    fn snap_args(&self, args: &[OscType]) -> Vec<OscType> {
+        args.iter()
+            .map(|a| match a {
+                OscType::Float(f) => OscType::Float(self.snap_float(*f)),
+                a => a.clone(),
+            })
+            .collect()
+    }
+}
+
+/// Wraps a translator, snapping the values it translates.
+pub struct Quantized {
+    inner: Box<dyn Translator>,
+    quantize: Quantize,
+}
+
+impl Quantized {
+    /// Wrap a translator.
+    pub fn wrap(inner: Box<dyn Translator>, quantize: Quantize) -> Box<dyn Translator> {
+        Box::new(Quantized { inner, quantize })
+    }
+}
+
+impl Translator for Quantized {
+    fn midi_to_osc(&self, midi: &MidiMessage) -> Option<OscPacket> {
+        match &self.quantize {
+            Quantize::Steps(_) => self
+                .inner
+                .midi_to_osc(midi)
+                .map(|p| self.quantize.snap_packet(p)),
+            Quantize::Values(_) => match self.quantize.snap_midi(midi) {
+                Some(snapped) => self.inner.midi_to_osc(&snapped),
+                None => self.inner.midi_to_osc(midi),
+            },
+        }
+    }
+
+    fn osc_to_midi(&self, addr_matcher: &Matcher, args: &[OscType]) -> Vec<MidiMessage> {
+        match &self.quantize {
+            Quantize::Steps(_) => self
+                .inner
+                .osc_to_midi(addr_matcher, &self.quantize.snap_args(args)),
+            Quantize::Values(_) => self
+                .inner
+                .osc_to_midi(addr_matcher, args)
+                .into_iter()
+                .map(|m| self.quantize.snap_midi(&m).unwrap_or(m))
+                .collect(),
+        }
+    }
+
+    fn coverage(&self) -> Vec<Coverage> {
+        self.inner.coverage()
+    }
+
+    fn slew_rate(&self) -> Option<f32> {
+        self.inner.slew_rate()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::translator::testing::{assert_midi_to_osc, assert_osc_to_midi};
+
+    fn cc(value: u8) -> MidiMessage {
+        MidiMessage::ControlChange(Channel::Ch1, ControlEvent { control: 7, value })
+    }
+
+    fn osc(value: f32) -> OscMessage {
+        OscMessage {
+            addr: "/volume".to_string(),
+            args: vec![OscType::Float(value)],
+        }
+    }
+
+    fn volume(quantize: Quantize) -> Box<dyn Translator> {
+        let t = ControlChangeRangeTranslator::new(Channel::Ch1, 7, 0, 127, "/volume").unwrap();
+        Quantized::wrap(t, quantize)
+    }
+
+    #[test]
+    fn even_steps() {
+        let q = Quantize::Steps(5);
+        for (v, snapped) in [
+            (0.0, 0.0),
+            (0.1, 0.0),
+            (0.2, 0.25),
+            (0.6, 0.5),
+            (0.9, 1.0),
+            (1.0, 1.0),
+            // Values out of range are clamped.
+            (-0.5, 0.0),
+            (1.5, 1.0),
+            // Exact midpoints round up.
+            (0.125, 0.25),
+            (0.625, 0.75),
+        ] {
+            assert_eq!(q.snap_float(v), snapped, "{v}");
+        }
+    }
+
+    #[test]
+    fn too_few_steps() {
+        for n in [0, 1] {
+            assert_eq!(Quantize::Steps(n).snap_float(0.3), 0.3);
+        }
+        assert_eq!(Quantize::Steps(2).snap_float(0.3), 0.0);
+        assert_eq!(Quantize::Steps(2).snap_float(0.5), 1.0);
+    }
+
+    #[test]
+    fn value_list() {
+        let q = Quantize::Values(vec![0, 32, 64, 127]);
+        for (v, snapped) in [
+            (0, 0),
+            (1, 0),
+            (40, 32),
+            (127, 127),
+            (100, 127),
+            // At an exact midpoint, the earlier value in the list wins.
+            (16, 0),
+            (48, 32),
+            (95, 64),
+            (96, 127),
+        ] {
+            assert_eq!(q.snap_cv(v), snapped, "{v}");
+        }
+    }
+
+    #[test]
+    fn value_list_edges() {
+        let q = Quantize::Values(vec![120, 10]);
+        assert_eq!(q.snap_cv(0), 10);
+        assert_eq!(q.snap_cv(127), 120);
+        // The list's order, not the values', breaks ties.
+        assert_eq!(q.snap_cv(65), 120);
+        assert_eq!(Quantize::Values(vec![]).snap_cv(50), 50);
+    }
+
+    #[test]
+    fn steps_snap_both_directions() {
+        let t = volume(Quantize::Steps(3));
+        assert_midi_to_osc(t.as_ref(), &cc(0), &osc(0.0));
+        assert_midi_to_osc(t.as_ref(), &cc(40), &osc(0.5));
+        assert_midi_to_osc(t.as_ref(), &cc(100), &osc(1.0));
+        assert_osc_to_midi(t.as_ref(), &osc(0.2), &cc(0));
+        assert_osc_to_midi(t.as_ref(), &osc(0.3), &cc(64));
+        assert_osc_to_midi(t.as_ref(), &osc(0.8), &cc(127));
+    }
+
+    #[test]
+    fn values_snap_both_directions() {
+        let t = volume(Quantize::Values(vec![0, 127]));
+        assert_midi_to_osc(t.as_ref(), &cc(60), &osc(0.0));
+        assert_midi_to_osc(t.as_ref(), &cc(70), &osc(1.0));
+        assert_osc_to_midi(t.as_ref(), &osc(0.4), &cc(0));
+        assert_osc_to_midi(t.as_ref(), &osc(0.6), &cc(127));
+    }
+}
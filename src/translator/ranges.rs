@@ -0,0 +1,202 @@
+//! Learned ranges of control values.
+//!
+//! A preset can limit a control to part of the MIDI range, such as an
+//! encoder programmed for 0 through 100, so that a mapping of the full range
+//! never reaches 1.0. The service can learn the values that each control
+//! actually sends, and keep them in a file, one control per line:
+//!
+//! ```text
+//! # CHANNEL CONTROL LOW HIGH
+//! 1 1 0 100
+//! 1 2 10 117
+//! ```
+//!
+//! Channels are numbered 1 through 16. A set calibrated with learned ranges
+//! stretches each learned range to the full MIDI range before its mappings
+//! see a control change, and narrows control changes they send back to it.
+//! Only controls with a learned range narrower than the full range are
+//! calibrated. Since calibrated mappings see the full range, those given a
+//! range of their own for a learned control should drop it.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::Path;
+
+use super::*;
+
+/// The lowest and highest values seen, by channel and control number.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LearnedRanges {
+    ranges: BTreeMap<(u8, u8), (u8, u8)>,
+}
+
+impl LearnedRanges {
+    /// Reads ranges from a file. A file that doesn't exist yet holds none.
+    pub fn load(path: &Path) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text).map_err(|e| format!("{}: {e}", path.display()).into()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Writes the ranges to a file, replacing it.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut text = String::from("# CHANNEL CONTROL LOW HIGH\n");
+        for line in self.lines() {
+            writeln!(text, "{line}").unwrap();
+        }
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+
+    /// Parses ranges, one per line. Blank lines and lines starting with `#`
+    /// are ignored.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut ranges = BTreeMap::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<u8> = line
+                .split_whitespace()
+                .map(|f| f.parse::<u8>())
+                .collect::<std::result::Result<_, _>>()
+                .map_err(|_| format!("line {}: expected numbers", i + 1))?;
+            match fields[..] {
+                [channel @ 1..=16, control @ 0..=127, low, high] if low <= high && high <= 127 => {
+                    ranges.insert((channel - 1, control), (low, high));
+                }
+                _ => {
+                    return Err(format!("line {}: expected CHANNEL CONTROL LOW HIGH", i + 1).into())
+                }
+            }
+        }
+        Ok(LearnedRanges { ranges })
+    }
+
+    /// The ranges, as they're written to a file.
+    pub fn lines(&self) -> Vec<String> {
+        self.ranges
+            .iter()
+            .map(|((channel, control), (low, high))| {
+                format!("{} {control} {low} {high}", channel + 1)
+            })
+            .collect()
+    }
+
+    /// Widens the range of the control that sent a control change to include
+    /// its value. Returns true if the range changed.
+    pub fn observe(&mut self, midi: &MidiMessage) -> bool {
+        let MidiMessage::ControlChange(ch, ControlEvent { control, value }) = midi else {
+            return false;
+        };
+        let mut changed = false;
+        let range = self.ranges.entry((*ch as u8, *control)).or_insert_with(|| {
+            changed = true;
+            (*value, *value)
+        });
+        let widened = (range.0.min(*value), range.1.max(*value));
+        changed |= widened != *range;
+        *range = widened;
+        changed
+    }
+
+    /// The range by which a control is calibrated, if it is.
+    fn calibration(&self, ch: Channel, control: u8) -> Option<(u8, u8)> {
+        match self.ranges.get(&(ch as u8, control)) {
+            Some(&(low, high)) if low < high && (low, high) != (0, 127) => Some((low, high)),
+            _ => None,
+        }
+    }
+
+    /// Stretches the value of a calibrated control change to the full range.
+    /// Returns `None` for other messages, which are passed on unchanged.
+    pub fn stretch(&self, midi: &MidiMessage) -> Option<MidiMessage> {
+        let MidiMessage::ControlChange(ch, ControlEvent { control, value }) = midi else {
+            return None;
+        };
+        let (low, high) = self.calibration(*ch, *control)?;
+        let value = cv_to_normalized_float(*value, low, high);
+        Some(MidiMessage::ControlChange(
+            *ch,
+            ControlEvent {
+                control: *control,
+                value: normalized_float_to_cv(value, 0, 127),
+            },
+        ))
+    }
+
+    /// Narrows the value of a control change to the calibrated control's
+    /// range.
+    pub fn narrow(&self, midi: MidiMessage) -> MidiMessage {
+        if let MidiMessage::ControlChange(ch, ControlEvent { control, value }) = midi {
+            if let Some((low, high)) = self.calibration(ch, control) {
+                let value = cv_to_normalized_float(value, 0, 127);
+                return MidiMessage::ControlChange(
+                    ch,
+                    ControlEvent {
+                        control,
+                        value: normalized_float_to_cv(value, low, high),
+                    },
+                );
+            }
+        }
+        midi
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PGM;
+
+    fn cc(control: u8, value: u8) -> MidiMessage {
+        MidiMessage::ControlChange(Channel::Ch1, ControlEvent { control, value })
+    }
+
+    fn osc(addr: &str, v: f32) -> OscMessage {
+        OscMessage {
+            addr: addr.to_string(),
+            args: vec![OscType::Float(v)],
+        }
+    }
+
+    #[test]
+    fn learned_ranges_calibrate_after_reload() {
+        let mut learned = LearnedRanges::default();
+        for value in [40, 10, 100, 60] {
+            learned.observe(&cc(7, value));
+        }
+        assert!(!learned.observe(&cc(7, 50)));
+        learned.observe(&cc(8, 0));
+        learned.observe(&cc(8, 127));
+        assert_eq!(learned.lines(), ["1 7 10 100", "1 8 0 127"]);
+
+        let path = std::env::temp_dir().join(format!("{PGM}-{}.ranges", std::process::id()));
+        learned.save(&path).unwrap();
+        let loaded = LearnedRanges::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded, learned);
+
+        let set = TranslationSetBuilder::new()
+            .cc(Channel::Ch1, 7)
+            .osc("/vol")
+            .cc(Channel::Ch1, 8)
+            .osc("/pan")
+            .build()
+            .unwrap()
+            .with_ranges(loaded);
+        for (value, v) in [(10, 0.0), (100, 1.0)] {
+            let expected = OscPacket::Message(osc("/vol", v));
+            assert_eq!(set.midi_msg_to_osc(&cc(7, value)), Some(expected));
+            let midi = set.osc_msg_to_slewed_midi(&osc("/vol", v));
+            assert_eq!(midi[0].0, cc(7, value));
+        }
+        // The full range isn't calibrated.
+        let expected = OscPacket::Message(osc("/pan", 1.0));
+        assert_eq!(set.midi_msg_to_osc(&cc(8, 127)), Some(expected));
+    }
+}
@@ -0,0 +1,130 @@
+//! A `Translator` for the raw MIDI namespace.
+//!
+//! The namespace exposes MIDI under fixed OSC addresses, without any
+//! mappings, alongside whatever mappings are configured:
+//!
+//! ```text
+//! /midi/{channel}/cc/{control}   control change, value 0 through 127
+//! /midi/{channel}/note/{key}     note on, velocity 1 through 127; 0 is note off
+//! /midi/{channel}/pb             pitch bend, 0 through 16383, centered on 8192
+//! ```
+//!
+//! Channels are numbered from 1 through 16. MIDI received is sent with an
+//! integer argument. OSC received can have an integer argument, taken as is,
+//! or a float from 0 to 1, scaled to the value's range. Addresses are taken
+//! literally, so patterns such as `/midi/1/cc/*` aren't expanded.
+
+use super::*;
+
+/// The prefix of the namespace's addresses.
+pub const RAW_MIDI_PREFIX: &str = "/midi/";
+
+/// The largest pitch bend value.
+const PITCH_BEND_MAX: u16 = 16383;
+
+/// Translates all control changes, notes and pitch bends to and from the
+/// raw MIDI namespace.
+pub struct RawMidiTranslator;
+
+impl Translator for RawMidiTranslator {
+    fn midi_to_osc(&self, midi: &MidiMessage) -> Option<OscPacket> {
+        use MidiMessage::*;
+        let (addr, value) = match midi {
+            ControlChange(_, ControlEvent { control, value }) => {
+                (format!("cc/{control}"), *value as i32)
+            }
+            NoteOn(_, KeyEvent { key, value }) => (format!("note/{key}"), *value as i32),
+            NoteOff(_, KeyEvent { key, .. }) => (format!("note/{key}"), 0),
+            PitchBend(_, lsb, msb) => ("pb".to_string(), (*msb as i32) << 7 | *lsb as i32),
+            _ => return None,
+        };
+        Some(OscPacket::Message(OscMessage {
+            addr: format!("{RAW_MIDI_PREFIX}{}/{addr}", channel_number(midi)?),
+            args: vec![OscType::Int(value)],
+        }))
+    }
+
+    fn osc_to_midi(&self, addr_matcher: &Matcher, args: &[OscType]) -> Vec<MidiMessage> {
+        parse(&addr_matcher.pattern, args).into_iter().collect()
+    }
+
+    fn coverage(&self) -> Vec<Coverage> {
+        vec![
+            Coverage::new(MidiFamily::ControlChange, Channels::ANY, Numbers::ALL),
+            Coverage::new(MidiFamily::Note, Channels::ANY, Numbers::ALL),
+        ]
+    }
+}
+
+/// The channel of a channel message, numbered from 1.
+fn channel_number(midi: &MidiMessage) -> Option<u8> {
+    use MidiMessage::*;
+    match midi {
+        ControlChange(ch, _) | NoteOn(ch, _) | NoteOff(ch, _) | PitchBend(ch, _, _) => {
+            Some(*ch as u8 + 1)
+        }
+        _ => None,
+    }
+}
+
+/// Translates a message addressed in the namespace.
+fn parse(addr: &str, args: &[OscType]) -> Option<MidiMessage> {
+    let mut parts = addr.strip_prefix(RAW_MIDI_PREFIX)?.split('/');
+    let channel: u8 = parts
+        .next()?
+        .parse()
+        .ok()
+        .filter(|c| (1..=16).contains(c))?;
+    let channel = Channel::from(channel - 1);
+    let kind = parts.next()?;
+    let number = match parts.next() {
+        Some(n) => Some(n.parse::<u8>().ok().filter(|n| *n < 128)?),
+        None => None,
+    };
+    if parts.next().is_some() {
+        return None;
+    }
+    match (kind, number) {
+        ("cc", Some(control)) => {
+            let value = arg_value(args.first()?, 127)? as u8;
+            Some(MidiMessage::ControlChange(
+                channel,
+                ControlEvent { control, value },
+            ))
+        }
+        ("note", Some(key)) => {
+            let value = arg_value(args.first()?, 127)? as u8;
+            let event = KeyEvent { key, value };
+            Some(if value == 0 {
+                MidiMessage::NoteOff(channel, event)
+            } else {
+                MidiMessage::NoteOn(channel, event)
+            })
+        }
+        ("pb", None) => {
+            let value = arg_value(args.first()?, PITCH_BEND_MAX)?;
+            Some(MidiMessage::PitchBend(
+                channel,
+                (value & 0x7f) as u8,
+                (value >> 7) as u8,
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// Takes an integer argument as it is, and scales a float from 0 to 1 to
+/// the range up to `max`. Values out of range are ignored.
+fn arg_value(arg: &OscType, max: u16) -> Option<u16> {
+    let scale = |v: f64| {
+        (0.0..=1.0)
+            .contains(&v)
+            .then(|| (v * max as f64).round() as u16)
+    };
+    match arg {
+        OscType::Int(v) if (0..=max as i32).contains(v) => Some(*v as u16),
+        OscType::Float(v) => scale(*v as f64),
+        OscType::Double(v) => scale(*v),
+        _ => None,
+    }
+}
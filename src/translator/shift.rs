@@ -0,0 +1,92 @@
+//! A shift button, which gives every control a second OSC address.
+//!
+//! While a shift button is held, the OSC that a set's mappings translate MIDI
+//! to is sent with the shift prefix added to its address, so `/track/1/pan`
+//! becomes, say, `/shift/track/1/pan`. In the other direction, only OSC
+//! under the prefix is translated to MIDI while the button is held, with the
+//! prefix removed, and only OSC that isn't under it while the button is
+//! released. So a host can keep the controls' feedback, such as LED rings,
+//! showing whichever layer is in use.
+//!
+//! Shifting is the `shift` flag of the bridge's state (see the `condition`
+//! module), so mappings can also be conditioned on it, and a host can shift
+//! the controls by setting the flag. A press or release of a shift button is
+//! sent as an OSC int, 1 or 0, on `/bcr2kosc/shift`. Addresses under
+//! `/bcr2kosc/` are never shifted.
+
+use super::*;
+
+/// The flag that is set while the controls are shifted.
+pub const SHIFT_FLAG: &str = "shift";
+
+/// The address that presses and releases of shift buttons are sent to.
+pub const SHIFT_ADDR: &str = "/bcr2kosc/shift";
+
+/// The prefix of addresses that are never shifted.
+const UNSHIFTED_PREFIX: &str = "/bcr2kosc/";
+
+/// Sets the shift flag while its button is held.
+pub struct ShiftTranslator {
+    button: Button,
+    state: BridgeState,
+}
+
+impl ShiftTranslator {
+    pub fn new(button: Button, state: BridgeState) -> Result<Box<dyn Translator>> {
+        Ok(Box::new(Self { button, state }))
+    }
+}
+
+impl Translator for ShiftTranslator {
+    fn midi_to_osc(&self, midi: &MidiMessage) -> Option<OscPacket> {
+        if !self.button.is_sent_by(midi) {
+            return None;
+        }
+        let held = self.button.is_pressed_by(midi);
+        self.state.set_flag(SHIFT_FLAG, held);
+        Some(OscPacket::Message(OscMessage {
+            addr: SHIFT_ADDR.to_string(),
+            args: vec![OscType::Int(held as i32)],
+        }))
+    }
+
+    fn osc_to_midi(&self, _addr_matcher: &Matcher, _args: &[OscType]) -> Vec<MidiMessage> {
+        vec![]
+    }
+
+    fn handles_midi(&self, midi: &MidiMessage) -> bool {
+        self.button.is_sent_by(midi)
+    }
+
+    fn coverage(&self) -> Vec<Coverage> {
+        vec![self.button.coverage()]
+    }
+}
+
+/// Adds the shift prefix to the address of every message in a packet that
+/// can be shifted.
+pub(super) fn shift_packet(prefix: &str, pkt: OscPacket) -> OscPacket {
+    match pkt {
+        OscPacket::Message(msg) if !msg.addr.starts_with(UNSHIFTED_PREFIX) => {
+            OscPacket::Message(OscMessage {
+                addr: format!("{prefix}{}", msg.addr),
+                ..msg
+            })
+        }
+        OscPacket::Message(msg) => OscPacket::Message(msg),
+        OscPacket::Bundle(b) => OscPacket::Bundle(OscBundle {
+            timetag: b.timetag,
+            content: b
+                .content
+                .into_iter()
+                .map(|p| shift_packet(prefix, p))
+                .collect(),
+        }),
+    }
+}
+
+/// Returns the rest of an address under the shift prefix, starting with `/`.
+pub(super) fn unshifted<'a>(prefix: &str, addr: &'a str) -> Option<&'a str> {
+    addr.strip_prefix(prefix)
+        .filter(|rest| rest.starts_with('/'))
+}
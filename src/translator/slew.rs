@@ -0,0 +1,223 @@
+//! Slew limiting of control changes sent to MIDI.
+//!
+//! A coarse OSC fader can jump a control from one end of its range to the
+//! other in a single message, which some synth parameters render as an
+//! audible step. A mapping with a slew rate has such jumps turned into a ramp
+//! of intermediate values, sent over time by a `SlewLimiter`.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use super::*;
+
+/// Wraps a translator, giving control changes it sends a slew rate.
+pub struct Slewed {
+    inner: Box<dyn Translator>,
+    rate: f32,
+}
+
+impl Slewed {
+    /// Wrap a translator. The rate is in control steps per second.
+    pub fn wrap(inner: Box<dyn Translator>, rate: f32) -> Box<dyn Translator> {
+        Box::new(Slewed { inner, rate })
+    }
+}
+
+impl Translator for Slewed {
+    fn midi_to_osc(&self, midi: &MidiMessage) -> Option<OscPacket> {
+        self.inner.midi_to_osc(midi)
+    }
+
+    fn osc_to_midi(&self, addr_matcher: &Matcher, args: &[OscType]) -> Vec<MidiMessage> {
+        self.inner.osc_to_midi(addr_matcher, args)
+    }
+
+    fn coverage(&self) -> Vec<Coverage> {
+        self.inner.coverage()
+    }
+
+    fn slew_rate(&self) -> Option<f32> {
+        Some(self.rate)
+    }
+}
+
+struct Ramp {
+    current: f32,
+    target: u8,
+    rate: f32,
+}
+
+/// Tracks the last value sent for each control, and the controls that are
+/// ramping toward a new value.
+#[derive(Default)]
+pub struct SlewLimiter {
+    last: HashMap<(u8, u8), u8>,
+    ramps: HashMap<(u8, u8), Ramp>,
+}
+
+impl SlewLimiter {
+    /// Submits a message for sending. Returns it unchanged if it should be
+    /// sent now, or `None` if it starts or redirects a ramp, in which case
+    /// intermediate values are produced by `advance`.
+    ///
+    /// Only control changes are slewed. The first value seen for a control is
+    /// always sent immediately, since there's nothing to ramp from.
+    pub fn submit(&mut self, msg: MidiMessage, rate: Option<f32>) -> Option<MidiMessage> {
+        let (ch, control, value) = match (&msg, rate) {
+            (MidiMessage::ControlChange(ch, ControlEvent { control, value }), Some(_)) => {
+                (*ch, *control, *value)
+            }
+            (MidiMessage::ControlChange(ch, ControlEvent { control, value }), None) => {
+                let key = (*ch as u8, *control);
+                self.ramps.remove(&key);
+                self.last.insert(key, *value);
+                return Some(msg);
+            }
+            _ => return Some(msg),
+        };
+        let rate = rate.unwrap_or_default();
+        let key = (ch as u8, control);
+        let current = match (self.ramps.get(&key), self.last.get(&key)) {
+            (Some(ramp), _) => ramp.current,
+            (None, Some(last)) => *last as f32,
+            (None, None) => {
+                self.last.insert(key, value);
+                return Some(msg);
+            }
+        };
+        if (value as f32 - current).abs() <= 1.0 || rate <= 0.0 {
+            self.ramps.remove(&key);
+            self.last.insert(key, value);
+            return Some(msg);
+        }
+        self.ramps.insert(
+            key,
+            Ramp {
+                current,
+                target: value,
+                rate,
+            },
+        );
+        None
+    }
+
+    /// Returns true if any control is ramping.
+    pub fn is_active(&self) -> bool {
+        !self.ramps.is_empty()
+    }
+
+    /// Moves each ramp forward by the elapsed time, returning the control
+    /// changes to send.
+    pub fn advance(&mut self, elapsed: Duration) -> Vec<MidiMessage> {
+        let mut msgs = vec![];
+        let mut done = vec![];
+        for (key, ramp) in self.ramps.iter_mut() {
+            let step = ramp.rate * elapsed.as_secs_f32();
+            let target = ramp.target as f32;
+            ramp.current = if ramp.current < target {
+                (ramp.current + step).min(target)
+            } else {
+                (ramp.current - step).max(target)
+            };
+            let value = ramp.current.round() as u8;
+            if self.last.get(key) != Some(&value) {
+                self.last.insert(*key, value);
+                msgs.push(MidiMessage::ControlChange(
+                    Channel::from(key.0),
+                    ControlEvent {
+                        control: key.1,
+                        value,
+                    },
+                ));
+            }
+            if value == ramp.target {
+                done.push(*key);
+            }
+        }
+        for key in done {
+            self.ramps.remove(&key);
+        }
+        msgs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: Option<f32> = Some(100.0);
+
+    fn cc(value: u8) -> MidiMessage {
+        MidiMessage::ControlChange(Channel::Ch1, ControlEvent { control: 7, value })
+    }
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    /// A limiter that last sent `value`.
+    fn limiter_at(value: u8) -> SlewLimiter {
+        let mut limiter = SlewLimiter::default();
+        assert_eq!(limiter.submit(cc(value), RATE), Some(cc(value)));
+        limiter
+    }
+
+    #[test]
+    fn first_value_is_sent() {
+        let mut limiter = limiter_at(100);
+        assert!(!limiter.is_active());
+        assert!(limiter.advance(ms(100)).is_empty());
+    }
+
+    #[test]
+    fn ramp_steps_by_rate() {
+        let mut limiter = limiter_at(0);
+        assert_eq!(limiter.submit(cc(100), RATE), None);
+        assert!(limiter.is_active());
+        assert_eq!(limiter.advance(ms(100)), vec![cc(10)]);
+        assert_eq!(limiter.advance(ms(250)), vec![cc(35)]);
+        // Too little time for a whole step.
+        assert!(limiter.advance(ms(1)).is_empty());
+        assert_eq!(limiter.advance(ms(99)), vec![cc(45)]);
+    }
+
+    #[test]
+    fn ramp_stops_at_target() {
+        let mut limiter = limiter_at(100);
+        assert_eq!(limiter.submit(cc(20), RATE), None);
+        assert_eq!(limiter.advance(ms(500)), vec![cc(50)]);
+        assert_eq!(limiter.advance(ms(5000)), vec![cc(20)]);
+        assert!(!limiter.is_active());
+        assert!(limiter.advance(ms(100)).is_empty());
+    }
+
+    #[test]
+    fn ramp_reverses() {
+        let mut limiter = limiter_at(0);
+        assert_eq!(limiter.submit(cc(100), RATE), None);
+        assert_eq!(limiter.advance(ms(500)), vec![cc(50)]);
+        assert_eq!(limiter.submit(cc(20), RATE), None);
+        assert_eq!(limiter.advance(ms(100)), vec![cc(40)]);
+        assert_eq!(limiter.advance(ms(1000)), vec![cc(20)]);
+        assert!(!limiter.is_active());
+    }
+
+    #[test]
+    fn small_changes_are_sent() {
+        let mut limiter = limiter_at(64);
+        assert_eq!(limiter.submit(cc(65), RATE), Some(cc(65)));
+        assert_eq!(limiter.submit(cc(64), RATE), Some(cc(64)));
+        assert!(!limiter.is_active());
+    }
+
+    #[test]
+    fn unslewed_value_ends_ramp() {
+        let mut limiter = limiter_at(0);
+        assert_eq!(limiter.submit(cc(100), RATE), None);
+        assert_eq!(limiter.submit(cc(90), None), Some(cc(90)));
+        assert!(!limiter.is_active());
+        // The next ramp starts from the value sent.
+        assert_eq!(limiter.submit(cc(0), RATE), None);
+        assert_eq!(limiter.advance(ms(100)), vec![cc(80)]);
+    }
+}
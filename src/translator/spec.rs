@@ -0,0 +1,609 @@
+//! Mappings described in text, one per line.
+//!
+//! Each line names the kind of mapping, its MIDI parameters, and its OSC
+//! address, followed by options. Channels are numbered 1 through 16. Blank
+//! lines and lines starting with `#` are ignored.
+//!
+//! ```text
+//! cc      CHANNEL CONTROL ADDRESS             a control change
+//! toggle  CHANNEL CONTROL ADDRESS             a control change used as a switch
+//! note    CHANNEL KEY ADDRESS                 a note
+//! bank    CHANNEL FIRST-LAST PREFIX           consecutive control changes
+//! cc*     CHANNELS CONTROL|* TEMPLATE         control changes, address template
+//! cc-channel CHANNEL CONTROL TEMPLATE         channels selected by template index
+//! cc-control CHANNEL CONTROL TEMPLATE         controls selected by template index
+//! cc-groups CHANNELS CONTROLS TEMPLATE        an encoder in each encoder group
+//! group-cc   CHANNEL CONTROL GROUP            a control change selecting a group
+//! group-note CHANNEL KEY GROUP                a note selecting a group
+//! shift-cc   CHANNEL CONTROL PREFIX           a control change shifting the rest
+//! shift-note CHANNEL KEY PREFIX               a note shifting the rest
+//! states  CHANNEL CONTROL ADDRESS STATES      a control change per OSC value
+//! ```
+//!
+//! `STATES` is a comma-separated list of `KEY:VALUE` pairs, each giving the
+//! control value sent for an OSC integer or string, such as
+//! `off:0,on:127,blink:64`. A key that parses as an integer is an integer.
+//!
+//! A `cc-groups` mapping gives the channel and control that an encoder sends
+//! in each encoder group, in order, as comma-separated lists of channels and
+//! controls; a list of one applies to every group. So `cc-groups 1
+//! 1,9,17,25 /encoder/1/group/{1-4}` maps the first encoder of a BCR2000's
+//! four groups in their factory programming.
+//!
+//! `CHANNELS` is `*` or a comma-separated list of channels. The options are
+//! `range=LOW-HIGH`, `slew=RATE`, `steps=N`, `values=A,B,...`,
+//! `feedback=ADDRESS`, `output=TYPE`, `coercion=strict|permissive`, and
+//! `threshold=EPSILON`, with the same meanings as the `Mapping` methods of the
+//! same names, and `long=MS` and `double=MS`, the times in milliseconds of
+//! long and double presses of buttons; see the `gesture` module, and
+//! `group=N`, the encoder group the mapping translates in; see the `group`
+//! module. `when=CONDITION` makes the mapping translate only while the
+//! condition holds, such as `when=bank:2` or `when=!shift`, and can be
+//! given more than once; see the `condition` module. Note mappings also take
+//! `velocity=fixed|linear|curve:EXP`; see the `velocity` module.
+//! `unit=UNIT`, `scale=LOW,HIGH` and `color=RRGGBB` tell people what the
+//! mapping controls, without changing what it translates; see the `info`
+//! module.
+//!
+//! A mapping, or button, can be described for people reading the
+//! configuration later, by ending its line with `#` and the description,
+//! which can't itself contain `#`. The description is shown with the
+//! mapping by `show-mappings` and in traces:
+//!
+//! ```text
+//! cc 1 7 /track/1/volume range=0-100 # Lead vocal level
+//! ```
+//!
+//! Mappings shared between files can be kept in a file of their own, and
+//! included with `include PATH`. A relative path is relative to the
+//! directory of the including file.
+//!
+//! Groups of similar mappings can be written once, as a mapping template,
+//! and used many times with different arguments. A template is defined
+//! between `define NAME PARAMETER...` and `end`; its lines refer to the
+//! parameters as `$PARAMETER` or `${PARAMETER}`. `use NAME ARGUMENT...`
+//! adds its mappings with each parameter replaced by the matching argument:
+//!
+//! ```text
+//! define strip CH N
+//! cc     $CH 1  /strip/$N/gain
+//! toggle $CH 65 /strip/$N/mute
+//! end
+//! use strip 1 1
+//! use strip 2 2
+//! ```
+//!
+//! Templates defined in an included file can be used after the `include`,
+//! and can't be defined again. Templates can use other templates, and
+//! included files can include others, up to a depth of 16.
+
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::time::Duration;
+
+use super::*;
+
+impl TranslationSetBuilder {
+    /// Add the mappings described by lines of text. Errors identify the
+    /// offending line by number, counting from 1. Included files are found
+    /// relative to the current directory.
+    pub fn text<S: AsRef<str>>(self, lines: &[S]) -> Result<Self> {
+        Expander::default().lines(self, lines, Path::new(""))
+    }
+
+    /// Add the mappings in a file. Errors identify the file and line.
+    pub fn file(self, path: &Path) -> Result<Self> {
+        Expander::default().include(self, path)
+    }
+
+    /// Add the mapping described by a single line of text. Includes and
+    /// templates aren't expanded.
+    pub fn line(self, line: &str) -> Result<Self> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(self);
+        }
+        let (line, description) = match line.split_once('#') {
+            Some((line, d)) => (line, Some(d.trim()).filter(|d| !d.is_empty())),
+            None => (line, None),
+        };
+        let start = self.translator_count();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.len() < 4 {
+            return Err(
+                format!("expected a kind, two MIDI parameters and an address: {line}").into(),
+            );
+        }
+        let (kind, a, b, address, options) = (words[0], words[1], words[2], words[3], &words[4..]);
+        let set = match kind {
+            "cc" => options_for(self.cc(channel(a)?, number(b)?), options)?.osc(address),
+            "toggle" => {
+                options_for(self.cc(channel(a)?, number(b)?).toggle(), options)?.osc(address)
+            }
+            "note" => {
+                let (curve, options) = velocity_option(options)?;
+                options_for(self.note(channel(a)?, number(b)?).velocity(curve), &options)?
+                    .osc(address)
+            }
+            "bank" => options_for(self.bank(channel(a)?, range(b)?), options)?.osc(address),
+            "cc*" => {
+                let control = if b == "*" { None } else { Some(number(b)?) };
+                options_for(self.cc_wildcard(channels(a)?, control), options)?.osc(address)
+            }
+            "states" => {
+                let (states, options) = options
+                    .split_first()
+                    .ok_or("expected a list of states after the address")?;
+                options_for(
+                    self.cc(channel(a)?, number(b)?).states(state_list(states)?),
+                    options,
+                )?
+                .osc(address)
+            }
+            "cc-channel" => options_for(
+                self.cc_indexed(IndexTarget::Channel, channel(a)?, number(b)?),
+                options,
+            )?
+            .osc(address),
+            "cc-control" => options_for(
+                self.cc_indexed(IndexTarget::Control, channel(a)?, number(b)?),
+                options,
+            )?
+            .osc(address),
+            "cc-groups" => options_for(self.cc_groups(groups(a, b)?), options)?.osc(address),
+            "group-cc" | "group-note" => {
+                if !options.is_empty() {
+                    return Err("a group button takes no options".into());
+                }
+                let button = if kind == "group-cc" {
+                    Button::Cc(channel(a)?, number(b)?)
+                } else {
+                    Button::Note(channel(a)?, number(b)?)
+                };
+                self.group_button(button, group(address)?)
+            }
+            "shift-cc" | "shift-note" => {
+                if !options.is_empty() {
+                    return Err("a shift button takes no options".into());
+                }
+                let button = if kind == "shift-cc" {
+                    Button::Cc(channel(a)?, number(b)?)
+                } else {
+                    Button::Note(channel(a)?, number(b)?)
+                };
+                self.shift_button(button, address)
+            }
+            _ => return Err(format!("unknown kind of mapping \"{kind}\"").into()),
+        };
+        Ok(match description {
+            Some(d) => set.describe_from(start, d),
+            None => set,
+        })
+    }
+}
+
+/// The most deeply that includes and template uses can nest.
+const MAX_DEPTH: usize = 16;
+
+/// A group of mapping lines with parameters.
+#[derive(Clone)]
+struct MappingTemplate {
+    params: Vec<String>,
+    lines: Vec<String>,
+}
+
+impl MappingTemplate {
+    /// Returns the lines, with parameters replaced by arguments.
+    fn expand(&self, args: &[&str]) -> Vec<String> {
+        // Longer names are replaced first, so that `$N` doesn't replace the
+        // start of `$NAME`.
+        let mut params: Vec<(&String, &str)> =
+            self.params.iter().zip(args.iter().copied()).collect();
+        params.sort_by_key(|(p, _)| std::cmp::Reverse(p.len()));
+        self.lines
+            .iter()
+            .map(|line| {
+                params.iter().fold(line.clone(), |line, (p, a)| {
+                    line.replace(&format!("${{{p}}}"), a)
+                        .replace(&format!("${p}"), a)
+                })
+            })
+            .collect()
+    }
+}
+
+/// Expands includes and mapping templates, adding the mappings that result.
+#[derive(Default)]
+struct Expander {
+    templates: HashMap<String, MappingTemplate>,
+    /// The template being defined, if any.
+    defining: Option<(String, MappingTemplate)>,
+    depth: usize,
+}
+
+impl Expander {
+    fn lines<S: AsRef<str>>(
+        &mut self,
+        set: TranslationSetBuilder,
+        lines: &[S],
+        dir: &Path,
+    ) -> Result<TranslationSetBuilder> {
+        let set = lines.iter().enumerate().try_fold(set, |set, (i, line)| {
+            self.line(set, line.as_ref(), dir)
+                .map_err(|e| format!("line {}: {e}", i + 1))
+        })?;
+        match self.defining.take() {
+            Some((name, _)) => Err(format!("template \"{name}\" has no end").into()),
+            None => Ok(set),
+        }
+    }
+
+    fn line(
+        &mut self,
+        set: TranslationSetBuilder,
+        line: &str,
+        dir: &Path,
+    ) -> Result<TranslationSetBuilder> {
+        let line = line.trim();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if let Some((name, template)) = &mut self.defining {
+            if words == ["end"] {
+                self.templates.insert(name.clone(), template.clone());
+                self.defining = None;
+            } else {
+                template.lines.push(line.to_string());
+            }
+            return Ok(set);
+        }
+        match words.first() {
+            Some(&"include") => match words[1..] {
+                [path] => self.include(set, &dir.join(path)),
+                _ => Err("expected include PATH".into()),
+            },
+            Some(&"define") => {
+                let name = words.get(1).ok_or("expected define NAME PARAMETER...")?;
+                if self.templates.contains_key(*name) {
+                    return Err(format!("template \"{name}\" is already defined").into());
+                }
+                let params = words[2..]
+                    .iter()
+                    .map(|p| p.trim_start_matches('$').to_string())
+                    .collect();
+                let template = MappingTemplate {
+                    params,
+                    lines: vec![],
+                };
+                self.defining = Some((name.to_string(), template));
+                Ok(set)
+            }
+            Some(&"use") => {
+                let name = words.get(1).ok_or("expected use NAME ARGUMENT...")?;
+                let template = self
+                    .templates
+                    .get(*name)
+                    .ok_or_else(|| format!("unknown template \"{name}\""))?;
+                let args = &words[2..];
+                if args.len() != template.params.len() {
+                    return Err(format!(
+                        "template \"{name}\" takes {} arguments, not {}",
+                        template.params.len(),
+                        args.len()
+                    )
+                    .into());
+                }
+                let lines = template.expand(args);
+                self.nested(|e| e.lines(set, &lines, dir))
+                    .map_err(|e| format!("{name}: {e}").into())
+            }
+            _ => set.line(line),
+        }
+    }
+
+    fn include(
+        &mut self,
+        set: TranslationSetBuilder,
+        path: &Path,
+    ) -> Result<TranslationSetBuilder> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        let lines: Vec<&str> = text.lines().collect();
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        self.nested(|e| e.lines(set, &lines, dir))
+            .map_err(|e| format!("{}: {e}", path.display()).into())
+    }
+
+    /// Runs `f` one level deeper, failing if that's too deep. This also stops
+    /// files that include themselves, and templates that use themselves.
+    fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        if self.depth == MAX_DEPTH {
+            return Err("includes or templates are nested too deeply".into());
+        }
+        self.depth += 1;
+        let r = f(self);
+        self.depth -= 1;
+        r
+    }
+}
+
+fn options_for<K>(mut mapping: Mapping<K>, options: &[&str]) -> Result<Mapping<K>> {
+    for option in options {
+        let (name, value) = option
+            .split_once('=')
+            .ok_or_else(|| format!("expected NAME=VALUE, not \"{option}\""))?;
+        mapping = match name {
+            "range" => mapping.range(range(value)?),
+            "slew" => mapping.slew(
+                value
+                    .parse()
+                    .map_err(|_| format!("invalid slew rate \"{value}\""))?,
+            ),
+            "steps" => mapping.steps(number(value)?),
+            "feedback" => mapping.feedback(value),
+            "output" => mapping.output(value.parse()?),
+            "coercion" => mapping.coercion(value.parse()?),
+            "threshold" => mapping.threshold(
+                value
+                    .parse()
+                    .map_err(|_| format!("invalid threshold \"{value}\""))?,
+            ),
+            "long" => mapping.long_press(millis(value)?),
+            "double" => mapping.double_press(millis(value)?),
+            "group" => mapping.group(group(value)?),
+            "when" => mapping.when(value.parse()?),
+            "values" => mapping.values(value.split(',').map(number).collect::<Result<_>>()?),
+            "unit" => mapping.unit(value),
+            "scale" => {
+                let (low, high) = parse_scale(value)?;
+                mapping.scale(low, high)
+            }
+            "color" => mapping.color(value.parse()?),
+            _ => return Err(format!("unknown option \"{name}\"").into()),
+        };
+    }
+    Ok(mapping)
+}
+
+/// Takes the velocity curve, if any, from a note mapping's options.
+fn velocity_option<'a>(options: &[&'a str]) -> Result<(VelocityCurve, Vec<&'a str>)> {
+    let mut curve = VelocityCurve::default();
+    let mut rest = vec![];
+    for option in options {
+        match option.strip_prefix("velocity=") {
+            Some(value) => curve = value.parse()?,
+            None => rest.push(*option),
+        }
+    }
+    Ok((curve, rest))
+}
+
+fn channel(s: &str) -> Result<Channel> {
+    match s.parse::<u8>() {
+        Ok(n) if (1..=16).contains(&n) => Ok(Channel::from(n - 1)),
+        _ => Err(format!("invalid MIDI channel \"{s}\"").into()),
+    }
+}
+
+fn channels(s: &str) -> Result<Channels> {
+    if s == "*" {
+        Ok(Channels::ANY)
+    } else {
+        s.split(',')
+            .map(channel)
+            .collect::<Result<Vec<_>>>()
+            .map(|v| v.into_iter().collect())
+    }
+}
+
+/// Pairs up lists of channels and controls, one of each per encoder group.
+fn groups(channels: &str, controls: &str) -> Result<Vec<(Channel, u8)>> {
+    let channels = channels
+        .split(',')
+        .map(channel)
+        .collect::<Result<Vec<_>>>()?;
+    let controls = controls
+        .split(',')
+        .map(number)
+        .collect::<Result<Vec<_>>>()?;
+    let count = channels.len().max(controls.len());
+    let fits = |n: usize| n == 1 || n == count;
+    if !fits(channels.len()) || !fits(controls.len()) {
+        return Err("expected as many channels as controls, or just one".into());
+    }
+    Ok((0..count)
+        .map(|i| {
+            let channel = channels[i.min(channels.len() - 1)];
+            let control = controls[i.min(controls.len() - 1)];
+            (channel, control)
+        })
+        .collect())
+}
+
+fn group(s: &str) -> Result<u8> {
+    match s.parse::<u8>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("invalid group \"{s}\"").into()),
+    }
+}
+
+fn number(s: &str) -> Result<u8> {
+    match s.parse::<u8>() {
+        Ok(n) if n < 128 => Ok(n),
+        _ => Err(format!("invalid MIDI value \"{s}\"").into()),
+    }
+}
+
+fn state_list(s: &str) -> Result<Vec<(StateKey, u8)>> {
+    s.split(',')
+        .map(|state| {
+            let (key, value) = state
+                .split_once(':')
+                .ok_or_else(|| format!("expected KEY:VALUE, not \"{state}\""))?;
+            let key = match key.parse::<i32>() {
+                Ok(i) => StateKey::Int(i),
+                Err(_) => StateKey::Name(key.to_string()),
+            };
+            Ok((key, number(value)?))
+        })
+        .collect()
+}
+
+fn millis(s: &str) -> Result<Duration> {
+    match s.parse::<u64>() {
+        Ok(ms) if ms > 0 => Ok(Duration::from_millis(ms)),
+        _ => Err(format!("invalid time in milliseconds \"{s}\"").into()),
+    }
+}
+
+fn range(s: &str) -> Result<RangeInclusive<u8>> {
+    let (low, high) = s
+        .split_once('-')
+        .ok_or_else(|| format!("expected LOW-HIGH, not \"{s}\""))?;
+    Ok(number(low)?..=number(high)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::PGM;
+
+    fn cc(channel: Channel, control: u8, value: u8) -> MidiMessage {
+        MidiMessage::ControlChange(channel, ControlEvent { control, value })
+    }
+
+    /// The address that a control change is translated to, if any.
+    fn address(set: &ServerTranslationSet, midi: MidiMessage) -> Option<String> {
+        match set.midi_msg_to_osc(&midi)? {
+            OscPacket::Message(m) => Some(m.addr),
+            OscPacket::Bundle(_) => None,
+        }
+    }
+
+    /// A directory of mapping files for a test, removed when dropped.
+    struct Files(PathBuf);
+
+    impl Files {
+        fn new(test: &str, files: &[(&str, &str)]) -> Self {
+            let dir = std::env::temp_dir().join(format!("{PGM}-{}-{test}", std::process::id()));
+            for (name, text) in files {
+                let path = dir.join(name);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, text).unwrap();
+            }
+            Files(dir)
+        }
+
+        fn load(&self, name: &str) -> Result<ServerTranslationSet> {
+            TranslationSetBuilder::new()
+                .file(&self.0.join(name))?
+                .build()
+        }
+    }
+
+    impl Drop for Files {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn text_error(lines: &[&str]) -> String {
+        match TranslationSetBuilder::new().text(lines) {
+            Ok(_) => panic!("expected an error from {lines:?}"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn nested_includes_are_relative_to_their_files() {
+        let files = Files::new(
+            "nested",
+            &[
+                ("main.map", "include base/strips.inc\nuse strip 1 1\n"),
+                (
+                    "base/strips.inc",
+                    "include common/gain.inc\ndefine strip CH N\nuse gain $CH $N\n\
+                     toggle $CH 65 /strip/$N/mute\nend\n",
+                ),
+                (
+                    "base/common/gain.inc",
+                    "define gain CH N\ncc $CH 1 /strip/${N}/gain\nend\n",
+                ),
+            ],
+        );
+        let set = files.load("main.map").unwrap();
+        let gain = address(&set, cc(Channel::Ch1, 1, 127));
+        assert_eq!(gain.as_deref(), Some("/strip/1/gain"));
+        let mute = address(&set, cc(Channel::Ch1, 65, 127));
+        assert_eq!(mute.as_deref(), Some("/strip/1/mute"));
+    }
+
+    #[test]
+    fn include_cycles_fail() {
+        let files = Files::new(
+            "cycle",
+            &[
+                ("a.map", "cc 1 1 /a\ninclude b.map\n"),
+                ("b.map", "include a.map\n"),
+            ],
+        );
+        let Err(e) = files.load("a.map") else {
+            panic!("expected an error from a cycle of includes");
+        };
+        let e = e.to_string();
+        assert!(e.contains("nested too deeply"), "{e}");
+        assert!(e.contains("line 2: "), "{e}");
+    }
+
+    #[test]
+    fn templates_using_themselves_fail() {
+        let e = text_error(&["define loop N", "use loop $N", "end", "use loop 1"]);
+        assert!(e.starts_with("line 4: loop: line 1: loop: "), "{e}");
+        assert!(e.ends_with("nested too deeply"), "{e}");
+    }
+
+    #[test]
+    fn unknown_templates_and_missing_files_fail() {
+        assert_eq!(
+            text_error(&["cc 1 1 /a", "use strip 1 1"]),
+            "line 2: unknown template \"strip\""
+        );
+        let e = text_error(&["include no-such-file.map"]);
+        assert!(e.starts_with("line 1: no-such-file.map: "), "{e}");
+    }
+
+    #[test]
+    fn templates_cant_be_redefined() {
+        assert_eq!(
+            text_error(&["define t N", "cc 1 $N /a", "end", "define t M", "end"]),
+            "line 4: template \"t\" is already defined"
+        );
+    }
+
+    #[test]
+    fn template_arguments_must_match() {
+        assert_eq!(
+            text_error(&["define t CH N", "cc $CH $N /a", "end", "use t 1"]),
+            "line 4: template \"t\" takes 2 arguments, not 1"
+        );
+        assert_eq!(
+            text_error(&["define t N", "cc 1 $N /a"]),
+            "template \"t\" has no end"
+        );
+        assert_eq!(
+            text_error(&["define t N", "cc 1 $N /a", "end", "use t 200"]),
+            "line 4: t: line 1: invalid MIDI value \"200\""
+        );
+    }
+
+    #[test]
+    fn longer_parameters_are_replaced_first() {
+        let set = TranslationSetBuilder::new()
+            .text(&["define t N NAME", "cc 1 $N /$NAME/$N", "end", "use t 3 vol"])
+            .unwrap()
+            .build()
+            .unwrap();
+        let addr = address(&set, cc(Channel::Ch1, 3, 0));
+        assert_eq!(addr.as_deref(), Some("/vol/3"));
+    }
+}
@@ -0,0 +1,428 @@
+//! OSC address templates, which carry MIDI parameters in the address.
+//!
+//! A template is an OSC address containing placeholders. `{c}` stands for a
+//! MIDI channel, numbered 1 through 16, and `{n}` for a control or note
+//! number, 0 through 127. For example, `/ch/{c}/cc/{n}` renders control 7 on
+//! channel 2 as `/ch/2/cc/7`, and parses that address back to the same values.
+//!
+//! A range placeholder such as `{1-16}` stands for an index within that range.
+//! Translators decide what the index selects; see `IndexTarget`.
+
+use std::fmt::Display;
+use std::ops::RangeInclusive;
+
+use rosc::address::verify_address;
+
+use super::*;
+
+/// A set of MIDI channels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Channels(u16);
+
+impl Channels {
+    /// All sixteen channels.
+    pub const ANY: Channels = Channels(0xffff);
+
+    /// Returns true if the channel is in the set.
+    pub fn contains(&self, channel: Channel) -> bool {
+        channel != Channel::Invalid && self.0 & (1 << channel as u8) != 0
+    }
+
+    /// Returns the channel if the set holds exactly one.
+    pub fn single(&self) -> Option<Channel> {
+        if self.0.count_ones() == 1 {
+            Some(Channel::from(self.0.trailing_zeros() as u8))
+        } else {
+            None
+        }
+    }
+
+    /// Iterates over the channels in the set, in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = Channel> + '_ {
+        (0..16u8)
+            .map(Channel::from)
+            .filter(move |c| self.contains(*c))
+    }
+}
+
+impl From<Channel> for Channels {
+    fn from(channel: Channel) -> Self {
+        std::iter::once(channel).collect()
+    }
+}
+
+impl FromIterator<Channel> for Channels {
+    fn from_iter<T: IntoIterator<Item = Channel>>(iter: T) -> Self {
+        Channels(
+            iter.into_iter()
+                .filter(|c| *c != Channel::Invalid)
+                .fold(0, |bits, c| bits | 1 << c as u8),
+        )
+    }
+}
+
+/// Values carried by an address rendered from, or parsed by, a template.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TemplateValues {
+    pub channel: Option<Channel>,
+    pub number: Option<u8>,
+    pub index: Option<u8>,
+}
+
+#[derive(Clone, Debug)]
+enum TemplatePart {
+    Literal(String),
+    Channel,
+    Number,
+    Index(u8, u8),
+}
+
+/// An OSC address with placeholders for MIDI parameters.
+#[derive(Clone, Debug)]
+pub struct AddressTemplate {
+    parts: Vec<TemplatePart>,
+}
+
+impl AddressTemplate {
+    /// Parses a template. Apart from placeholders, it must be a valid OSC
+    /// address.
+    pub fn new(template: &str) -> Result<Self> {
+        let mut parts = vec![];
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(TemplatePart::Literal(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("unterminated placeholder in \"{template}\""))?;
+            parts.push(match &rest[start + 1..start + end] {
+                "c" => TemplatePart::Channel,
+                "n" => TemplatePart::Number,
+                p => match parse_index_range(p) {
+                    Some((lo, hi)) => TemplatePart::Index(lo, hi),
+                    None => {
+                        return Err(format!("unknown placeholder {{{p}}} in \"{template}\"").into())
+                    }
+                },
+            });
+            rest = &rest[start + end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(TemplatePart::Literal(rest.to_string()));
+        }
+        let template = AddressTemplate { parts };
+        // Placeholders render as digits, so a sample rendering is a valid
+        // address exactly when the template's literal text is.
+        OscAddress::new(template.render(&TemplateValues {
+            channel: Some(Channel::Ch1),
+            number: Some(0),
+            index: Some(0),
+        }))?;
+        Ok(template)
+    }
+
+    /// Returns true if the template has a channel placeholder.
+    pub fn has_channel(&self) -> bool {
+        self.parts
+            .iter()
+            .any(|p| matches!(p, TemplatePart::Channel))
+    }
+
+    /// Returns true if the template has a number placeholder.
+    pub fn has_number(&self) -> bool {
+        self.parts.iter().any(|p| matches!(p, TemplatePart::Number))
+    }
+
+    /// Returns the range of the template's index placeholder, if it has one.
+    pub fn index_range(&self) -> Option<RangeInclusive<u8>> {
+        self.parts.iter().find_map(|p| match p {
+            TemplatePart::Index(lo, hi) => Some(*lo..=*hi),
+            _ => None,
+        })
+    }
+
+    /// Renders an address. Placeholders without a value render as zero.
+    pub fn render(&self, values: &TemplateValues) -> String {
+        let mut s = String::new();
+        for part in &self.parts {
+            match part {
+                TemplatePart::Literal(l) => s.push_str(l),
+                TemplatePart::Channel => {
+                    let ch = values.channel.map(|c| c as u8 + 1).unwrap_or(0);
+                    s.push_str(&ch.to_string());
+                }
+                TemplatePart::Number => s.push_str(&values.number.unwrap_or(0).to_string()),
+                TemplatePart::Index(..) => s.push_str(&values.index.unwrap_or(0).to_string()),
+            }
+        }
+        s
+    }
+
+    /// Extracts the values from a literal OSC address, if it matches the
+    /// template.
+    pub fn parse(&self, address: &str) -> Option<TemplateValues> {
+        let mut values = TemplateValues::default();
+        let mut rest = address;
+        for part in &self.parts {
+            match part {
+                TemplatePart::Literal(l) => rest = rest.strip_prefix(l.as_str())?,
+                TemplatePart::Channel => {
+                    let (n, r) = split_number(rest)?;
+                    if !(1..=16).contains(&n) {
+                        return None;
+                    }
+                    values.channel = Some(Channel::from(n - 1));
+                    rest = r;
+                }
+                TemplatePart::Number => {
+                    let (n, r) = split_number(rest)?;
+                    if n > 127 {
+                        return None;
+                    }
+                    values.number = Some(n);
+                    rest = r;
+                }
+                TemplatePart::Index(lo, hi) => {
+                    let (n, r) = split_number(rest)?;
+                    if !(*lo..=*hi).contains(&n) {
+                        return None;
+                    }
+                    values.index = Some(n);
+                    rest = r;
+                }
+            }
+        }
+        if rest.is_empty() {
+            Some(values)
+        } else {
+            None
+        }
+    }
+
+    /// Finds the values of every rendering of the template matched by an
+    /// incoming OSC address pattern, given the candidate channels and numbers.
+    ///
+    /// Literal addresses are parsed directly. Patterns are matched against
+    /// each candidate rendering, which is slower but rarely needed.
+    pub fn matches(
+        &self,
+        addr_matcher: &Matcher,
+        channels: Channels,
+        numbers: RangeInclusive<u8>,
+    ) -> Vec<TemplateValues> {
+        if verify_address(&addr_matcher.pattern).is_ok() {
+            return self.parse(&addr_matcher.pattern).into_iter().collect();
+        }
+        let channels: Vec<Option<Channel>> = if self.has_channel() {
+            channels.iter().map(Some).collect()
+        } else {
+            vec![None]
+        };
+        let numbers: Vec<Option<u8>> = if self.has_number() {
+            numbers.map(Some).collect()
+        } else {
+            vec![None]
+        };
+        let indexes: Vec<Option<u8>> = match self.index_range() {
+            Some(r) => r.map(Some).collect(),
+            None => vec![None],
+        };
+        let mut found = vec![];
+        for channel in &channels {
+            for number in &numbers {
+                for index in &indexes {
+                    let values = TemplateValues {
+                        channel: *channel,
+                        number: *number,
+                        index: *index,
+                    };
+                    if let Ok(address) = OscAddress::new(self.render(&values)) {
+                        if addr_matcher.match_address(&address) {
+                            found.push(values);
+                        }
+                    }
+                }
+            }
+        }
+        found
+    }
+}
+
+impl Display for AddressTemplate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for part in &self.parts {
+            match part {
+                TemplatePart::Literal(l) => l.fmt(f)?,
+                TemplatePart::Channel => "{c}".fmt(f)?,
+                TemplatePart::Number => "{n}".fmt(f)?,
+                TemplatePart::Index(lo, hi) => write!(f, "{{{lo}-{hi}}}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_index_range(s: &str) -> Option<(u8, u8)> {
+    let (lo, hi) = s.split_once('-')?;
+    let (lo, hi) = (lo.parse().ok()?, hi.parse().ok()?);
+    if lo <= hi {
+        Some((lo, hi))
+    } else {
+        None
+    }
+}
+
+fn split_number(s: &str) -> Option<(u8, &str)> {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    Some((s[..end].parse().ok()?, &s[end..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::translator::testing::assert_round_trip;
+
+    fn cc(channel: Channel, control: u8, value: u8) -> MidiMessage {
+        MidiMessage::ControlChange(channel, ControlEvent { control, value })
+    }
+
+    fn osc(addr: &str, value: f32) -> OscMessage {
+        OscMessage {
+            addr: addr.to_string(),
+            args: vec![OscType::Float(value)],
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        for template in [
+            "/ch/{c",
+            "/ch/{x}",
+            "/ch/{}",
+            "/ch/{5-1}",
+            "/ch/{1-x}",
+            "/ch/{1-300}",
+            "ch/{c}",
+            "/ch/{c}/a b",
+            "/ch/{c}/*",
+            "/ch/{c}#",
+        ] {
+            assert!(AddressTemplate::new(template).is_err(), "{template}");
+        }
+    }
+
+    #[test]
+    fn display_matches_source() {
+        for template in ["/ch/{c}/cc/{n}", "/fader/{1-8}", "/volume", "/{c}{n}"] {
+            let t = AddressTemplate::new(template).unwrap();
+            assert_eq!(t.to_string(), template);
+        }
+    }
+
+    #[test]
+    fn render_and_parse() {
+        let t = AddressTemplate::new("/ch/{c}/cc/{n}/{1-8}").unwrap();
+        let values = TemplateValues {
+            channel: Some(Channel::Ch16),
+            number: Some(127),
+            index: Some(8),
+        };
+        assert_eq!(t.render(&values), "/ch/16/cc/127/8");
+        assert_eq!(t.parse("/ch/16/cc/127/8"), Some(values));
+        for address in [
+            "/ch/0/cc/1/1",
+            "/ch/17/cc/1/1",
+            "/ch/1/cc/128/1",
+            "/ch/1/cc/1/9",
+            "/ch/1/cc/1/0",
+            "/ch/1/cc/x/1",
+            "/ch/1/cc/1/1/",
+            "/ch/1/cc/1",
+            "/ch/1/cc/999/1",
+        ] {
+            assert_eq!(t.parse(address), None, "{address}");
+        }
+    }
+
+    #[test]
+    fn channel_wildcard_round_trip() {
+        let t = ControlChangeTemplateTranslator::new(
+            Channels::ANY,
+            Some(30),
+            0,
+            127,
+            "/chan/{c}/level",
+        )
+        .unwrap();
+        assert_round_trip(
+            t.as_ref(),
+            &cc(Channel::Ch1, 30, 127),
+            &osc("/chan/1/level", 1.0),
+        );
+        assert_round_trip(
+            t.as_ref(),
+            &cc(Channel::Ch16, 30, 0),
+            &osc("/chan/16/level", 0.0),
+        );
+        assert!(t.midi_to_osc(&cc(Channel::Ch1, 31, 0)).is_none());
+        let matcher = Matcher::new("/chan/17/level").unwrap();
+        assert!(t.osc_to_midi(&matcher, &[OscType::Float(1.0)]).is_empty());
+    }
+
+    #[test]
+    fn channel_and_number_round_trip() {
+        let channels = [Channel::Ch2, Channel::Ch3].into_iter().collect();
+        let t =
+            ControlChangeTemplateTranslator::new(channels, None, 0, 127, "/ch/{c}/cc/{n}").unwrap();
+        assert_round_trip(
+            t.as_ref(),
+            &cc(Channel::Ch2, 0, 127),
+            &osc("/ch/2/cc/0", 1.0),
+        );
+        assert_round_trip(
+            t.as_ref(),
+            &cc(Channel::Ch3, 127, 0),
+            &osc("/ch/3/cc/127", 0.0),
+        );
+        assert!(t.midi_to_osc(&cc(Channel::Ch1, 0, 0)).is_none());
+        let matcher = Matcher::new("/ch/1/cc/0").unwrap();
+        assert!(t.osc_to_midi(&matcher, &[OscType::Float(1.0)]).is_empty());
+    }
+
+    #[test]
+    fn pattern_matches_several_channels() {
+        let t = ControlChangeTemplateTranslator::new(
+            Channels::ANY,
+            Some(30),
+            0,
+            127,
+            "/chan/{c}/level",
+        )
+        .unwrap();
+        let matcher = Matcher::new("/chan/{2,5}/level").unwrap();
+        assert_eq!(
+            t.osc_to_midi(&matcher, &[OscType::Float(1.0)]),
+            vec![cc(Channel::Ch2, 30, 127), cc(Channel::Ch5, 30, 127)]
+        );
+    }
+
+    #[test]
+    fn placeholders_required() {
+        assert!(
+            ControlChangeTemplateTranslator::new(Channels::ANY, Some(1), 0, 127, "/level").is_err()
+        );
+        assert!(
+            ControlChangeTemplateTranslator::new(Channel::Ch1.into(), None, 0, 127, "/level")
+                .is_err()
+        );
+        assert!(ControlChangeTemplateTranslator::new(
+            Channel::Ch1.into(),
+            Some(1),
+            0,
+            127,
+            "/level"
+        )
+        .is_ok());
+    }
+}
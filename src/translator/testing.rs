@@ -0,0 +1,350 @@
+#![allow(dead_code)]
+//! Checks of translations in both directions.
+//!
+//! `assert_round_trip` checks a single translator. Golden files check a whole
+//! set of mappings: each line is a case, pairing a MIDI message with an OSC
+//! message, and an arrow saying which directions are checked.
+//!
+//! ```text
+//! cc 1 7 127        <=> /volume 1.0       both directions
+//! note-on 1 60 100  =>  /key/60 1.0       MIDI to OSC only
+//! note-off 1 60 0   <=  /key/60 0.0       OSC to MIDI only
+//! ```
+//!
+//! MIDI messages are `cc CHANNEL CONTROL VALUE`, `note-on CHANNEL KEY
+//! VELOCITY` or `note-off CHANNEL KEY VELOCITY`, with channels numbered 1
+//! through 16. OSC arguments are `true`, `false`, `nil`, `inf`, integers,
+//! floats, which must contain a decimal point, or strings. Floats match if
+//! they differ by less than 0.0005, so four decimal places suffice. Blank
+//! lines and lines starting with `#` are ignored.
+
+use std::path::Path;
+
+use super::*;
+
+/// How closely floats must match.
+const FLOAT_TOLERANCE: f32 = 0.0005;
+
+/// The directions in which a golden case is checked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// MIDI to OSC, and OSC to MIDI.
+    Both,
+    /// MIDI to OSC only.
+    MidiToOsc,
+    /// OSC to MIDI only.
+    OscToMidi,
+}
+
+/// One case from a golden file.
+#[derive(Debug)]
+pub struct GoldenCase {
+    /// The line of the file, counting from 1.
+    pub line: usize,
+    /// The MIDI side of the case.
+    pub midi: MidiMessage,
+    /// The OSC side of the case.
+    pub osc: OscMessage,
+    /// Which directions are checked.
+    pub direction: Direction,
+}
+
+/// Panics unless `translator` translates `midi` to `osc`, and `osc` back to
+/// `midi`.
+pub fn assert_round_trip(translator: &dyn Translator, midi: &MidiMessage, osc: &OscMessage) {
+    assert_midi_to_osc(translator, midi, osc);
+    assert_osc_to_midi(translator, osc, midi);
+}
+
+/// Panics unless `translator` translates `midi` to `osc`.
+pub fn assert_midi_to_osc(translator: &dyn Translator, midi: &MidiMessage, osc: &OscMessage) {
+    let out = translator.midi_to_osc(midi);
+    if let Err(e) = expect_osc(out.as_ref(), osc) {
+        panic!("{midi:?}: {e}");
+    }
+}
+
+/// Panics unless `translator` translates `osc` to `midi`.
+pub fn assert_osc_to_midi(translator: &dyn Translator, osc: &OscMessage, midi: &MidiMessage) {
+    let matcher = Matcher::new(&osc.addr).expect("invalid OSC address");
+    let out = translator.osc_to_midi(
+        &matcher,
+        &coerce_args(&osc.args, translator.coercion().unwrap_or_default()),
+    );
+    if let Err(e) = expect_midi(&out, midi) {
+        panic!("{osc:?}: {e}");
+    }
+}
+
+/// Reads the cases in a golden file.
+pub fn read_golden(path: &Path) -> Result<Vec<GoldenCase>> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    parse_golden(&text).map_err(|e| format!("{}: {e}", path.display()).into())
+}
+
+/// Parses the cases in the text of a golden file.
+pub fn parse_golden(text: &str) -> Result<Vec<GoldenCase>> {
+    let mut cases = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let case = parse_case(i + 1, line).map_err(|e| format!("line {}: {e}", i + 1))?;
+        cases.push(case);
+    }
+    Ok(cases)
+}
+
+impl ServerTranslationSet {
+    /// Checks golden cases against the set, returning a description of each
+    /// failure.
+    pub fn check_golden(&self, cases: &[GoldenCase]) -> Vec<String> {
+        let mut failures = vec![];
+        for case in cases {
+            if case.direction != Direction::OscToMidi {
+                let out = self.midi_msg_to_osc(&case.midi);
+                if let Err(e) = expect_osc(out.as_ref(), &case.osc) {
+                    failures.push(format!("line {}: {:?}: {e}", case.line, case.midi));
+                }
+            }
+            if case.direction != Direction::MidiToOsc {
+                let out: Vec<MidiMessage> = self
+                    .osc_msg_to_slewed_midi(&case.osc)
+                    .into_iter()
+                    .map(|(m, _)| m)
+                    .collect();
+                if let Err(e) = expect_midi(&out, &case.midi) {
+                    failures.push(format!("line {}: {:?}: {e}", case.line, case.osc));
+                }
+            }
+        }
+        failures
+    }
+}
+
+/// Checks that a packet holds a message matching `expected`, describing the
+/// packet's messages if it doesn't.
+pub fn expect_osc(
+    out: Option<&OscPacket>,
+    expected: &OscMessage,
+) -> std::result::Result<(), String> {
+    let msgs = out.map(packet_messages).unwrap_or_default();
+    if msgs.iter().any(|m| osc_matches(m, expected)) {
+        Ok(())
+    } else {
+        Err(format!("expected {expected:?}, got {msgs:?}"))
+    }
+}
+
+/// Checks that `out` holds `expected`, describing `out` if it doesn't.
+pub fn expect_midi(out: &[MidiMessage], expected: &MidiMessage) -> std::result::Result<(), String> {
+    if out.iter().any(|m| m == expected) {
+        Ok(())
+    } else {
+        Err(format!("expected {expected:?}, got {out:?}"))
+    }
+}
+
+fn osc_matches(actual: &OscMessage, expected: &OscMessage) -> bool {
+    actual.addr == expected.addr
+        && actual.args.len() == expected.args.len()
+        && actual
+            .args
+            .iter()
+            .zip(&expected.args)
+            .all(|(a, e)| match (a, e) {
+                (OscType::Float(a), OscType::Float(e)) => (a - e).abs() < FLOAT_TOLERANCE,
+                _ => a == e,
+            })
+}
+
+fn parse_case(line: usize, text: &str) -> Result<GoldenCase> {
+    let (direction, (midi, osc)) = if let Some(sides) = text.split_once("<=>") {
+        (Direction::Both, sides)
+    } else if let Some(sides) = text.split_once("=>") {
+        (Direction::MidiToOsc, sides)
+    } else if let Some(sides) = text.split_once("<=") {
+        (Direction::OscToMidi, sides)
+    } else {
+        return Err("expected <=>, => or <=".into());
+    };
+    Ok(GoldenCase {
+        line,
+        midi: parse_midi(midi)?,
+        osc: parse_osc(osc)?,
+        direction,
+    })
+}
+
+fn parse_midi(text: &str) -> Result<MidiMessage> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    if words.len() != 4 {
+        return Err(format!("expected a MIDI message and three numbers: {text}").into());
+    }
+    let channel = match words[1].parse::<u8>() {
+        Ok(n) if (1..=16).contains(&n) => Channel::from(n - 1),
+        _ => return Err(format!("invalid MIDI channel \"{}\"", words[1]).into()),
+    };
+    let (a, b) = (data_byte(words[2])?, data_byte(words[3])?);
+    Ok(match words[0] {
+        "cc" => MidiMessage::ControlChange(
+            channel,
+            ControlEvent {
+                control: a,
+                value: b,
+            },
+        ),
+        "note-on" => MidiMessage::NoteOn(channel, KeyEvent { key: a, value: b }),
+        "note-off" => MidiMessage::NoteOff(channel, KeyEvent { key: a, value: b }),
+        kind => return Err(format!("unknown MIDI message \"{kind}\"").into()),
+    })
+}
+
+fn data_byte(s: &str) -> Result<u8> {
+    match s.parse::<u8>() {
+        Ok(n) if n < 128 => Ok(n),
+        _ => Err(format!("invalid MIDI value \"{s}\"").into()),
+    }
+}
+
+fn parse_osc(text: &str) -> Result<OscMessage> {
+    let mut words = text.split_whitespace();
+    let addr = words.next().ok_or("expected an OSC address")?;
+    let args = words
+        .map(|w| match w {
+            "true" => OscType::Bool(true),
+            "false" => OscType::Bool(false),
+            "nil" => OscType::Nil,
+            "inf" => OscType::Inf,
+            _ if w.contains('.') && w.parse::<f32>().is_ok() => OscType::Float(w.parse().unwrap()),
+            _ => match w.parse::<i32>() {
+                Ok(i) => OscType::Int(i),
+                Err(_) => OscType::String(w.to_string()),
+            },
+        })
+        .collect();
+    Ok(OscMessage {
+        addr: addr.to_string(),
+        args,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cc(channel: Channel, control: u8, value: u8) -> MidiMessage {
+        MidiMessage::ControlChange(channel, ControlEvent { control, value })
+    }
+
+    fn osc(addr: &str, value: f32) -> OscMessage {
+        OscMessage {
+            addr: addr.to_string(),
+            args: vec![OscType::Float(value)],
+        }
+    }
+
+    #[test]
+    fn cc_round_trip() {
+        let t = ControlChangeRangeTranslator::new(Channel::Ch1, 7, 0, 127, "/volume").unwrap();
+        assert_round_trip(&*t, &cc(Channel::Ch1, 7, 127), &osc("/volume", 1.0));
+        assert_round_trip(&*t, &cc(Channel::Ch1, 7, 0), &osc("/volume", 0.0));
+    }
+
+    #[test]
+    #[should_panic]
+    fn wrong_channel_fails() {
+        let t = ControlChangeRangeTranslator::new(Channel::Ch1, 7, 0, 127, "/volume").unwrap();
+        assert_midi_to_osc(&*t, &cc(Channel::Ch2, 7, 127), &osc("/volume", 1.0));
+    }
+
+    #[test]
+    fn note_round_trip() {
+        let t = NoteTranslator::new(Channel::Ch1, 60, VelocityCurve::Fixed, "/key/60").unwrap();
+        let on = MidiMessage::NoteOn(
+            Channel::Ch1,
+            KeyEvent {
+                key: 60,
+                value: 127,
+            },
+        );
+        assert_round_trip(&*t, &on, &osc("/key/60", 1.0));
+    }
+
+    /// Wraps a packet in `depth` bundles of one.
+    fn nest(pkt: OscPacket, depth: usize) -> OscPacket {
+        (0..depth).fold(pkt, |p, _| {
+            OscPacket::Bundle(OscBundle {
+                timetag: OscTime::from((0, 1)),
+                content: vec![p],
+            })
+        })
+    }
+
+    #[test]
+    fn bundles_are_walked_in_order() {
+        let m = |a: &str| OscPacket::Message(osc(a, 0.0));
+        let pkt = nest(
+            OscPacket::Bundle(OscBundle {
+                timetag: OscTime::from((0, 1)),
+                content: vec![m("/a"), nest(m("/b"), 3), m("/c"), nest(m("/d"), 1)],
+            }),
+            2,
+        );
+        let addrs: Vec<&str> = packet_messages(&pkt)
+            .iter()
+            .map(|m| m.addr.as_str())
+            .collect();
+        assert_eq!(addrs, ["/a", "/b", "/c", "/d"]);
+    }
+
+    #[test]
+    fn deeply_nested_bundles_are_translated() {
+        let t = ControlChangeRangeTranslator::new(Channel::Ch1, 7, 0, 127, "/volume").unwrap();
+        let set = ServerTranslationSet::new(vec![t]);
+        let pkt = nest(OscPacket::Message(osc("/volume", 1.0)), 2000);
+        let midi: Vec<MidiMessage> = packet_messages(&pkt)
+            .into_iter()
+            .flat_map(|m| set.osc_msg_to_slewed_midi(m))
+            .map(|(m, _)| m)
+            .collect();
+        assert_eq!(midi, [cc(Channel::Ch1, 7, 127)]);
+    }
+
+    #[test]
+    fn malformed_cases_are_rejected() {
+        assert!(parse_golden("cc 1 7 127 /volume 1.0").is_err());
+        assert!(parse_golden("cc 17 7 127 <=> /volume 1.0").is_err());
+        assert!(parse_golden("pitch 1 7 127 <=> /volume 1.0").is_err());
+        assert!(parse_golden("cc 1 7 128 <=> /volume 1.0").is_err());
+    }
+
+    /// Each golden file in `testdata/golden` is checked against the mappings
+    /// in the file of the same name with the extension `map`.
+    #[test]
+    fn golden_files() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/golden");
+        let mut checked = 0;
+        for entry in std::fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|e| e != "golden") {
+                continue;
+            }
+            let set = TranslationSetBuilder::new()
+                .file(&path.with_extension("map"))
+                .and_then(TranslationSetBuilder::build)
+                .unwrap();
+            let cases = read_golden(&path).unwrap();
+            let failures = set.check_golden(&cases);
+            assert!(
+                failures.is_empty(),
+                "{}:\n{}",
+                path.display(),
+                failures.join("\n")
+            );
+            checked += 1;
+        }
+        assert!(checked > 0, "no golden files in {}", dir.display());
+    }
+}
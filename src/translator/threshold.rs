@@ -0,0 +1,199 @@
+//! Filtering of values that haven't changed enough.
+//!
+//! Some hosts send their full state periodically, repeating values that
+//! haven't changed, and some controls jitter by tiny amounts. A mapping with
+//! a threshold drops a value that differs from the last one it passed for
+//! the same OSC address by less than the threshold, in either direction.
+//! Thresholds are in the normalized range of 0.0 through 1.0, so 0.01 is 1%
+//! of full scale. Arguments other than floats, such as strings, are dropped
+//! only if they're unchanged.
+//!
+//! Dropped values aren't counted as unmatched, since the mapping handles
+//! them; see `Translator::handles_osc`. The threshold is applied outside a
+//! mapping's other options, so it sees the values they produce.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use super::*;
+
+/// Wraps a translator, dropping values that differ too little from the last
+/// ones it passed.
+pub struct Thresholded {
+    inner: Box<dyn Translator>,
+    threshold: f32,
+    /// The last arguments received from OSC, by address.
+    received: Mutex<HashMap<String, Vec<OscType>>>,
+    /// The last arguments sent to OSC, by address.
+    sent: Mutex<HashMap<String, Vec<OscType>>>,
+}
+
+impl Thresholded {
+    /// Wrap a translator.
+    pub fn wrap(inner: Box<dyn Translator>, threshold: f32) -> Box<dyn Translator> {
+        Box::new(Thresholded {
+            inner,
+            threshold,
+            received: Mutex::default(),
+            sent: Mutex::default(),
+        })
+    }
+
+    /// Records the arguments for an address, unless they're too close to the
+    /// last ones recorded. Returns true if they were recorded.
+    fn changed(&self, last: &Mutex<HashMap<String, Vec<OscType>>>, msg: &OscMessage) -> bool {
+        let mut last = last.lock().unwrap();
+        match last.get(&msg.addr) {
+            Some(args) if self.close(args, &msg.args) => false,
+            _ => {
+                last.insert(msg.addr.clone(), msg.args.clone());
+                true
+            }
+        }
+    }
+
+    fn close(&self, a: &[OscType], b: &[OscType]) -> bool {
+        a.len() == b.len()
+            && a.iter().zip(b).all(|pair| match pair {
+                (OscType::Float(x), OscType::Float(y)) => (x - y).abs() < self.threshold,
+                (x, y) => x == y,
+            })
+    }
+
+    /// Drops the messages of a packet that haven't changed enough, returning
+    /// `None` if none are left.
+    fn filter_packet(&self, packet: OscPacket) -> Option<OscPacket> {
+        match packet {
+            OscPacket::Message(m) => self
+                .changed(&self.sent, &m)
+                .then_some(OscPacket::Message(m)),
+            OscPacket::Bundle(b) => {
+                let content: Vec<OscPacket> = b
+                    .content
+                    .into_iter()
+                    .filter_map(|p| self.filter_packet(p))
+                    .collect();
+                if content.is_empty() {
+                    None
+                } else {
+                    Some(OscPacket::Bundle(OscBundle {
+                        timetag: b.timetag,
+                        content,
+                    }))
+                }
+            }
+        }
+    }
+}
+
+impl Translator for Thresholded {
+    fn midi_to_osc(&self, midi: &MidiMessage) -> Option<OscPacket> {
+        self.inner
+            .midi_to_osc(midi)
+            .and_then(|p| self.filter_packet(p))
+    }
+
+    fn osc_to_midi(&self, addr_matcher: &Matcher, args: &[OscType]) -> Vec<MidiMessage> {
+        let msg = OscMessage {
+            addr: addr_matcher.pattern.clone(),
+            args: args.to_vec(),
+        };
+        let out = self.inner.osc_to_midi(addr_matcher, args);
+        // Only values that this mapping translates are recorded.
+        if out.is_empty() || self.changed(&self.received, &msg) {
+            out
+        } else {
+            vec![]
+        }
+    }
+
+    fn coverage(&self) -> Vec<Coverage> {
+        self.inner.coverage()
+    }
+
+    fn slew_rate(&self) -> Option<f32> {
+        self.inner.slew_rate()
+    }
+
+    fn coercion(&self) -> Option<Coercion> {
+        self.inner.coercion()
+    }
+
+    fn handles_midi(&self, midi: &MidiMessage) -> bool {
+        self.inner.handles_midi(midi)
+    }
+
+    fn handles_osc(&self, addr_matcher: &Matcher, args: &[OscType]) -> bool {
+        self.inner.handles_osc(addr_matcher, args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn volume() -> Box<dyn Translator> {
+        ControlChangeRangeTranslator::new(Channel::Ch1, 7, 0, 127, "/volume").unwrap()
+    }
+
+    fn to_midi(t: &dyn Translator, value: f32) -> Vec<MidiMessage> {
+        let matcher = Matcher::new("/volume").unwrap();
+        t.osc_to_midi(&matcher, &[OscType::Float(value)])
+    }
+
+    fn cc(value: u8) -> MidiMessage {
+        MidiMessage::ControlChange(Channel::Ch1, ControlEvent { control: 7, value })
+    }
+
+    #[test]
+    fn small_osc_changes_are_dropped() {
+        let t = Thresholded::wrap(volume(), 0.05);
+        let plain = volume();
+        assert_eq!(to_midi(t.as_ref(), 0.5), to_midi(plain.as_ref(), 0.5));
+        assert!(to_midi(t.as_ref(), 0.5).is_empty());
+        assert!(to_midi(t.as_ref(), 0.52).is_empty());
+        assert!(to_midi(t.as_ref(), 0.46).is_empty());
+        // Changes are measured from the last value passed, so drift adds up,
+        // and the latest value is the one delivered.
+        assert!(to_midi(t.as_ref(), 0.54).is_empty());
+        assert_eq!(to_midi(t.as_ref(), 0.56), to_midi(plain.as_ref(), 0.56));
+        assert!(to_midi(t.as_ref(), 0.52).is_empty());
+        assert_eq!(to_midi(t.as_ref(), 0.5), to_midi(plain.as_ref(), 0.5));
+    }
+
+    #[test]
+    fn small_midi_changes_are_dropped() {
+        let t = Thresholded::wrap(volume(), 0.05);
+        let plain = volume();
+        assert_eq!(t.midi_to_osc(&cc(64)), plain.midi_to_osc(&cc(64)));
+        assert_eq!(t.midi_to_osc(&cc(64)), None);
+        assert_eq!(t.midi_to_osc(&cc(65)), None);
+        assert_eq!(t.midi_to_osc(&cc(70)), None);
+        assert_eq!(t.midi_to_osc(&cc(72)), plain.midi_to_osc(&cc(72)));
+        assert_eq!(t.midi_to_osc(&cc(0)), plain.midi_to_osc(&cc(0)));
+    }
+
+    #[test]
+    fn directions_are_separate() {
+        let t = Thresholded::wrap(volume(), 0.05);
+        assert!(!to_midi(t.as_ref(), 0.5).is_empty());
+        assert!(t.midi_to_osc(&cc(64)).is_some());
+    }
+
+    #[test]
+    fn other_arguments_are_dropped_if_unchanged() {
+        let t = Thresholded {
+            inner: volume(),
+            threshold: 0.05,
+            received: Mutex::default(),
+            sent: Mutex::default(),
+        };
+        let msg = |s: &str| OscMessage {
+            addr: "/volume".to_string(),
+            args: vec![OscType::String(s.to_string())],
+        };
+        assert!(t.changed(&t.sent, &msg("a")));
+        assert!(!t.changed(&t.sent, &msg("a")));
+        assert!(t.changed(&t.sent, &msg("b")));
+    }
+}
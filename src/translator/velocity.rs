@@ -0,0 +1,159 @@
+//! Velocity curves of note mappings.
+//!
+//! By default a note mapping is a switch: any Note On with a non-zero
+//! velocity sends 1.0, and OSC of at least 0.5 sends a Note On with velocity
+//! 127. Pads and other velocity-sensitive controls can instead carry their
+//! velocity, under a curve:
+//!
+//! Curve        MIDI to OSC                  OSC to MIDI
+//! fixed        1.0 for any velocity         127 at 0.5 or more
+//! linear       velocity / 127               value * 127
+//! curve:EXP    (velocity / 127) ^ EXP       127 * value ^ (1 / EXP)
+//!
+//! An exponent above 1 gives soft hits more room; below 1, hard hits. Under
+//! the linear and exponential curves, OSC of 0.0 or less sends Note Off, and
+//! any other value sends a Note On with a velocity of at least 1, since a
+//! velocity of 0 would be taken as Note Off.
+
+use std::str::FromStr;
+
+/// How a note mapping translates velocity.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum VelocityCurve {
+    /// On or off, regardless of velocity.
+    #[default]
+    Fixed,
+    /// Velocity in proportion to the value.
+    Linear,
+    /// The normalized velocity raised to this exponent.
+    Exponential(f32),
+}
+
+impl VelocityCurve {
+    /// Translates the velocity of a Note On to a value, 0.0 through 1.0.
+    pub fn to_value(self, velocity: u8) -> f32 {
+        let v = velocity.min(127) as f32 / 127.0;
+        match self {
+            VelocityCurve::Fixed if velocity > 0 => 1.0,
+            VelocityCurve::Fixed => 0.0,
+            VelocityCurve::Linear => v,
+            VelocityCurve::Exponential(exp) => v.powf(exp),
+        }
+    }
+
+    /// Translates a value to the velocity of a Note On, or `None` for Note
+    /// Off.
+    pub fn to_velocity(self, value: f32) -> Option<u8> {
+        let v = match self {
+            VelocityCurve::Fixed => return (value >= 0.5).then_some(127),
+            _ if value <= 0.0 => return None,
+            VelocityCurve::Linear => value.min(1.0),
+            VelocityCurve::Exponential(exp) => value.min(1.0).powf(1.0 / exp),
+        };
+        Some(((v * 127.0).round() as u8).max(1))
+    }
+}
+
+impl FromStr for VelocityCurve {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "fixed" => Ok(VelocityCurve::Fixed),
+            None if s == "linear" => Ok(VelocityCurve::Linear),
+            Some(("curve", exp)) => match exp.parse::<f32>() {
+                Ok(e) if e > 0.0 && e.is_finite() => Ok(VelocityCurve::Exponential(e)),
+                _ => Err(format!("invalid velocity curve exponent \"{exp}\"")),
+            },
+            _ => Err(format!("unknown velocity curve \"{s}\"")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use midi_control::{Channel, KeyEvent, MidiMessage};
+    use rosc::{OscMessage, OscType};
+
+    use super::*;
+    use crate::translator::testing::{expect_midi, expect_osc};
+    use crate::translator::TranslationSetBuilder;
+
+    fn note_on(velocity: u8) -> MidiMessage {
+        MidiMessage::NoteOn(
+            Channel::Ch1,
+            KeyEvent {
+                key: 60,
+                value: velocity,
+            },
+        )
+    }
+
+    fn osc(v: f32) -> OscMessage {
+        OscMessage {
+            addr: "/pad".to_string(),
+            args: vec![OscType::Float(v)],
+        }
+    }
+
+    #[test]
+    fn curves_parse() {
+        assert_eq!("fixed".parse(), Ok(VelocityCurve::Fixed));
+        assert_eq!("linear".parse(), Ok(VelocityCurve::Linear));
+        assert_eq!("curve:2".parse(), Ok(VelocityCurve::Exponential(2.0)));
+        assert!("curve:0".parse::<VelocityCurve>().is_err());
+        assert!("curve:inf".parse::<VelocityCurve>().is_err());
+        assert!("log".parse::<VelocityCurve>().is_err());
+    }
+
+    #[test]
+    fn fixed_is_a_switch() {
+        let fixed = VelocityCurve::Fixed;
+        assert_eq!(fixed.to_value(1), 1.0);
+        assert_eq!(fixed.to_value(0), 0.0);
+        assert_eq!(fixed.to_velocity(0.5), Some(127));
+        assert_eq!(fixed.to_velocity(0.49), None);
+    }
+
+    #[test]
+    fn linear_and_exponential_curves() {
+        let linear = VelocityCurve::Linear;
+        assert_eq!(linear.to_value(127), 1.0);
+        assert_eq!(linear.to_velocity(0.5), Some(64));
+        let soft = VelocityCurve::Exponential(2.0);
+        assert!((soft.to_value(64) - 0.254).abs() < 0.001);
+        assert_eq!(soft.to_velocity(0.25), Some(64));
+        for v in [1, 32, 64, 100, 127] {
+            assert_eq!(soft.to_velocity(soft.to_value(v)), Some(v));
+        }
+    }
+
+    #[test]
+    fn quiet_values_still_sound() {
+        for curve in [VelocityCurve::Linear, VelocityCurve::Exponential(3.0)] {
+            assert_eq!(curve.to_velocity(1e-7), Some(1));
+            assert_eq!(curve.to_velocity(0.0), None);
+            assert_eq!(curve.to_velocity(-1.0), None);
+            assert_eq!(curve.to_velocity(2.0), Some(127));
+        }
+    }
+
+    #[test]
+    fn note_mappings_take_a_curve() {
+        let set = TranslationSetBuilder::new()
+            .line("note 1 60 /pad velocity=curve:2")
+            .and_then(TranslationSetBuilder::build)
+            .unwrap();
+        let off = MidiMessage::NoteOff(Channel::Ch1, KeyEvent { key: 60, value: 0 });
+        let soft = VelocityCurve::Exponential(2.0).to_value(64);
+        for (midi, value) in [(note_on(127), 1.0), (note_on(64), soft), (off, 0.0)] {
+            expect_osc(set.midi_msg_to_osc(&midi).as_ref(), &osc(value)).unwrap();
+            let out: Vec<MidiMessage> = set
+                .osc_msg_to_slewed_midi(&osc(value))
+                .into_iter()
+                .map(|(m, _)| m)
+                .collect();
+            expect_midi(&out, &midi).unwrap();
+        }
+    }
+}